@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use blst::BLST_ERROR;
+use blst::min_pk as bls;
+use message::Certificate;
+use ring::signature;
+use serde::{Deserialize, Serialize};
+
+/// Domain separation tag of the BLS signatures on echoes.
+pub const BLS_SIGN_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/*
+* A committee member as a light client knows it: its stake and its keys. A
+* BLS key is trusted as given, the client is expected to have checked its
+* proof of possession when it learned the committee.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Member {
+    pub id: u32,
+    pub stake: u64,
+    pub pub_key: Vec<u8>,
+    pub bls_key: Option<Vec<u8>>,
+}
+
+/* more than 2/3 of the committee's stake */
+pub fn is_quorum(committee:&[Member], stake:u64) -> bool {
+    let total: u64 = committee.iter().map(|member| member.stake).sum();
+    stake > 2 * total / 3
+}
+
+/* the stake of the distinct members whose Ed25519 signature on `msg` is valid */
+pub fn signed_stake<'a, I: IntoIterator<Item = (u32, &'a [u8])>>(committee:&[Member], msg:&[u8], signs:I) -> u64 {
+    let mut signers = HashSet::new();
+    signs.into_iter()
+        .filter_map(|(id, sign)| {
+            let member = committee.iter().find(|member| member.id == id)?;
+            signature::UnparsedPublicKey::new(&signature::ED25519, &member.pub_key)
+                .verify(msg, sign)
+                .ok()?;
+            Some(member)
+        })
+        .filter(|member| signers.insert(member.id))
+        .map(|member| member.stake)
+        .sum()
+}
+
+/*
+* Whether more than 2/3 of the committee's stake signed `digest` in the
+* certificate, the way echoes sign a batch digest. Signatures of non-members
+* do not count, and an aggregate counts only if all its signers are members
+* with a BLS key.
+*/
+pub fn is_certified(committee:&[Member], digest:&[u8], cert:&Certificate) -> bool {
+    let member = |id:u32| committee.iter().find(|member| member.id == id);
+    let stake: u64 = match cert {
+        Certificate::Signs(cert) => signed_stake(committee, digest, cert.iter()),
+        Certificate::Aggregate(cert) => {
+            let signers: Option<Vec<(u64, bls::PublicKey)>> = cert.signer_ids().into_iter()
+                .map(|id| {
+                    let member = member(id)?;
+                    let pub_key = bls::PublicKey::from_bytes(member.bls_key.as_ref()?).ok()?;
+                    Some((member.stake, pub_key))
+                })
+                .collect();
+            match (signers, bls::Signature::from_bytes(&cert.sign)) {
+                (Some(signers), Ok(sign)) if !signers.is_empty() => {
+                    let pub_keys: Vec<&bls::PublicKey> = signers.iter().map(|(_, pub_key)| pub_key).collect();
+                    if sign.fast_aggregate_verify(true, digest, BLS_SIGN_DST, &pub_keys) == BLST_ERROR::BLST_SUCCESS {
+                        signers.iter().map(|(stake, _)| stake).sum()
+                    } else {
+                        0
+                    }
+                }
+                _ => 0,
+            }
+        }
+    };
+    is_quorum(committee, stake)
+}
@@ -0,0 +1,141 @@
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use network::{FrameCodec, Framing, Link, Tcp, Transport};
+use rand::seq::index;
+use std::net::SocketAddr;
+use tokio_util::codec::Framed;
+
+use crate::{batch_leaf, tx_digest, tx_leaf};
+use crate::{DasError, DasHeader, DasRequest, DasResponse, DasSample, Inclusion, Member, DAS_CHUNKS, DAS_MAX_SAMPLES};
+
+/*
+* A light client of the DAS endpoint of one sequencer node. Requests are sent
+* one at a time over a single connection, with the default network framing.
+*/
+pub struct DasClient {
+    address: SocketAddr,
+    transport: Framed<Link, FrameCodec>,
+}
+
+impl DasClient {
+    pub async fn connect(address:SocketAddr) -> Result<Self, DasError> {
+        Self::connect_with_transport(address, &Tcp).await
+    }
+
+    /* same as `connect`, over another transport, e.g. a network::MemoryNetwork */
+    pub async fn connect_with_transport(address:SocketAddr, transport:&dyn Transport) -> Result<Self, DasError> {
+        let link = transport
+            .connect(address)
+            .await
+            .map_err(|e| DasError::Network(address, e))?;
+        let transport = Framing::default().framed(link);
+        Ok(DasClient { address, transport })
+    }
+
+    pub async fn header(&mut self, originator:u32, round:u32) -> Result<DasHeader, DasError> {
+        match self.request(DasRequest::Header{ originator, round }).await? {
+            DasResponse::Header(header) if header.originator == originator && header.round == round => Ok(header),
+            DasResponse::NotFound => Err(DasError::NotFound(originator, round)),
+            _ => Err(DasError::Unexpected(self.address)),
+        }
+    }
+
+    /* the chunks at `indices`, as served; check them with DasSample::verify */
+    pub async fn samples(&mut self, originator:u32, round:u32, indices:Vec<u32>) -> Result<Vec<DasSample>, DasError> {
+        match self.request(DasRequest::Samples{ originator, round, indices }).await? {
+            DasResponse::Samples(samples) => Ok(samples),
+            DasResponse::NotFound => Err(DasError::NotFound(originator, round)),
+            _ => Err(DasError::Unexpected(self.address)),
+        }
+    }
+
+    /*
+    * Checks that the header's root is certified by `committee`, the members
+    * of the batch's round, then samples `count` distinct random chunks of
+    * the batch and checks each of them against the root. Returns the
+    * confidence that the batch is available.
+    */
+    pub async fn check_availability(
+        &mut self,
+        originator:u32,
+        round:u32,
+        count:usize,
+        committee:&[Member]
+    ) -> Result<f64, DasError> {
+        let header = self.header(originator, round).await?;
+        if !header.verify(committee) {
+            return Err(DasError::BadHeader(originator, round));
+        }
+        let indices: Vec<u32> = index::sample(&mut rand::thread_rng(), DAS_CHUNKS, count.min(DAS_CHUNKS))
+            .into_iter()
+            .map(|i| i as u32)
+            .collect();
+
+        for batch in indices.chunks(DAS_MAX_SAMPLES) {
+            let samples = self.samples(originator, round, batch.to_vec()).await?;
+            for index in batch {
+                let valid = samples.iter().any(|sample| sample.index == *index && sample.verify(&header));
+                if !valid {
+                    return Err(DasError::BadSample(*index));
+                }
+            }
+        }
+        Ok(header.confidence(indices.len()))
+    }
+
+    /*
+    * The proof that the batch of `originator`, with `batch_digest`, is in
+    * the round, checked against `committee`, the committee of the last round
+    * of the checkpoint interval. NotFound until the round is covered by a
+    * stable checkpoint.
+    */
+    pub async fn inclusion(
+        &mut self,
+        originator:u32,
+        round:u32,
+        batch_digest:&[u8],
+        committee:&[Member]
+    ) -> Result<Inclusion, DasError> {
+        let inclusion = match self.request(DasRequest::Inclusion{ originator, round }).await? {
+            DasResponse::Inclusion(inclusion) if inclusion.round == round => inclusion,
+            DasResponse::NotFound => return Err(DasError::NotFound(originator, round)),
+            _ => return Err(DasError::Unexpected(self.address)),
+        };
+        if !inclusion.verify(&batch_leaf(originator, batch_digest), committee) {
+            return Err(DasError::BadInclusion(originator, round));
+        }
+        Ok(inclusion)
+    }
+
+    /*
+    * The proof that a transaction was reported in a batch of `originator`,
+    * the node it was submitted to, checked the same way. Submitting does not
+    * return it: the client asks once the round is checkpointed.
+    */
+    pub async fn tx_inclusion(&mut self, originator:u32, tx:&[u8], committee:&[Member]) -> Result<Inclusion, DasError> {
+        let tx_digest = tx_digest(tx);
+        let inclusion = match self.request(DasRequest::TxInclusion{ originator, tx_digest: tx_digest.clone() }).await? {
+            DasResponse::Inclusion(inclusion) => inclusion,
+            DasResponse::NotFound => return Err(DasError::TxNotFound),
+            _ => return Err(DasError::Unexpected(self.address)),
+        };
+        if !inclusion.verify(&tx_leaf(originator, &tx_digest), committee) {
+            return Err(DasError::BadInclusion(originator, inclusion.round));
+        }
+        Ok(inclusion)
+    }
+
+    async fn request(&mut self, request:DasRequest) -> Result<DasResponse, DasError> {
+        let bytes = Bytes::from(bincode::serialize(&request).expect("failed to serialize request"));
+        self.transport
+            .send(bytes)
+            .await
+            .map_err(|e| DasError::Network(self.address, e))?;
+        match self.transport.next().await {
+            Some(Ok(bytes)) => bincode::deserialize(&bytes).map_err(|e| DasError::Malformed(self.address, e)),
+            Some(Err(e)) => Err(DasError::Network(self.address, e)),
+            None => Err(DasError::Closed(self.address)),
+        }
+    }
+}
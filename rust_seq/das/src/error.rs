@@ -0,0 +1,32 @@
+use std::net::SocketAddr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DasError {
+    #[error("Failed to reach {0}: {1}")]
+    Network(SocketAddr, std::io::Error),
+
+    #[error("Connection to {0} closed")]
+    Closed(SocketAddr),
+
+    #[error("Malformed response from {0}: {1}")]
+    Malformed(SocketAddr, bincode::Error),
+
+    #[error("Unexpected response from {0}")]
+    Unexpected(SocketAddr),
+
+    #[error("Round {1} of {0} is not available at the node")]
+    NotFound(u32, u32),
+
+    #[error("Header of round {1} of {0} is not certified by the committee")]
+    BadHeader(u32, u32),
+
+    #[error("Chunk {0} is missing or does not match the root")]
+    BadSample(u32),
+
+    #[error("Bad inclusion proof for round {1} of {0}")]
+    BadInclusion(u32, u32),
+
+    #[error("The transaction is not sequenced at the node, or not checkpointed yet")]
+    TxNotFound,
+}
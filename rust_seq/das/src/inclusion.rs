@@ -0,0 +1,101 @@
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::cert::{is_quorum, signed_stake, Member};
+use crate::merkle;
+
+/// A checkpoint is taken every CHECKPOINT_INTERVAL rounds, and its data root
+/// covers the interval's rounds.
+pub const CHECKPOINT_INTERVAL: u32 = 10;
+
+/*
+* The bytes a node signs when voting for a checkpoint: the digest of the log
+* up to round rn, and the data root of the interval it closes, the Merkle root
+* of the round roots of [rn - CHECKPOINT_INTERVAL, rn).
+*/
+pub fn checkpoint_msg(rn:u32, digest:&[u8], data_root:&[u8]) -> Vec<u8> {
+    let mut msg = b"checkpoint".to_vec();
+    msg.extend_from_slice(&rn.to_le_bytes());
+    msg.extend_from_slice(digest);
+    msg.extend_from_slice(data_root);
+    msg
+}
+
+/*
+* CheckpointCert proves that the delivered log of rounds [0, rn) has the given
+* digest, and that the round roots of the interval it closes have the given
+* data root (see checkpoint_msg): 2f+1 nodes signed (rn, digest, data_root).
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckpointCert {
+    pub rn: u32,
+    pub digest: Vec<u8>,
+    pub data_root: Vec<u8>,
+    pub signs: Vec<(u32, Vec<u8>)>,
+}
+
+impl CheckpointCert {
+    /* checks the signatures against `committee`, the committee of round rn - 1 */
+    pub fn verify(&self, committee:&[Member]) -> bool {
+        let msg = checkpoint_msg(self.rn, &self.digest, &self.data_root);
+        let signs = self.signs.iter().map(|(id, sign)| (*id, sign.as_slice()));
+        is_quorum(committee, signed_stake(committee, &msg, signs))
+    }
+}
+
+/* the digest of a transaction, as the sequencer orders it */
+pub fn tx_digest(tx:&[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, tx).as_ref().to_vec()
+}
+
+/* the leaf of a batch in its round's tree */
+pub fn batch_leaf(originator:u32, batch_digest:&[u8]) -> Vec<u8> {
+    let mut leaf = b"batch".to_vec();
+    leaf.extend_from_slice(&originator.to_le_bytes());
+    leaf.extend_from_slice(batch_digest);
+    leaf
+}
+
+/* the leaf of a transaction the originator reported in its batch of the round */
+pub fn tx_leaf(originator:u32, tx_digest:&[u8]) -> Vec<u8> {
+    let mut leaf = b"tx".to_vec();
+    leaf.extend_from_slice(&originator.to_le_bytes());
+    leaf.extend_from_slice(tx_digest);
+    leaf
+}
+
+/*
+* The proof that a leaf (a batch or a transaction) is in the tree of a round,
+* and that the round is covered by a stable checkpoint: the path of the leaf
+* to the round root, the path of the round root to the checkpoint's data
+* root, and the checkpoint certificate. No single node is trusted with it.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Inclusion {
+    pub round: u32,
+    pub index: u32,
+    pub num_leaves: u32,
+    pub proof: Vec<Vec<u8>>,
+    pub round_root: Vec<u8>,
+    pub round_proof: Vec<Vec<u8>>,
+    pub checkpoint: CheckpointCert,
+}
+
+impl Inclusion {
+    /* checks that `leaf` is in the round, against the committee of the checkpoint */
+    pub fn verify(&self, leaf:&[u8], committee:&[Member]) -> bool {
+        let first = match self.checkpoint.rn.checked_sub(CHECKPOINT_INTERVAL) {
+            Some(first) if (first..self.checkpoint.rn).contains(&self.round) => first,
+            _ => return false,
+        };
+        merkle::verify(&self.round_root, self.num_leaves as usize, self.index as usize, leaf, &self.proof)
+            && merkle::verify(
+                &self.checkpoint.data_root,
+                CHECKPOINT_INTERVAL as usize,
+                (self.round - first) as usize,
+                &self.round_root,
+                &self.round_proof
+            )
+            && self.checkpoint.verify(committee)
+    }
+}
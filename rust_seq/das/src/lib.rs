@@ -0,0 +1,122 @@
+/*
+* Data availability sampling of sequenced batches. A sequencer node serves,
+* for each delivered batch, a header with the Merkle root of the batch's
+* erasure-coded chunks, and chunks with their inclusion proofs. The root is
+* part of the batch digest the committee certifies, so a light client that
+* knows the committee checks it without trusting the node, then samples a
+* few random chunks and checks them against the root instead of downloading
+* the batch. The node also proves that a batch, or a transaction reported in
+* one, is in a round covered by a stable checkpoint (see Inclusion).
+*/
+mod cert;
+mod client;
+mod error;
+mod inclusion;
+pub mod merkle;
+
+#[cfg(test)]
+#[path = "tests/das_tests.rs"]
+pub mod das_tests;
+
+use message::Certificate;
+use serde::{Deserialize, Serialize};
+
+pub use crate::cert::{is_certified, Member, BLS_SIGN_DST};
+pub use crate::client::DasClient;
+pub use crate::error::DasError;
+pub use crate::inclusion::{batch_leaf, checkpoint_msg, tx_digest, tx_leaf, CheckpointCert, Inclusion, CHECKPOINT_INTERVAL};
+
+/// Chunks a batch is coded into for sampling.
+pub const DAS_CHUNKS: usize = 64;
+/// Chunks needed to decode a batch: half of them, so that hiding the batch
+/// means withholding more than half of the chunks.
+pub const DAS_DATA_CHUNKS: usize = DAS_CHUNKS / 2;
+/// Max number of chunks served for a single request.
+pub const DAS_MAX_SAMPLES: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DasRequest {
+    Header{ originator:u32, round:u32 },
+    Samples{ originator:u32, round:u32, indices:Vec<u32> },
+    Inclusion{ originator:u32, round:u32 },
+    TxInclusion{ originator:u32, tx_digest:Vec<u8> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DasResponse {
+    Header(DasHeader),
+    Samples(Vec<DasSample>),
+    Inclusion(Inclusion),
+    /*
+    * the batch (or round, or transaction) is not delivered, no longer kept,
+    * or not yet covered by a stable checkpoint there
+    */
+    NotFound,
+}
+
+/*
+* The digest the committee certifies for a batch: the root of a two-leaf
+* Merkle tree, the digest of the payload (the root of the chunks it is
+* dispersed in, with AVID) and the root of its sampling chunks.
+*/
+pub fn batch_digest(payload_digest:&[u8], das_root:&[u8]) -> Vec<u8> {
+    merkle::MerkleTree::new(&[payload_digest, das_root]).root()
+}
+
+/*
+* The sampling root of a batch, with what proves the committee certified it:
+* the payload digest it is certified with, and the certificate.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DasHeader {
+    pub originator: u32,
+    pub round: u32,
+    pub payload_digest: Vec<u8>,
+    pub root: Vec<u8>,
+    pub cert: Certificate,
+}
+
+impl DasHeader {
+    pub fn digest(&self) -> Vec<u8> {
+        batch_digest(&self.payload_digest, &self.root)
+    }
+
+    /* checks that a quorum of `committee` certified the root */
+    pub fn verify(&self, committee:&[Member]) -> bool {
+        is_certified(committee, &self.digest(), &self.cert)
+    }
+
+    /* the probability that the batch can be decoded, after `samples` distinct chunks were served */
+    pub fn confidence(&self, samples:usize) -> f64 {
+        confidence(DAS_CHUNKS, DAS_DATA_CHUNKS, samples)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DasSample {
+    pub index: u32,
+    pub chunk: Vec<u8>,
+    pub proof: Vec<Vec<u8>>,
+}
+
+impl DasSample {
+    pub fn verify(&self, header:&DasHeader) -> bool {
+        merkle::verify(&header.root, DAS_CHUNKS, self.index as usize, &self.chunk, &self.proof)
+    }
+}
+
+/*
+* A batch coded into `num_chunks`, any `data_chunks` of which decode it, is
+* unavailable only if more than num_chunks - data_chunks chunks are withheld.
+* Then each distinct chunk sampled at random is served with probability at
+* most (data_chunks - 1 - i) / (num_chunks - i), and the confidence is one
+* minus the probability that all of them were. This holds against a node that
+* does not know which samples come from whom: a node serving a single client
+* can still fool it by answering exactly its queries.
+*/
+pub fn confidence(num_chunks:usize, data_chunks:usize, samples:usize) -> f64 {
+    let fooled: f64 = (0..samples.min(num_chunks))
+        .map(|i| data_chunks.saturating_sub(1 + i) as f64 / (num_chunks - i) as f64)
+        .product();
+    1.0 - fooled
+}
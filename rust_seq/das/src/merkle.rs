@@ -0,0 +1,85 @@
+use ring::digest;
+
+type U8Arr = Vec<u8>;
+
+#[cfg(test)]
+#[path = "tests/merkle_tests.rs"]
+pub mod merkle_tests;
+
+/*
+* A binary Merkle tree over a list of leaves. Leaves and inner nodes are
+* hashed with different prefixes, so a leaf cannot pass for a node. A level
+* with an odd number of nodes pairs its last node with itself.
+*/
+pub struct MerkleTree {
+    levels: Vec<Vec<U8Arr>>, // levels[0] -> leaf hashes, the last level -> the root
+}
+
+impl MerkleTree {
+    pub fn new<T: AsRef<[u8]>>(leaves:&[T]) -> Self {
+        assert!(!leaves.is_empty());
+        let mut levels = vec![leaves.iter().map(|leaf| hash_leaf(leaf.as_ref())).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| hash_node(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> U8Arr {
+        self.levels.last().unwrap()[0].clone()
+    }
+
+    /* the sibling of each node on the path from leaf `index` to the root */
+    pub fn proof(&self, index:usize) -> Vec<U8Arr> {
+        let mut index = index;
+        let mut proof = Vec::with_capacity(self.levels.len() - 1);
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = level.get(index ^ 1).unwrap_or(&level[index]);
+            proof.push(sibling.clone());
+            index /= 2;
+        }
+        proof
+    }
+}
+
+/* checks that `leaf` is leaf `index` of a tree of `num_leaves` leaves */
+pub fn verify(root:&[u8], num_leaves:usize, index:usize, leaf:&[u8], proof:&[U8Arr]) -> bool {
+    if index >= num_leaves || proof.len() != depth(num_leaves) {
+        return false;
+    }
+    let mut index = index;
+    let mut hash = hash_leaf(leaf);
+    for sibling in proof {
+        hash = if index.is_multiple_of(2) {
+            hash_node(&hash, sibling)
+        } else {
+            hash_node(sibling, &hash)
+        };
+        index /= 2;
+    }
+    hash == root
+}
+
+/* the length of a proof in a tree of `num_leaves` leaves */
+pub fn depth(num_leaves:usize) -> usize {
+    num_leaves.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+fn hash_leaf(leaf:&[u8]) -> U8Arr {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[0]);
+    ctx.update(leaf);
+    ctx.finish().as_ref().to_vec()
+}
+
+fn hash_node(left:&[u8], right:&[u8]) -> U8Arr {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[1]);
+    ctx.update(left);
+    ctx.update(right);
+    ctx.finish().as_ref().to_vec()
+}
@@ -0,0 +1,147 @@
+// das_tests.rs
+use super::*;
+use merkle::MerkleTree;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use message::{AggCert, Certificate};
+
+fn header_of(chunks:&[Vec<u8>]) -> DasHeader {
+    DasHeader {
+        originator: 1,
+        round: 7,
+        payload_digest: vec![0; 32],
+        root: MerkleTree::new(chunks).root(),
+        cert: Certificate::default(),
+    }
+}
+
+/* a committee of `n` members of stake 1, and their Ed25519 keys */
+fn committee(n:u32) -> (Vec<Member>, Vec<Ed25519KeyPair>) {
+    let keypairs: Vec<Ed25519KeyPair> = (0..n)
+        .map(|_| {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+        })
+        .collect();
+    let members = keypairs.iter()
+        .enumerate()
+        .map(|(id, keypair)| Member { id: id as u32, stake: 1, pub_key: keypair.public_key().as_ref().to_vec(), bls_key: None })
+        .collect();
+    (members, keypairs)
+}
+
+#[test]
+fn test_samples_are_checked_against_the_root() {
+    let chunks: Vec<Vec<u8>> = (0..DAS_CHUNKS).map(|i| vec![i as u8; 16]).collect();
+    let tree = MerkleTree::new(&chunks);
+    let header = header_of(&chunks);
+    let sample = |index: usize| DasSample { index: index as u32, chunk: chunks[index].clone(), proof: tree.proof(index) };
+
+    assert!(sample(5).verify(&header));
+    let mut moved = sample(5);
+    moved.index = 6;
+    assert!(!moved.verify(&header));
+    let mut tampered = sample(5);
+    tampered.chunk[0] ^= 1;
+    assert!(!tampered.verify(&header));
+}
+
+#[test]
+fn test_confidence_grows_with_samples() {
+    assert_eq!(confidence(64, 32, 0), 0.0);
+    // a withheld batch serves a random chunk with probability 31/64
+    assert!((confidence(64, 32, 1) - 33.0 / 64.0).abs() < 1e-9);
+    assert!(confidence(64, 32, 10) > 0.999);
+    assert!(confidence(64, 32, 10) < confidence(64, 32, 11));
+    // past data_chunks - 1 distinct samples, a withheld batch cannot hide
+    assert_eq!(confidence(64, 32, 32), 1.0);
+    assert_eq!(confidence(4, 2, 100), 1.0);
+}
+
+#[test]
+fn test_header_root_is_certified() {
+    let chunks: Vec<Vec<u8>> = (0..DAS_CHUNKS).map(|i| vec![i as u8; 16]).collect();
+    let mut header = header_of(&chunks);
+    let (committee, keypairs) = committee(4);
+    let signs = |ids:&[usize], digest:&[u8]| ids.iter().map(|id| (*id as u32, keypairs[*id].sign(digest).as_ref().to_vec())).collect();
+
+    header.cert = Certificate::Signs(signs(&[0, 2, 3], &header.digest()));
+    assert!(header.verify(&committee));
+    // 2 of 4 is not a quorum, nor are signatures of non-members
+    header.cert = Certificate::Signs(signs(&[0, 2], &header.digest()));
+    assert!(!header.verify(&committee));
+    header.cert = Certificate::Signs(signs(&[0, 1, 2], &header.digest()));
+    let others: Vec<Member> = committee.iter().filter(|member| member.id != 1).cloned().collect();
+    assert!(!header.verify(&others));
+
+    // the certificate is on the sampling root as well as the payload
+    let mut other = header.clone();
+    other.root = vec![0; 32];
+    assert!(!other.verify(&committee));
+    other = header.clone();
+    other.payload_digest = vec![1; 32];
+    assert!(!other.verify(&committee));
+}
+
+#[test]
+fn test_inclusion_proof() {
+    let (committee, keypairs) = committee(4);
+    let leaves: Vec<Vec<u8>> = vec![batch_leaf(10, &[1; 32]), batch_leaf(11, &[2; 32]), tx_leaf(10, &tx_digest(b"tx"))];
+    let tree = MerkleTree::new(&leaves);
+    // the round roots of the interval [20, 30), round 23 being ours
+    let mut round_roots: Vec<Vec<u8>> = (0..CHECKPOINT_INTERVAL).map(|i| vec![i as u8; 32]).collect();
+    round_roots[3] = tree.root();
+    let data_tree = MerkleTree::new(&round_roots);
+    let mut checkpoint = CheckpointCert { rn: 30, digest: vec![7; 32], data_root: data_tree.root(), signs: Vec::new() };
+    let msg = checkpoint_msg(30, &checkpoint.digest, &checkpoint.data_root);
+    checkpoint.signs = [3, 0, 1].iter().map(|id| (*id, keypairs[*id as usize].sign(&msg).as_ref().to_vec())).collect();
+    let inclusion = |index:usize| Inclusion {
+        round: 23,
+        index: index as u32,
+        num_leaves: 3,
+        proof: tree.proof(index),
+        round_root: tree.root(),
+        round_proof: data_tree.proof(3),
+        checkpoint: checkpoint.clone(),
+    };
+
+    assert!(inclusion(1).verify(&batch_leaf(11, &[2; 32]), &committee));
+    assert!(inclusion(2).verify(&tx_leaf(10, &tx_digest(b"tx")), &committee));
+    // the leaf binds the originator as well as the digest
+    assert!(!inclusion(1).verify(&batch_leaf(10, &[2; 32]), &committee));
+    assert!(!inclusion(2).verify(&tx_leaf(11, &tx_digest(b"tx")), &committee));
+    assert!(!inclusion(2).verify(&tx_leaf(10, &tx_digest(b"other")), &committee));
+    // the round must be the one at its place in the checkpoint interval
+    let mut moved = inclusion(1);
+    moved.round = 24;
+    assert!(!moved.verify(&batch_leaf(11, &[2; 32]), &committee));
+    moved.round = 33;
+    assert!(!moved.verify(&batch_leaf(11, &[2; 32]), &committee));
+    // and the checkpoint signed by a quorum, counting each member once
+    let mut weak = inclusion(1);
+    weak.checkpoint.signs = vec![checkpoint.signs[0].clone(), checkpoint.signs[1].clone(), checkpoint.signs[0].clone()];
+    assert!(!weak.verify(&batch_leaf(11, &[2; 32]), &committee));
+}
+
+#[test]
+fn test_aggregate_certificate() {
+    use blst::min_pk as bls;
+    let secrets: Vec<bls::SecretKey> = (0..4u8).map(|i| bls::SecretKey::key_gen(&[i; 32], &[]).unwrap()).collect();
+    let mut committee = committee(4).0;
+    for (member, secret) in committee.iter_mut().zip(&secrets) {
+        member.bls_key = Some(secret.sk_to_pk().to_bytes().to_vec());
+    }
+    let digest = batch_digest(&[1; 32], &[2; 32]);
+    let aggregate = |ids:&[u32]| {
+        let signs: Vec<bls::Signature> = ids.iter().map(|id| secrets[*id as usize].sign(&digest, BLS_SIGN_DST, &[])).collect();
+        let signs: Vec<&bls::Signature> = signs.iter().collect();
+        let sign = bls::AggregateSignature::aggregate(&signs, true).unwrap().to_signature().to_bytes().to_vec();
+        Certificate::Aggregate(AggCert::new(ids, sign))
+    };
+
+    assert!(is_certified(&committee, &digest, &aggregate(&[0, 1, 3])));
+    assert!(!is_certified(&committee, &digest, &aggregate(&[0, 1])));
+    assert!(!is_certified(&committee, &[0; 32], &aggregate(&[0, 1, 3])));
+    // a signer without a known BLS key voids the aggregate
+    committee[3].bls_key = None;
+    assert!(!is_certified(&committee, &digest, &aggregate(&[0, 1, 3])));
+}
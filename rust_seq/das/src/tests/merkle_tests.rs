@@ -0,0 +1,36 @@
+// merkle_tests.rs
+use super::*;
+
+#[test]
+fn test_every_leaf_has_a_proof() {
+    for num_leaves in 1..=9 {
+        let leaves: Vec<U8Arr> = (0..num_leaves).map(|i| vec![i as u8; 10]).collect();
+        let tree = MerkleTree::new(&leaves);
+        let root = tree.root();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(i);
+            assert_eq!(proof.len(), depth(num_leaves));
+            assert!(verify(&root, num_leaves, i, leaf, &proof));
+        }
+    }
+}
+
+#[test]
+fn test_bad_proofs_are_rejected() {
+    let leaves: Vec<U8Arr> = (0..5).map(|i| vec![i as u8; 10]).collect();
+    let tree = MerkleTree::new(&leaves);
+    let root = tree.root();
+    let proof = tree.proof(2);
+
+    assert!(!verify(&root, 5, 2, &leaves[3], &proof));
+    assert!(!verify(&root, 5, 3, &leaves[2], &proof));
+    assert!(!verify(&root, 4, 2, &leaves[2], &proof[..2]));
+    // the last leaf is paired with itself, but there is no leaf past it
+    let last = tree.proof(4);
+    assert!(verify(&root, 5, 4, &leaves[4], &last));
+    assert!(!verify(&root, 5, 5, &leaves[4], &last));
+
+    let mut forged = proof.clone();
+    forged[1][0] ^= 1;
+    assert!(!verify(&root, 5, 2, &leaves[2], &forged));
+}
@@ -6,151 +6,682 @@ const SEND_MSG:u8 = 0x1;
 const ECHO_MSG:u8 = 0x2;
 const FIN_MSG:u8 = 0x3;
 const SUP_MSG:u8 = 0x4;
+const SYNC_REQ_MSG:u8 = 0x5;
+const SYNC_RESP_MSG:u8 = 0x6;
+const CHECKPOINT_MSG:u8 = 0x7;
+const AGG_FIN_MSG:u8 = 0x8;
+const AGG_SUP_MSG:u8 = 0x9;
+const AGG_SYNC_RESP_MSG:u8 = 0xa;
+const CHUNK_MSG:u8 = 0xb;
+const CLOSE_MSG:u8 = 0xc;
+const CHUNK_REQ_MSG:u8 = 0xd;
 const SIGN_LEN:usize = 64;
+const DIGEST_LEN:usize = 32;
+/// Length of an aggregated (BLS) signature.
+pub const AGG_SIGN_LEN:usize = 96;
+/// Node ids are below MAX_NODES, so a certificate has at most that many signers.
+pub const MAX_NODES:usize = 64;
+
+/*
+* A set of node ids, as a bitmap: bit i of byte i/8 is node i. An id is in it
+* at most once, and the ids come out in ascending order.
+*/
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Signers(Vec<u8>);
+impl Signers {
+    pub fn from_ids(ids:&[u32]) -> Self {
+        let mut signers = Signers::default();
+        for id in ids {
+            signers.insert(*id);
+        }
+        signers
+    }
+
+    /* false if the id was already in */
+    pub fn insert(&mut self, id:u32) -> bool {
+        let byte = id as usize / 8;
+        if self.0.len() <= byte {
+            self.0.resize(byte + 1, 0);
+        }
+        let added = self.0[byte] & (1 << (id % 8)) == 0;
+        self.0[byte] |= 1 << (id % 8);
+        added
+    }
+
+    pub fn contains(&self, id:u32) -> bool {
+        self.0.get(id as usize / 8).is_some_and(|byte| byte & (1 << (id % 8)) != 0)
+    }
+
+    /* ascending */
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.0.len() as u32 * 8).filter(|id| self.contains(*id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn encoded_len(&self) -> usize {
+        4 + self.0.len()
+    }
+}
+
+/*
+* A certificate of individual signatures: who signed, as a bitmap, and their
+* signatures in the order of their ids. A node signs at most once, which the
+* type guarantees; decoding a certificate whose bitmap and signatures do not
+* match fails.
+*/
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(try_from = "(Signers, Vec<Vec<u8>>)")]
+pub struct SignCert {
+    signers: Signers,
+    signs: Vec<Vec<u8>>,
+}
+impl SignCert {
+    /* adds a signature, unless the node already signed */
+    pub fn insert(&mut self, id:u32, sign:Vec<u8>) -> bool {
+        if !self.signers.insert(id) {
+            return false;
+        }
+        let pos = self.signers.ids().position(|signer| signer == id).unwrap();
+        self.signs.insert(pos, sign);
+        true
+    }
+
+    pub fn is_signer(&self, id:u32) -> bool {
+        self.signers.contains(id)
+    }
+
+    pub fn signers(&self) -> &Signers {
+        &self.signers
+    }
+
+    /* (id, signature) pairs, by ascending id */
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> + '_ {
+        self.signers.ids().zip(self.signs.iter().map(|sign| sign.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.signs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signs.is_empty()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.signers.encoded_len() + self.signs.len() * SIGN_LEN
+    }
+}
+
+/* keeps the first signature of each node */
+impl FromIterator<(u32, Vec<u8>)> for SignCert {
+    fn from_iter<I: IntoIterator<Item = (u32, Vec<u8>)>>(signs:I) -> Self {
+        let mut cert = SignCert::default();
+        for (id, sign) in signs {
+            cert.insert(id, sign);
+        }
+        cert
+    }
+}
+
+impl TryFrom<(Signers, Vec<Vec<u8>>)> for SignCert {
+    type Error = String;
+    fn try_from((signers, signs):(Signers, Vec<Vec<u8>>)) -> Result<Self, Self::Error> {
+        if signers.len() != signs.len() {
+            return Err(format!("{} signers but {} signatures", signers.len(), signs.len()));
+        }
+        Ok(SignCert { signers, signs })
+    }
+}
+
+/*
+* A constant-size certificate: one aggregated signature of the signers.
+*/
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AggCert {
+    pub signers: Signers,
+    pub sign: Vec<u8>,
+}
+impl AggCert {
+    pub fn new(signers:&[u32], sign:Vec<u8>) -> Self {
+        AggCert { signers: Signers::from_ids(signers), sign }
+    }
+
+    pub fn is_signer(&self, id:u32) -> bool {
+        self.signers.contains(id)
+    }
+
+    /* the signer ids, ascending */
+    pub fn signer_ids(&self) -> Vec<u32> {
+        self.signers.ids().collect()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.signers.encoded_len() + AGG_SIGN_LEN
+    }
+}
+
+/* the certificate of a delivered batch, in either scheme */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Certificate {
+    Signs(SignCert),
+    Aggregate(AggCert),
+}
+
+impl Default for Certificate {
+    fn default() -> Self {
+        Certificate::Signs(SignCert::default())
+    }
+}
+
+impl Certificate {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Certificate::Signs(cert) => cert.is_empty(),
+            Certificate::Aggregate(_) => false,
+        }
+    }
+
+    pub fn is_signer(&self, id:u32) -> bool {
+        match self {
+            Certificate::Signs(cert) => cert.is_signer(id),
+            Certificate::Aggregate(cert) => cert.is_signer(id),
+        }
+    }
+
+    /* the bytes the certificate takes in a message */
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Certificate::Signs(cert) => cert.encoded_len(),
+            Certificate::Aggregate(cert) => cert.encoded_len(),
+        }
+    }
+
+    /* the bytes the same signers take as Ed25519 signatures, to compare the schemes */
+    pub fn signs_len(&self) -> usize {
+        match self {
+            Certificate::Signs(cert) => cert.encoded_len(),
+            Certificate::Aggregate(cert) => cert.signers.encoded_len() + cert.signers.len() * SIGN_LEN,
+        }
+    }
+}
 /*
 * TODO: have to mind little and big endian!
 * it will not cause any prob while the sender and receiver share same endian,
 * but big problem will happen if they differ.
 */
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub enum Message {
-    Syn{ sender: u32, pub_key:Vec<u8> },
-    Send{ sender:u32, rn:u32, payload:Vec<u8> },
-    Echo{ sender:u32, rn:u32, sign:Vec<u8> },
-    Fin{
+    /* bls_key and its proof of possession are empty unless BLS certificates are used */
+    Syn{ sender: u32, pub_key:Vec<u8>, bls_key:Vec<u8>, bls_pop:Vec<u8> },
+    /* epoch is the committee epoch of round rn, see sequencer/src/committee.rs */
+    Send{ sender:u32, epoch:u32, rn:u32, payload:Vec<u8> },
+    Echo{ sender:u32, epoch:u32, rn:u32, sign:Vec<u8> },
+    Fin{ sender:u32, epoch:u32, rn:u32, cert:SignCert },
+    Sup{
         sender:u32,
+        epoch:u32,
         rn:u32,
-        sign_cnt:u32,
-        signs:Vec<(u32, Vec<u8>)>   // taeung:: u32 for node_id??
+        cert:SignCert,
+        originator:u32,
+        payload:Vec<u8>
     },
-    Sup{
+    /* delivered[i] -> number of rounds of peer i delivered so far, without gaps */
+    SyncReq{ sender:u32, delivered:Vec<u32> },
+    /* a certified batch sent to a lagging peer */
+    SyncResp{
+        sender:u32,
+        rn:u32,
+        cert:SignCert,
+        originator:u32,
+        payload:Vec<u8>
+    },
+    /*
+    * signed digest of the delivered log up to (excluding) round rn, and the
+    * data root of the checkpoint interval it closes
+    */
+    Checkpoint{ sender:u32, rn:u32, digest:Vec<u8>, data_root:Vec<u8>, sign:Vec<u8> },
+    /* Fin, Sup and SyncResp with an aggregated certificate */
+    AggFin{ sender:u32, epoch:u32, rn:u32, cert:AggCert },
+    AggSup{
+        sender:u32,
+        epoch:u32,
+        rn:u32,
+        cert:AggCert,
+        originator:u32,
+        payload:Vec<u8>
+    },
+    AggSyncResp{
         sender:u32,
         rn:u32,
-        sign_cnt:u32,
-        signs:Vec<(u32, Vec<u8>)>,
+        cert:AggCert,
         originator:u32,
         payload:Vec<u8>
     },
+    /*
+    * Chunk `index` of the erasure-coded payload of the originator's round rn,
+    * with its Merkle proof against root, and the root of the payload's
+    * sampling chunks, which the batch digest covers too. The originator sends
+    * each member its own chunk, which the member hands to the nodes that ask
+    * for it.
+    */
+    Chunk{
+        sender:u32,
+        epoch:u32,
+        rn:u32,
+        originator:u32,
+        index:u32,
+        root:Vec<u8>,
+        das_root:Vec<u8>,
+        proof:Vec<Vec<u8>>,
+        chunk:Vec<u8>
+    },
+    /*
+    * The voter's vote to close round rn without the batches of the absent
+    * originators. A voter multicasts its own vote (sender == voter); the
+    * votes it relays to a node that is behind have sender != voter.
+    */
+    Close{ sender:u32, voter:u32, rn:u32, absent:Signers, sign:Vec<u8> },
+    /* asks a member for its chunk of the originator's batch of round rn */
+    ChunkReq{ sender:u32, epoch:u32, rn:u32, originator:u32 },
 }
 impl Message {
+    /* the node the message claims to come from */
+    pub fn sender(&self) -> u32 {
+        match self {
+            Message::Syn{ sender, .. }
+            | Message::Send{ sender, .. }
+            | Message::Echo{ sender, .. }
+            | Message::Fin{ sender, .. }
+            | Message::Sup{ sender, .. }
+            | Message::SyncReq{ sender, .. }
+            | Message::SyncResp{ sender, .. }
+            | Message::Checkpoint{ sender, .. }
+            | Message::AggFin{ sender, .. }
+            | Message::AggSup{ sender, .. }
+            | Message::AggSyncResp{ sender, .. }
+            | Message::Chunk{ sender, .. }
+            | Message::Close{ sender, .. }
+            | Message::ChunkReq{ sender, .. } => *sender,
+        }
+    }
+
+    /* fails, rather than panics, on a truncated or malformed message */
+    #[allow(clippy::result_unit_err)]
     pub fn from_bytes(bytes:Bytes) -> Result<Message, ()> {
-        match bytes[0] {
+        match *bytes.first().ok_or(())? {
             SYN_MSG => {
-                Ok(Message::Syn {
-                    sender: deserialize::<u32>(&bytes[1..5]).unwrap(),
-                    pub_key: (bytes[5..]).to_vec(),
-                })
+                let sender = read_u32(&bytes, 1)?;
+                let key_len = read_u32(&bytes, 5)? as usize;
+                let pub_key = read_slice(&bytes, 9, key_len)?.to_vec();
+                let mut idx = 9 + key_len;
+                let bls_len = read_u32(&bytes, idx)? as usize;
+                idx += 4;
+                let bls_key = read_slice(&bytes, idx, bls_len)?.to_vec();
+                let bls_pop = read_rest(&bytes, idx + bls_len)?;
+                Ok(Message::Syn { sender, pub_key, bls_key, bls_pop })
             },
             SEND_MSG => {
                 Ok(Message::Send {
-                    sender: deserialize::<u32>(&bytes[1..5]).unwrap(),
-                    rn: deserialize::<u32>(&bytes[5..9]).unwrap(),
+                    sender: read_u32(&bytes, 1)?,
+                    epoch: read_u32(&bytes, 5)?,
+                    rn: read_u32(&bytes, 9)?,
                     /* TODO: to_vec() may be slow. it may deeply copy things */
-                    payload: (bytes[9..]).to_vec(),
+                    payload: read_rest(&bytes, 13)?,
                 })
             },
             ECHO_MSG => {
                 Ok(Message::Echo {
-                    sender: deserialize::<u32>(&bytes[1..5]).unwrap(),
-                    rn: deserialize::<u32>(&bytes[5..9]).unwrap(),
+                    sender: read_u32(&bytes, 1)?,
+                    epoch: read_u32(&bytes, 5)?,
+                    rn: read_u32(&bytes, 9)?,
                     /* TODO: to_vec() may be slow. it may deeply copy things */
-                    sign: (bytes[9..]).to_vec(),
+                    sign: read_rest(&bytes, 13)?,
                 })
             },
             FIN_MSG => {
-                let sender = deserialize::<u32>(&bytes[1..5]).unwrap();
-                let rn = deserialize::<u32>(&bytes[5..9]).unwrap();
-                let sign_cnt = deserialize::<u32>(&bytes[9..13]).unwrap();
-                let mut signs = Vec::with_capacity(sign_cnt as usize);
-                let mut idx = 13;
-                for _ in 0..sign_cnt {
-                    let node_id = deserialize::<u32>(&bytes[idx..idx+4]).unwrap();
-                    idx += 4;
-                    let sign = bytes[idx..idx+SIGN_LEN].to_vec();
-                    idx += SIGN_LEN;
-                    signs.push((node_id, sign));
-                }
-                Ok(Message::Fin{ sender, rn, sign_cnt, signs })
+                let sender = read_u32(&bytes, 1)?;
+                let epoch = read_u32(&bytes, 5)?;
+                let rn = read_u32(&bytes, 9)?;
+                let (cert, _) = read_sign_cert(&bytes, 13)?;
+                Ok(Message::Fin{ sender, epoch, rn, cert })
             },
             SUP_MSG => {
-                let sender = deserialize::<u32>(&bytes[1..5]).unwrap();
-                let rn = deserialize::<u32>(&bytes[5..9]).unwrap();
-                let sign_cnt = deserialize::<u32>(&bytes[9..13]).unwrap();
-                let mut signs = Vec::with_capacity(sign_cnt as usize);
-                let mut idx = 13;
-                for _ in 0..sign_cnt {
-                    let node_id = deserialize::<u32>(&bytes[idx..idx+4]).unwrap();
-                    idx += 4;
-                    let sign = bytes[idx..idx+SIGN_LEN].to_vec();
-                    idx += SIGN_LEN;
-                    signs.push((node_id, sign));
+                let sender = read_u32(&bytes, 1)?;
+                let epoch = read_u32(&bytes, 5)?;
+                let rn = read_u32(&bytes, 9)?;
+                let (cert, idx) = read_sign_cert(&bytes, 13)?;
+                let originator = read_u32(&bytes, idx)?;
+                let payload = read_rest(&bytes, idx + 4)?;
+                Ok(Message::Sup { sender, epoch, rn, cert, originator, payload })
+            },
+            SYNC_REQ_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let cnt = read_u32(&bytes, 5)? as usize;
+                // the count is checked against what follows before anything is allocated
+                if cnt > bytes.len().saturating_sub(9) / 4 {
+                    return Err(());
                 }
-                let originator = deserialize::<u32>(&bytes[idx..idx+4]).unwrap();
-                idx += 4;
-                let payload = bytes[idx..].to_vec();
-                Ok(Message::Sup { sender, rn, sign_cnt, signs, originator, payload })
+                let delivered = (0..cnt)
+                    .map(|i| read_u32(&bytes, 9 + i*4))
+                    .collect::<Result<_, _>>()?;
+                Ok(Message::SyncReq { sender, delivered })
+            },
+            SYNC_RESP_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let rn = read_u32(&bytes, 5)?;
+                let (cert, idx) = read_sign_cert(&bytes, 9)?;
+                let originator = read_u32(&bytes, idx)?;
+                let payload = read_rest(&bytes, idx + 4)?;
+                Ok(Message::SyncResp { sender, rn, cert, originator, payload })
+            },
+            CHECKPOINT_MSG => {
+                Ok(Message::Checkpoint {
+                    sender: read_u32(&bytes, 1)?,
+                    rn: read_u32(&bytes, 5)?,
+                    digest: read_slice(&bytes, 9, DIGEST_LEN)?.to_vec(),
+                    data_root: read_slice(&bytes, 9 + DIGEST_LEN, DIGEST_LEN)?.to_vec(),
+                    sign: read_rest(&bytes, 9 + 2 * DIGEST_LEN)?,
+                })
+            },
+            AGG_FIN_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let epoch = read_u32(&bytes, 5)?;
+                let rn = read_u32(&bytes, 9)?;
+                let (cert, _) = read_agg_cert(&bytes, 13)?;
+                Ok(Message::AggFin{ sender, epoch, rn, cert })
+            },
+            AGG_SUP_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let epoch = read_u32(&bytes, 5)?;
+                let rn = read_u32(&bytes, 9)?;
+                let (cert, idx) = read_agg_cert(&bytes, 13)?;
+                let originator = read_u32(&bytes, idx)?;
+                let payload = read_rest(&bytes, idx + 4)?;
+                Ok(Message::AggSup{ sender, epoch, rn, cert, originator, payload })
+            },
+            AGG_SYNC_RESP_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let rn = read_u32(&bytes, 5)?;
+                let (cert, idx) = read_agg_cert(&bytes, 9)?;
+                let originator = read_u32(&bytes, idx)?;
+                let payload = read_rest(&bytes, idx + 4)?;
+                Ok(Message::AggSyncResp{ sender, rn, cert, originator, payload })
+            },
+            CHUNK_MSG => {
+                let field = |i:usize| read_u32(&bytes, 1 + i*4);
+                let (sender, epoch, rn, originator, index) = (field(0)?, field(1)?, field(2)?, field(3)?, field(4)?);
+                let proof_len = field(5)? as usize;
+                let root = read_slice(&bytes, 25, DIGEST_LEN)?.to_vec();
+                let das_root = read_slice(&bytes, 25 + DIGEST_LEN, DIGEST_LEN)?.to_vec();
+                let mut idx = 25 + 2 * DIGEST_LEN;
+                let mut proof = Vec::with_capacity(proof_len.min(64));
+                for _ in 0..proof_len {
+                    proof.push(read_slice(&bytes, idx, DIGEST_LEN)?.to_vec());
+                    idx += DIGEST_LEN;
+                }
+                let chunk = read_rest(&bytes, idx)?;
+                Ok(Message::Chunk{ sender, epoch, rn, originator, index, root, das_root, proof, chunk })
+            },
+            CLOSE_MSG => {
+                let sender = read_u32(&bytes, 1)?;
+                let voter = read_u32(&bytes, 5)?;
+                let rn = read_u32(&bytes, 9)?;
+                let (absent, idx) = read_signers(&bytes, 13)?;
+                let sign = read_rest(&bytes, idx)?;
+                Ok(Message::Close{ sender, voter, rn, absent, sign })
+            },
+            CHUNK_REQ_MSG => {
+                Ok(Message::ChunkReq {
+                    sender: read_u32(&bytes, 1)?,
+                    epoch: read_u32(&bytes, 5)?,
+                    rn: read_u32(&bytes, 9)?,
+                    originator: read_u32(&bytes, 13)?,
+                })
             },
             
             _ =>  Err(()),
         }
     }
     /* ownership? */
+    #[allow(clippy::result_unit_err)]
     pub fn to_bytes(self) -> Result<Bytes, ()> {
         match self {
-            Message::Syn{sender, pub_key} => {
-                let mut buf = BytesMut::with_capacity(1 + 4 + pub_key.len());
+            Message::Syn{sender, pub_key, bls_key, bls_pop} => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + pub_key.len() + bls_key.len() + bls_pop.len());
                 buf.put_u8(SYN_MSG); // indicating send msg
                 buf.put_u32_le(sender);
-                buf.put(Bytes::from(pub_key));
+                buf.put_u32_le(pub_key.len() as u32);
+                buf.extend_from_slice(&pub_key);
+                buf.put_u32_le(bls_key.len() as u32);
+                buf.extend_from_slice(&bls_key);
+                buf.extend_from_slice(&bls_pop);
                 Ok(buf.into())
             }
-            Message::Send{sender, rn, payload} => {
-                let mut buf = BytesMut::with_capacity(payload.len() + 1 + 8);
+            Message::Send{sender, epoch, rn, payload} => {
+                let mut buf = BytesMut::with_capacity(payload.len() + 1 + 12);
                 buf.put_u8(SEND_MSG); // indicating send msg
                 buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
                 buf.put_u32_le(rn);
                 buf.put(Bytes::from(payload));
                 Ok(buf.into())
             },
-            Message::Echo{sender, rn, sign} => {
-                let mut buf = BytesMut::with_capacity(sign.len() + 1 + 8);
+            Message::Echo{sender, epoch, rn, sign} => {
+                let mut buf = BytesMut::with_capacity(sign.len() + 1 + 12);
                 buf.put_u8(ECHO_MSG); // indicating echo msg
                 buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
                 buf.put_u32_le(rn);
                 buf.put(Bytes::from(sign));
                 Ok(buf.into())
             },
-            Message::Fin{sender, rn, sign_cnt, signs} => {
-                let mut buf = BytesMut::with_capacity(sign_cnt as usize*(64+4) + 1 + 12);
+            Message::Fin{sender, epoch, rn, cert} => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + cert.encoded_len());
                 buf.put_u8(FIN_MSG);
                 buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
                 buf.put_u32_le(rn);
-                buf.put_u32_le(sign_cnt);
-                for (node_id, sign) in signs {
-                    buf.put_u32_le(node_id);
-                    buf.extend_from_slice(&sign);
-                }
+                put_sign_cert(&mut buf, cert)?;
                 Ok(buf.into())
             },
-            Message::Sup { sender, rn, sign_cnt, signs, originator, payload } => {
-                let mut buf = BytesMut::with_capacity(sign_cnt as usize*(64+4) + 1 + 12 + 4 + payload.len() as usize);
+            Message::Sup { sender, epoch, rn, cert, originator, payload } => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + cert.encoded_len() + 4 + payload.len());
                 buf.put_u8(SUP_MSG);
                 buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
+                buf.put_u32_le(rn);
+                put_sign_cert(&mut buf, cert)?;
+                buf.put_u32_le(originator);
+                buf.put(Bytes::from(payload));
+                Ok(buf.freeze())
+            },
+            Message::SyncReq { sender, delivered } => {
+                let mut buf = BytesMut::with_capacity(1 + 8 + delivered.len() * 4);
+                buf.put_u8(SYNC_REQ_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(delivered.len() as u32);
+                for cnt in delivered {
+                    buf.put_u32_le(cnt);
+                }
+                Ok(buf.freeze())
+            },
+            Message::SyncResp { sender, rn, cert, originator, payload } => {
+                let mut buf = BytesMut::with_capacity(1 + 8 + cert.encoded_len() + 4 + payload.len());
+                buf.put_u8(SYNC_RESP_MSG);
+                buf.put_u32_le(sender);
                 buf.put_u32_le(rn);
-                buf.put_u32_le(sign_cnt);
-                for (node_id, sign) in signs {
-                    buf.put_u32_le(node_id);
-                    buf.extend_from_slice(&sign);
+                put_sign_cert(&mut buf, cert)?;
+                buf.put_u32_le(originator);
+                buf.put(Bytes::from(payload));
+                Ok(buf.freeze())
+            },
+            Message::Checkpoint { sender, rn, digest, data_root, sign } => {
+                if digest.len() != DIGEST_LEN || data_root.len() != DIGEST_LEN {
+                    return Err(());
                 }
+                let mut buf = BytesMut::with_capacity(1 + 8 + 2 * DIGEST_LEN + sign.len());
+                buf.put_u8(CHECKPOINT_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(rn);
+                buf.extend_from_slice(&digest);
+                buf.extend_from_slice(&data_root);
+                buf.extend_from_slice(&sign);
+                Ok(buf.freeze())
+            },
+            Message::AggFin { sender, epoch, rn, cert } => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + cert.encoded_len());
+                buf.put_u8(AGG_FIN_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
+                buf.put_u32_le(rn);
+                put_agg_cert(&mut buf, cert)?;
+                Ok(buf.freeze())
+            },
+            Message::AggSup { sender, epoch, rn, cert, originator, payload } => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + cert.encoded_len() + 4 + payload.len());
+                buf.put_u8(AGG_SUP_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
+                buf.put_u32_le(rn);
+                put_agg_cert(&mut buf, cert)?;
                 buf.put_u32_le(originator);
                 buf.put(Bytes::from(payload));
                 Ok(buf.freeze())
             },
-            
+            Message::AggSyncResp { sender, rn, cert, originator, payload } => {
+                let mut buf = BytesMut::with_capacity(1 + 8 + cert.encoded_len() + 4 + payload.len());
+                buf.put_u8(AGG_SYNC_RESP_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(rn);
+                put_agg_cert(&mut buf, cert)?;
+                buf.put_u32_le(originator);
+                buf.put(Bytes::from(payload));
+                Ok(buf.freeze())
+            },
+            Message::Chunk { sender, epoch, rn, originator, index, root, das_root, proof, chunk } => {
+                if root.len() != DIGEST_LEN || das_root.len() != DIGEST_LEN || proof.iter().any(|node| node.len() != DIGEST_LEN) {
+                    return Err(());
+                }
+                let mut buf = BytesMut::with_capacity(1 + 24 + (2 + proof.len()) * DIGEST_LEN + chunk.len());
+                buf.put_u8(CHUNK_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
+                buf.put_u32_le(rn);
+                buf.put_u32_le(originator);
+                buf.put_u32_le(index);
+                buf.put_u32_le(proof.len() as u32);
+                buf.extend_from_slice(&root);
+                buf.extend_from_slice(&das_root);
+                for node in proof {
+                    buf.extend_from_slice(&node);
+                }
+                buf.put(Bytes::from(chunk));
+                Ok(buf.freeze())
+            },
+            Message::Close { sender, voter, rn, absent, sign } => {
+                let mut buf = BytesMut::with_capacity(1 + 12 + absent.encoded_len() + sign.len());
+                buf.put_u8(CLOSE_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(voter);
+                buf.put_u32_le(rn);
+                put_signers(&mut buf, &absent);
+                buf.extend_from_slice(&sign);
+                Ok(buf.freeze())
+            },
+            Message::ChunkReq { sender, epoch, rn, originator } => {
+                let mut buf = BytesMut::with_capacity(1 + 16);
+                buf.put_u8(CHUNK_REQ_MSG);
+                buf.put_u32_le(sender);
+                buf.put_u32_le(epoch);
+                buf.put_u32_le(rn);
+                buf.put_u32_le(originator);
+                Ok(buf.freeze())
+            },
         }
     }
 }
 
+/* reads a bitmap length and the bitmap starting at idx */
+fn read_signers(bytes:&Bytes, idx:usize) -> Result<(Signers, usize), ()> {
+    let len = read_u32(bytes, idx)? as usize;
+    if len > MAX_NODES.div_ceil(8) {
+        return Err(());
+    }
+    let bitmap = read_slice(bytes, idx + 4, len)?.to_vec();
+    Ok((Signers(bitmap), idx + 4 + len))
+}
+
+/* the len bytes at idx, if the message is that long */
+fn read_slice(bytes:&[u8], idx:usize, len:usize) -> Result<&[u8], ()> {
+    bytes.get(idx..idx.checked_add(len).ok_or(())?).ok_or(())
+}
+
+fn read_u32(bytes:&[u8], idx:usize) -> Result<u32, ()> {
+    deserialize::<u32>(read_slice(bytes, idx, 4)?).map_err(|_| ())
+}
+
+/* everything from idx on, which may be nothing */
+fn read_rest(bytes:&[u8], idx:usize) -> Result<Vec<u8>, ()> {
+    Ok(bytes.get(idx..).ok_or(())?.to_vec())
+}
+
+/* reads the signers, then one signature per signer */
+fn read_sign_cert(bytes:&Bytes, idx:usize) -> Result<(SignCert, usize), ()> {
+    let (signers, mut idx) = read_signers(bytes, idx)?;
+    // the signatures are checked to be there before anything is allocated
+    if signers.len() > MAX_NODES || bytes.len().saturating_sub(idx) < signers.len() * SIGN_LEN {
+        return Err(());
+    }
+    let mut signs = Vec::with_capacity(signers.len());
+    for _ in 0..signers.len() {
+        signs.push(read_slice(bytes, idx, SIGN_LEN)?.to_vec());
+        idx += SIGN_LEN;
+    }
+    Ok((SignCert { signers, signs }, idx))
+}
+
+fn put_signers(buf:&mut BytesMut, signers:&Signers) {
+    buf.put_u32_le(signers.0.len() as u32);
+    buf.extend_from_slice(&signers.0);
+}
+
+fn put_sign_cert(buf:&mut BytesMut, cert:SignCert) -> Result<(), ()> {
+    if cert.signs.iter().any(|sign| sign.len() != SIGN_LEN) {
+        return Err(());
+    }
+    put_signers(buf, &cert.signers);
+    for sign in cert.signs {
+        buf.extend_from_slice(&sign);
+    }
+    Ok(())
+}
+
+/* reads the signers and the aggregated signature starting at idx */
+fn read_agg_cert(bytes:&Bytes, idx:usize) -> Result<(AggCert, usize), ()> {
+    let (signers, idx) = read_signers(bytes, idx)?;
+    let sign = bytes.get(idx..idx+AGG_SIGN_LEN).ok_or(())?.to_vec();
+    Ok((AggCert { signers, sign }, idx + AGG_SIGN_LEN))
+}
+
+fn put_agg_cert(buf:&mut BytesMut, cert:AggCert) -> Result<(), ()> {
+    if cert.sign.len() != AGG_SIGN_LEN {
+        return Err(());
+    }
+    put_signers(buf, &cert.signers);
+    buf.extend_from_slice(&cert.sign);
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -159,24 +690,280 @@ mod tests {
     fn test_send_to_byte() {
         let msg = Message::Send{
             sender: 67305985,
+            epoch: 202050057,
             rn: 134678021,
             payload: [8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec(),
         };
         assert_eq!(
             msg.to_bytes().unwrap(), 
-            [1, 1, 2, 3, 4, 5, 6, 7, 8, 8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec()
+            [1, 1, 2, 3, 4, 9, 10, 11, 12, 5, 6, 7, 8, 8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec()
         );
     }
 
     #[test]
     fn test_send_from_byte() {
-        let array:Vec<u8> = [1, 1, 2, 3, 4, 5, 6, 7, 8, 8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec();
+        let array:Vec<u8> = [1, 1, 2, 3, 4, 9, 10, 11, 12, 5, 6, 7, 8, 8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec();
         let msg =  Message::from_bytes(Bytes::from(array)).unwrap();
-        if let Message::Send{sender, rn, payload} = msg {
+        if let Message::Send{sender, epoch, rn, payload} = msg {
             assert_eq!(sender, 67305985);
+            assert_eq!(epoch, 202050057);
             assert_eq!(rn, 134678021);
             assert_eq!(payload, [8, 9, 8, 9, 8, 9, 8, 9, 8, 9].to_vec());
         }
         else { panic!(); }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sender() {
+        let syn = Message::Syn{ sender: 5, pub_key: vec![1; 32], bls_key: vec![], bls_pop: vec![] };
+        assert_eq!(syn.sender(), 5);
+        let req = Message::SyncReq{ sender: 2, delivered: vec![0, 3] };
+        assert_eq!(req.sender(), 2);
+    }
+
+    #[test]
+    fn test_sup_roundtrip() {
+        let cert: SignCert = vec![(2, vec![2; SIGN_LEN]), (0, vec![1; SIGN_LEN])].into_iter().collect();
+        let msg = Message::Sup{
+            sender: 1,
+            epoch: 3,
+            rn: 9,
+            cert: cert.clone(),
+            originator: 2,
+            payload: vec![7; 20],
+        };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::Sup{
+            sender: 1,
+            epoch: 3,
+            rn: 9,
+            cert,
+            originator: 2,
+            payload: vec![7; 20],
+        });
+    }
+
+    #[test]
+    fn test_chunk_roundtrip() {
+        let chunk = || Message::Chunk{
+            sender: 3,
+            epoch: 1,
+            rn: 4,
+            originator: 0,
+            index: 3,
+            root: vec![9; DIGEST_LEN],
+            das_root: vec![8; DIGEST_LEN],
+            proof: vec![vec![1; DIGEST_LEN], vec![2; DIGEST_LEN]],
+            chunk: vec![5; 100],
+        };
+        let bytes = chunk().to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 24 + 4 * DIGEST_LEN + 100);
+        assert_eq!(Message::from_bytes(bytes.clone()).unwrap(), chunk());
+        // a proof cut short
+        assert!(Message::from_bytes(bytes.slice(..1 + 24 + 3 * DIGEST_LEN)).is_err());
+
+        let req = || Message::ChunkReq{ sender: 2, epoch: 1, rn: 4, originator: 0 };
+        let bytes = req().to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 16);
+        assert_eq!(Message::from_bytes(bytes).unwrap(), req());
+    }
+
+    #[test]
+    fn test_sync_req_roundtrip() {
+        let msg = Message::SyncReq{ sender: 2, delivered: vec![5, 0, 7, 3] };
+        let bytes = msg.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 8 + 16);
+        assert_eq!(
+            Message::from_bytes(bytes).unwrap(),
+            Message::SyncReq{ sender: 2, delivered: vec![5, 0, 7, 3] }
+        );
+    }
+
+    #[test]
+    fn test_sync_resp_roundtrip() {
+        let cert: SignCert = vec![(0, vec![1; SIGN_LEN]), (3, vec![2; SIGN_LEN])].into_iter().collect();
+        let msg = Message::SyncResp{
+            sender: 1,
+            rn: 9,
+            cert: cert.clone(),
+            originator: 3,
+            payload: vec![7; 20],
+        };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::SyncResp{
+            sender: 1,
+            rn: 9,
+            cert,
+            originator: 3,
+            payload: vec![7; 20],
+        });
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let msg = Message::Checkpoint{ sender: 1, rn: 20, digest: vec![3; DIGEST_LEN], data_root: vec![5; DIGEST_LEN], sign: vec![4; SIGN_LEN] };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::Checkpoint{
+            sender: 1,
+            rn: 20,
+            digest: vec![3; DIGEST_LEN],
+            data_root: vec![5; DIGEST_LEN],
+            sign: vec![4; SIGN_LEN]
+        });
+
+        let msg = Message::Checkpoint{ sender: 1, rn: 20, digest: vec![3; 8], data_root: vec![5; DIGEST_LEN], sign: vec![4; SIGN_LEN] };
+        assert!(msg.to_bytes().is_err());
+        let msg = Message::Checkpoint{ sender: 1, rn: 20, digest: vec![3; DIGEST_LEN], data_root: vec![], sign: vec![4; SIGN_LEN] };
+        assert!(msg.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_close_roundtrip() {
+        let close = || Message::Close{ sender: 2, voter: 1, rn: 7, absent: Signers::from_ids(&[3, 9]), sign: vec![4; SIGN_LEN] };
+        let bytes = close().to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 12 + 4 + 2 + SIGN_LEN);
+        assert_eq!(Message::from_bytes(bytes).unwrap(), close());
+    }
+
+    #[test]
+    fn test_syn_roundtrip() {
+        let msg = Message::Syn{ sender: 3, pub_key: vec![1; 32], bls_key: vec![2; 48], bls_pop: vec![3; 96] };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::Syn{ sender: 3, pub_key: vec![1; 32], bls_key: vec![2; 48], bls_pop: vec![3; 96] });
+
+        let msg = Message::Syn{ sender: 3, pub_key: vec![1; 32], bls_key: Vec::new(), bls_pop: Vec::new() };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::Syn{ sender: 3, pub_key: vec![1; 32], bls_key: Vec::new(), bls_pop: Vec::new() });
+    }
+
+    #[test]
+    fn test_agg_cert_bitmap() {
+        let cert = AggCert::new(&[9, 0, 3], vec![0; AGG_SIGN_LEN]);
+        assert_eq!(cert.signers.as_bytes(), [0b1001, 0b10]);
+        assert!(cert.is_signer(0) && cert.is_signer(3) && cert.is_signer(9));
+        assert!(!cert.is_signer(1) && !cert.is_signer(100));
+        assert_eq!(cert.signer_ids(), vec![0, 3, 9]);
+    }
+
+    #[test]
+    fn test_certificate_sizes() {
+        let signs: SignCert = vec![(0, vec![1; SIGN_LEN]), (3, vec![2; SIGN_LEN]), (9, vec![3; SIGN_LEN])].into_iter().collect();
+        let signs = Certificate::Signs(signs);
+        let aggregate = Certificate::Aggregate(AggCert::new(&[9, 0, 3], vec![0; AGG_SIGN_LEN]));
+        assert_eq!(signs.encoded_len(), 4 + 2 + 3 * SIGN_LEN);
+        assert_eq!(signs.signs_len(), signs.encoded_len());
+        // the same signers, one signature for all of them
+        assert_eq!(aggregate.encoded_len(), 4 + 2 + AGG_SIGN_LEN);
+        assert_eq!(aggregate.signs_len(), signs.encoded_len());
+    }
+
+    #[test]
+    fn test_agg_sup_roundtrip() {
+        let cert = AggCert::new(&[0, 1, 3], vec![5; AGG_SIGN_LEN]);
+        let msg = Message::AggSup{ sender: 1, epoch: 2, rn: 9, cert: cert.clone(), originator: 3, payload: vec![7; 20] };
+        let decoded = Message::from_bytes(msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded, Message::AggSup{ sender: 1, epoch: 2, rn: 9, cert, originator: 3, payload: vec![7; 20] });
+
+        let bad = AggCert::new(&[0], vec![5; SIGN_LEN]);
+        assert!(Message::AggFin{ sender: 1, epoch: 0, rn: 0, cert: bad }.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_agg_fin_is_constant_size() {
+        let ids: Vec<u32> = (0..64).collect();
+        let fin = Message::Fin{
+            sender: 0,
+            epoch: 0,
+            rn: 0,
+            cert: ids[..43].iter().map(|id| (*id, vec![0; SIGN_LEN])).collect(),
+        };
+        assert_eq!(fin.to_bytes().unwrap().len(), 13 + 4 + 6 + 43 * SIGN_LEN);
+        let agg_fin = Message::AggFin{ sender: 0, epoch: 0, rn: 0, cert: AggCert::new(&ids[..43], vec![0; AGG_SIGN_LEN]) };
+        assert_eq!(agg_fin.to_bytes().unwrap().len(), 13 + 4 + 6 + AGG_SIGN_LEN);
+    }
+
+    #[test]
+    fn test_sign_cert_signers_are_unique() {
+        let mut cert = SignCert::default();
+        assert!(cert.insert(5, vec![5; SIGN_LEN]));
+        assert!(cert.insert(1, vec![1; SIGN_LEN]));
+        assert!(!cert.insert(5, vec![6; SIGN_LEN]));
+        assert_eq!(cert.len(), 2);
+        assert!(cert.is_signer(1) && cert.is_signer(5) && !cert.is_signer(0));
+        let signs: Vec<(u32, &[u8])> = cert.iter().collect();
+        assert_eq!(signs, vec![(1, &[1; SIGN_LEN][..]), (5, &[5; SIGN_LEN][..])]);
+
+        let collected: SignCert = vec![(5, vec![5; SIGN_LEN]), (1, vec![1; SIGN_LEN]), (5, vec![6; SIGN_LEN])]
+            .into_iter()
+            .collect();
+        assert_eq!(collected, cert);
+    }
+
+    #[test]
+    fn test_sign_cert_decoding_is_checked() {
+        let cert: SignCert = vec![(0, vec![1; SIGN_LEN]), (2, vec![2; SIGN_LEN])].into_iter().collect();
+        let bytes = Message::Fin{ sender: 1, epoch: 0, rn: 0, cert: cert.clone() }.to_bytes().unwrap();
+        // one signature short
+        assert!(Message::from_bytes(bytes.slice(..bytes.len() - 1)).is_err());
+
+        // a bitmap with more signers than signatures
+        let stored = bincode::serialize(&cert).unwrap();
+        assert_eq!(bincode::deserialize::<SignCert>(&stored).unwrap(), cert);
+        let forged = bincode::serialize(&(Signers::from_ids(&[0, 1, 2]), vec![vec![1; SIGN_LEN]; 2])).unwrap();
+        assert!(bincode::deserialize::<SignCert>(&forged).is_err());
+
+        let short: SignCert = vec![(0, vec![1; 8])].into_iter().collect();
+        assert!(Message::Fin{ sender: 1, epoch: 0, rn: 0, cert: short }.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_truncated_messages_are_errors() {
+        let cert: SignCert = vec![(0, vec![1; SIGN_LEN])].into_iter().collect();
+        let agg_cert = AggCert::new(&[0, 1], vec![5; AGG_SIGN_LEN]);
+        let messages = vec![
+            Message::Syn{ sender: 1, pub_key: vec![1; 32], bls_key: vec![2; 48], bls_pop: vec![3; 96] },
+            Message::Send{ sender: 1, epoch: 0, rn: 2, payload: vec![1; 10] },
+            Message::Echo{ sender: 1, epoch: 0, rn: 2, sign: vec![1; SIGN_LEN] },
+            Message::Fin{ sender: 1, epoch: 0, rn: 2, cert: cert.clone() },
+            Message::Sup{ sender: 1, epoch: 0, rn: 2, cert: cert.clone(), originator: 3, payload: vec![1; 10] },
+            Message::SyncReq{ sender: 1, delivered: vec![4, 4, 3, 4] },
+            Message::SyncResp{ sender: 1, rn: 2, cert, originator: 3, payload: vec![1; 10] },
+            Message::Checkpoint{ sender: 1, rn: 10, digest: vec![1; DIGEST_LEN], data_root: vec![3; DIGEST_LEN], sign: vec![2; SIGN_LEN] },
+            Message::AggFin{ sender: 1, epoch: 0, rn: 2, cert: agg_cert.clone() },
+            Message::AggSup{ sender: 1, epoch: 0, rn: 2, cert: agg_cert.clone(), originator: 3, payload: vec![1; 10] },
+            Message::AggSyncResp{ sender: 1, rn: 2, cert: agg_cert, originator: 3, payload: vec![1; 10] },
+            Message::Chunk{ sender: 1, epoch: 0, rn: 2, originator: 3, index: 1, root: vec![9; DIGEST_LEN], das_root: vec![8; DIGEST_LEN], proof: vec![vec![1; DIGEST_LEN]], chunk: vec![5; 10] },
+            Message::Close{ sender: 1, voter: 1, rn: 2, absent: Signers::from_ids(&[3]), sign: vec![2; SIGN_LEN] },
+            Message::ChunkReq{ sender: 1, epoch: 0, rn: 2, originator: 3 },
+        ];
+        assert!(Message::from_bytes(Bytes::new()).is_err());
+        // no prefix of a message makes decoding panic, and none short of its header decodes
+        for message in messages {
+            let bytes = message.to_bytes().unwrap();
+            for len in 1..bytes.len() {
+                let decoded = Message::from_bytes(bytes.slice(..len));
+                assert!(len >= 13 || decoded.is_err());
+            }
+        }
+
+        // a count of rounds the message does not hold
+        let mut bytes = Message::SyncReq{ sender: 1, delivered: vec![4, 4] }.to_bytes().unwrap().to_vec();
+        bytes[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Message::from_bytes(Bytes::from(bytes)).is_err());
+
+        // signers the message holds no signatures for, and more signers than nodes
+        let fin = |bitmap:&[u8]| {
+            let mut bytes = vec![FIN_MSG];
+            for field in [1u32, 0, 2, bitmap.len() as u32] {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes.extend_from_slice(bitmap);
+            bytes.extend_from_slice(&[1; SIGN_LEN]);
+            Message::from_bytes(Bytes::from(bytes))
+        };
+        assert!(fin(&[0b1]).is_ok());
+        assert!(fin(&[0b11]).is_err());
+        assert!(fin(&[0xff; 8]).is_err());
+        assert!(fin(&[0xff; 1000]).is_err());
+    }
+}
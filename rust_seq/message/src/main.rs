@@ -10,7 +10,7 @@ fn main() {
     let _zeros = BytesMut::zeroed(10);
 
     let msg = Message::from_bytes(Bytes::from(array));
-    if let Message::Send{sender, rn, payload} = msg.unwrap() {
+    if let Message::Send{sender, rn, payload, ..} = msg.unwrap() {
         println!("s {}, rn {}, pay: {:?}", sender, rn, payload);
     };
     /*
@@ -26,6 +26,7 @@ fn main() {
 
     let msg = Message::Echo{
         sender: 67305985,
+        epoch: 0,
         rn: 134678021,
         // payload: vec![1;32],
         sign: [9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 9, 8, 7].to_vec(),
@@ -22,4 +22,13 @@ pub enum NetworkError {
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Failed to secure the connection with {0}: {1}")]
+    FailedHandshake(SocketAddr, String),
+
+    #[error("Invalid stream of chunks from {0}: {1}")]
+    InvalidStream(SocketAddr, String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
 }
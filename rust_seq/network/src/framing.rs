@@ -0,0 +1,107 @@
+use crate::secure::Cipher;
+use bytes::{Bytes, BytesMut};
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/framing_tests.rs"]
+pub mod framing_tests;
+
+/// Default upper bound on the size of a frame, large enough for a full batch.
+pub const MAX_FRAME_LENGTH: usize = 120_000_000;
+/// Default bound on the messages a peer has partially sent at once, when messages are chunked.
+pub const MAX_PARTIAL: usize = 16;
+
+/// How messages are framed on the wire: a 4-byte length prefix followed by the message, or by a
+/// chunk of it if messages are chunked. Both ends of a connection must agree on it, so every sender
+/// and receiver of the crate takes its framing from here (`Framing::default()` unless configured
+/// otherwise).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Framing {
+    /// Byte order of the length prefix.
+    pub little_endian: bool,
+    /// Frames longer than this are rejected by the reader and the writer, and so are messages
+    /// reassembled from chunks.
+    pub max_frame_length: usize,
+    /// If set, messages are cut into chunks of at most this many bytes, each a frame with a small
+    /// header (see `stream`). The chunks of different messages interleave on a connection, and
+    /// the receiver puts them back together. The chunk size itself is up to each sender.
+    pub chunk_size: Option<usize>,
+    /// How many messages a peer may have partially sent at once, when messages are chunked.
+    pub max_partial: usize,
+    /// Hash the messages received as their chunks arrive, for `MessageHandler::dispatch_hashed`.
+    pub hash: bool,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            little_endian: true,
+            max_frame_length: MAX_FRAME_LENGTH,
+            chunk_size: None,
+            max_partial: MAX_PARTIAL,
+            hash: false,
+        }
+    }
+}
+
+impl Framing {
+    pub fn codec(&self) -> FrameCodec {
+        let mut builder = LengthDelimitedCodec::builder();
+        if self.little_endian {
+            builder.little_endian();
+        } else {
+            builder.big_endian();
+        }
+        FrameCodec {
+            frames: builder.max_frame_length(self.max_frame_length).new_codec(),
+            cipher: None,
+        }
+    }
+
+    pub fn framed<T: AsyncRead + AsyncWrite>(&self, stream: T) -> Framed<T, FrameCodec> {
+        Framed::new(stream, self.codec())
+    }
+}
+
+/// Length-delimited frames, encrypted once the connection is secured (see `secure`).
+pub struct FrameCodec {
+    frames: LengthDelimitedCodec,
+    cipher: Option<Cipher>,
+}
+
+impl FrameCodec {
+    /// Encrypts the frames from now on.
+    pub(crate) fn secure(&mut self, cipher: Cipher) {
+        self.cipher = Some(cipher);
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        let frame = match self.frames.decode(src)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        match &mut self.cipher {
+            Some(cipher) => cipher.open(frame).map(Some),
+            None => Ok(Some(frame)),
+        }
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        let frame = match &mut self.cipher {
+            Some(cipher) => cipher.seal(frame)?,
+            None => frame,
+        };
+        self.frames.encode(frame, dst)
+    }
+}
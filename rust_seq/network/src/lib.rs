@@ -1,13 +1,26 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 mod error;
+mod framing;
+mod queue;
 mod receiver;
 mod reliable_sender;
+mod secure;
 mod simple_sender;
+mod simulator;
+mod stream;
+mod transport;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
+pub use crate::error::NetworkError;
+pub use crate::framing::{FrameCodec, Framing, MAX_FRAME_LENGTH, MAX_PARTIAL};
+pub use crate::queue::{Overflow, Priority, QueueConfig, QueueStats, QUEUE_CAPACITY, URGENT_BURST};
+pub use crate::receiver::{MessageHandler, Peer, Receiver, Writer};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
-pub use crate::simple_sender::SimpleSender;
+pub use crate::secure::{PeerKey, SecureConfig};
+pub use crate::simple_sender::{ConnectionEvent, SimpleSender};
+pub use crate::simulator::{Latency, LinkConfig, Partition, SimNetwork, REORDER_DELAY};
+pub use crate::stream::Digest;
+pub use crate::transport::{Io, Link, Listener, MemoryNetwork, Tcp, Transport, MEMORY_BUFFER};
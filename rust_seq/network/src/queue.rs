@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+#[cfg(test)]
+#[path = "tests/queue_tests.rs"]
+pub mod queue_tests;
+
+/// How many messages wait for a peer by default.
+pub const QUEUE_CAPACITY: usize = 1_000;
+/// How many urgent messages get ahead of a waiting bulk one, at most.
+pub const URGENT_BURST: usize = 8;
+
+/// The class of a message. The urgent messages queued for a peer are written ahead of the bulk ones,
+/// e.g. votes ahead of payloads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    Urgent,
+    #[default]
+    Bulk,
+}
+
+impl Priority {
+    pub(crate) fn lane(self) -> usize {
+        match self {
+            Priority::Urgent => 0,
+            Priority::Bulk => 1,
+        }
+    }
+}
+
+/// What a full queue does with one more message.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    /// Make room by dropping the oldest message, whatever its class.
+    DropOldest,
+    /// Make room by dropping the oldest message of the lowest class queued, bulk before urgent.
+    /// The default, so that a backlog of payloads never costs a peer its votes.
+    #[default]
+    DropLowest,
+    /// Drop the new message.
+    DropNewest,
+    /// Give up on the peer: drop all its messages and close its connection. The next message
+    /// sent to it opens a new one.
+    Disconnect,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "drop-lowest" => Ok(Overflow::DropLowest),
+            "drop-newest" => Ok(Overflow::DropNewest),
+            "disconnect" => Ok(Overflow::Disconnect),
+            _ => Err(format!("unknown overflow policy {}", s)),
+        }
+    }
+}
+
+/// Bounds the messages waiting for each peer of a sender.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: QUEUE_CAPACITY,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// The state of the queue of a peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    /// The messages waiting to be written.
+    pub depth: usize,
+    /// The messages dropped so far because the queue was full.
+    pub dropped: u64,
+}
+
+struct State<T> {
+    /// One lane per priority class, the messages are numbered in the order they were pushed.
+    lanes: [VecDeque<(u64, T)>; 2],
+    pushed: u64,
+    /// The urgent messages popped in a row while a bulk one was waiting.
+    burst: usize,
+    dropped: u64,
+    closed: bool,
+}
+
+impl<T> State<T> {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(|lane| lane.len()).sum()
+    }
+
+    fn drop_oldest(&mut self) {
+        let lane = match (self.lanes[0].front(), self.lanes[1].front()) {
+            (Some((urgent, _)), Some((bulk, _))) if bulk < urgent => 1,
+            (None, _) => 1,
+            _ => 0,
+        };
+        self.lanes[lane].pop_front();
+    }
+
+    fn drop_lowest(&mut self) {
+        if self.lanes[1].pop_front().is_none() {
+            self.lanes[0].pop_front();
+        }
+    }
+}
+
+/// The messages a sender hands to one of its connections. Pushing never waits: a full queue
+/// applies its overflow policy instead, so a slow peer only holds back its own messages. Urgent
+/// messages are popped first, but no more than `URGENT_BURST` in a row while bulk ones wait.
+pub(crate) struct Queue<T> {
+    state: Mutex<State<T>>,
+    notify: Notify,
+    config: QueueConfig,
+}
+
+impl<T> Queue<T> {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            state: Mutex::new(State {
+                lanes: [VecDeque::new(), VecDeque::new()],
+                pushed: 0,
+                burst: 0,
+                dropped: 0,
+                closed: false,
+            }),
+            notify: Notify::new(),
+            config,
+        }
+    }
+
+    /// Queue a message for the connection. It returns false once the queue is closed, e.g. because
+    /// it overflowed with `Overflow::Disconnect`.
+    pub fn push(&self, item: T, priority: Priority) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        if state.len() >= self.config.capacity {
+            match self.config.overflow {
+                Overflow::DropOldest => {
+                    state.drop_oldest();
+                    state.dropped += 1;
+                }
+                Overflow::DropLowest => {
+                    state.drop_lowest();
+                    state.dropped += 1;
+                }
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return true;
+                }
+                Overflow::Disconnect => {
+                    state.dropped += state.len() as u64 + 1;
+                    state.lanes.iter_mut().for_each(|lane| lane.clear());
+                    state.closed = true;
+                    drop(state);
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        let number = state.pushed;
+        state.pushed += 1;
+        state.lanes[priority.lane()].push_back((number, item));
+        drop(state);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Put back a message the connection failed to write, ahead of the others of its class.
+    pub fn push_front(&self, item: T, priority: Priority) {
+        let mut state = self.state.lock().unwrap();
+        let number = state.lanes[priority.lane()]
+            .front()
+            .map_or(state.pushed, |(number, _)| *number);
+        state.lanes[priority.lane()].push_front((number, item));
+    }
+
+    /// The next urgent message, if one is queued.
+    pub fn pop_urgent(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        state.lanes[0].pop_front().map(|(_, item)| item)
+    }
+
+    /// The next message and its class, once there is one. Once the queue is closed, the messages
+    /// left are still returned, then `None`.
+    pub async fn pop(&self) -> Option<(T, Priority)> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let bulk_waits = !state.lanes[1].is_empty();
+                let urgent = !state.lanes[0].is_empty();
+                if urgent && (state.burst < URGENT_BURST || !bulk_waits) {
+                    let (_, item) = state.lanes[0].pop_front().unwrap();
+                    state.burst = if bulk_waits { state.burst + 1 } else { 0 };
+                    return Some((item, Priority::Urgent));
+                }
+                if let Some((_, item)) = state.lanes[1].pop_front() {
+                    state.burst = 0;
+                    return Some((item, Priority::Bulk));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Resolves once the queue is closed.
+    pub async fn closed(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.state.lock().unwrap().closed {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            depth: state.len(),
+            dropped: state.dropped,
+        }
+    }
+}
@@ -1,29 +1,63 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::framing::{FrameCodec, Framing};
+use crate::secure::{self, PeerKey, SecureConfig};
+use crate::stream::{Digest, Reassembler};
+use crate::transport::{Link, Tcp, Transport};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
-use log::{debug, info, warn};
+use log::{info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use std::sync::Arc;
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
-/// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+/// Convenient alias for the writer end of a connection.
+pub type Writer = SplitSink<Framed<Link, FrameCodec>, Bytes>;
+
+/// What a handler knows about the other end of a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peer {
+    /// The remote address of the connection.
+    pub address: SocketAddr,
+    /// Numbers the connections accepted by a receiver: a peer that reconnects gets a new one.
+    pub connection: u64,
+    /// The key the peer authenticated with, if the connection is secure. Plain connections do
+    /// not tell who is on the other end.
+    pub key: Option<PeerKey>,
+}
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// Defines how to handle an incoming message. A typical usage is to define a `MessageHandler` with a
     /// number of `Sender<T>` channels. Then implement `dispatch` to deserialize incoming messages and
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
-    /// responses or acknowledgements to the sender machine (see unit tests for examples).
-    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+    /// responses or acknowledgements to the sender machine (see unit tests for examples). `peer`
+    /// tells who sent the message, as far as the connection knows.
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Called instead of `dispatch` when the framing hashes messages (see `Framing::hash`), with the
+    /// SHA-256 of `message` computed as its chunks arrived. By default the digest is ignored.
+    async fn dispatch_hashed(
+        &self,
+        writer: &mut Writer,
+        peer: &Peer,
+        message: Bytes,
+        _digest: Digest,
+    ) -> Result<(), Box<dyn Error>> {
+        self.dispatch(writer, peer, message).await
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -33,50 +67,136 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// How messages are framed on incoming connections.
+    framing: Framing,
+    /// If set, connections are authenticated and encrypted.
+    secure: Option<SecureConfig>,
+    /// Where connections are accepted from.
+    transport: Arc<dyn Transport>,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
     /// Spawn a new network receiver handling connections from any incoming peer.
     pub fn spawn(address: SocketAddr, handler: Handler) {
+        Self::spawn_with_framing(address, handler, Framing::default());
+    }
+
+    /// Same as `spawn`, with the given framing instead of the default one.
+    pub fn spawn_with_framing(address: SocketAddr, handler: Handler, framing: Framing) {
+        Self::spawn_with_transport(address, handler, framing, None, Arc::new(Tcp));
+    }
+
+    /// Same as `spawn_with_framing`, but only serves peers that complete a secure handshake.
+    pub fn spawn_secure(
+        address: SocketAddr,
+        handler: Handler,
+        framing: Framing,
+        secure: SecureConfig,
+    ) {
+        Self::spawn_with_transport(address, handler, framing, Some(secure), Arc::new(Tcp));
+    }
+
+    /// The most general form of `spawn`: listens on `address` of `transport`, and only serves the
+    /// peers that complete a secure handshake if `secure` is set.
+    pub fn spawn_with_transport(
+        address: SocketAddr,
+        handler: Handler,
+        framing: Framing,
+        secure: Option<SecureConfig>,
+        transport: Arc<dyn Transport>,
+    ) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                handler,
+                framing,
+                secure,
+                transport,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
     async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
+        let mut listener = self
+            .transport
+            .bind(self.address)
             .await
-            .expect("Failed to bind TCP port");
+            .expect("Failed to bind port");
 
         println!("Listening on {}", self.address);
+        let mut connection = 0;
         loop {
             let (socket, peer) = match listener.accept().await {
-                Ok(value) => { println!("connected to {:?}", value); value },
+                Ok(value) => { println!("connected to {:?}", value.1); value },
                 Err(e) => {
                     println!("{}", NetworkError::FailedToListen(e));
                     continue;
                 }
             };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            connection += 1;
+            Self::spawn_runner(
+                socket,
+                peer,
+                connection,
+                self.handler.clone(),
+                self.framing,
+                self.secure.clone(),
+            )
+            .await;
         }
     }
 
-    /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
+    /// Spawn a new runner to handle a specific connection. It receives messages and process them
     /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    async fn spawn_runner(
+        socket: Link,
+        peer: SocketAddr,
+        connection: u64,
+        handler: Handler,
+        framing: Framing,
+        secure: Option<SecureConfig>,
+    ) {
         tokio::spawn(async move {
-            let transport = LengthDelimitedCodec::builder()
-                .little_endian()
-                .max_frame_length(120_000_000)
-                .new_framed(socket);
-            // let transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let mut transport = framing.framed(socket);
+            let mut identity = Peer {
+                address: peer,
+                connection,
+                key: None,
+            };
+            if let Some(secure) = &secure {
+                match secure::accept(&mut transport, secure, peer).await {
+                    Ok(key) => identity.key = Some(key),
+                    Err(e) => {
+                        warn!("{}", e);
+                        return;
+                    }
+                }
+            }
             let (mut writer, mut reader) = transport.split();
+            let mut reassembler = Reassembler::new(&framing);
             while let Some(frame) = reader.next().await {
                 match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
+                    Ok(frame) => {
+                        let result = match reassembler.push(frame) {
+                            Ok(Some((message, None))) => {
+                                handler.dispatch(&mut writer, &identity, message).await
+                            }
+                            Ok(Some((message, Some(digest)))) => {
+                                handler
+                                    .dispatch_hashed(&mut writer, &identity, message, digest)
+                                    .await
+                            }
+                            Ok(None) => continue,
+                            Err(e) => {
+                                warn!("{}", NetworkError::InvalidStream(peer, e));
+                                return;
+                            }
+                        };
+                        if let Err(e) = result {
                             println!("here: {}", e);
                             return;
                         }
@@ -1,5 +1,10 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::framing::{FrameCodec, Framing};
+use crate::queue::{Priority, Queue, QueueConfig, QueueStats};
+use crate::secure::{self, SecureConfig};
+use crate::stream::Outbox;
+use crate::transport::{Link, Tcp, Transport};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
@@ -11,11 +16,10 @@ use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/reliable_sender_tests.rs"]
@@ -25,14 +29,23 @@ pub mod reliable_sender_tests;
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
-/// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
+/// We communicate with our 'connections' through a bounded queue kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
-/// receive an ACK back (until they succeed or are canceled).
+/// receive an ACK back (until they succeed or are canceled). A message the queue of its peer drops on
+/// overflow is canceled: its handler resolves to an error.
 pub struct ReliableSender {
-    /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    /// A map holding the queues of our connections.
+    connections: HashMap<SocketAddr, Arc<Queue<InnerMessage>>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// How messages are framed on our connections.
+    framing: Framing,
+    /// If set, connections are authenticated and encrypted.
+    secure: Option<SecureConfig>,
+    /// Bounds the queue of each connection.
+    queues: QueueConfig,
+    /// Where connections are opened.
+    transport: Arc<dyn Transport>,
 }
 
 impl std::default::Default for ReliableSender {
@@ -43,44 +56,114 @@ impl std::default::Default for ReliableSender {
 
 impl ReliableSender {
     pub fn new() -> Self {
+        Self::with_framing(Framing::default())
+    }
+
+    /// A sender whose connections use the given framing instead of the default one.
+    pub fn with_framing(framing: Framing) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            framing,
+            secure: None,
+            queues: QueueConfig::default(),
+            transport: Arc::new(Tcp),
         }
     }
 
+    /// Bound the queues of the connections opened from now on with `queues`.
+    pub fn with_queues(mut self, queues: QueueConfig) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    /// Open the connections from now on over `transport` instead of TCP.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Secure the connections opened from now on, and only talk to the peers `secure` accepts.
+    pub fn with_secure(mut self, secure: SecureConfig) -> Self {
+        self.secure = Some(secure);
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
-        let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
-        tx
+    fn spawn_connection(&self, address: SocketAddr) -> Arc<Queue<InnerMessage>> {
+        let queue = Arc::new(Queue::new(self.queues));
+        Connection::spawn(
+            address,
+            queue.clone(),
+            self.framing,
+            self.secure.clone(),
+            self.transport.clone(),
+        );
+        queue
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
-        let (sender, receiver) = oneshot::channel();
-        self.connections
-            .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
-            .send(InnerMessage {
-                data,
-                cancel_handler: sender,
-            })
+        self.send_with_priority(address, data, Priority::default())
             .await
-            .expect("Failed to send internal message");
+    }
+
+    /// Same as `send`, the message gets ahead of those of lower classes queued for the peer. Once
+    /// written, it waits for its ACK like any other.
+    pub async fn send_with_priority(
+        &mut self,
+        address: SocketAddr,
+        data: Bytes,
+        priority: Priority,
+    ) -> CancelHandler {
+        let (sender, receiver) = oneshot::channel();
+        if !self.connections.contains_key(&address) {
+            let queue = self.spawn_connection(address);
+            self.connections.insert(address, queue);
+        }
+        let message = InnerMessage {
+            data,
+            cancel_handler: sender,
+        };
+        if !self.connections[&address].push(message, priority) {
+            // The peer could not keep up, its connection is closed. The next message opens a new one.
+            warn!("Dropped the connection to {}, its queue overflowed", address);
+            self.connections.remove(&address);
+        }
         receiver
     }
 
+    /// The queue of each of our connections.
+    pub fn queue_stats(&self) -> HashMap<SocketAddr, QueueStats> {
+        self.connections
+            .iter()
+            .map(|(address, queue)| (*address, queue.stats()))
+            .collect()
+    }
+
     /// Broadcast the message to all specified addresses in a reliable manner. It returns a vector of
     /// cancel handlers ordered as the input `addresses` vector.
     pub async fn broadcast(
         &mut self,
         addresses: Vec<SocketAddr>,
         data: Bytes,
+    ) -> Vec<CancelHandler> {
+        self.broadcast_with_priority(addresses, data, Priority::default())
+            .await
+    }
+
+    /// Same as `broadcast`, with the given class.
+    pub async fn broadcast_with_priority(
+        &mut self,
+        addresses: Vec<SocketAddr>,
+        data: Bytes,
+        priority: Priority,
     ) -> Vec<CancelHandler> {
         let mut handlers = Vec::new();
         for address in addresses {
-            let handler = self.send(address, data.clone()).await;
+            let handler = self
+                .send_with_priority(address, data.clone(), priority)
+                .await;
             handlers.push(handler);
         }
         handlers
@@ -100,6 +183,16 @@ impl ReliableSender {
     }
 }
 
+impl Drop for ReliableSender {
+    fn drop(&mut self) {
+        // The connections write what is queued, then stop: the messages not acknowledged by then
+        // are canceled.
+        for queue in self.connections.values() {
+            queue.close();
+        }
+    }
+}
+
 /// Simple message used by `ReliableSender` to communicate with its connections.
 #[derive(Debug)]
 struct InnerMessage {
@@ -114,35 +207,51 @@ struct InnerMessage {
 struct Connection {
     /// The destination address.
     address: SocketAddr,
-    /// Channel from which the connection receives its commands.
-    receiver: Receiver<InnerMessage>,
+    /// The messages to write.
+    queue: Arc<Queue<InnerMessage>>,
     /// The initial delay to wait before re-attempting a connection (in ms).
     retry_delay: u64,
-    /// Buffer keeping all messages that need to be re-transmitted.
-    buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    /// Buffer keeping the messages taken from the queue that need to be (re-)transmitted.
+    buffer: VecDeque<(Bytes, Priority, oneshot::Sender<Bytes>)>,
+    /// How messages are framed on the connection.
+    framing: Framing,
+    /// If set, the connection is authenticated and encrypted.
+    secure: Option<SecureConfig>,
+    /// Where the connection is opened.
+    transport: Arc<dyn Transport>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(
+        address: SocketAddr,
+        queue: Arc<Queue<InnerMessage>>,
+        framing: Framing,
+        secure: Option<SecureConfig>,
+        transport: Arc<dyn Transport>,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
-                receiver,
+                queue,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                framing,
+                secure,
+                transport,
             }
             .run()
             .await;
         });
     }
 
-    /// Main loop trying to connect to the peer and transmit messages.
+    /// Main loop trying to connect to the peer and transmit messages. It returns once the queue is
+    /// closed.
     async fn run(&mut self) {
         let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
-                Ok(stream) => {
+            match self.connect(retry).await {
+                Ok(transport) => {
                     info!("Outgoing connection established with {}", self.address);
 
                     // Reset the delay.
@@ -150,75 +259,88 @@ impl Connection {
                     retry = 0;
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
-                    // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
-                    warn!("{}", error);
+                    // The following function only returns if there is an error, or once the queue is closed.
+                    match self.keep_alive(transport).await {
+                        Ok(()) => return,
+                        Err(e) => warn!("{}", e),
+                    }
                 }
                 Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
-                    let timer = sleep(Duration::from_millis(delay));
-                    tokio::pin!(timer);
-
-                    'waiter: loop {
-                        tokio::select! {
-                            // Wait an increasing delay before attempting to reconnect.
-                            () = &mut timer => {
-                                delay = min(2*delay, 60_000);
-                                retry +=1;
-                                break 'waiter;
-                            },
-
-                            // Drain the channel into the buffer to not saturate the channel and block the caller task.
-                            // The caller is responsible to cleanup the buffer through the cancel handlers.
-                            Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
-                            }
-                        }
+                    warn!("{}", e);
+                    // Wait an increasing delay before attempting to reconnect, the messages sent
+                    // meanwhile wait in the queue.
+                    self.buffer.retain(|(_, _, handler)| !handler.is_closed());
+                    tokio::select! {
+                        () = sleep(Duration::from_millis(delay)) => {
+                            delay = min(2*delay, 60_000);
+                            retry +=1;
+                        },
+                        () = self.queue.closed() => return,
                     }
                 }
             }
         }
     }
 
+    /// Connect to the peer, and run the handshake if the connection is secure.
+    async fn connect(&self, retry: u16) -> Result<Framed<Link, FrameCodec>, NetworkError> {
+        let stream = self
+            .transport
+            .connect(self.address)
+            .await
+            .map_err(|e| NetworkError::FailedToConnect(self.address, retry, e))?;
+        let mut transport = self.framing.framed(stream);
+        if let Some(secure) = &self.secure {
+            secure::connect(&mut transport, secure, self.address).await?;
+        }
+        Ok(transport)
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(
+        &mut self,
+        transport: Framed<Link, FrameCodec>,
+    ) -> Result<(), NetworkError> {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        // The messages being written, frame by frame.
+        let mut outbox = Outbox::new(&self.framing);
+
+        let (mut writer, mut reader) = transport.split();
         let error = 'connection: loop {
-            // Try to send all messages of the buffer.
-            while let Some((data, handler)) = self.buffer.pop_front() {
-                // Skip messages that have been cancelled.
-                if handler.is_closed() {
-                    continue;
+            // Hand the messages of the buffer to the outbox, skipping those that have been cancelled.
+            while let Some((data, priority, handler)) = self.buffer.pop_front() {
+                if !handler.is_closed() {
+                    outbox.push(data, priority, handler);
                 }
+            }
 
-                // Try to send the message.
-                match writer.send(data.clone()).await {
-                    Ok(()) => {
-                        // The message has been sent, we remove it from the buffer and add it to
-                        // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
-                    }
-                    Err(e) => {
-                        // We failed to send the message, we put it back into the buffer.
-                        self.buffer.push_front((data, handler));
-                        break 'connection NetworkError::FailedToSendMessage(self.address, e);
-                    }
+            // Try to send the next frame, the urgent messages queued meanwhile get ahead of the
+            // chunks left of bulk ones.
+            if let Some(frame) = outbox.next_frame() {
+                if let Err(e) = writer.send(frame).await {
+                    break 'connection NetworkError::FailedToSendMessage(self.address, e);
+                }
+                // Once all of it has been sent, the message waits for an ACK in `pending_replies`.
+                if let Some(message) = outbox.written() {
+                    pending_replies.push_back(message);
                 }
+                while let Some(InnerMessage { data, cancel_handler }) = self.queue.pop_urgent() {
+                    outbox.push(data, Priority::Urgent, cancel_handler);
+                }
+                continue;
             }
 
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                    // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                message = self.queue.pop() => match message {
+                    Some((InnerMessage{data, cancel_handler}, priority)) => outbox.push(data, priority, cancel_handler),
+                    None => return Ok(()),
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
+                    let (data, priority, handler) = match pending_replies.pop_front() {
                         Some(message) => message,
                         None => break 'connection NetworkError::UnexpectedAck(self.address)
                     };
@@ -230,7 +352,7 @@ impl Connection {
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
                             // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
+                            pending_replies.push_front((data, priority, handler));
                             break 'connection NetworkError::FailedToReceiveAck(self.address);
                         }
                     }
@@ -238,11 +360,15 @@ impl Connection {
             }
         };
 
-        // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
-        // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
+        // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK,
+        // and those not fully sent, back into the sending buffer, we will try to send them again once we manage to
+        // establish a new connection.
+        for message in outbox.drain().into_iter().rev() {
+            self.buffer.push_front(message);
+        }
         while let Some(message) = pending_replies.pop_back() {
             self.buffer.push_front(message);
         }
-        error
+        Err(error)
     }
 }
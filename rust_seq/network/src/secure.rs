@@ -0,0 +1,289 @@
+use crate::error::NetworkError;
+use crate::framing::FrameCodec;
+use crate::transport::Link;
+use bytes::{Bytes, BytesMut};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use ring::signature::{Ed25519KeyPair, KeyPair as _, UnparsedPublicKey, ED25519};
+use snow::{Builder, HandshakeState, TransportState};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::Framed;
+
+#[cfg(test)]
+#[path = "tests/secure_tests.rs"]
+pub mod secure_tests;
+
+/// Both ends give up on a handshake that takes longer than this (in ms).
+pub const HANDSHAKE_TIMEOUT: u64 = 5_000;
+
+/// The Noise protocol links run: both ends send their static key encrypted, so neither needs to
+/// know the other's beforehand, and the Ed25519 identity rides in the handshake payloads.
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"fg-beta secure link v2";
+/// What an Ed25519 key signs to vouch for the static Noise key of its node.
+const STATIC_KEY_PREFIX: &[u8] = b"fg-beta noise static key:";
+/// The longest Noise message, and the tag each one carries.
+const NOISE_MSG_LEN: usize = 65_535;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// The Ed25519 public key a peer proved it holds during the handshake.
+pub type PeerKey = Vec<u8>;
+
+/// Decides whether we talk to the peer holding a key.
+type Authorize = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// The Ed25519 key a node authenticates its links with, and the peers it accepts. Every connection
+/// starts with a Noise XX handshake (see `handshake`) in which both ends prove they hold their
+/// key, and the frames that follow are encrypted with the Noise transport keys.
+#[derive(Clone)]
+pub struct SecureConfig {
+    keypair: Arc<Ed25519KeyPair>,
+    /// The static X25519 key of our Noise handshakes, made for this config.
+    static_key: Arc<Vec<u8>>,
+    /// Our Ed25519 key and its signature of the public half of `static_key`.
+    identity: Arc<Vec<u8>>,
+    authorize: Authorize,
+    peer_keys: Arc<HashMap<SocketAddr, PeerKey>>,
+}
+
+impl SecureConfig {
+    /// A config accepting any peer that authenticates, from the PKCS#8 document of our key.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, NetworkError> {
+        let keypair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|e| NetworkError::InvalidKey(e.to_string()))?;
+        let static_key = Builder::new(noise_params())
+            .generate_keypair()
+            .map_err(|e| NetworkError::InvalidKey(e.to_string()))?;
+        let signature = keypair.sign(&[STATIC_KEY_PREFIX, &static_key.public].concat());
+        let identity = [keypair.public_key().as_ref(), signature.as_ref()].concat();
+        Ok(Self {
+            keypair: Arc::new(keypair),
+            static_key: Arc::new(static_key.private),
+            identity: Arc::new(identity),
+            authorize: Arc::new(|_| true),
+            peer_keys: Arc::default(),
+        })
+    }
+
+    /// Only accept the peers whose key passes `authorize`.
+    pub fn with_authorize<F>(mut self, authorize: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.authorize = Arc::new(authorize);
+        self
+    }
+
+    /// The key each address is expected to answer with. A connection we open to one of them
+    /// fails if the peer holds another key, even one that `authorize` accepts.
+    pub fn with_peer_keys(mut self, peer_keys: HashMap<SocketAddr, PeerKey>) -> Self {
+        self.peer_keys = Arc::new(peer_keys);
+        self
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.keypair.public_key().as_ref()
+    }
+
+    fn handshake_state(&self, initiator: bool) -> Result<HandshakeState, String> {
+        let builder = Builder::new(noise_params())
+            .local_private_key(&self.static_key)
+            .prologue(PROLOGUE);
+        let state = if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        };
+        state.map_err(|e| e.to_string())
+    }
+}
+
+fn noise_params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().expect("valid noise parameters")
+}
+
+/// Encrypts the frames we send and decrypts the ones we receive, with the transport keys of the
+/// Noise handshake. A frame longer than a Noise message goes as several, and as every message
+/// is numbered, a replayed, dropped or reordered frame fails to decrypt.
+pub struct Cipher {
+    transport: TransportState,
+}
+
+impl Cipher {
+    fn new(transport: TransportState) -> Self {
+        Self { transport }
+    }
+
+    pub fn seal(&mut self, frame: Bytes) -> io::Result<Bytes> {
+        let pieces = frame.len().div_ceil(NOISE_MSG_LEN - TAG_LEN).max(1);
+        let mut buffer = vec![0u8; frame.len() + pieces * TAG_LEN];
+        let mut len = 0;
+        // an empty frame is still one (authenticated) message
+        for piece in frame.chunks(NOISE_MSG_LEN - TAG_LEN).chain(frame.is_empty().then_some(&[][..])) {
+            len += self
+                .transport
+                .write_message(piece, &mut buffer[len..])
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to seal frame"))?;
+        }
+        buffer.truncate(len);
+        Ok(Bytes::from(buffer))
+    }
+
+    pub fn open(&mut self, frame: BytesMut) -> io::Result<BytesMut> {
+        if frame.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty frame"));
+        }
+        let mut buffer = BytesMut::zeroed(frame.len());
+        let mut len = 0;
+        for piece in frame.chunks(NOISE_MSG_LEN) {
+            len += self
+                .transport
+                .read_message(piece, &mut buffer[len..])
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to open frame"))?;
+        }
+        buffer.truncate(len);
+        Ok(buffer)
+    }
+}
+
+/// Checks that the payload holds an Ed25519 key we accept, signing the static key the other end
+/// proved in the handshake.
+fn check_identity(
+    config: &SecureConfig,
+    state: &HandshakeState,
+    payload: &[u8],
+) -> Result<PeerKey, String> {
+    if payload.len() != KEY_LEN + SIGNATURE_LEN {
+        return Err("malformed identity".to_string());
+    }
+    let remote_static = state
+        .get_remote_static()
+        .ok_or_else(|| "no static key".to_string())?;
+    let (key, signature) = payload.split_at(KEY_LEN);
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(&[STATIC_KEY_PREFIX, remote_static].concat(), signature)
+        .map_err(|_| "bad signature".to_string())?;
+    if !(config.authorize)(key) {
+        return Err("key not authorized".to_string());
+    }
+    Ok(key.to_vec())
+}
+
+async fn next_frame(framed: &mut Framed<Link, FrameCodec>) -> Result<BytesMut, String> {
+    match framed.next().await {
+        Some(Ok(frame)) => Ok(frame),
+        Some(Err(e)) => Err(e.to_string()),
+        None => Err("connection closed".to_string()),
+    }
+}
+
+/// Writes the next handshake message, carrying `payload`, as one plain frame.
+async fn write_handshake(
+    framed: &mut Framed<Link, FrameCodec>,
+    state: &mut HandshakeState,
+    payload: &[u8],
+) -> Result<(), String> {
+    let mut message = vec![0u8; NOISE_MSG_LEN];
+    let len = state
+        .write_message(payload, &mut message)
+        .map_err(|e| e.to_string())?;
+    message.truncate(len);
+    framed
+        .send(Bytes::from(message))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Reads the next handshake message, and returns its payload.
+async fn read_handshake(
+    framed: &mut Framed<Link, FrameCodec>,
+    state: &mut HandshakeState,
+) -> Result<Vec<u8>, String> {
+    let frame = next_frame(framed).await?;
+    let mut payload = vec![0u8; frame.len()];
+    let len = state
+        .read_message(&frame, &mut payload)
+        .map_err(|e| e.to_string())?;
+    payload.truncate(len);
+    Ok(payload)
+}
+
+/// The handshake is Noise XX over the plain frames of the connection:
+///
+///   initiator -> responder: e
+///   responder -> initiator: e, ee, s, es, payload_r
+///   initiator -> responder: s, se, payload_i
+///
+/// Each payload is the Ed25519 key of its end and that key's signature of the end's static
+/// X25519 key, which the handshake proves the end holds: the Ed25519 key is thus bound to this
+/// connection, and only sent encrypted.
+async fn handshake(
+    framed: &mut Framed<Link, FrameCodec>,
+    config: &SecureConfig,
+    initiator: bool,
+) -> Result<PeerKey, String> {
+    let mut state = config.handshake_state(initiator)?;
+    let peer_key = if initiator {
+        write_handshake(framed, &mut state, &[]).await?;
+        let payload = read_handshake(framed, &mut state).await?;
+        let peer_key = check_identity(config, &state, &payload)?;
+        write_handshake(framed, &mut state, &config.identity).await?;
+        peer_key
+    } else {
+        read_handshake(framed, &mut state).await?;
+        write_handshake(framed, &mut state, &config.identity).await?;
+        let payload = read_handshake(framed, &mut state).await?;
+        check_identity(config, &state, &payload)?
+    };
+    let transport = state.into_transport_mode().map_err(|e| e.to_string())?;
+    framed.codec_mut().secure(Cipher::new(transport));
+    Ok(peer_key)
+}
+
+/// Runs the handshake on a connection we opened to `address`, and returns the key of the peer,
+/// which must be the one expected at that address if any.
+pub async fn connect(
+    framed: &mut Framed<Link, FrameCodec>,
+    config: &SecureConfig,
+    address: SocketAddr,
+) -> Result<PeerKey, NetworkError> {
+    let key = bounded_handshake(framed, config, address, true).await?;
+    match config.peer_keys.get(&address) {
+        Some(expected) if *expected != key => Err(NetworkError::FailedHandshake(
+            address,
+            "unexpected key".to_string(),
+        )),
+        _ => Ok(key),
+    }
+}
+
+/// Runs the handshake on a connection accepted from `address`, and returns the key of the peer.
+pub async fn accept(
+    framed: &mut Framed<Link, FrameCodec>,
+    config: &SecureConfig,
+    address: SocketAddr,
+) -> Result<PeerKey, NetworkError> {
+    bounded_handshake(framed, config, address, false).await
+}
+
+async fn bounded_handshake(
+    framed: &mut Framed<Link, FrameCodec>,
+    config: &SecureConfig,
+    address: SocketAddr,
+    initiator: bool,
+) -> Result<PeerKey, NetworkError> {
+    let duration = Duration::from_millis(HANDSHAKE_TIMEOUT);
+    match timeout(duration, handshake(framed, config, initiator)).await {
+        Ok(result) => result.map_err(|e| NetworkError::FailedHandshake(address, e)),
+        Err(_) => Err(NetworkError::FailedHandshake(
+            address,
+            "timed out".to_string(),
+        )),
+    }
+}
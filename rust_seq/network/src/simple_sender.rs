@@ -1,30 +1,58 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::framing::{FrameCodec, Framing};
+use crate::queue::{Priority, Queue, QueueConfig, QueueStats};
+use crate::secure::{self, SecureConfig};
+use crate::stream::Outbox;
+use crate::transport::{Link, Tcp, Transport};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{/*info,*/ warn};
+use log::{info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
+use std::cmp::min;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{LengthDelimitedCodec};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
 use tokio::time::{sleep, Duration};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/simple_sender_tests.rs"]
 pub mod simple_sender_tests;
 
+/// Reported by a `SimpleSender` whenever one of its connections goes up or down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    Connected(SocketAddr),
+    Disconnected(SocketAddr),
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
-/// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
+/// We communicate with our 'connections' through a bounded queue kept by the HashMap called `connections`.
+/// A connection that fails is re-established with an exponential backoff, and the messages sent meanwhile are
+/// queued. Sending never waits for a peer: once its queue is full, the overflow policy decides what to drop.
+/// Delivery is still best-effort: without ACKs, a message written just before the peer went down is lost.
 pub struct SimpleSender {
-    /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<Bytes>>,
+    /// A map holding the queues of our connections.
+    connections: HashMap<SocketAddr, Arc<Queue<Bytes>>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// How messages are framed on our connections.
+    framing: Framing,
+    /// Sent first on every (re-)established connection, e.g. a handshake the peer needs before anything else.
+    hello: Option<Bytes>,
+    /// Where the connections report when they go up or down.
+    events: Option<Sender<ConnectionEvent>>,
+    /// If set, connections are authenticated and encrypted.
+    secure: Option<SecureConfig>,
+    /// Bounds the queue of each connection.
+    queues: QueueConfig,
+    /// Where connections are opened.
+    transport: Arc<dyn Transport>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -35,46 +63,128 @@ impl std::default::Default for SimpleSender {
 
 impl SimpleSender {
     pub fn new() -> Self {
+        Self::with_framing(Framing::default())
+    }
+
+    /// A sender whose connections use the given framing instead of the default one.
+    pub fn with_framing(framing: Framing) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            framing,
+            hello: None,
+            events: None,
+            secure: None,
+            queues: QueueConfig::default(),
+            transport: Arc::new(Tcp),
         }
     }
 
+    /// Bound the queues of the connections opened from now on with `queues`.
+    pub fn with_queues(mut self, queues: QueueConfig) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    /// Report the state changes of the connections opened from now on to `events`.
+    pub fn with_events(mut self, events: Sender<ConnectionEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Open the connections from now on over `transport` instead of TCP.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Secure the connections opened from now on, and only talk to the peers `secure` accepts.
+    pub fn with_secure(mut self, secure: SecureConfig) -> Self {
+        self.secure = Some(secure);
+        self
+    }
+
+    /// Connect to `peers` and greet them with `syn_msg`. It is also the hello of every connection opened
+    /// from now on, and is sent again each time a connection is re-established.
     pub async fn init(&mut self, syn_msg: Bytes, peers: Vec<SocketAddr>) {
+        self.hello = Some(syn_msg.clone());
         for peer in peers {
-            self.send(peer, syn_msg.clone()).await;
+            match self.connections.get(&peer) {
+                // Connections opened before we had a hello greet the peer once.
+                Some(_) => self.send(peer, syn_msg.clone()).await,
+                None => {
+                    let queue = self.spawn_connection(peer);
+                    self.connections.insert(peer, queue);
+                }
+            }
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
-        let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
-        tx
+    fn spawn_connection(&self, address: SocketAddr) -> Arc<Queue<Bytes>> {
+        let queue = Arc::new(Queue::new(self.queues));
+        Connection::spawn(
+            address,
+            queue.clone(),
+            self.framing,
+            self.hello.clone(),
+            self.events.clone(),
+            self.secure.clone(),
+            self.transport.clone(),
+        );
+        queue
     }
 
     /// Try (best-effort) to send a message to a specific address.
     /// This is useful to answer sync requests.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
-        // Try to re-use an existing connection if possible.
-        if let Some(tx) = self.connections.get(&address) {
-            if tx.send(data.clone()).await.is_ok() {
-                return;
-            }
-        }
+        self.send_with_priority(address, data, Priority::default())
+            .await
+    }
 
-        // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
-        if tx.send(data).await.is_ok() {
-            self.connections.insert(address, tx);
+    /// Same as `send`, the message gets ahead of those of lower classes queued for the peer.
+    pub async fn send_with_priority(
+        &mut self,
+        address: SocketAddr,
+        data: Bytes,
+        priority: Priority,
+    ) {
+        // Re-use the existing connection if possible, or make a new one.
+        if !self.connections.contains_key(&address) {
+            let queue = self.spawn_connection(address);
+            self.connections.insert(address, queue);
+        }
+        if !self.connections[&address].push(data, priority) {
+            // The peer could not keep up, its connection is closed. The next message opens a new one.
+            warn!("Dropped the connection to {}, its queue overflowed", address);
+            self.connections.remove(&address);
         }
     }
 
+    /// The queue of each of our connections.
+    pub fn queue_stats(&self) -> HashMap<SocketAddr, QueueStats> {
+        self.connections
+            .iter()
+            .map(|(address, queue)| (*address, queue.stats()))
+            .collect()
+    }
+
     /// Try (best-effort) to broadcast the message to all specified addresses.
     pub async fn broadcast(&mut self, addresses: Vec<SocketAddr>, data: Bytes) {
+        self.broadcast_with_priority(addresses, data, Priority::default())
+            .await
+    }
+
+    /// Same as `broadcast`, with the given class.
+    pub async fn broadcast_with_priority(
+        &mut self,
+        addresses: Vec<SocketAddr>,
+        data: Bytes,
+        priority: Priority,
+    ) {
         for address in addresses {
-            self.send(address, data.clone()).await;
+            self.send_with_priority(address, data.clone(), priority)
+                .await;
         }
     }
 
@@ -92,45 +202,158 @@ impl SimpleSender {
     }
 }
 
+impl Drop for SimpleSender {
+    fn drop(&mut self) {
+        // The connections write what is queued, then stop.
+        for queue in self.connections.values() {
+            queue.close();
+        }
+    }
+}
+
 /// A connection is responsible to establish and keep alive (if possible) a connection with a single peer.
 struct Connection {
     /// The destination address.
     address: SocketAddr,
-    /// Channel from which the connection receives its commands.
-    receiver: Receiver<Bytes>,
+    /// The messages to write.
+    queue: Arc<Queue<Bytes>>,
+    /// How messages are framed on the connection.
+    framing: Framing,
+    /// Sent first each time the connection is established.
+    hello: Option<Bytes>,
+    /// Where we report that the connection went up or down.
+    events: Option<Sender<ConnectionEvent>>,
+    /// If set, the connection is authenticated and encrypted.
+    secure: Option<SecureConfig>,
+    /// Where the connection is opened.
+    transport: Arc<dyn Transport>,
+    /// The initial delay to wait before re-attempting a connection (in ms).
+    retry_delay: u64,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(
+        address: SocketAddr,
+        queue: Arc<Queue<Bytes>>,
+        framing: Framing,
+        hello: Option<Bytes>,
+        events: Option<Sender<ConnectionEvent>>,
+        secure: Option<SecureConfig>,
+        transport: Arc<dyn Transport>,
+    ) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                queue,
+                framing,
+                hello,
+                events,
+                secure,
+                transport,
+                retry_delay: 100,
+            }
+            .run()
+            .await;
         });
     }
 
-    /// Main loop trying to connect to the peer and transmit messages.
+    /// Main loop trying to connect to the peer and transmit messages. It returns once the queue is
+    /// closed, i.e. the `SimpleSender` is dropped or the queue overflowed with `Overflow::Disconnect`.
     async fn run(&mut self) {
-        // Try to connect to the peer.
-        let (mut writer, mut reader) = loop {
-            match TcpStream::connect(self.address).await {
-                Ok(stream) => break LengthDelimitedCodec::builder()
-                    .little_endian()
-                    .max_frame_length(120_000_000)
-                    .new_framed(stream)
-                    .split(),
-                Err(_) => sleep(Duration::from_millis(100)).await,
+        let mut delay = self.retry_delay;
+        let mut retry = 0;
+        loop {
+            match self.connect(retry).await {
+                Ok(transport) => {
+                    info!("Outgoing connection established with {}", self.address);
+                    self.notify(ConnectionEvent::Connected(self.address)).await;
+
+                    // Reset the delay.
+                    delay = self.retry_delay;
+                    retry = 0;
+
+                    // Transmit messages until the connection fails.
+                    let result = self.keep_alive(transport).await;
+                    self.notify(ConnectionEvent::Disconnected(self.address))
+                        .await;
+                    match result {
+                        Ok(()) => return,
+                        Err(e) => warn!("{}", e),
+                    }
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    // Wait an increasing delay before attempting to reconnect, the messages sent
+                    // meanwhile wait in the queue.
+                    tokio::select! {
+                        () = sleep(Duration::from_millis(delay)) => {
+                            delay = min(2*delay, 60_000);
+                            retry +=1;
+                        },
+                        () = self.queue.closed() => return,
+                    }
+                }
             }
-        };
-        println!("Outgoing connection established with {}", self.address);
+        }
+    }
 
-        // Transmit messages once we have established a connection.
+    /// Connect to the peer, and run the handshake if the connection is secure.
+    async fn connect(&self, retry: u16) -> Result<Framed<Link, FrameCodec>, NetworkError> {
+        let stream = self
+            .transport
+            .connect(self.address)
+            .await
+            .map_err(|e| NetworkError::FailedToConnect(self.address, retry, e))?;
+        let mut transport = self.framing.framed(stream);
+        if let Some(secure) = &self.secure {
+            secure::connect(&mut transport, secure, self.address).await?;
+        }
+        Ok(transport)
+    }
+
+    async fn notify(&self, event: ConnectionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event).await;
+        }
+    }
+
+    /// Transmit messages once we have established a connection. It only returns if the connection
+    /// fails, or with `Ok` once the queue is closed.
+    async fn keep_alive(
+        &mut self,
+        transport: Framed<Link, FrameCodec>,
+    ) -> Result<(), NetworkError> {
+        let (mut writer, mut reader) = transport.split();
+        let mut outbox = Outbox::new(&self.framing);
+        if let Some(hello) = &self.hello {
+            outbox.push(hello.clone(), Priority::Urgent, false);
+        }
         loop {
-            // Check if there are any new messages to send or if we get an ACK for messages we already sent.
-            tokio::select! {
-                Some(data) = self.receiver.recv() => {
-                    if let Err(e) = writer.send(data).await {
-                        println!("sender: {}", NetworkError::FailedToSendMessage(self.address, e));
-                        return;
+            // Write the messages taken from the queue frame by frame: the urgent messages queued
+            // meanwhile get ahead of the chunks left of bulk ones.
+            if let Some(frame) = outbox.next_frame() {
+                if let Err(e) = writer.send(frame).await {
+                    // The messages not fully written are kept for the next connection, the hello
+                    // is sent anyway.
+                    for (data, priority, queued) in outbox.drain().into_iter().rev() {
+                        if queued {
+                            self.queue.push_front(data, priority);
+                        }
                     }
+                    return Err(NetworkError::FailedToSendMessage(self.address, e));
+                }
+                outbox.written();
+                while let Some(data) = self.queue.pop_urgent() {
+                    outbox.push(data, Priority::Urgent, true);
+                }
+                continue;
+            }
+
+            // Check if there are any new messages to send or if the peer replied.
+            tokio::select! {
+                data = self.queue.pop() => match data {
+                    Some((data, priority)) => outbox.push(data, priority, true),
+                    None => return Ok(()),
                 },
                 response = reader.next() => {
                     match response {
@@ -139,8 +362,7 @@ impl Connection {
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            warn!("{}", NetworkError::FailedToReceiveAck(self.address));
-                            return;
+                            return Err(NetworkError::FailedToReceiveAck(self.address));
                         }
                     }
                 },
@@ -0,0 +1,396 @@
+use crate::framing::Framing;
+use crate::stream::chunk_header;
+use crate::transport::{Link, Listener, MemoryNetwork, Transport, MEMORY_BUFFER};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::join;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::cmp::{max, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap};
+use std::f64::consts::PI;
+use std::hash::{Hash as _, Hasher as _};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, split, AsyncRead, AsyncWrite};
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+#[cfg(test)]
+#[path = "tests/simulator_tests.rs"]
+pub mod simulator_tests;
+
+/// How long a message picked for reordering is held back, unless configured otherwise.
+pub const REORDER_DELAY: Duration = Duration::from_millis(10);
+
+/// How long messages take to cross a link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// Uniformly distributed between the two bounds.
+    Uniform(Duration, Duration),
+    /// Normally distributed, latencies below zero count as zero.
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Fixed(Duration::ZERO)
+    }
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut SmallRng) -> Duration {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform(low, high) => low + high.saturating_sub(low).mul_f64(rng.gen()),
+            Latency::Normal { mean, std_dev } => {
+                // Box-Muller, 1 - u keeps the logarithm finite.
+                let (u, v): (f64, f64) = (rng.gen(), rng.gen());
+                let z = (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * PI * v).cos();
+                let latency = mean.as_secs_f64() + z * std_dev.as_secs_f64();
+                Duration::from_secs_f64(latency.max(0.0))
+            }
+        }
+    }
+}
+
+/// The faults of one direction of a link. A message is lost, duplicated or reordered as a whole:
+/// all its chunks share its fate, when messages are chunked (see `Framing::chunk_size`). Those
+/// three need plain links, as the frames of a secure one are numbered: a fault there shows up as
+/// a frame the receiver rejects, and the connection is reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConfig {
+    pub latency: Latency,
+    /// The bytes per second the link carries, if capped: a message is sent once those before it are.
+    pub bandwidth: Option<u64>,
+    /// The probability that a message is lost.
+    pub drop: f64,
+    /// The probability that a message is delivered twice.
+    pub duplicate: f64,
+    /// The probability that a message is held back by `reorder_delay`, so that the messages sent
+    /// after it may overtake it. The others arrive in order.
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Latency::default(),
+            bandwidth: None,
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: REORDER_DELAY,
+        }
+    }
+}
+
+/// Cuts `side` off from all other nodes between `start` and `end`, counted from the creation of
+/// the `SimNetwork`. The links across the partition break when it starts, and cannot be opened
+/// again until it heals.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub start: Duration,
+    pub end: Duration,
+    pub side: Vec<SocketAddr>,
+}
+
+impl Partition {
+    fn separates(&self, a: SocketAddr, b: SocketAddr) -> bool {
+        self.side.contains(&a) != self.side.contains(&b)
+    }
+}
+
+/// A `MemoryNetwork` whose links misbehave: messages are delayed, throttled, lost, duplicated and
+/// reordered as configured for each link, and partitions come and go as scripted. Each node runs
+/// over its own `SimNetwork::node`, so that the network knows which link a connection is on.
+///
+/// The faults of each connection are drawn from `seed`, the link and the number of the
+/// connection on it: the same messages get the same faults on every run. Latencies are measured
+/// with the tokio clock, so a test whose clock is paused replays exactly, reorderings included.
+#[derive(Clone)]
+pub struct SimNetwork {
+    memory: MemoryNetwork,
+    seed: u64,
+    start: Instant,
+    /// How the nodes frame messages, to tell messages apart.
+    framing: Framing,
+    /// The faults of the links not in `links`.
+    default: LinkConfig,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConfig>,
+    partitions: Vec<Partition>,
+    /// How many connections were opened on each link.
+    connections: Arc<Mutex<HashMap<(SocketAddr, SocketAddr), u64>>>,
+}
+
+impl SimNetwork {
+    /// A network of perfect links, until configured otherwise. Partitions are timed from now.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            memory: MemoryNetwork::new(),
+            seed,
+            start: Instant::now(),
+            framing: Framing::default(),
+            default: LinkConfig::default(),
+            links: HashMap::new(),
+            partitions: Vec::new(),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The nodes frame the messages they send with `framing`.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// The faults of every link, unless set for the link itself.
+    pub fn with_default(mut self, config: LinkConfig) -> Self {
+        self.default = config;
+        self
+    }
+
+    /// The faults of the messages `from` sends to `to`. The replies `to` writes back on those
+    /// connections get the faults of the link from `to` to `from`.
+    pub fn with_link(mut self, from: SocketAddr, to: SocketAddr, config: LinkConfig) -> Self {
+        self.links.insert((from, to), config);
+        self
+    }
+
+    pub fn with_partition(mut self, partition: Partition) -> Self {
+        self.partitions.push(partition);
+        self
+    }
+
+    /// The transport of the node listening on `address`. It sees the network as configured so
+    /// far.
+    pub fn node(&self, address: SocketAddr) -> Arc<dyn Transport> {
+        Arc::new(SimNode {
+            address,
+            network: self.clone(),
+        })
+    }
+
+    fn config(&self, from: SocketAddr, to: SocketAddr) -> LinkConfig {
+        self.links.get(&(from, to)).copied().unwrap_or(self.default)
+    }
+
+    fn rng(&self, from: SocketAddr, to: SocketAddr, connection: u64) -> SmallRng {
+        let mut hasher = DefaultHasher::new();
+        (self.seed, from, to, connection).hash(&mut hasher);
+        SmallRng::seed_from_u64(hasher.finish())
+    }
+}
+
+struct SimNode {
+    address: SocketAddr,
+    network: SimNetwork,
+}
+
+#[async_trait]
+impl Transport for SimNode {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Link> {
+        let network = &self.network;
+        let elapsed = network.start.elapsed();
+        let partitions = network
+            .partitions
+            .iter()
+            .filter(|partition| partition.separates(self.address, address));
+        if partitions
+            .clone()
+            .any(|partition| partition.start <= elapsed && elapsed < partition.end)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} is partitioned from {}", address, self.address),
+            ));
+        }
+        let cut = partitions
+            .map(|partition| partition.start)
+            .filter(|start| *start > elapsed)
+            .min()
+            .map(|start| network.start + start);
+        let remote = network.memory.connect(address).await?;
+
+        let connection = {
+            let mut connections = network.connections.lock().unwrap();
+            let count = connections.entry((self.address, address)).or_insert(0);
+            *count += 1;
+            *count
+        };
+        let forward = Pipe::new(
+            network.config(self.address, address),
+            network.rng(self.address, address, connection),
+            network.framing.chunk_size.is_some(),
+        );
+        // The replies of the peer are never chunked.
+        let backward = Pipe::new(
+            network.config(address, self.address),
+            network.rng(address, self.address, connection),
+            false,
+        );
+        let framing = network.framing;
+        let (ours, theirs) = duplex(MEMORY_BUFFER);
+        tokio::spawn(async move {
+            let (our_reader, our_writer) = split(theirs);
+            let (remote_reader, remote_writer) = split(remote);
+            let pipes = join(
+                forward.run(our_reader, remote_writer, framing),
+                backward.run(remote_reader, our_writer, framing),
+            );
+            // Once both pipes are gone, so is the connection.
+            match cut {
+                Some(cut) => {
+                    tokio::select! {
+                        _ = pipes => (),
+                        () = sleep_until(cut) => (),
+                    }
+                }
+                None => {
+                    pipes.await;
+                }
+            }
+        });
+        Ok(Box::new(ours))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        self.network.memory.bind(address).await
+    }
+}
+
+/// What becomes of a message, and of its chunks.
+struct Fate {
+    drop: bool,
+    /// The chunks to send again once the message is sent, if it is duplicated.
+    duplicate: Option<Vec<Bytes>>,
+    held: bool,
+    /// When its last chunk so far is delivered.
+    last: Instant,
+}
+
+/// Carries the frames of one direction of a connection, each once it is due.
+struct Pipe {
+    config: LinkConfig,
+    rng: SmallRng,
+    chunked: bool,
+    /// When the frames sent so far are, as far as the bandwidth goes.
+    sent: Instant,
+    /// When the last frame not held back is delivered, those after it may not arrive earlier.
+    in_order: Instant,
+    /// The messages whose chunks are not all sent.
+    partial: HashMap<u32, Fate>,
+    due: BinaryHeap<Reverse<(Instant, u64, Bytes)>>,
+    scheduled: u64,
+}
+
+impl Pipe {
+    fn new(config: LinkConfig, rng: SmallRng, chunked: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            rng,
+            chunked,
+            sent: now,
+            in_order: now,
+            partial: HashMap::new(),
+            due: BinaryHeap::new(),
+            scheduled: 0,
+        }
+    }
+
+    async fn run<R, W>(mut self, reader: R, writer: W, framing: Framing)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut reader = FramedRead::new(reader, framing.codec());
+        let mut writer = FramedWrite::new(writer, framing.codec());
+        let mut open = true;
+        while open || !self.due.is_empty() {
+            let next = self.due.peek().map(|Reverse((at, _, _))| *at);
+            tokio::select! {
+                frame = reader.next(), if open => match frame {
+                    Some(Ok(frame)) => self.schedule(frame.freeze()),
+                    // The frames on their way are still delivered.
+                    _ => open = false,
+                },
+                () = sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let Reverse((_, _, frame)) = self.due.pop().unwrap();
+                    if writer.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn fate(&mut self) -> Fate {
+        // Draw all three, so that a fault does not shift those of the next messages.
+        let (drop, duplicate, held): (f64, f64, f64) =
+            (self.rng.gen(), self.rng.gen(), self.rng.gen());
+        Fate {
+            drop: drop < self.config.drop,
+            duplicate: (duplicate < self.config.duplicate).then(Vec::new),
+            held: held < self.config.reorder,
+            last: Instant::now(),
+        }
+    }
+
+    fn schedule(&mut self, frame: Bytes) {
+        let (id, last) = match self.chunked {
+            true => chunk_header(&frame).map_or((None, true), |(id, last)| (Some(id), last)),
+            false => (None, true),
+        };
+        let mut fate = match id.and_then(|id| self.partial.remove(&id)) {
+            Some(fate) => fate,
+            None => self.fate(),
+        };
+        if !fate.drop {
+            if let Some(copies) = &mut fate.duplicate {
+                copies.push(frame.clone());
+            }
+            self.deliver(frame, &mut fate);
+        }
+        match (id, last) {
+            (Some(id), false) => {
+                self.partial.insert(id, fate);
+            }
+            // The copy follows the whole message, so that its chunks do not mix with those of the
+            // original.
+            _ => {
+                for frame in fate.duplicate.take().unwrap_or_default() {
+                    self.deliver(frame, &mut fate);
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, frame: Bytes, fate: &mut Fate) {
+        let now = Instant::now();
+        let sent = match self.config.bandwidth {
+            Some(bandwidth) => {
+                let start = max(now, self.sent);
+                self.sent = start + Duration::from_secs_f64(frame.len() as f64 / bandwidth as f64);
+                self.sent
+            }
+            None => now,
+        };
+        let mut at = sent + self.config.latency.sample(&mut self.rng);
+        if fate.held {
+            at = max(at + self.config.reorder_delay, fate.last);
+        } else {
+            at = max(at, self.in_order);
+            self.in_order = at;
+        }
+        fate.last = at;
+        self.scheduled += 1;
+        self.due.push(Reverse((at, self.scheduled, frame)));
+    }
+}
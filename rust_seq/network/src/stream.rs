@@ -0,0 +1,202 @@
+use crate::framing::Framing;
+use crate::queue::{Priority, URGENT_BURST};
+use bytes::{Buf as _, BufMut as _, Bytes, BytesMut};
+use ring::digest::{Context, SHA256};
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/stream_tests.rs"]
+pub mod stream_tests;
+
+/// The header of a chunk: the number of its message on the connection (4 bytes), and whether it is
+/// the last chunk of the message (1 byte).
+const HEADER_LEN: usize = 5;
+
+/// The number of the message a chunk belongs to, and whether it is the last chunk of it.
+pub(crate) fn chunk_header(frame: &[u8]) -> Option<(u32, bool)> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let id = u32::from_le_bytes(frame[..4].try_into().unwrap());
+    Some((id, frame[4] != 0))
+}
+
+/// The SHA-256 of a message, computed as its chunks arrived.
+pub type Digest = [u8; 32];
+
+/// A message being written, `offset` is where its next chunk starts.
+struct Stream<T> {
+    id: u32,
+    data: Bytes,
+    priority: Priority,
+    offset: usize,
+    tag: T,
+}
+
+/// The messages a connection is writing. When the framing cuts messages into chunks (see
+/// `Framing::chunk_size`), the chunks of urgent messages are written ahead of those left of bulk
+/// ones, so a large payload does not hold up the votes queued behind it. `T` tags each message,
+/// e.g. with the handler to notify once it is acknowledged.
+pub(crate) struct Outbox<T> {
+    chunk_size: Option<usize>,
+    next_id: u32,
+    /// One lane per priority class.
+    lanes: [VecDeque<Stream<T>>; 2],
+    /// The lane of the frame returned by `next_frame`, until it is `written`.
+    writing: Option<usize>,
+    /// The urgent frames written in a row while a bulk message was waiting.
+    burst: usize,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(framing: &Framing) -> Self {
+        Self {
+            chunk_size: framing.chunk_size,
+            next_id: 0,
+            lanes: [VecDeque::new(), VecDeque::new()],
+            writing: None,
+            burst: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: Bytes, priority: Priority, tag: T) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.lanes[priority.lane()].push_back(Stream {
+            id,
+            data,
+            priority,
+            offset: 0,
+            tag,
+        });
+    }
+
+    /// The next frame to write. Call `written` once it is.
+    pub fn next_frame(&mut self) -> Option<Bytes> {
+        let bulk_waits = !self.lanes[1].is_empty();
+        let urgent = !self.lanes[0].is_empty() && (self.burst < URGENT_BURST || !bulk_waits);
+        let lane = match (urgent, bulk_waits) {
+            (true, _) => 0,
+            (false, true) => 1,
+            (false, false) => return None,
+        };
+        self.writing = Some(lane);
+        let stream = self.lanes[lane].front().unwrap();
+        let chunk_size = match self.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None => return Some(stream.data.clone()),
+        };
+        let end = min(stream.offset + chunk_size, stream.data.len());
+        let mut frame = BytesMut::with_capacity(HEADER_LEN + end - stream.offset);
+        frame.put_u32_le(stream.id);
+        frame.put_u8((end == stream.data.len()) as u8);
+        frame.put_slice(&stream.data[stream.offset..end]);
+        Some(frame.freeze())
+    }
+
+    /// The frame from `next_frame` was written. It returns the message once all of it is, with its
+    /// class and tag.
+    pub fn written(&mut self) -> Option<(Bytes, Priority, T)> {
+        let lane = self.writing.take()?;
+        let bulk_waits = !self.lanes[1].is_empty();
+        self.burst = match lane {
+            0 if bulk_waits => self.burst + 1,
+            _ => 0,
+        };
+        let stream = self.lanes[lane].front_mut()?;
+        stream.offset = match self.chunk_size {
+            Some(chunk_size) => min(stream.offset + chunk_size, stream.data.len()),
+            None => stream.data.len(),
+        };
+        if stream.offset < stream.data.len() {
+            return None;
+        }
+        let stream = self.lanes[lane].pop_front()?;
+        Some((stream.data, stream.priority, stream.tag))
+    }
+
+    /// The messages not fully written, urgent first, e.g. to write them again on a new connection.
+    pub fn drain(&mut self) -> Vec<(Bytes, Priority, T)> {
+        self.writing = None;
+        self.lanes
+            .iter_mut()
+            .flat_map(|lane| lane.drain(..))
+            .map(|stream| (stream.data, stream.priority, stream.tag))
+            .collect()
+    }
+}
+
+struct Partial {
+    data: BytesMut,
+    hash: Option<Context>,
+}
+
+/// Puts the messages received on a connection back together from their chunks, and hashes them
+/// if the framing asks for it (see `Framing::hash`).
+pub(crate) struct Reassembler {
+    chunked: bool,
+    hash: bool,
+    max_length: usize,
+    max_partial: usize,
+    partials: HashMap<u32, Partial>,
+}
+
+impl Reassembler {
+    pub fn new(framing: &Framing) -> Self {
+        Self {
+            chunked: framing.chunk_size.is_some(),
+            hash: framing.hash,
+            max_length: framing.max_frame_length,
+            max_partial: framing.max_partial,
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Takes a frame, and returns the message it completes, if any. An error means the peer does
+    /// not follow the framing, the connection should be dropped.
+    pub fn push(&mut self, mut frame: BytesMut) -> Result<Option<(Bytes, Option<Digest>)>, String> {
+        if !self.chunked {
+            let digest = self.hash.then(|| {
+                let mut hash = Context::new(&SHA256);
+                hash.update(&frame);
+                finish(hash)
+            });
+            return Ok(Some((frame.freeze(), digest)));
+        }
+
+        let (id, last) = chunk_header(&frame).ok_or("truncated chunk header")?;
+        frame.advance(HEADER_LEN);
+        // A message of one chunk is never partial.
+        if !last && !self.partials.contains_key(&id) && self.partials.len() >= self.max_partial {
+            return Err(format!("more than {} partial messages", self.max_partial));
+        }
+        let hash = self.hash;
+        let partial = self.partials.entry(id).or_insert_with(|| Partial {
+            data: BytesMut::new(),
+            hash: hash.then(|| Context::new(&SHA256)),
+        });
+        if partial.data.len() + frame.len() > self.max_length {
+            return Err(format!("message longer than {} bytes", self.max_length));
+        }
+        if let Some(hash) = &mut partial.hash {
+            hash.update(&frame);
+        }
+        // The first chunk is kept as is, a message of one chunk is not copied.
+        if partial.data.is_empty() {
+            partial.data = frame;
+        } else {
+            partial.data.extend_from_slice(&frame);
+        }
+        if !last {
+            return Ok(None);
+        }
+        let partial = self.partials.remove(&id).unwrap();
+        Ok(Some((partial.data.freeze(), partial.hash.map(finish))))
+    }
+}
+
+fn finish(hash: Context) -> Digest {
+    hash.finish().as_ref().try_into().unwrap()
+}
@@ -3,15 +3,27 @@ use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::Framed;
 
-pub fn listener(address: SocketAddr, expected: String) -> JoinHandle<()> {
+use crate::{FrameCodec, Framing, MemoryNetwork, Transport as _};
+
+pub fn listener(network: &MemoryNetwork, address: SocketAddr, expected: String) -> JoinHandle<()> {
+    listener_with_codec(network, address, expected, Framing::default().codec())
+}
+
+/// Same as `listener`, but frames the connection with the given codec.
+pub fn listener_with_codec(
+    network: &MemoryNetwork,
+    address: SocketAddr,
+    expected: String,
+    codec: FrameCodec,
+) -> JoinHandle<()> {
+    let network = network.clone();
     tokio::spawn(async move {
-        let listener = TcpListener::bind(&address).await.unwrap();
+        let mut listener = network.bind(address).await.unwrap();
         let (socket, _) = listener.accept().await.unwrap();
-        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let transport = Framed::new(socket, codec);
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
@@ -0,0 +1,174 @@
+use super::*;
+use crate::common::listener_with_codec;
+use crate::{
+    Digest, MemoryNetwork, MessageHandler, Peer, Priority, Receiver, ReliableSender, SimpleSender,
+    Writer,
+};
+use ring::digest::{digest, SHA256};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+
+#[derive(Clone)]
+struct AckHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for AckHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn reliable_sender_to_receiver() {
+    // Both ends use the default framing.
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7000".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_transport(
+        address,
+        AckHandler { deliver: tx },
+        Framing::default(),
+        None,
+        network.clone(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    let mut sender = ReliableSender::new().with_transport(network);
+    let message = Bytes::from(vec![7u8; 10_000_000]);
+    let cancel_handler = sender.send(address, message.clone()).await;
+    assert_eq!(rx.recv().await, Some(message));
+    assert_eq!(cancel_handler.await.unwrap(), Bytes::from("Ack"));
+}
+
+#[tokio::test]
+async fn configured_framing() {
+    let framing = Framing {
+        little_endian: false,
+        max_frame_length: 1_000,
+        ..Framing::default()
+    };
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_transport(
+        address,
+        AckHandler { deliver: tx },
+        framing,
+        None,
+        network.clone(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    let mut sender = SimpleSender::with_framing(framing).with_transport(network.clone());
+    sender.send(address, Bytes::from("Hello, world!")).await;
+    assert_eq!(rx.recv().await, Some(Bytes::from("Hello, world!")));
+
+    // A frame over the limit is not delivered.
+    let mut sender = SimpleSender::with_framing(Framing::default()).with_transport(network);
+    sender.send(address, Bytes::from(vec![0u8; 2_000])).await;
+    assert!(timeout(Duration::from_millis(200), rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mismatched_framing() {
+    // A big-endian length prefix read as little-endian exceeds the default limit.
+    let network = MemoryNetwork::new();
+    let address = "127.0.0.1:7200".parse::<SocketAddr>().unwrap();
+    let handle = listener_with_codec(
+        &network,
+        address,
+        "Hello, world!".to_string(),
+        Framing::default().codec(),
+    );
+    let mut sender = ReliableSender::with_framing(Framing {
+        little_endian: false,
+        ..Framing::default()
+    })
+    .with_transport(Arc::new(network));
+    let _cancel_handler = sender.send(address, Bytes::from("Hello, world!")).await;
+    assert!(handle.await.is_err());
+}
+
+#[derive(Clone)]
+struct HashHandler {
+    deliver: Sender<(Bytes, Option<Digest>)>,
+}
+
+#[async_trait]
+impl MessageHandler for HashHandler {
+    async fn dispatch(
+        &self,
+        _writer: &mut Writer,
+        _peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        self.deliver.send((message, None)).await.unwrap();
+        Ok(())
+    }
+
+    async fn dispatch_hashed(
+        &self,
+        writer: &mut Writer,
+        _peer: &Peer,
+        message: Bytes,
+        digest: Digest,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send((message, Some(digest))).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn chunked_streams() {
+    let framing = Framing {
+        chunk_size: Some(64 * 1024),
+        hash: true,
+        ..Framing::default()
+    };
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7600".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(2);
+    Receiver::spawn_with_transport(
+        address,
+        HashHandler { deliver: tx },
+        framing,
+        None,
+        network.clone(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // The vote sent after a large payload makes it first, and both are acknowledged.
+    let mut sender = ReliableSender::with_framing(framing).with_transport(network);
+    let payload = Bytes::from(vec![7u8; 10_000_000]);
+    let bulk = sender.send(address, payload.clone()).await;
+    let vote = sender
+        .send_with_priority(address, Bytes::from("vote"), Priority::Urgent)
+        .await;
+    let sha256 = |data: &[u8]| digest(&SHA256, data).as_ref().to_vec();
+    let (message, hash) = rx.recv().await.unwrap();
+    assert_eq!(message, Bytes::from("vote"));
+    assert_eq!(hash.unwrap().to_vec(), sha256(b"vote"));
+    let (message, hash) = rx.recv().await.unwrap();
+    assert_eq!(message, payload);
+    assert_eq!(hash.unwrap().to_vec(), sha256(&payload));
+    assert_eq!(vote.await.unwrap(), Bytes::from("Ack"));
+    assert_eq!(bulk.await.unwrap(), Bytes::from("Ack"));
+}
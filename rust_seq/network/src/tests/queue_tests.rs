@@ -0,0 +1,110 @@
+use super::*;
+
+fn queue(capacity: usize, overflow: Overflow) -> Queue<u32> {
+    let queue = Queue::new(QueueConfig { capacity, overflow });
+    for i in 0..capacity as u32 {
+        assert!(queue.push(i, Priority::Bulk));
+    }
+    queue
+}
+
+async fn drain(queue: &Queue<u32>) -> Vec<u32> {
+    queue.close();
+    let mut items = Vec::new();
+    while let Some((item, _)) = queue.pop().await {
+        items.push(item);
+    }
+    items
+}
+
+#[tokio::test]
+async fn overflow() {
+    let oldest = queue(3, Overflow::DropOldest);
+    assert!(oldest.push(3, Priority::Bulk));
+    assert_eq!(oldest.stats(), QueueStats { depth: 3, dropped: 1 });
+    assert_eq!(drain(&oldest).await, vec![1, 2, 3]);
+
+    let newest = queue(3, Overflow::DropNewest);
+    assert!(newest.push(3, Priority::Bulk));
+    assert_eq!(newest.stats(), QueueStats { depth: 3, dropped: 1 });
+    assert_eq!(drain(&newest).await, vec![0, 1, 2]);
+
+    // The queue closes, and everything in it is dropped.
+    let disconnect = queue(3, Overflow::Disconnect);
+    assert!(!disconnect.push(3, Priority::Bulk));
+    assert_eq!(disconnect.stats(), QueueStats { depth: 0, dropped: 4 });
+    assert!(!disconnect.push(4, Priority::Bulk));
+    assert_eq!(disconnect.pop().await, None);
+}
+
+#[tokio::test]
+async fn overflow_by_class() {
+    // 0 is urgent and the oldest: dropping the oldest drops it, dropping the lowest class does not.
+    let mixed = |overflow| {
+        let queue = Queue::new(QueueConfig {
+            capacity: 3,
+            overflow,
+        });
+        queue.push(0, Priority::Urgent);
+        queue.push(1, Priority::Bulk);
+        queue.push(2, Priority::Urgent);
+        queue.push(3, Priority::Urgent);
+        queue
+    };
+    assert_eq!(drain(&mixed(Overflow::DropOldest)).await, vec![2, 3, 1]);
+    assert_eq!(drain(&mixed(Overflow::DropLowest)).await, vec![0, 2, 3]);
+}
+
+#[tokio::test]
+async fn urgent_first() {
+    let queue = Queue::new(QueueConfig::default());
+    queue.push(0, Priority::Bulk);
+    queue.push(1, Priority::Urgent);
+    assert_eq!(queue.pop().await, Some((1, Priority::Urgent)));
+    assert_eq!(queue.pop().await, Some((0, Priority::Bulk)));
+
+    // A waiting bulk message is not starved.
+    queue.push(0, Priority::Bulk);
+    for i in 1..=2 * URGENT_BURST as u32 {
+        queue.push(i, Priority::Urgent);
+    }
+    let order = drain(&queue).await;
+    assert_eq!(order[URGENT_BURST], 0);
+}
+
+#[tokio::test]
+async fn pop_waits_for_push() {
+    let queue = std::sync::Arc::new(Queue::new(QueueConfig::default()));
+    let popper = {
+        let queue = queue.clone();
+        tokio::spawn(async move { queue.pop().await })
+    };
+    tokio::task::yield_now().await;
+    queue.push(7, Priority::Urgent);
+    assert_eq!(popper.await.unwrap(), Some((7, Priority::Urgent)));
+}
+
+#[test]
+fn default_overflow_keeps_urgent_messages() {
+    let queue = Queue::new(QueueConfig {
+        capacity: 3,
+        ..QueueConfig::default()
+    });
+    queue.push(0, Priority::Urgent);
+    queue.push(1, Priority::Bulk);
+    queue.push(2, Priority::Urgent);
+    queue.push(3, Priority::Bulk);
+    queue.push(4, Priority::Urgent);
+    assert_eq!(queue.stats().dropped, 2);
+    assert_eq!(queue.pop_urgent(), Some(0));
+    assert_eq!(queue.pop_urgent(), Some(2));
+    assert_eq!(queue.pop_urgent(), Some(4));
+}
+
+#[test]
+fn overflow_from_str() {
+    assert_eq!("drop-oldest".parse::<Overflow>(), Ok(Overflow::DropOldest));
+    assert_eq!("drop-lowest".parse::<Overflow>(), Ok(Overflow::DropLowest));
+    assert_eq!("disconnect".parse::<Overflow>(), Ok(Overflow::Disconnect));
+    assert!("block".parse::<Overflow>().is_err());
+}
@@ -1,9 +1,11 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
+use crate::MemoryNetwork;
 use futures::sink::SinkExt as _;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
 use tokio::time::{sleep, Duration};
+use tokio_util::codec::LengthDelimitedCodec;
 
 #[derive(Clone)]
 struct TestHandler {
@@ -12,7 +14,12 @@ struct TestHandler {
 
 #[async_trait]
 impl MessageHandler for TestHandler {
-    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        _peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
         // Reply with an ACK.
         let _ = writer.send(Bytes::from("Ack")).await;
 
@@ -28,16 +35,26 @@ impl MessageHandler for TestHandler {
 #[tokio::test]
 async fn receive() {
     // Make the network receiver.
+    let network = Arc::new(MemoryNetwork::new());
     let address = "127.0.0.1:4000".parse::<SocketAddr>().unwrap();
     let (tx, mut rx) = channel(1);
-    Receiver::spawn(address, TestHandler { deliver: tx });
+    Receiver::spawn_with_transport(
+        address,
+        TestHandler { deliver: tx },
+        Framing::default(),
+        None,
+        network.clone(),
+    );
     sleep(Duration::from_millis(50)).await;
 
     // Send a message.
     let sent = "Hello, world!";
     let bytes = Bytes::from(bincode::serialize(sent).unwrap());
-    let stream = TcpStream::connect(address).await.unwrap();
-    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let stream = network.connect(address).await.unwrap();
+    let mut transport = LengthDelimitedCodec::builder()
+        .little_endian()
+        .max_frame_length(120_000_000)
+        .new_framed(stream);
     transport.send(bytes.clone()).await.unwrap();
 
     // Ensure the message gets passed to the channel.
@@ -46,3 +63,53 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[derive(Clone)]
+struct PeerHandler {
+    deliver: Sender<Peer>,
+}
+
+#[async_trait]
+impl MessageHandler for PeerHandler {
+    async fn dispatch(
+        &self,
+        _writer: &mut Writer,
+        peer: &Peer,
+        _message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        self.deliver.send(peer.clone()).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn peer_of_connection() {
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_transport(
+        address,
+        PeerHandler { deliver: tx },
+        Framing::default(),
+        None,
+        network.clone(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Two messages on one connection, then one on another.
+    let mut peers = Vec::new();
+    for messages in [2, 1] {
+        let stream = network.connect(address).await.unwrap();
+        let mut transport = Framing::default().framed(stream);
+        for _ in 0..messages {
+            transport.send(Bytes::from("Hello")).await.unwrap();
+            let peer = rx.recv().await.unwrap();
+            assert_eq!(peer.key, None);
+            peers.push((peer.address, peer.connection));
+        }
+    }
+    // each connection has its own address, as the OS would give it
+    assert_eq!(peers[0], peers[1]);
+    assert_ne!(peers[1].0, peers[2].0);
+    assert_ne!(peers[1].1, peers[2].1);
+}
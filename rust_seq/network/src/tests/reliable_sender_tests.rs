@@ -1,17 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::listener;
+use crate::MemoryNetwork;
 use futures::future::try_join_all;
 
 #[tokio::test]
 async fn send() {
-    // Run a TCP server.
+    // Run a server.
+    let network = MemoryNetwork::new();
     let address = "127.0.0.1:5000".parse::<SocketAddr>().unwrap();
     let message = "Hello, world!";
-    let handle = listener(address, message.to_string());
+    let handle = listener(&network, address, message.to_string());
 
     // Make the network sender and send the message.
-    let mut sender = ReliableSender::new();
+    let mut sender = ReliableSender::new().with_transport(Arc::new(network));
     let cancel_handler = sender.send(address, Bytes::from(message)).await;
 
     // Ensure we get back an acknowledgement.
@@ -23,21 +25,22 @@ async fn send() {
 
 #[tokio::test]
 async fn broadcast() {
-    // Run 3 TCP servers.
+    // Run 3 servers.
+    let network = MemoryNetwork::new();
     let message = "Hello, world!";
     let (handles, addresses): (Vec<_>, Vec<_>) = (0..3)
         .map(|x| {
             let address = format!("127.0.0.1:{}", 5_200 + x)
                 .parse::<SocketAddr>()
                 .unwrap();
-            (listener(address, message.to_string()), address)
+            (listener(&network, address, message.to_string()), address)
         })
         .collect::<Vec<_>>()
         .into_iter()
         .unzip();
 
     // Make the network sender and send the message.
-    let mut sender = ReliableSender::new();
+    let mut sender = ReliableSender::new().with_transport(Arc::new(network));
     let cancel_handlers = sender.broadcast(addresses, Bytes::from(message)).await;
 
     // Ensure we get back an acknowledgement for each message.
@@ -50,14 +53,15 @@ async fn broadcast() {
 #[tokio::test]
 async fn retry() {
     // Make the network sender and send the message  (no listeners are running).
+    let network = MemoryNetwork::new();
     let address = "127.0.0.1:5300".parse::<SocketAddr>().unwrap();
     let message = "Hello, world!";
-    let mut sender = ReliableSender::new();
+    let mut sender = ReliableSender::new().with_transport(Arc::new(network.clone()));
     let cancel_handler = sender.send(address, Bytes::from(message)).await;
 
-    // Run a TCP server.
+    // Run a server.
     sleep(Duration::from_millis(50)).await;
-    let handle = listener(address, message.to_string());
+    let handle = listener(&network, address, message.to_string());
 
     // Ensure we get back an acknowledgement.
     assert!(cancel_handler.await.is_ok());
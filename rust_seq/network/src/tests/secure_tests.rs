@@ -0,0 +1,226 @@
+use super::*;
+use crate::{
+    Framing, MemoryNetwork, MessageHandler, Peer, Receiver, ReliableSender, SimpleSender, Writer,
+};
+use async_trait::async_trait;
+use ring::rand::SystemRandom;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::sleep;
+
+#[derive(Clone)]
+struct KeyHandler {
+    deliver: Sender<(Option<PeerKey>, Bytes)>,
+}
+
+#[async_trait]
+impl MessageHandler for KeyHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver
+            .send((peer.key.clone(), message))
+            .await
+            .unwrap();
+        Ok(())
+    }
+}
+
+fn config() -> SecureConfig {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    SecureConfig::from_pkcs8(pkcs8.as_ref()).unwrap()
+}
+
+/// A secure receiver at `address` of `network`, handing what it gets to `deliver`.
+fn spawn_receiver(
+    network: &Arc<MemoryNetwork>,
+    address: SocketAddr,
+    deliver: Sender<(Option<PeerKey>, Bytes)>,
+    config: SecureConfig,
+) {
+    Receiver::spawn_with_transport(
+        address,
+        KeyHandler { deliver },
+        Framing::default(),
+        Some(config),
+        network.clone(),
+    );
+}
+
+#[tokio::test]
+async fn secure_send() {
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7300".parse::<SocketAddr>().unwrap();
+    let (receiver, sender) = (config(), config());
+    let (tx, mut rx) = channel(1);
+    spawn_receiver(&network, address, tx, receiver);
+    sleep(Duration::from_millis(50)).await;
+
+    // The handler learns who sent the message, and the ACK makes it back through the link.
+    let mut reliable = ReliableSender::new()
+        .with_secure(sender.clone())
+        .with_transport(network.clone());
+    let cancel_handler = reliable.send(address, Bytes::from("Hello")).await;
+    let expected = (Some(sender.public_key().to_vec()), Bytes::from("Hello"));
+    assert_eq!(rx.recv().await, Some(expected));
+    assert_eq!(cancel_handler.await.unwrap(), Bytes::from("Ack"));
+
+    let mut simple = SimpleSender::new()
+        .with_secure(sender.clone())
+        .with_transport(network);
+    simple
+        .send(address, Bytes::from(vec![7u8; 1_000_000]))
+        .await;
+    let (key, message) = rx.recv().await.unwrap();
+    assert_eq!(key.as_deref(), Some(sender.public_key()));
+    assert_eq!(message, Bytes::from(vec![7u8; 1_000_000]));
+}
+
+#[tokio::test]
+async fn reject_unauthenticated() {
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7400".parse::<SocketAddr>().unwrap();
+    let (receiver, allowed, other) = (config(), config(), config());
+    let allowed_key = allowed.public_key().to_vec();
+    let (tx, mut rx) = channel(1);
+    spawn_receiver(
+        &network,
+        address,
+        tx,
+        receiver.with_authorize(move |key| key == allowed_key.as_slice()),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // A plain connection, and a key the receiver does not accept.
+    let mut plain = SimpleSender::new().with_transport(network.clone());
+    plain.send(address, Bytes::from("plain")).await;
+    let mut unauthorized = SimpleSender::new()
+        .with_secure(other)
+        .with_transport(network.clone());
+    unauthorized
+        .send(address, Bytes::from("unauthorized"))
+        .await;
+    assert!(timeout(Duration::from_millis(300), rx.recv())
+        .await
+        .is_err());
+
+    let mut sender = SimpleSender::new()
+        .with_secure(allowed)
+        .with_transport(network);
+    sender.send(address, Bytes::from("allowed")).await;
+    assert_eq!(rx.recv().await.unwrap().1, Bytes::from("allowed"));
+}
+
+#[tokio::test]
+async fn reject_unexpected_receiver() {
+    let network = Arc::new(MemoryNetwork::new());
+    let address = "127.0.0.1:7500".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    spawn_receiver(&network, address, tx, config());
+    sleep(Duration::from_millis(50)).await;
+
+    // The sender only talks to a key the receiver does not hold.
+    let expected = config().public_key().to_vec();
+    let mut sender = SimpleSender::new()
+        .with_secure(config().with_authorize(move |key| key == expected.as_slice()))
+        .with_transport(network);
+    sender.send(address, Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_millis(300), rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn reject_impersonating_receiver() {
+    let network = Arc::new(MemoryNetwork::new());
+    let (first, second) = (
+        "127.0.0.1:7600".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:7601".parse::<SocketAddr>().unwrap(),
+    );
+    let (first_config, second_config) = (config(), config());
+    let (tx, mut rx) = channel(1);
+    for (address, config) in [(first, first_config.clone()), (second, second_config.clone())] {
+        spawn_receiver(&network, address, tx.clone(), config);
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // Both keys are accepted, but the second one answers at the address of the first.
+    let keys = [
+        first_config.public_key().to_vec(),
+        second_config.public_key().to_vec(),
+    ];
+    let peer_keys = HashMap::from([(first, keys[1].clone()), (second, keys[1].clone())]);
+    let sender = config()
+        .with_authorize(move |key| keys.iter().any(|k| k == key))
+        .with_peer_keys(peer_keys);
+    let mut sender = SimpleSender::new()
+        .with_secure(sender)
+        .with_transport(network);
+    sender.send(first, Bytes::from("impersonated")).await;
+    assert!(timeout(Duration::from_millis(300), rx.recv())
+        .await
+        .is_err());
+
+    sender.send(second, Bytes::from("Hello")).await;
+    assert_eq!(rx.recv().await.unwrap().1, Bytes::from("Hello"));
+}
+
+/// Frame ciphers of both ends of a connection, after a handshake without payloads.
+fn ciphers() -> (Cipher, Cipher) {
+    let (mut initiator, mut responder) = (
+        config().handshake_state(true).unwrap(),
+        config().handshake_state(false).unwrap(),
+    );
+    let (mut message, mut payload) = (vec![0; NOISE_MSG_LEN], vec![0; NOISE_MSG_LEN]);
+    for i in 0..3 {
+        let (from, to) = if i == 1 {
+            (&mut responder, &mut initiator)
+        } else {
+            (&mut initiator, &mut responder)
+        };
+        let len = from.write_message(&[], &mut message).unwrap();
+        to.read_message(&message[..len], &mut payload).unwrap();
+    }
+    (
+        Cipher::new(initiator.into_transport_mode().unwrap()),
+        Cipher::new(responder.into_transport_mode().unwrap()),
+    )
+}
+
+#[test]
+fn frames_are_numbered() {
+    let (mut initiator, mut responder) = ciphers();
+    let first = initiator.seal(Bytes::from("first")).unwrap();
+    assert_ne!(&first[..], b"first");
+    assert_eq!(
+        &responder.open(BytesMut::from(&first[..])).unwrap()[..],
+        b"first"
+    );
+    // A replayed frame does not open.
+    assert!(responder.open(BytesMut::from(&first[..])).is_err());
+
+    // Nor does a tampered one.
+    let (mut initiator, mut responder) = ciphers();
+    let mut tampered = BytesMut::from(&initiator.seal(Bytes::from("first")).unwrap()[..]);
+    tampered[0] ^= 1;
+    assert!(responder.open(tampered).is_err());
+}
+
+#[test]
+fn frames_longer_than_a_noise_message() {
+    let (mut initiator, mut responder) = ciphers();
+    for frame in [vec![], vec![7u8; NOISE_MSG_LEN - TAG_LEN], vec![7u8; 200_000]] {
+        let sealed = initiator.seal(Bytes::from(frame.clone())).unwrap();
+        assert!(!sealed.is_empty());
+        assert_eq!(&responder.open(BytesMut::from(&sealed[..])).unwrap()[..], &frame[..]);
+    }
+    // a frame cut short of its last message does not open
+    let sealed = initiator.seal(Bytes::from(vec![7u8; 200_000])).unwrap();
+    assert!(responder
+        .open(BytesMut::from(&sealed[..sealed.len() - 1]))
+        .is_err());
+}
@@ -1,17 +1,21 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::listener;
+use crate::common::{listener, listener_with_codec};
+use crate::{MemoryNetwork, Overflow, QueueConfig};
 use futures::future::try_join_all;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn simple_send() {
-    // Run a TCP server.
+    // Run a server.
+    let network = MemoryNetwork::new();
     let address = "127.0.0.1:6100".parse::<SocketAddr>().unwrap();
     let message = "Hello, world!";
-    let handle = listener(address, message.to_string());
+    let handle = listener(&network, address, message.to_string());
 
     // Make the network sender and send the message.
-    let mut sender = SimpleSender::new();
+    let mut sender = SimpleSender::new().with_transport(Arc::new(network));
     sender.send(address, Bytes::from(message)).await;
 
     // Ensure the server received the message (ie. it did not panic).
@@ -20,23 +24,125 @@ async fn simple_send() {
 
 #[tokio::test]
 async fn broadcast() {
-    // Run 3 TCP servers.
+    // Run 3 servers.
+    let network = MemoryNetwork::new();
     let message = "Hello, world!";
     let (handles, addresses): (Vec<_>, Vec<_>) = (0..3)
         .map(|x| {
             let address = format!("127.0.0.1:{}", 6_200 + x)
                 .parse::<SocketAddr>()
                 .unwrap();
-            (listener(address, message.to_string()), address)
+            (listener(&network, address, message.to_string()), address)
         })
         .collect::<Vec<_>>()
         .into_iter()
         .unzip();
 
     // Make the network sender and send the message.
-    let mut sender = SimpleSender::new();
+    let mut sender = SimpleSender::new().with_transport(Arc::new(network));
     sender.broadcast(addresses, Bytes::from(message)).await;
 
     // Ensure all servers received the broadcast.
     assert!(try_join_all(handles).await.is_ok());
 }
+
+/// Accept one connection at `address`, read `count` messages and close it.
+async fn accept_and_read(network: &MemoryNetwork, address: SocketAddr, count: usize) -> Vec<Bytes> {
+    let mut listener = network.bind(address).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let (_, mut reader) = Framing::default().framed(socket).split();
+    let mut received = Vec::new();
+    for _ in 0..count {
+        received.push(reader.next().await.unwrap().unwrap().freeze());
+    }
+    received
+}
+
+#[tokio::test]
+async fn reconnect_replays_hello() {
+    let network = MemoryNetwork::new();
+    let address = "127.0.0.1:6300".parse::<SocketAddr>().unwrap();
+    let (tx_events, mut rx_events) = channel(10);
+    let mut sender = SimpleSender::new()
+        .with_events(tx_events)
+        .with_transport(Arc::new(network.clone()));
+
+    // The peer is not up yet: the message waits for it, behind the hello.
+    sender.init(Bytes::from("Hello"), vec![address]).await;
+    sender.send(address, Bytes::from("1")).await;
+    let received = accept_and_read(&network, address, 2).await;
+    assert_eq!(received, vec![Bytes::from("Hello"), Bytes::from("1")]);
+    assert_eq!(
+        rx_events.recv().await,
+        Some(ConnectionEvent::Connected(address))
+    );
+    assert_eq!(
+        rx_events.recv().await,
+        Some(ConnectionEvent::Disconnected(address))
+    );
+
+    // The peer went down: we reconnect once it is back, and greet it again.
+    sender.send(address, Bytes::from("2")).await;
+    let received = accept_and_read(&network, address, 2).await;
+    assert_eq!(received, vec![Bytes::from("Hello"), Bytes::from("2")]);
+    assert_eq!(
+        rx_events.recv().await,
+        Some(ConnectionEvent::Connected(address))
+    );
+}
+
+#[tokio::test]
+async fn slow_peer_does_not_block() {
+    // A peer that accepts the connection but never reads from it, and one that does.
+    let network = MemoryNetwork::new();
+    let slow = "127.0.0.1:6400".parse::<SocketAddr>().unwrap();
+    let mut listener = network.bind(slow).await.unwrap();
+    let stalled = tokio::spawn(async move { listener.accept().await.unwrap() });
+    let fast = "127.0.0.1:6401".parse::<SocketAddr>().unwrap();
+    let handle = listener_with_codec(&network, fast, "Hello".to_string(), Framing::default().codec());
+
+    // Fill the link buffers of the slow peer, then its queue.
+    let queues = QueueConfig {
+        capacity: 4,
+        overflow: Overflow::DropOldest,
+    };
+    let mut sender = SimpleSender::new()
+        .with_queues(queues)
+        .with_transport(Arc::new(network));
+    let data = Bytes::from(vec![0u8; 1_000_000]);
+    for _ in 0..100 {
+        sender.send(slow, data.clone()).await;
+        sleep(Duration::from_millis(5)).await;
+    }
+    let stats = sender.queue_stats()[&slow];
+    assert_eq!(stats.depth, 4);
+    assert!(stats.dropped > 0);
+
+    // The fast peer still gets its message right away.
+    sender.broadcast(vec![slow, fast], Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_secs(1), handle).await.is_ok());
+    drop(stalled);
+}
+
+#[tokio::test]
+async fn disconnect_on_overflow() {
+    // Nobody listens: the messages pile up until the queue gives up on the peer.
+    let address = "127.0.0.1:6500".parse::<SocketAddr>().unwrap();
+    let queues = QueueConfig {
+        capacity: 2,
+        overflow: Overflow::Disconnect,
+    };
+    let mut sender = SimpleSender::new()
+        .with_queues(queues)
+        .with_transport(Arc::new(MemoryNetwork::new()));
+    for i in 0..2 {
+        sender.send(address, Bytes::from(format!("{}", i))).await;
+    }
+    assert_eq!(sender.queue_stats()[&address].depth, 2);
+    sender.send(address, Bytes::from("2")).await;
+    assert!(!sender.queue_stats().contains_key(&address));
+
+    // The next message opens a new connection.
+    sender.send(address, Bytes::from("3")).await;
+    assert_eq!(sender.queue_stats()[&address].depth, 1);
+}
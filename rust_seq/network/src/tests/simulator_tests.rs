@@ -0,0 +1,177 @@
+use super::*;
+use crate::{MessageHandler, Peer, Receiver, SimpleSender, Writer};
+use std::error::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::sleep;
+
+/// What the receiver got: the number of the connection, the message, and when.
+type Received = (u64, Bytes, Instant);
+
+#[derive(Clone)]
+struct RecordHandler {
+    deliver: UnboundedSender<Received>,
+}
+
+#[async_trait]
+impl MessageHandler for RecordHandler {
+    async fn dispatch(
+        &self,
+        _writer: &mut Writer,
+        peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = self
+            .deliver
+            .send((peer.connection, message, Instant::now()));
+        Ok(())
+    }
+}
+
+fn address(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+/// Runs a receiver on `to` and returns a sender on `from`, both over `network`.
+async fn link(
+    network: &SimNetwork,
+    from: SocketAddr,
+    to: SocketAddr,
+) -> (SimpleSender, UnboundedReceiver<Received>) {
+    let (tx, rx) = unbounded_channel();
+    let framing = network.framing;
+    Receiver::spawn_with_transport(
+        to,
+        RecordHandler { deliver: tx },
+        framing,
+        None,
+        network.node(to),
+    );
+    sleep(Duration::from_millis(1)).await;
+    let sender = SimpleSender::with_framing(framing).with_transport(network.node(from));
+    (sender, rx)
+}
+
+fn received(rx: &mut UnboundedReceiver<Received>) -> Vec<Received> {
+    let mut received = Vec::new();
+    while let Ok(message) = rx.try_recv() {
+        received.push(message);
+    }
+    received
+}
+
+fn lossy() -> LinkConfig {
+    LinkConfig {
+        latency: Latency::Uniform(Duration::from_millis(1), Duration::from_millis(5)),
+        drop: 0.2,
+        duplicate: 0.2,
+        reorder: 0.2,
+        ..LinkConfig::default()
+    }
+}
+
+/// The numbers sent from 0 to 1 over a lossy network, in the order they arrive.
+async fn lossy_run(seed: u64) -> Vec<u32> {
+    let network = SimNetwork::new(seed).with_default(lossy());
+    let (mut sender, mut rx) = link(&network, address(0), address(1)).await;
+    for i in 0..100u32 {
+        sender
+            .send(address(1), Bytes::from(i.to_le_bytes().to_vec()))
+            .await;
+    }
+    sleep(Duration::from_secs(1)).await;
+    received(&mut rx)
+        .into_iter()
+        .map(|(_, message, _)| u32::from_le_bytes(message[..].try_into().unwrap()))
+        .collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn faults_replay_with_the_seed() {
+    let run = lossy_run(7).await;
+    assert_eq!(run, lossy_run(7).await);
+    assert_ne!(run, lossy_run(8).await);
+
+    // Some messages are lost, some duplicated, some overtaken.
+    let mut distinct = run.clone();
+    distinct.sort_unstable();
+    distinct.dedup();
+    assert!(distinct.len() < 100);
+    assert!(distinct.len() < run.len());
+    assert!(run.windows(2).any(|pair| pair[0] > pair[1]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn chunked_messages_stay_whole() {
+    let framing = Framing {
+        chunk_size: Some(1_000),
+        ..Framing::default()
+    };
+    let network = SimNetwork::new(1)
+        .with_framing(framing)
+        .with_default(lossy());
+    let (mut sender, mut rx) = link(&network, address(0), address(1)).await;
+    for i in 0..50u8 {
+        sender.send(address(1), Bytes::from(vec![i; 5_000])).await;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    // The faults never break a message, nor the connection.
+    let received = received(&mut rx);
+    assert!(received.len() > 10);
+    for (connection, message, _) in received {
+        assert_eq!(connection, 1);
+        assert_eq!(message.len(), 5_000);
+        assert!(message.iter().all(|byte| *byte == message[0]));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_and_latency() {
+    let network = SimNetwork::new(0).with_default(LinkConfig {
+        latency: Latency::Fixed(Duration::from_millis(50)),
+        bandwidth: Some(1_000_000),
+        ..LinkConfig::default()
+    });
+    let (mut sender, mut rx) = link(&network, address(0), address(1)).await;
+    let start = Instant::now();
+    sender.send(address(1), Bytes::from(vec![0; 100_000])).await;
+    sender.send(address(1), Bytes::from("after")).await;
+    sleep(Duration::from_secs(1)).await;
+
+    // 100 ms to send the first message, then 50 ms to cross the link. The second one waits.
+    let arrivals: Vec<_> = received(&mut rx)
+        .into_iter()
+        .map(|(_, _, at)| at - start)
+        .collect();
+    assert_eq!(arrivals.len(), 2);
+    assert!(arrivals[0] >= Duration::from_millis(150));
+    assert!(arrivals[0] < Duration::from_millis(155));
+    assert!(arrivals[1] > arrivals[0]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn partitions_heal() {
+    let network = SimNetwork::new(0).with_partition(Partition {
+        start: Duration::from_millis(100),
+        end: Duration::from_millis(300),
+        side: vec![address(1)],
+    });
+    let start = network.start;
+    let (mut sender, mut rx) = link(&network, address(0), address(1)).await;
+    for i in 0..60u8 {
+        sender.send(address(1), Bytes::from(vec![i])).await;
+        sleep(Duration::from_millis(10)).await;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    // Nothing crosses the partition. Once it heals, the sender reconnects and delivers the
+    // messages it queued meanwhile.
+    let received = received(&mut rx);
+    let arrivals: Vec<_> = received.iter().map(|(_, _, at)| *at - start).collect();
+    assert!(arrivals.iter().any(|at| *at < Duration::from_millis(100)));
+    assert!(arrivals
+        .iter()
+        .all(|at| *at <= Duration::from_millis(100) || *at >= Duration::from_millis(300)));
+    let (connection, last, _) = received.last().unwrap();
+    assert_eq!((*connection, &last[..]), (2, &[59][..]));
+}
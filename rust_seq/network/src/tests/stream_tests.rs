@@ -0,0 +1,114 @@
+use super::*;
+use ring::digest::digest;
+
+fn chunked(chunk_size: usize) -> Framing {
+    Framing {
+        chunk_size: Some(chunk_size),
+        hash: true,
+        ..Framing::default()
+    }
+}
+
+/// Writes all the frames of `outbox`, and returns the messages `reassembler` puts back together.
+fn transfer<T>(outbox: &mut Outbox<T>, reassembler: &mut Reassembler) -> Vec<(Bytes, Digest)> {
+    let mut received = Vec::new();
+    while let Some(frame) = outbox.next_frame() {
+        outbox.written();
+        if let Some((message, digest)) = reassembler.push(BytesMut::from(&frame[..])).unwrap() {
+            received.push((message, digest.unwrap()));
+        }
+    }
+    received
+}
+
+fn sha256(data: &[u8]) -> Digest {
+    digest(&SHA256, data).as_ref().try_into().unwrap()
+}
+
+#[test]
+fn chunks_interleave() {
+    let framing = chunked(1_000);
+    let (mut outbox, mut reassembler) = (Outbox::new(&framing), Reassembler::new(&framing));
+    let bulk = Bytes::from((0..10_000).map(|i| i as u8).collect::<Vec<_>>());
+    outbox.push(bulk.clone(), Priority::Bulk, ());
+
+    // The vote queued once the payload is under way gets ahead of the chunks left of it.
+    let frame = outbox.next_frame().unwrap();
+    assert_eq!(frame.len(), HEADER_LEN + 1_000);
+    assert!(outbox.written().is_none());
+    assert!(reassembler.push(BytesMut::from(&frame[..])).unwrap().is_none());
+    outbox.push(Bytes::from("vote"), Priority::Urgent, ());
+    let received = transfer(&mut outbox, &mut reassembler);
+    assert_eq!(
+        received,
+        vec![
+            (Bytes::from("vote"), sha256(b"vote")),
+            (bulk.clone(), sha256(&bulk))
+        ]
+    );
+}
+
+#[test]
+fn unchunked_frames_are_messages() {
+    let framing = Framing::default();
+    let mut outbox = Outbox::new(&framing);
+    outbox.push(Bytes::from("Hello"), Priority::Bulk, 7);
+    assert_eq!(outbox.next_frame(), Some(Bytes::from("Hello")));
+    assert_eq!(
+        outbox.written(),
+        Some((Bytes::from("Hello"), Priority::Bulk, 7))
+    );
+    let mut reassembler = Reassembler::new(&framing);
+    assert_eq!(
+        reassembler.push(BytesMut::from("Hello")).unwrap(),
+        Some((Bytes::from("Hello"), None))
+    );
+}
+
+#[test]
+fn unfinished_messages_are_drained() {
+    let mut outbox = Outbox::new(&chunked(2));
+    outbox.push(Bytes::from("bulk"), Priority::Bulk, 0);
+    outbox.push(Bytes::from("vote"), Priority::Urgent, 1);
+    outbox.next_frame();
+    outbox.written();
+    assert_eq!(
+        outbox.drain(),
+        vec![
+            (Bytes::from("vote"), Priority::Urgent, 1),
+            (Bytes::from("bulk"), Priority::Bulk, 0)
+        ]
+    );
+    assert!(outbox.next_frame().is_none());
+}
+
+#[test]
+fn reassembly_limits() {
+    let frame = |id: u32, last: bool, body: &[u8]| {
+        let mut frame = BytesMut::new();
+        frame.put_u32_le(id);
+        frame.put_u8(last as u8);
+        frame.put_slice(body);
+        frame
+    };
+    let framing = Framing {
+        max_frame_length: 10,
+        max_partial: 2,
+        ..chunked(4)
+    };
+
+    // Two messages may be partial at once, a third may not. Complete ones always get through.
+    let mut reassembler = Reassembler::new(&framing);
+    assert!(reassembler.push(frame(0, false, b"ab")).unwrap().is_none());
+    assert!(reassembler.push(frame(1, false, b"ab")).unwrap().is_none());
+    assert!(reassembler.push(frame(3, true, b"ab")).unwrap().is_some());
+    assert!(reassembler.push(frame(2, false, b"ab")).is_err());
+
+    // Nor may a message grow past the largest frame.
+    let mut reassembler = Reassembler::new(&framing);
+    for _ in 0..2 {
+        assert!(reassembler.push(frame(0, false, b"abcd")).unwrap().is_none());
+    }
+    assert!(reassembler.push(frame(0, true, b"abcd")).is_err());
+    assert!(reassembler.push(BytesMut::from("ab")).is_err());
+}
@@ -0,0 +1,93 @@
+use super::*;
+use crate::{MessageHandler, Peer, Receiver, ReliableSender, SimpleSender, Writer};
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+
+#[derive(Clone)]
+struct AckHandler {
+    deliver: Sender<(SocketAddr, Bytes)>,
+}
+
+#[async_trait]
+impl MessageHandler for AckHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        peer: &Peer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send((peer.address, message)).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn memory_committee() {
+    // Three nodes listen on the same port of different hosts, which only a network of their own
+    // allows.
+    let network = MemoryNetwork::new();
+    let addresses: Vec<SocketAddr> = (1..=3)
+        .map(|i| format!("10.0.0.{}:8080", i).parse().unwrap())
+        .collect();
+    let (tx, mut rx) = channel(10);
+    for address in &addresses {
+        Receiver::spawn_with_transport(
+            *address,
+            AckHandler {
+                deliver: tx.clone(),
+            },
+            Default::default(),
+            None,
+            Arc::new(network.clone()),
+        );
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    let mut sender = ReliableSender::new().with_transport(Arc::new(network.clone()));
+    let handlers = sender
+        .broadcast(addresses.clone(), Bytes::from("Hello, world!"))
+        .await;
+    for handler in handlers {
+        assert_eq!(handler.await.unwrap(), Bytes::from("Ack"));
+    }
+    let mut senders = Vec::new();
+    for _ in &addresses {
+        let (from, message) = rx.recv().await.unwrap();
+        assert_eq!(message, Bytes::from("Hello, world!"));
+        senders.push(from);
+    }
+    // Each link gets its own address.
+    senders.sort();
+    senders.dedup();
+    assert_eq!(senders.len(), addresses.len());
+
+    // Nothing listens on the host.
+    let mut sender = SimpleSender::new();
+    sender.send(addresses[0], Bytes::from("lost")).await;
+    assert!(timeout(Duration::from_millis(200), rx.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn memory_listeners() {
+    let network = MemoryNetwork::new();
+    let address: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+    let refused = network.connect(address).await.err().unwrap();
+    assert_eq!(refused.kind(), io::ErrorKind::ConnectionRefused);
+
+    // A listener on the unspecified address takes the connections to its port.
+    let mut listener = network.bind("0.0.0.0:9000".parse().unwrap()).await.unwrap();
+    let in_use = network.bind("0.0.0.0:9000".parse().unwrap()).await.err().unwrap();
+    assert_eq!(in_use.kind(), io::ErrorKind::AddrInUse);
+    let _link = network.connect(address).await.unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+    // The address is free once its listener is dropped.
+    drop(listener);
+    assert!(network.connect(address).await.is_err());
+    assert!(network.bind(address).await.is_ok());
+}
@@ -0,0 +1,168 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+#[cfg(test)]
+#[path = "tests/transport_tests.rs"]
+pub mod transport_tests;
+
+/// How many bytes an in-memory link buffers in each direction before its writer waits, much like
+/// the socket buffers of a TCP connection.
+pub const MEMORY_BUFFER: usize = 256 * 1024;
+
+/// The first port handed to the connecting end of an in-memory link.
+const FIRST_EPHEMERAL_PORT: u16 = 49_152;
+
+/// A byte stream between two nodes, on which messages are framed (see `Framing`).
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+/// A connection opened or accepted by a `Transport`.
+pub type Link = Box<dyn Io>;
+
+/// Opens connections to peers and accepts theirs. The receiver and the senders of the crate run
+/// over TCP by default (see `Tcp`), or over any other transport they are given, e.g. a
+/// `MemoryNetwork` to run several nodes in one process without sockets.
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Open a connection to the node listening on `address`.
+    async fn connect(&self, address: SocketAddr) -> io::Result<Link>;
+
+    /// Listen on `address` for the connections of peers.
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>>;
+}
+
+/// Accepts the connections made to the address it is bound to.
+#[async_trait]
+pub trait Listener: Send {
+    /// The next connection, and the address of the peer that opened it.
+    async fn accept(&mut self) -> io::Result<(Link, SocketAddr)>;
+}
+
+/// Plain TCP sockets.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tcp;
+
+#[async_trait]
+impl Transport for Tcp {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Link> {
+        Ok(Box::new(TcpStream::connect(address).await?))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(address).await?))
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    async fn accept(&mut self) -> io::Result<(Link, SocketAddr)> {
+        let (socket, peer) = TcpListener::accept(self).await?;
+        Ok((Box::new(socket), peer))
+    }
+}
+
+/// The connections waiting to be accepted by a listener.
+type Backlog = UnboundedSender<(Link, SocketAddr)>;
+
+#[derive(Default)]
+struct Listeners {
+    bound: HashMap<SocketAddr, Backlog>,
+    next_port: u16,
+}
+
+impl Listeners {
+    /// The listener a connection to `address` reaches: one bound to that very address, or to the
+    /// unspecified address with the same port, as with TCP.
+    fn backlog(&self, address: SocketAddr) -> Option<&Backlog> {
+        let unspecified = match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        self.bound
+            .get(&address)
+            .or_else(|| self.bound.get(&SocketAddr::new(unspecified, address.port())))
+    }
+
+    /// An address for the connecting end of a link, as the OS picks an ephemeral port.
+    fn ephemeral(&mut self) -> SocketAddr {
+        let port = FIRST_EPHEMERAL_PORT.wrapping_add(self.next_port);
+        self.next_port = self.next_port.wrapping_add(1) % (u16::MAX - FIRST_EPHEMERAL_PORT);
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+}
+
+/// A network of in-process links. Nodes sharing one (it is cheap to clone) reach each other by
+/// address as they would over TCP, but every connection is a pair of in-memory pipes: a whole
+/// committee can run in one test process, and nothing is bound on the host.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<Listeners>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryNetwork {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Link> {
+        let mut listeners = self.listeners.lock().unwrap();
+        let local = listeners.ephemeral();
+        let refused = || io::Error::new(io::ErrorKind::ConnectionRefused, address.to_string());
+        let backlog = listeners.backlog(address).ok_or_else(refused)?;
+        let (ours, theirs) = duplex(MEMORY_BUFFER);
+        backlog
+            .send((Box::new(theirs), local))
+            .map_err(|_| refused())?;
+        Ok(Box::new(ours))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.bound.contains_key(&address) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                address.to_string(),
+            ));
+        }
+        let (backlog, incoming) = unbounded_channel();
+        listeners.bound.insert(address, backlog);
+        Ok(Box::new(MemoryListener {
+            address,
+            listeners: self.listeners.clone(),
+            incoming,
+        }))
+    }
+}
+
+/// Frees its address once dropped, so that another listener may bind it.
+struct MemoryListener {
+    address: SocketAddr,
+    listeners: Arc<Mutex<Listeners>>,
+    incoming: UnboundedReceiver<(Link, SocketAddr)>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> io::Result<(Link, SocketAddr)> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionAborted))
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.listeners.lock().unwrap().bound.remove(&self.address);
+    }
+}
@@ -0,0 +1,485 @@
+#![allow(clippy::too_many_arguments)]
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::{Mutex as tk_mutex, RwLock as tk_rwlock};
+use tokio::time as tk_time;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use message::Message;
+use ring::digest;
+
+use crate::committee::Committee;
+use crate::crypto_pool::CryptoPool;
+use das::merkle::{self, MerkleTree};
+use das::{DAS_CHUNKS, DAS_DATA_CHUNKS};
+use network::Priority;
+use crate::sequencer::{CastType, CertList, U8Arr, set_round, sign_echo};
+use crate::signature::{BlsKeyPair, KeyPair};
+use crate::store::{Record, Store};
+
+#[cfg(test)]
+#[path = "tests/avid_tests.rs"]
+pub mod avid_tests;
+
+pub(crate) type ChunkList = Vec<tk_mutex<Vec<Chunks>>>;
+
+/// How long a delivered batch may wait to be decoded before every member is asked for its chunk.
+pub const CHUNK_RETRY: tk_time::Duration = tk_time::Duration::from_secs(2);
+
+/*
+* How a batch reaches the committee. With Full, the originator sends the whole
+* payload to every member, n times the payload of upload. With Avid, it encodes
+* the payload into one chunk per member, any f+1 of which decode it, and sends
+* each member only its chunk. A member that got its chunk asks f others for
+* theirs, the f+1 chunks it needs, so that a batch costs about (n+2)|payload|
+* across the committee, against the (n-1)|payload| of Full, but only
+* n/(f+1)|payload| of it is the originator's upload. Echoes sign the Merkle
+* root of the chunks rather than the payload digest (see batch_digest).
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dispersal {
+    #[default]
+    Full,
+    Avid,
+}
+
+impl FromStr for Dispersal {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Dispersal::Full),
+            "avid" => Ok(Dispersal::Avid),
+            _ => Err(format!("unknown dispersal mode {}", s)),
+        }
+    }
+}
+
+/* the chunks needed to decode a batch coded into `num_chunks`: f+1 */
+pub fn data_chunks(num_chunks:usize) -> usize {
+    (num_chunks - 1) / 3 + 1
+}
+
+/*
+* Reed-Solomon codes the payload, prefixed with its length, into `num_chunks`
+* chunks of equal size.
+*/
+pub fn encode(payload:&[u8], num_chunks:usize) -> Vec<U8Arr> {
+    encode_with(payload, data_chunks(num_chunks), num_chunks)
+}
+
+/* the same, with any `data_chunks` of the chunks decoding the payload */
+pub fn encode_with(payload:&[u8], data_chunks:usize, num_chunks:usize) -> Vec<U8Arr> {
+    let mut data = (payload.len() as u64).to_le_bytes().to_vec();
+    data.extend_from_slice(payload);
+    let chunk_len = data.len().div_ceil(data_chunks);
+    data.resize(chunk_len * data_chunks, 0);
+
+    let mut chunks: Vec<U8Arr> = data.chunks(chunk_len).map(|chunk| chunk.to_vec()).collect();
+    chunks.resize(num_chunks, vec![0; chunk_len]);
+    if num_chunks > data_chunks {
+        ReedSolomon::new(data_chunks, num_chunks - data_chunks)
+            .and_then(|coder| coder.encode(&mut chunks))
+            .expect("failed to encode payload");
+    }
+    chunks
+}
+
+/* the payload back from any f+1 chunks, None if they do not decode */
+pub fn decode(chunks:Vec<Option<U8Arr>>) -> Option<U8Arr> {
+    let data_chunks = data_chunks(chunks.len());
+    decode_with(chunks, data_chunks)
+}
+
+/* the payload back from any `data_chunks` chunks coded by encode_with */
+pub fn decode_with(mut chunks:Vec<Option<U8Arr>>, data_chunks:usize) -> Option<U8Arr> {
+    let num_chunks = chunks.len();
+    if num_chunks > data_chunks {
+        ReedSolomon::new(data_chunks, num_chunks - data_chunks)
+            .and_then(|coder| coder.reconstruct_data(&mut chunks))
+            .ok()?;
+    }
+    let data: U8Arr = chunks[..data_chunks].iter()
+        .map(|chunk| chunk.as_deref())
+        .collect::<Option<Vec<&[u8]>>>()?
+        .concat();
+    let len = u64::from_le_bytes(data.get(..8)?.try_into().ok()?) as usize;
+    data.get(8..8usize.checked_add(len)?).map(|payload| payload.to_vec())
+}
+
+/* the root the chunks are committed to, and each chunk with its proof */
+pub fn disperse(payload:&[u8], num_chunks:usize) -> (U8Arr, Vec<(U8Arr, Vec<U8Arr>)>) {
+    let chunks = encode(payload, num_chunks);
+    let tree = MerkleTree::new(&chunks);
+    let chunks = chunks.into_iter()
+        .enumerate()
+        .map(|(i, chunk)| (chunk, tree.proof(i)))
+        .collect();
+    (tree.root(), chunks)
+}
+
+/*
+* Decodes the payload, then encodes it again: a faulty originator may have
+* committed to chunks that are not a codeword, which different sets of f+1
+* chunks would decode differently. Either every set decodes to the same
+* payload, or the batch is rejected by everyone. The payload is checked
+* against the batch digest, so its sampling root is checked the same way.
+*/
+pub fn decode_checked(chunks:Vec<Option<U8Arr>>, digest:&[u8]) -> Option<U8Arr> {
+    let num_chunks = chunks.len();
+    let payload = decode(chunks)?;
+    (batch_digest(Dispersal::Avid, num_chunks, &payload) == digest).then_some(payload)
+}
+
+/* the chunks a batch is coded into for sampling by light clients */
+pub fn das_chunks(payload:&[u8]) -> Vec<U8Arr> {
+    encode_with(payload, DAS_DATA_CHUNKS, DAS_CHUNKS)
+}
+
+pub fn das_root(payload:&[u8]) -> U8Arr {
+    MerkleTree::new(&das_chunks(payload)).root()
+}
+
+/* the digest of the payload, or the root of its chunks */
+pub fn payload_digest(dispersal:Dispersal, num_chunks:usize, payload:&[u8]) -> U8Arr {
+    match dispersal {
+        Dispersal::Full => digest::digest(&digest::SHA256, payload).as_ref().to_vec(),
+        Dispersal::Avid => MerkleTree::new(&encode(payload, num_chunks)).root(),
+    }
+}
+
+/*
+* What echoes of a batch sign: its payload digest along with the root of its
+* sampling chunks, so that the committee certifies what light clients sample
+* against (see das::batch_digest).
+*/
+pub fn batch_digest(dispersal:Dispersal, num_chunks:usize, payload:&[u8]) -> U8Arr {
+    das::batch_digest(&payload_digest(dispersal, num_chunks, payload), &das_root(payload))
+}
+
+/* the chunk the originator sent us, which we hand to those who ask */
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnChunk {
+    pub index: usize,
+    pub root: U8Arr,
+    pub das_root: U8Arr,
+    pub proof: Vec<U8Arr>,
+    pub chunk: U8Arr,
+}
+
+/*
+* The chunks received for a batch, by batch digest (see batch_digest), since
+* a faulty originator may commit to several. The chunks of a digest are
+* dropped once decoded. Our own
+* chunk is kept to answer requests, and so are the requests that come
+* before it.
+*/
+#[derive(Default)]
+pub struct Chunks {
+    by_digest: HashMap<U8Arr, Vec<Option<U8Arr>>>,
+    decoded: HashSet<U8Arr>,
+    own: Option<OwnChunk>,
+    requests: Vec<u32>,
+}
+
+impl Chunks {
+    /*
+    * Adds a chunk whose proof has been checked. Returns the chunks of its digest
+    * once there are enough of them to decode, only the first time.
+    */
+    pub fn add(&mut self, digest:&[u8], num_chunks:usize, index:usize, chunk:U8Arr) -> Option<Vec<Option<U8Arr>>> {
+        if self.decoded.contains(digest) {
+            return None;
+        }
+        let chunks = self.by_digest.entry(digest.to_vec()).or_insert_with(|| vec![None; num_chunks]);
+        if chunks.len() != num_chunks || index >= num_chunks {
+            return None;
+        }
+        chunks[index] = Some(chunk);
+        if chunks.iter().flatten().count() < data_chunks(num_chunks) {
+            return None;
+        }
+        self.decoded.insert(digest.to_vec());
+        self.by_digest.remove(digest)
+    }
+
+    /* whether enough chunks of the digest came in to decode */
+    pub fn complete(&self, digest:&[u8]) -> bool {
+        self.decoded.contains(digest)
+    }
+
+    /* keeps our own chunk, the first one, and returns who asked for it meanwhile */
+    pub fn set_own(&mut self, own:OwnChunk) -> Vec<u32> {
+        self.own.get_or_insert(own);
+        std::mem::take(&mut self.requests)
+    }
+
+    /* our own chunk for the requester, or None if we have not got it yet: it is sent on arrival */
+    pub fn request(&mut self, requester:u32) -> Option<OwnChunk> {
+        if self.own.is_none() && !self.requests.contains(&requester) {
+            self.requests.push(requester);
+        }
+        self.own.clone()
+    }
+}
+
+/* the members we ask for their chunk: the f+1 chunks we need, with ours, from those after us, not the originator */
+pub fn chunk_sources(committee:&Committee, self_node_ind:u32, originator:u32) -> Vec<u32> {
+    let ids = committee.ids();
+    let start = committee.position(self_node_ind).unwrap_or(0);
+    ids.iter()
+        .cycle()
+        .skip(start + 1)
+        .take(ids.len())
+        .filter(|id| **id != self_node_ind && **id != originator)
+        .take(data_chunks(ids.len()) - 1)
+        .copied()
+        .collect()
+}
+
+async fn send_chunk(
+    self_node_ind:u32,
+    dest:u32,
+    epoch:u32,
+    rn:usize,
+    originator:usize,
+    own:OwnChunk,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    tx_send.send(CastType::Unicast{
+        dest,
+        round: Some(rn as u32),
+        priority: Priority::Bulk,
+        bytes: Message::Chunk{
+            sender: self_node_ind,
+            epoch,
+            rn: rn as u32,
+            originator: originator as u32,
+            index: own.index as u32,
+            root: own.root,
+            das_root: own.das_root,
+            proof: own.proof,
+            chunk: own.chunk,
+        }
+        .to_bytes()
+        .unwrap(),
+    })
+    .await
+    .expect("failed to send chunk");
+}
+
+/* keeps our own chunk of a batch, and sends it to those who asked for it before */
+pub async fn keep_own_chunk(
+    self_node_ind:u32,
+    epoch:u32,
+    rn:usize,
+    originator:usize,
+    own:OwnChunk,
+    chunk_list:&ChunkList,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    let requests = {
+        let mut chunk_list = chunk_list[originator].lock().await;
+        while chunk_list.len() <= rn {
+            chunk_list.push(Chunks::default());
+        }
+        chunk_list[rn].set_own(own.clone())
+    };
+    for requester in requests {
+        send_chunk(self_node_ind, requester, epoch, rn, originator, own.clone(), tx_send).await;
+    }
+}
+
+/* a member asks for our chunk of the originator's batch of round rn */
+pub async fn handle_chunk_req(
+    self_node_ind:u32,
+    sender:u32,
+    epoch:u32,
+    rn:usize,
+    originator:usize,
+    chunk_list:Arc<ChunkList>,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    let own = {
+        let mut chunk_list = chunk_list[originator].lock().await;
+        while chunk_list.len() <= rn {
+            chunk_list.push(Chunks::default());
+        }
+        chunk_list[rn].request(sender)
+    };
+    if let Some(own) = own {
+        send_chunk(self_node_ind, sender, epoch, rn, originator, own, tx_send).await;
+    }
+}
+
+/*
+* A batch delivered before it is decoded: if the chunks to decode it are
+* still not in after CHUNK_RETRY, because the members we asked are faulty or
+* we never got our own chunk to ask with, every member is asked for its
+* chunk.
+*/
+pub async fn retrieve(
+    self_node_ind:u32,
+    epoch:u32,
+    rn:usize,
+    originator:usize,
+    digest:U8Arr,
+    chunk_list:Arc<ChunkList>,
+    tx_send:tokio_mpsc::Sender<CastType>
+){
+    tk_time::sleep(CHUNK_RETRY).await;
+    if chunk_list[originator].lock().await.get(rn).is_some_and(|chunks| chunks.complete(&digest)) {
+        return;
+    }
+    println!("asking every member for the chunks of {}'s msg of round {}", originator, rn);
+    let _ = tx_send.send(CastType::Multicast{
+        epoch,
+        round: Some(rn as u32),
+        priority: Priority::Urgent,
+        bytes: Message::ChunkReq{ sender: self_node_ind, epoch, rn: rn as u32, originator: originator as u32 }
+            .to_bytes()
+            .unwrap(),
+    })
+    .await;
+}
+
+/*
+* Handles chunk `index` of the originator's batch of round rn. The originator
+* sends a member its own chunk, which the member echoes (signing the root
+* along with the sampling root), keeps for those who ask, and asks
+* chunk_sources for theirs; a member sends nothing but its own chunk. Once
+* f+1 chunks of a batch digest are in, the payload is decoded, and stored if
+* the batch was delivered meanwhile.
+*/
+pub async fn handle_chunk_msg(
+    self_node_ind:u32,
+    sender:u32,
+    epoch:u32,
+    rn:usize,
+    originator:usize,
+    index:usize,
+    root:U8Arr,
+    das_root:U8Arr,
+    proof:Vec<U8Arr>,
+    chunk:U8Arr,
+    committee:Arc<Committee>,
+    keypair:Arc<KeyPair>,
+    bls:Option<Arc<BlsKeyPair>>,
+    crypto:Arc<CryptoPool>,
+    sent_echo:Arc<Vec<tk_mutex<Vec<bool>>>>,
+    chunk_list:Arc<ChunkList>,
+    tx_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    cert_list:Arc<CertList>,
+    delivered:Arc<Vec<tk_rwlock<Vec<bool>>>>,
+    store:Arc<dyn Store>,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    if originator == self_node_ind as usize {
+        return;
+    }
+    let from_originator = sender as usize == originator;
+    if !from_originator && committee.position(sender) != Some(index) {
+        return;
+    }
+    let num_chunks = committee.size();
+    let (valid, root, proof, chunk) = crypto.run(move || {
+        let valid = merkle::verify(&root, num_chunks, index, &chunk, &proof);
+        (valid, root, proof, chunk)
+    }).await;
+    if !valid {
+        eprintln!("bad chunk {} of {}'s msg of round {} from {}", index, originator, rn, sender);
+        return;
+    }
+    let digest = das::batch_digest(&root, &das_root);
+
+    if from_originator && committee.position(self_node_ind) == Some(index) {
+        let first = {
+            let mut sent_echo = sent_echo[originator].lock().await;
+            while sent_echo.len() <= rn {
+                sent_echo.push(false);
+            }
+            !std::mem::replace(&mut sent_echo[rn], true)
+        };
+        if first {
+            // stored before echoing, the Sup messages for it are checked against the digest
+            set_round(&hash_list[originator], rn, digest.clone()).await;
+            let sign = {
+                let digest = digest.clone();
+                crypto.run(move || sign_echo(&keypair, bls.as_deref(), &digest)).await
+            };
+            tx_send.send(CastType::Unicast{
+                dest: originator as u32,
+                round: Some(rn as u32),
+                priority: Priority::Urgent,
+                bytes: Message::Echo{ sender: self_node_ind, epoch, rn: rn as u32, sign }
+                    .to_bytes()
+                    .unwrap(),
+            })
+            .await
+            .expect("failed to send echo msg");
+            let own = OwnChunk{ index, root, das_root, proof, chunk: chunk.clone() };
+            keep_own_chunk(self_node_ind, epoch, rn, originator, own, &chunk_list, tx_send).await;
+            for dest in chunk_sources(&committee, self_node_ind, originator as u32) {
+                tx_send.send(CastType::Unicast{
+                    dest,
+                    round: Some(rn as u32),
+                    priority: Priority::Urgent,
+                    bytes: Message::ChunkReq{ sender: self_node_ind, epoch, rn: rn as u32, originator: originator as u32 }
+                        .to_bytes()
+                        .unwrap(),
+                })
+                .await
+                .expect("failed to ask for chunk");
+            }
+        }
+    }
+
+    let chunks = {
+        let mut chunk_list = chunk_list[originator].lock().await;
+        while chunk_list.len() <= rn {
+            chunk_list.push(Chunks::default());
+        }
+        match chunk_list[rn].add(&digest, num_chunks, index, chunk) {
+            Some(chunks) => chunks,
+            None => return,
+        }
+    };
+    let (payload, digest) = crypto.run(move || (decode_checked(chunks, &digest), digest)).await;
+    let payload = match payload {
+        Some(payload) => payload,
+        None => {
+            eprintln!("{}'s msg of round {} is not consistently encoded", originator, rn);
+            return;
+        }
+    };
+    {
+        let mut hash_list = hash_list[originator].write().await;
+        while hash_list.len() <= rn {
+            hash_list.push(Vec::new());
+        }
+        if hash_list[rn].is_empty() {
+            hash_list[rn] = digest.clone();
+        } else if hash_list[rn] != digest {
+            eprintln!("{}'s msg of round {} decoded under another digest, ignored", originator, rn);
+            return;
+        }
+    }
+    set_round(&tx_list[originator], rn, payload.clone()).await;
+    println!("{}'s msg for round {} is decoded", originator, rn);
+
+    // delivered before it could be decoded, it is stored now
+    if delivered[originator].read().await.get(rn) != Some(&true) {
+        return;
+    }
+    let certificate = cert_list[originator].read().await.get(rn).cloned().unwrap_or_default();
+    if let Err(e) = store.put(Record {
+        round: rn as u32,
+        originator: originator as u32,
+        digest,
+        payload,
+        certificate,
+    }) {
+        eprintln!("failed to store {}'s msg for round {}: {}", originator, rn, e);
+    }
+}
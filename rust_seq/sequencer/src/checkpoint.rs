@@ -0,0 +1,345 @@
+#![allow(clippy::too_many_arguments)]
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::{Mutex as tk_mutex, RwLock as tk_rwlock};
+use ring::digest;
+use message::{Message, Signers};
+use network::Priority;
+
+use crate::committee::Committee;
+use crate::sequencer::{CastType, U8Arr};
+use crate::signature::KeyPair;
+use crate::store::{CheckpointCert, Store};
+/* shared with light clients, which check inclusion proofs against checkpoints */
+pub use das::{checkpoint_msg, CHECKPOINT_INTERVAL};
+
+#[cfg(test)]
+#[path = "tests/checkpoint_tests.rs"]
+pub mod checkpoint_tests;
+
+/// How often the checkpointer looks for newly closed rounds (in ms).
+pub const CHECKPOINT_TICK: u64 = 200;
+/// How long a round waits for the batches missing once those of a quorum are in (in ms).
+pub const FOLD_TIMEOUT: u64 = 10_000;
+/// Close votes are kept for rounds up to this far past the next one to fold.
+pub const CLOSE_WINDOW: u32 = 100;
+
+/* the bytes a node signs when voting to close round rn without the absent batches */
+pub fn close_msg(rn:u32, absent:&Signers) -> Vec<u8> {
+    let mut msg = b"close".to_vec();
+    msg.extend_from_slice(&rn.to_le_bytes());
+    msg.extend_from_slice(absent.as_bytes());
+    msg
+}
+
+/*
+* Folds one delivered batch into the running digest of the log. The log is
+* ordered by round, then by originator, so every node that delivered the same
+* batches ends up with the same digest.
+*/
+pub fn extend_digest(prev:&[u8], rn:u32, originator:u32, batch_digest:&[u8]) -> U8Arr {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(prev);
+    ctx.update(&rn.to_le_bytes());
+    ctx.update(&originator.to_le_bytes());
+    ctx.update(batch_digest);
+    ctx.finish().as_ref().to_vec()
+}
+
+/*
+* Decides when a round is closed: once the batches of its whole committee are
+* delivered, or FOLD_TIMEOUT after those of a quorum of the stake are, without
+* the others. The rounds after the one being closed are observed too, so that
+* the timeouts of the rounds a crashed member misses run side by side: it
+* delays folding by FOLD_TIMEOUT, instead of halting it.
+* Closing a round without some batches only casts our Close vote; which
+* batches are folded is agreed by the votes (see Closes).
+*/
+#[derive(Default)]
+pub struct FoldTimer {
+    quorum_since: BTreeMap<u32, Instant>, // quorum_since[rn] -> when a quorum of round rn's batches was first in
+}
+
+impl FoldTimer {
+    /* notes when round rn first has a quorum of batches, and whether it has one */
+    pub fn observe(&mut self, rn:u32, committee:&Committee, present:&[u32], now:Instant) -> bool {
+        if committee.stake(present.iter().copied()) < committee.quorum() {
+            return false;
+        }
+        self.quorum_since.entry(rn).or_insert(now);
+        true
+    }
+
+    /* whether round rn, the next one to fold, can be closed with the batches present */
+    pub fn ready(&mut self, rn:u32, committee:&Committee, present:&[u32], now:Instant) -> bool {
+        present.len() == committee.size() || (
+            self.observe(rn, committee, present, now)
+                && now.duration_since(self.quorum_since[&rn]) >= Duration::from_millis(FOLD_TIMEOUT)
+        )
+    }
+
+    /* forgets the rounds up to rn, once it is folded */
+    pub fn folded(&mut self, rn:u32) {
+        self.quorum_since = self.quorum_since.split_off(&(rn + 1));
+    }
+}
+
+/*
+* Closes collects the votes to close a round without some of its batches. A
+* node votes once its FoldTimer is ready, for the originators whose batch it
+* has neither delivered nor echoed, and it does not echo them afterwards. An
+* originator is left out of a round once members holding a quorum of the
+* stake voted it absent: its batch can then no longer gather the echoes of a
+* quorum, and a batch that is certified is never left out, since two quorums
+* share an honest member. So every node folds the same batches, whenever they
+* arrive. An originator whose batch is echoed by some members, yet neither
+* certified nor voted out, keeps its round open.
+*/
+#[derive(Default)]
+pub struct Closes {
+    votes: BTreeMap<u32, HashMap<u32, (Signers, U8Arr)>>, // votes[rn][voter] -> (absent originators, sign)
+}
+
+impl Closes {
+    pub fn voted(&self, rn:u32, voter:u32) -> bool {
+        self.votes.get(&rn).is_some_and(|votes| votes.contains_key(&voter))
+    }
+
+    /* adds a vote whose signature has already been checked, unless the voter already voted */
+    pub fn add_vote(&mut self, rn:u32, voter:u32, absent:Signers, sign:U8Arr) -> bool {
+        let votes = self.votes.entry(rn).or_default();
+        if votes.contains_key(&voter) {
+            return false;
+        }
+        votes.insert(voter, (absent, sign));
+        true
+    }
+
+    /* the (voter, absent, sign) of round rn, to relay to a node that is behind */
+    pub fn votes(&self, rn:u32) -> Vec<(u32, Signers, U8Arr)> {
+        self.votes.get(&rn).map_or_else(Vec::new, |votes| {
+            votes.iter().map(|(voter, (absent, sign))| (*voter, absent.clone(), sign.clone())).collect()
+        })
+    }
+
+    /* the originators of round rn that members holding a quorum of the stake voted absent */
+    pub fn excluded(&self, rn:u32, committee:&Committee) -> Vec<u32> {
+        let votes = match self.votes.get(&rn) {
+            Some(votes) => votes,
+            None => return Vec::new(),
+        };
+        committee.ids()
+            .into_iter()
+            .filter(|id| {
+                let voters = votes.iter().filter(|(_, (absent, _))| absent.contains(*id)).map(|(voter, _)| *voter);
+                committee.stake(voters) >= committee.quorum()
+            })
+            .collect()
+    }
+
+    /* drops the votes of the rounds below */
+    pub fn prune(&mut self, below:u32) {
+        self.votes = self.votes.split_off(&below);
+    }
+}
+
+/* whether every member of the round's committee has its batch present or is left out */
+pub fn closed(committee:&Committee, present:&[u32], excluded:&[u32]) -> bool {
+    committee.ids().iter().all(|id| present.contains(id) || excluded.contains(id))
+}
+
+/* what a checkpoint vote signs besides the round: (digest, data root) */
+type Signed = (U8Arr, U8Arr);
+
+/*
+* Checkpoints collects checkpoint votes until a quorum of the stake signed the
+* same digest and data root for a round. Only the first vote of a node for a round counts.
+* A node votes for a checkpoint once it folded its rounds, so the votes also
+* tell how far each member has folded.
+*/
+#[derive(Default)]
+pub struct Checkpoints {
+    votes: BTreeMap<u32, HashMap<u32, (Signed, U8Arr)>>, // votes[rn][signer] -> ((digest, data root), sign)
+    voted: HashMap<u32, u32>, // voted[signer] -> latest checkpoint it voted for, the rounds it folded
+    stable: u32, // rounds below are covered by a stable checkpoint
+    folded: u32, // rounds below are delivered here and folded into our digest
+    pruned: u32, // rounds below have had their state dropped from memory
+}
+
+impl Checkpoints {
+    pub fn stable(&self) -> u32 {
+        self.stable
+    }
+
+    pub fn folded(&self) -> u32 {
+        self.folded
+    }
+
+    pub fn set_folded(&mut self, rn:u32) {
+        self.folded = rn;
+    }
+
+    pub fn pruned(&self) -> u32 {
+        self.pruned
+    }
+
+    /*
+    * The rounds [from, below) that are covered by a stable checkpoint, and
+    * folded here and by every member of the committee since the last call,
+    * to be pruned. Until then a member that is behind can still sync them
+    * from the store; one that is down holds pruning back until it is
+    * reconfigured out. They count as pruned from now on, so that messages
+    * of those rounds are dropped.
+    */
+    pub fn start_pruning(&mut self, committee:&Committee) -> Option<(u32, u32)> {
+        let members = committee.ids().into_iter().map(|id| self.voted.get(&id).copied().unwrap_or(0)).min();
+        let below = self.stable.min(self.folded).min(members.unwrap_or(0));
+        if below <= self.pruned {
+            return None;
+        }
+        let from = std::mem::replace(&mut self.pruned, below);
+        Some((from, below))
+    }
+
+    /*
+    * Adds a vote whose signature has already been checked. Returns the
+    * certificate once the vote completes a quorum on a new checkpoint.
+    */
+    pub fn add_vote(
+        &mut self,
+        rn:u32,
+        signer:u32,
+        digest:U8Arr,
+        data_root:U8Arr,
+        sign:U8Arr,
+        committee:&Committee
+    ) -> Option<CheckpointCert> {
+        let voted = self.voted.entry(signer).or_default();
+        *voted = (*voted).max(rn);
+        if rn <= self.stable {
+            return None;
+        }
+        let votes = self.votes.entry(rn).or_default();
+        let signed = votes.entry(signer).or_insert(((digest, data_root), sign)).0.clone();
+        let mut signs: Vec<(u32, U8Arr)> = votes
+            .iter()
+            .filter(|(_, (s, _))| *s == signed)
+            .map(|(id, (_, sign))| (*id, sign.clone()))
+            .collect();
+        if committee.stake(signs.iter().map(|(id, _)| *id)) < committee.quorum() {
+            return None;
+        }
+
+        signs.sort_unstable_by_key(|(id, _)| *id);
+        self.stable = rn;
+        self.votes = self.votes.split_off(&(rn + 1));
+        let (digest, data_root) = signed;
+        Some(CheckpointCert { rn, digest, data_root, signs })
+    }
+}
+
+/*
+* Handles a checkpoint vote (ours included). Once the checkpoint is stable its
+* certificate is persisted; the checkpointer then prunes the rounds it covers
+* that every member folded (see Checkpoints::start_pruning).
+*/
+pub async fn handle_checkpoint_msg(
+    self_node_ind:u32,
+    self_pub_key:&[u8],
+    sender:u32,
+    rn:u32,
+    digest:U8Arr,
+    data_root:U8Arr,
+    sign:U8Arr,
+    committee:Arc<Committee>,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    checkpoints:Arc<tk_mutex<Checkpoints>>,
+    store:Arc<dyn Store>,
+){
+    {
+        let peer_pkeys = peer_pkeys.read().await;
+        let pub_key = if sender == self_node_ind {
+            self_pub_key
+        } else {
+            match peer_pkeys.get(sender as usize) {
+                Some(Some(pub_key)) => pub_key.as_slice(),
+                _ => return,
+            }
+        };
+        if !KeyPair::verify_signature(pub_key, &checkpoint_msg(rn, &digest, &data_root), &sign) {
+            eprintln!("wrong checkpoint signature from {}!", sender);
+            return;
+        }
+    }
+
+    let cert = match checkpoints.lock().await.add_vote(rn, sender, digest, data_root, sign, &committee) {
+        Some(cert) => cert,
+        None => return,
+    };
+    println!("checkpoint for round {} is stable", rn);
+    if let Err(e) = store.put_checkpoint(cert) {
+        eprintln!("failed to store checkpoint for round {}: {}", rn, e);
+    }
+}
+
+/*
+* Handles a Close vote of a member of round rn's committee (ours excluded),
+* multicast by the voter or relayed. A node that folded the round already
+* answers the voter with every vote it holds for it, since the voter is
+* behind and may have dropped them.
+*/
+pub async fn handle_close_msg(
+    self_node_ind:u32,
+    sender:u32,
+    voter:u32,
+    rn:u32,
+    absent:Signers,
+    sign:U8Arr,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    closes:Arc<tk_mutex<Closes>>,
+    checkpoints:Arc<tk_mutex<Checkpoints>>,
+    tx_send:&tokio_mpsc::Sender<CastType>,
+){
+    {
+        let peer_pkeys = peer_pkeys.read().await;
+        let pub_key = match peer_pkeys.get(voter as usize) {
+            Some(Some(pub_key)) => pub_key,
+            _ => return,
+        };
+        if !KeyPair::verify_signature(pub_key, &close_msg(rn, &absent), &sign) {
+            eprintln!("wrong close signature of {} from {}!", voter, sender);
+            return;
+        }
+    }
+    let (folded, pruned) = {
+        let checkpoints = checkpoints.lock().await;
+        (checkpoints.folded(), checkpoints.pruned())
+    };
+    if rn < pruned || rn >= folded + CLOSE_WINDOW {
+        return;
+    }
+
+    let votes = {
+        let mut closes = closes.lock().await;
+        closes.add_vote(rn, voter, absent, sign);
+        if sender != voter || rn >= folded {
+            return;
+        }
+        closes.votes(rn)
+    };
+    for (voter, absent, sign) in votes {
+        let sent = tx_send.send(CastType::Unicast{
+            dest: sender,
+            round: None,
+            priority: Priority::Urgent,
+            bytes: Message::Close{ sender: self_node_ind, voter, rn, absent, sign }
+                .to_bytes()
+                .unwrap(),
+        }).await;
+        if sent.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,370 @@
+#![allow(clippy::too_many_arguments)]
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::RwLock as tk_rwlock;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use network::Priority;
+use crate::sequencer::{CastType, U8Arr};
+
+#[cfg(test)]
+#[path = "tests/committee_tests.rs"]
+pub mod committee_tests;
+
+/// Node ids are below MAX_NODES; per-originator state is allocated for all of them.
+pub const MAX_NODES: usize = message::MAX_NODES;
+/// A committee delivered in round r takes over at round r + RECONFIG_DELAY.
+pub const RECONFIG_DELAY: u32 = 10;
+/// How often a node looks for a reconfiguration to propose (in ms).
+pub const RECONFIG_POLL: u64 = 1_000;
+/* a batch starting with this tag is a reconfiguration transaction */
+const RECONFIG_TAG: &[u8] = b"reconfig";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Member {
+    pub id: u32,
+    pub address: SocketAddr,
+    pub pub_key: Option<U8Arr>, // None: keep the key announced through Syn
+    pub stake: u64, // voting weight, 1 unless configured
+}
+
+/*
+* The set of nodes running the protocol from round start_round on, until the
+* committee of the next epoch takes over. Quorums are computed per committee,
+* over the stake of its members: a quorum is more than 2/3 of the stake, so any
+* two intersect in more than 1/3, and a validity threshold more than 1/3. With
+* equal stakes and 3f+1 nodes these are the usual 2f+1 and f+1 nodes.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Committee {
+    pub epoch: u32,
+    pub start_round: u32,
+    pub members: Vec<Member>,
+}
+
+impl Committee {
+    /* the first num_nodes entries of the address book, keys come through Syn */
+    pub fn genesis(address_book:&[SocketAddr], num_nodes:u32) -> Self {
+        Committee {
+            epoch: 0,
+            start_round: 0,
+            members: address_book.iter()
+                .take(num_nodes as usize)
+                .enumerate()
+                .map(|(id, address)| Member { id: id as u32, address: *address, pub_key: None, stake: 1 })
+                .collect(),
+        }
+    }
+
+    /* sets the stake of the members, in id order */
+    pub fn with_stakes(mut self, stakes:&[u64]) -> Self {
+        for (member, stake) in self.members.iter_mut().zip(stakes) {
+            member.stake = *stake;
+        }
+        self
+    }
+
+    /* sets the configured keys of the members, in id order */
+    pub fn with_keys(mut self, keys:&[Option<U8Arr>]) -> Self {
+        for (member, pub_key) in self.members.iter_mut().zip(keys) {
+            member.pub_key = pub_key.clone();
+        }
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.members.iter().map(|m| m.stake).sum()
+    }
+
+    /* the faulty stake tolerated, f */
+    pub fn f_stake(&self) -> u64 {
+        self.total_stake().saturating_sub(1) / 3
+    }
+
+    /* stake needed for a quorum, more than 2/3 of the total */
+    pub fn quorum(&self) -> u64 {
+        2 * self.total_stake() / 3 + 1
+    }
+
+    /* stake needed so that at least one honest node is included, more than 1/3 of the total */
+    pub fn validity(&self) -> u64 {
+        self.total_stake() / 3 + 1
+    }
+
+    /* 0 for non-members */
+    pub fn stake_of(&self, id:u32) -> u64 {
+        self.members.iter().find(|m| m.id == id).map_or(0, |m| m.stake)
+    }
+
+    /* the stake of the distinct members among `ids` */
+    pub fn stake<I: IntoIterator<Item = u32>>(&self, ids:I) -> u64 {
+        let ids: HashSet<u32> = ids.into_iter().collect();
+        ids.into_iter().map(|id| self.stake_of(id)).sum()
+    }
+
+    pub fn contains(&self, id:u32) -> bool {
+        self.members.iter().any(|m| m.id == id)
+    }
+
+    pub fn ids(&self) -> Vec<u32> {
+        self.members.iter().map(|m| m.id).collect()
+    }
+
+    /* the index of a member in the committee, e.g. which chunk of a batch it holds */
+    pub fn position(&self, id:u32) -> Option<usize> {
+        self.members.iter().position(|m| m.id == id)
+    }
+
+    pub fn address(&self, id:u32) -> Option<SocketAddr> {
+        self.members.iter().find(|m| m.id == id).map(|m| m.address)
+    }
+}
+
+/*
+* A reconfiguration transaction: the committee of the given epoch. It is
+* ordered like any other batch, and applied by every node at the same point of
+* the delivered log, once members holding a quorum of the stake have proposed
+* it (see Epochs::propose).
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reconfig {
+    pub epoch: u32,
+    pub members: Vec<Member>,
+}
+
+impl Reconfig {
+    pub fn to_payload(&self) -> U8Arr {
+        let mut payload = RECONFIG_TAG.to_vec();
+        payload.extend(bincode::serialize(self).unwrap());
+        payload
+    }
+
+    pub fn from_payload(payload:&[u8]) -> Option<Reconfig> {
+        payload.strip_prefix(RECONFIG_TAG)
+            .and_then(|bytes| bincode::deserialize(bytes).ok())
+    }
+
+    /*
+    * Parses the operator's reconfiguration file: the epoch on the first line,
+    * then one `<id> <address> <hex public key or -> [stake]` line per member.
+    */
+    pub fn from_config(config:&str) -> Option<Reconfig> {
+        let mut lines = config.lines().filter(|l| !l.trim().is_empty());
+        let epoch = lines.next()?.trim().parse().ok()?;
+        let mut members = Vec::new();
+        for line in lines {
+            let mut iter = line.split_whitespace();
+            let id = iter.next()?.parse().ok()?;
+            let address = iter.next()?.parse().ok()?;
+            let pub_key = match iter.next()? {
+                "-" => None,
+                key => Some(from_hex(key)?),
+            };
+            let stake = match iter.next() {
+                Some(stake) => stake.parse().ok()?,
+                None => 1,
+            };
+            members.push(Member { id, address, pub_key, stake });
+        }
+        Some(Reconfig { epoch, members })
+    }
+}
+
+/*
+* Epochs is the history of committees, oldest first. The committee in charge
+* of a round is the last one that started at or before it.
+*/
+pub struct Epochs {
+    committees: Vec<Arc<Committee>>,
+    proposals: Vec<(Reconfig, HashSet<u32>)>, // for the next epoch, with their proposers
+}
+
+impl Epochs {
+    pub fn new(genesis:Committee) -> Self {
+        Epochs { committees: vec![Arc::new(genesis)], proposals: Vec::new() }
+    }
+
+    /* the latest known committee, which may not have started yet */
+    pub fn latest(&self) -> &Arc<Committee> {
+        self.committees.last().unwrap()
+    }
+
+    pub fn get(&self, epoch:u32) -> Option<&Arc<Committee>> {
+        self.committees.iter().find(|c| c.epoch == epoch)
+    }
+
+    pub fn for_round(&self, rn:u32) -> &Arc<Committee> {
+        self.committees.iter()
+            .rev()
+            .find(|c| c.start_round <= rn)
+            .unwrap_or(&self.committees[0])
+    }
+
+    /* true if `sender` may send messages of round rn stamped with `epoch` */
+    pub fn check(&self, epoch:u32, rn:u32, sender:u32) -> bool {
+        let committee = self.for_round(rn);
+        committee.epoch == epoch && committee.contains(sender)
+    }
+
+    /* the most recent address of a node, in any committee */
+    pub fn address(&self, id:u32) -> Option<SocketAddr> {
+        self.committees.iter().rev().find_map(|c| c.address(id))
+    }
+
+    /* the most recent key configured for a node, in any committee */
+    pub fn pub_key(&self, id:u32) -> Option<&U8Arr> {
+        self.committees.iter()
+            .rev()
+            .find_map(|c| c.members.iter().find(|m| m.id == id)?.pub_key.as_ref())
+    }
+
+    /* one past the highest node id that has ever been in a committee */
+    pub fn num_ids(&self) -> usize {
+        self.committees.iter()
+            .flat_map(|c| c.members.iter())
+            .map(|m| m.id as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /*
+    * Records that `proposer` put `reconfig` in its batch of round rn. Once
+    * members of that round's committee holding a quorum of its stake have
+    * proposed the same reconfiguration, it is returned, to be applied; a
+    * single member cannot change the committee. Proposals for any epoch but
+    * the next one are dropped.
+    */
+    pub fn propose(&mut self, rn:u32, proposer:u32, mut reconfig:Reconfig) -> Option<Reconfig> {
+        let next_epoch = self.latest().epoch + 1;
+        self.proposals.retain(|(r, _)| r.epoch == next_epoch);
+        let committee = self.for_round(rn).clone();
+        if reconfig.epoch != next_epoch || !committee.contains(proposer) {
+            return None;
+        }
+        reconfig.members.sort_by_key(|m| m.id);
+        let ind = match self.proposals.iter().position(|(r, _)| *r == reconfig) {
+            Some(ind) => ind,
+            None => {
+                self.proposals.push((reconfig, HashSet::new()));
+                self.proposals.len() - 1
+            }
+        };
+        let proposers = &mut self.proposals[ind].1;
+        proposers.insert(proposer);
+        if committee.stake(proposers.iter().copied()) < committee.quorum() {
+            return None;
+        }
+        let (reconfig, _) = self.proposals.swap_remove(ind);
+        self.proposals.clear();
+        Some(reconfig)
+    }
+
+    /*
+    * Schedules the committee of a reconfiguration delivered in round rn. It
+    * must be for the next epoch, hold some stake, have distinct ids below
+    * MAX_NODES, and start after the latest committee. Returns the scheduled committee,
+    * whose members are sorted by id.
+    */
+    pub fn apply(&mut self, rn:u32, mut reconfig:Reconfig) -> Option<Arc<Committee>> {
+        let latest = self.latest();
+        let start_round = rn.checked_add(RECONFIG_DELAY)?;
+        if reconfig.epoch != latest.epoch + 1
+            || start_round <= latest.start_round
+            || reconfig.members.iter().map(|m| m.stake).sum::<u64>() == 0
+        {
+            return None;
+        }
+        reconfig.members.sort_unstable_by_key(|m| m.id);
+        if reconfig.members.windows(2).any(|w| w[0].id == w[1].id)
+            || reconfig.members.iter().any(|m| m.id as usize >= MAX_NODES)
+        {
+            return None;
+        }
+
+        let committee = Arc::new(Committee {
+            epoch: reconfig.epoch,
+            start_round,
+            members: reconfig.members,
+        });
+        self.committees.push(committee.clone());
+        Some(committee)
+    }
+}
+
+/*
+* Handles a reconfiguration proposed by `proposer` in round rn of the delivered
+* log, and applies it once a quorum has proposed it. The keys it carries
+* replace the ones announced through Syn, and we greet the members of the new
+* committee with our own key, since nodes that just joined have not heard from
+* us yet.
+*/
+pub async fn handle_reconfig(
+    self_node_ind:u32,
+    rn:u32,
+    proposer:u32,
+    reconfig:Reconfig,
+    syn_bytes:Bytes,
+    epochs:Arc<tk_rwlock<Epochs>>,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    let epoch = reconfig.epoch;
+    let committee = {
+        let mut epochs = epochs.write().await;
+        let reconfig = match epochs.propose(rn, proposer, reconfig) {
+            Some(reconfig) => reconfig,
+            None => return,
+        };
+        match epochs.apply(rn, reconfig) {
+            Some(committee) => committee,
+            None => {
+                eprintln!("invalid reconfiguration to epoch {} in round {}, ignored", epoch, rn);
+                return;
+            }
+        }
+    };
+    println!("epoch {} starts at round {} with {} nodes",
+        committee.epoch,
+        committee.start_round,
+        committee.size()
+    );
+
+    {
+        let mut peer_pkeys = peer_pkeys.write().await;
+        for member in committee.members.iter().filter(|m| m.id != self_node_ind) {
+            if let Some(pub_key) = &member.pub_key {
+                peer_pkeys[member.id as usize] = Some(pub_key.clone());
+            }
+        }
+    }
+
+    if let Err(e) = tx_send.send(CastType::Multicast{
+        epoch: committee.epoch,
+        round: None,
+        priority: Priority::Urgent,
+        bytes: syn_bytes,
+    }).await {
+        eprintln!("failed to greet the committee of epoch {}: {}", committee.epoch, e);
+    }
+}
+
+pub fn to_hex(bytes:&[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex:&str) -> Option<U8Arr> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i+2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,130 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc as tokio_mpsc;
+use network::MemoryNetwork;
+
+pub mod sequencer;
+mod signature;
+pub mod store;
+pub mod sync;
+pub mod checkpoint;
+pub mod committee;
+pub mod crypto_pool;
+pub mod avid;
+pub mod sampling;
+pub mod inclusion;
+pub mod fair;
+pub mod sealed;
+pub mod l1;
+pub mod delivery;
+#[cfg(test)]
+#[path = "tests/fixtures.rs"]
+pub mod fixtures;
+use sequencer::{MeasureDs, Sequencer};
+use store::MemStore;
+use committee::Committee;
+use signature::KeyPair;
+use crypto_pool::{CryptoPool, CRYPTO_QUEUE_DEPTH};
+
+/*
+* Compares the round latency of a committee with hashing and signature checks
+* run on the tokio workers and on a CryptoPool. The nodes run in full (Send,
+* Echo, Fin, Sup and the checkpointer) over a MemoryNetwork, sharing the
+* runtime's workers and the pool as they would the cores of one machine; a
+* round's latency is from its start to the delivery of the node's own batch.
+*
+* usage: cargo r --release --bin crypto_bench -- [payload_size] [num_nodes] [rounds] [workers]
+*/
+
+/// The periodic sender waits this long before its first round.
+const START_DELAY: u64 = 5; // in s
+/// And sends a round every second; the last rounds get this long to be delivered.
+const DRAIN_DELAY: u64 = 3; // in s
+const BASE_PORT: u16 = 19700;
+
+fn main() {
+    let mut args = std::env::args().skip(1).map(|s| s.parse::<usize>().expect("arguments are numbers"));
+    let payload_size = args.next().unwrap_or(20_000_000);
+    let num_nodes = args.next().unwrap_or(4);
+    let rounds = args.next().unwrap_or(5);
+    let workers = args.next().unwrap_or(2);
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    println!("payload {}, {} nodes, {} rounds, {} tokio workers, {} cores",
+        payload_size, num_nodes, rounds, workers, cores);
+
+    let mut results = Vec::new();
+    for threads in [0, cores] {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .enable_all()
+            .build()
+            .unwrap();
+        let crypto = Arc::new(CryptoPool::new(threads, CRYPTO_QUEUE_DEPTH));
+        let latency = runtime.block_on(run(crypto.clone(), payload_size, num_nodes, rounds));
+        // the nodes' tasks are dropped with the runtime
+        runtime.shutdown_background();
+        let mode = if threads == 0 { "inline" } else { "pool" };
+        results.push((mode, latency, crypto.metrics()));
+    }
+    for (mode, latency, metrics) in results {
+        let delivered: Vec<u128> = latency.iter().flatten().copied().collect();
+        println!("{:>6}: round latency avg {:>6}ms p50 {:>6}ms max {:>6}ms | {} of {} rounds delivered",
+            mode,
+            delivered.iter().sum::<u128>() / delivered.len().max(1) as u128,
+            percentile(&delivered, 50),
+            percentile(&delivered, 100),
+            delivered.len(),
+            latency.len(),
+        );
+        println!("        {}", metrics);
+    }
+}
+
+/* the latency of the first `rounds` rounds of every node */
+async fn run(crypto:Arc<CryptoPool>, payload_size:usize, num_nodes:usize, rounds:usize) -> Vec<Option<u128>> {
+    let network = Arc::new(MemoryNetwork::new());
+    let address_book: Vec<SocketAddr> = (0..num_nodes)
+        .map(|i| SocketAddr::from(([127, 0, 0, 1], BASE_PORT + i as u16)))
+        .collect();
+    let mut measures = Vec::with_capacity(num_nodes);
+    for node_ind in 0..num_nodes as u32 {
+        let measure = Arc::new(MeasureDs::new());
+        measures.push(measure.clone());
+        let sequencer = Sequencer::new(
+            node_ind,
+            Committee::genesis(&address_book, num_nodes as u32),
+            address_book[node_ind as usize],
+            payload_size,
+            KeyPair::new(),
+            Arc::new(MemStore::new()),
+            measure,
+        )
+        .with_crypto_pool(crypto.clone())
+        .with_transport(network.clone());
+        let (tx_recv, rx_recv) = tokio_mpsc::channel(10_000);
+        let (tx_send, rx_send) = tokio_mpsc::channel(10_000);
+        sequencer.spawn_receiver(tx_recv);
+        sequencer.spawn_sender(rx_send);
+        sequencer.spawn_periodic_sender(tx_send.clone());
+        sequencer.spawn_sync(tx_send.clone());
+        sequencer.spawn_checkpointer(tx_send.clone());
+        tokio::spawn(sequencer.run_main_loop(rx_recv, tx_send));
+    }
+    tokio::time::sleep(Duration::from_secs(START_DELAY + rounds as u64 + DRAIN_DELAY)).await;
+
+    let mut latency = Vec::with_capacity(num_nodes * rounds);
+    for measure in measures {
+        let mut rounds_latency = measure.round_latency().await;
+        rounds_latency.resize(rounds, None);
+        latency.extend(rounds_latency);
+    }
+    latency
+}
+
+fn percentile(samples:&[u128], p:usize) -> u128 {
+    let mut samples = samples.to_vec();
+    samples.sort_unstable();
+    let i = (samples.len() * p / 100).min(samples.len().saturating_sub(1));
+    samples.get(i).copied().unwrap_or_default()
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::sync::{oneshot, Semaphore};
+
+#[cfg(test)]
+#[path = "tests/crypto_pool_tests.rs"]
+pub mod crypto_pool_tests;
+
+/// Jobs waiting for or running on a CryptoPool; submitting more waits for a slot.
+pub const CRYPTO_QUEUE_DEPTH: usize = 1024;
+
+/*
+* A pool of threads for CPU-bound work: hashing payloads and checking
+* signatures. Running it on the tokio workers holds them for milliseconds per
+* 20MB payload, and no socket is served meanwhile. The queue is bounded, so a
+* flood of messages slows their handlers down instead of piling up jobs.
+*
+* With no threads, jobs run in place on the calling task, as they used to.
+*/
+pub struct CryptoPool {
+    pool: Option<rayon::ThreadPool>,
+    slots: Semaphore,
+    queue_depth: usize,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Default)]
+struct Metrics {
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    queued: AtomicU64,
+    max_queued: AtomicU64,
+    wait_us: AtomicU64, // from submission until a thread picks the job up
+    busy_us: AtomicU64,
+}
+
+/* a snapshot of the pool's metrics */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolMetrics {
+    pub threads: usize,
+    pub queue_depth: usize,
+    pub submitted: u64,
+    pub completed: u64,
+    pub queued: u64,
+    pub max_queued: u64,
+    pub wait_us: u64,
+    pub busy_us: u64,
+}
+
+impl Default for CryptoPool {
+    fn default() -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(threads, CRYPTO_QUEUE_DEPTH)
+    }
+}
+
+impl CryptoPool {
+    pub fn new(threads:usize, queue_depth:usize) -> Self {
+        assert!(queue_depth > 0);
+        let pool = (threads > 0).then(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("crypto-{}", i))
+                .build()
+                .expect("failed to start the crypto pool")
+        });
+        CryptoPool {
+            pool,
+            slots: Semaphore::new(queue_depth),
+            queue_depth,
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /* runs the job on the pool's threads and waits for its result */
+    pub async fn run<F, T>(&self, job:F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let _slot = self.slots.acquire().await.unwrap();
+        let metrics = &self.metrics;
+        metrics.submitted.fetch_add(1, Ordering::Relaxed);
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => {
+                let start = Instant::now();
+                let result = job();
+                metrics.busy_us.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                metrics.completed.fetch_add(1, Ordering::Relaxed);
+                return result;
+            }
+        };
+
+        let queued = metrics.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics.max_queued.fetch_max(queued, Ordering::Relaxed);
+        let metrics = metrics.clone();
+        let submitted_at = Instant::now();
+        let (tx, rx) = oneshot::channel();
+        pool.spawn(move || {
+            let start = Instant::now();
+            metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            metrics.wait_us.fetch_add((start - submitted_at).as_micros() as u64, Ordering::Relaxed);
+            let result = job();
+            metrics.busy_us.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+            _ = tx.send(result);
+        });
+        rx.await.expect("crypto job panicked")
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or(0, |pool| pool.current_num_threads())
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let metrics = &self.metrics;
+        PoolMetrics {
+            threads: self.threads(),
+            queue_depth: self.queue_depth,
+            submitted: metrics.submitted.load(Ordering::Relaxed),
+            completed: metrics.completed.load(Ordering::Relaxed),
+            queued: metrics.queued.load(Ordering::Relaxed),
+            max_queued: metrics.max_queued.load(Ordering::Relaxed),
+            wait_us: metrics.wait_us.load(Ordering::Relaxed),
+            busy_us: metrics.busy_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Display for PoolMetrics {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let avg = |total:u64| total.checked_div(self.completed).unwrap_or(0);
+        write!(f, "crypto pool: {} threads, {} jobs ({} queued, max {} of {}), avg wait {}us, avg busy {}us",
+            self.threads,
+            self.completed,
+            self.queued,
+            self.max_queued,
+            self.queue_depth,
+            avg(self.wait_us),
+            avg(self.busy_us),
+        )
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use network::{CancelHandler, Framing, QueueStats};
+
+#[cfg(test)]
+#[path = "tests/delivery_tests.rs"]
+pub mod delivery_tests;
+
+/// How often the sender cancels the messages of the rounds folded meanwhile (in ms).
+pub const CANCEL_TICK: u64 = 500;
+/// How often the sender reports the peers it has a backlog for (in ms).
+pub const QUEUE_REPORT: u64 = 5_000;
+/// Messages to peers are written in chunks of this many bytes.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/*
+* how messages are framed on the links between peers: chunked, so that the
+* votes queued behind a payload being written get ahead of the rest of it
+*/
+pub fn peer_framing() -> Framing {
+    Framing{ chunk_size: Some(CHUNK_SIZE), ..Framing::default() }
+}
+
+/*
+* how the messages of a round reach the peers: `simple` (default) sends them
+* once, `reliable` retransmits them until the peer acknowledges them or the
+* round is folded
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Delivery {
+    #[default]
+    Simple,
+    Reliable,
+}
+
+impl FromStr for Delivery {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "simple" => Ok(Delivery::Simple),
+            "reliable" => Ok(Delivery::Reliable),
+            _ => Err(format!("unknown delivery {}", s)),
+        }
+    }
+}
+
+/*
+* whether the links between peers are `plain` TCP (default), or `secure`:
+* authenticated with the node keys, the ones of the Syn, and encrypted
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LinkSecurity {
+    #[default]
+    Plain,
+    Secure,
+}
+
+impl FromStr for LinkSecurity {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LinkSecurity::Plain),
+            "secure" => Ok(LinkSecurity::Secure),
+            _ => Err(format!("unknown link security {}", s)),
+        }
+    }
+}
+
+/*
+* RoundCancels keeps the cancel handlers of the messages sent reliably, by
+* round. Dropping a handler cancels its message. Once a round is folded, i.e.
+* delivered here for every originator (rounds are only pruned after that),
+* its messages are no longer retransmitted: a peer still missing the round
+* gets it through sync instead.
+*/
+#[derive(Default)]
+pub struct RoundCancels {
+    handlers: BTreeMap<u32, Vec<CancelHandler>>,
+}
+
+impl RoundCancels {
+    pub fn add(&mut self, round:u32, handlers:Vec<CancelHandler>) {
+        self.handlers.entry(round).or_default().extend(handlers);
+    }
+
+    /* cancels the pending messages of the rounds below `folded` */
+    pub fn cancel_below(&mut self, folded:u32) {
+        self.handlers = self.handlers.split_off(&folded);
+    }
+}
+
+/*
+* QueueMonitor picks the peers worth a report out of the queue stats of the
+* senders: those whose queue is at least half full, and those that had
+* messages dropped since the last report.
+*/
+pub struct QueueMonitor {
+    capacity: usize,
+    dropped: HashMap<SocketAddr, u64>,
+}
+
+impl QueueMonitor {
+    pub fn new(capacity:usize) -> Self {
+        QueueMonitor { capacity, dropped: HashMap::new() }
+    }
+
+    pub fn congested(&mut self, stats:HashMap<SocketAddr, QueueStats>) -> Vec<(SocketAddr, QueueStats)> {
+        let mut congested: Vec<_> = stats.into_iter()
+            .filter(|(address, stats)| {
+                let dropped = self.dropped.insert(*address, stats.dropped).unwrap_or(0);
+                2 * stats.depth >= self.capacity || stats.dropped > dropped
+            })
+            .collect();
+        congested.sort_by_key(|(address, _)| *address);
+        congested
+    }
+}
@@ -1,6 +1,6 @@
 use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use futures::sink::SinkExt;
+use network::Framing;
 
 #[tokio::main]
 async fn main(){
@@ -12,10 +12,7 @@ async fn main(){
 	let mut writer = Framed::new(stream, codec);
 	*/
 
-	let mut writer = LengthDelimitedCodec::builder()
-		.little_endian()
-		.max_frame_length(40_000_000)
-		.new_framed(stream);
+	let mut writer = Framing::default().framed(stream);
 	let message = vec![b'1'; 20_000_000];
 	loop {
 		writer.send(message.clone().into()).await.expect("failed to send message");
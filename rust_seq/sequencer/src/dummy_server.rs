@@ -3,10 +3,25 @@ use tokio::sync::mpsc as tokio_mpsc;
 use std::error::Error;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 use async_trait::async_trait;
-use network::{Receiver, MessageHandler, Writer};
+use network::{Receiver, MessageHandler, Peer, Writer};
 
 pub mod sequencer;
 mod signature;
+pub mod store;
+pub mod sync;
+pub mod checkpoint;
+pub mod committee;
+pub mod crypto_pool;
+pub mod avid;
+pub mod sampling;
+pub mod inclusion;
+pub mod fair;
+pub mod sealed;
+pub mod l1;
+pub mod delivery;
+#[cfg(test)]
+#[path = "tests/fixtures.rs"]
+pub mod fixtures;
 
 const PORT:u16 = 13330;
 const CHANNEL_CAPACITY: usize = 3;
@@ -30,7 +45,7 @@ struct PeerReceiverHandler {
 }
 #[async_trait]
 impl MessageHandler for PeerReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes)
+    async fn dispatch(&self, _writer: &mut Writer, _peer: &Peer, message: Bytes)
         -> Result<(), Box<dyn Error>>
     {
         self.tx_recv
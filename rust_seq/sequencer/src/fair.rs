@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as tk_mutex;
+use bytes::Bytes;
+use async_trait::async_trait;
+use network::{MessageHandler, Peer, Writer};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::committee::Committee;
+use crate::sequencer::U8Arr;
+
+#[cfg(test)]
+#[path = "tests/fair_tests.rs"]
+pub mod fair_tests;
+
+/// Clients send transactions to the node's port plus this offset.
+pub const TX_PORT_OFFSET: u16 = 2000;
+/// Max transactions a node reports in one batch, the rest wait for the next.
+pub const MAX_REPORTED_TXS: usize = 10_000;
+/// Max bytes of transactions a node reports in one batch.
+pub const MAX_REPORTED_BYTES: usize = 4 * 1024 * 1024;
+/// Transactions larger than this (in bytes) are refused.
+pub const MAX_TX_SIZE: usize = 64 * 1024;
+/// The mempool refuses transactions once it holds this many,
+pub const MEMPOOL_TXS: usize = 100_000;
+/// or this many bytes of them.
+pub const MEMPOOL_BYTES: usize = 64 * 1024 * 1024;
+/// Rounds a transaction may wait for a quorum of reports before it is dropped.
+pub const FAIR_WINDOW: u32 = 10;
+
+const REPORT_TAG: &[u8] = b"report";
+
+/*
+* How the transactions of a round are ordered. With Rounds, a batch is opaque
+* and ordered by its round and originator, so the node that receives a
+* transaction decides when it is included. With Fair, clients send their
+* transactions to every member, and a member's batch reports the ones it
+* received with its local receive time; the order comes from the reports of a
+* quorum (see FairOrderer).
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TxOrder {
+    #[default]
+    Rounds,
+    Fair,
+}
+
+impl FromStr for TxOrder {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "rounds" => Ok(TxOrder::Rounds),
+            "fair" => Ok(TxOrder::Fair),
+            _ => Err(format!("unknown ordering {}", s)),
+        }
+    }
+}
+
+/* the transactions a member received since its last batch, with their receive times in µs */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub txs: Vec<(U8Arr, u64)>,
+}
+
+impl Report {
+    pub fn to_payload(&self) -> U8Arr {
+        let mut payload = REPORT_TAG.to_vec();
+        payload.extend(bincode::serialize(self).unwrap());
+        payload
+    }
+
+    pub fn from_payload(payload:&[u8]) -> Option<Report> {
+        payload.strip_prefix(REPORT_TAG)
+            .and_then(|bytes| bincode::deserialize(bytes).ok())
+    }
+
+    /* the transactions taken from the report: no more than an honest member puts in it */
+    pub fn sequenced(&self) -> impl Iterator<Item = &(U8Arr, u64)> {
+        self.txs.iter()
+            .take(MAX_REPORTED_TXS)
+            .filter(|(tx, _)| tx.len() <= MAX_TX_SIZE)
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+pub use das::tx_digest;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MempoolError {
+    #[error("Transaction of {0} bytes is larger than {MAX_TX_SIZE}")]
+    TooLarge(usize),
+
+    #[error("Mempool is full")]
+    Full,
+}
+
+/* transactions received from clients, not reported yet, bounded by MEMPOOL_TXS and MEMPOOL_BYTES */
+#[derive(Default)]
+pub struct Mempool {
+    txs: Vec<(U8Arr, u64)>,
+    digests: HashSet<U8Arr>,
+    bytes: usize,
+}
+
+impl Mempool {
+    /* stamps a transaction with the time it is received; duplicates are ignored */
+    pub fn add(&mut self, tx:U8Arr) -> Result<(), MempoolError> {
+        if tx.len() > MAX_TX_SIZE {
+            return Err(MempoolError::TooLarge(tx.len()));
+        }
+        if self.txs.len() >= MEMPOOL_TXS || self.bytes + tx.len() > MEMPOOL_BYTES {
+            return Err(MempoolError::Full);
+        }
+        if self.digests.insert(tx_digest(&tx)) {
+            self.bytes += tx.len();
+            self.txs.push((tx, now_micros()));
+        }
+        Ok(())
+    }
+
+    /* the oldest transactions, up to MAX_REPORTED_TXS and MAX_REPORTED_BYTES */
+    pub fn report(&mut self) -> Report {
+        let (mut count, mut bytes) = (0, 0);
+        for (tx, _) in self.txs.iter().take(MAX_REPORTED_TXS) {
+            if bytes + tx.len() > MAX_REPORTED_BYTES {
+                break;
+            }
+            count += 1;
+            bytes += tx.len();
+        }
+        let txs: Vec<(U8Arr, u64)> = self.txs.drain(..count).collect();
+        for (tx, _) in txs.iter() {
+            self.digests.remove(&tx_digest(tx));
+        }
+        self.bytes -= bytes;
+        Report { txs }
+    }
+}
+
+struct PendingTx {
+    tx: U8Arr,
+    first_round: u32,
+    times: HashMap<u32, u64>, // times[member] -> receive time it reported
+}
+
+/*
+* FairOrderer computes the fair order from the reports of each delivered
+* round, in round order, so every node gets the same order. A transaction is
+* ordered in the first round where members with a quorum of the stake have
+* reported it, at the stake-weighted median of their receive times. At most f
+* of that stake is faulty, so the median lies between two times reported by
+* correct members: a faulty member alone cannot move a transaction before one
+* that every correct member received earlier.
+* A transaction that gathers its quorum in a later round is ordered in that
+* round, after the transactions ordered before it, whatever its median.
+* The digests of the ordered transactions are kept until their round is
+* pruned (see Checkpoints::start_pruning), so a transaction reported again
+* until then is not ordered twice. One submitted again after that is ordered
+* again: a client whose transaction must run once makes it unique (e.g. with
+* a nonce), and the application drops the replays.
+*/
+#[derive(Default)]
+pub struct FairOrderer {
+    pending: HashMap<U8Arr, PendingTx>,
+    ordered: HashMap<U8Arr, u32>, // ordered[digest] -> round, to ignore later reports
+}
+
+impl FairOrderer {
+    /* `reports` are the (originator, report) of the round's delivered batches */
+    pub fn add_round(&mut self, round:u32, committee:&Committee, reports:Vec<(u32, Report)>) -> Vec<U8Arr> {
+        for (member, report) in reports {
+            if !committee.contains(member) {
+                continue;
+            }
+            for (tx, time) in report.sequenced() {
+                let digest = tx_digest(tx);
+                if self.ordered.contains_key(&digest) {
+                    continue;
+                }
+                self.pending.entry(digest)
+                    .or_insert_with(|| PendingTx { tx: tx.clone(), first_round: round, times: HashMap::new() })
+                    .times
+                    .entry(member)
+                    .or_insert(*time);
+            }
+        }
+
+        let mut ready: Vec<(u64, U8Arr)> = self.pending.iter()
+            .filter(|(_, pending)| committee.stake(pending.times.keys().copied()) >= committee.quorum())
+            .map(|(digest, pending)| (median_time(committee, &pending.times), digest.clone()))
+            .collect();
+        ready.sort();
+
+        let mut order = Vec::with_capacity(ready.len());
+        for (_, digest) in ready {
+            let pending = self.pending.remove(&digest).unwrap();
+            self.ordered.insert(digest, round);
+            order.push(pending.tx);
+        }
+        self.pending.retain(|_, pending| pending.first_round + FAIR_WINDOW > round);
+        order
+    }
+
+    /* forgets the transactions ordered in the rounds below */
+    pub fn prune(&mut self, below:u32) {
+        self.ordered.retain(|_, ordered_round| *ordered_round >= below);
+    }
+}
+
+/* the first reported time at which half of the reporting stake is reached */
+fn median_time(committee:&Committee, times:&HashMap<u32, u64>) -> u64 {
+    let mut times: Vec<(u64, u32)> = times.iter().map(|(member, time)| (*time, *member)).collect();
+    times.sort();
+    let total: u64 = times.iter().map(|(_, member)| committee.stake_of(*member)).sum();
+    let mut stake = 0;
+    for (time, member) in times.iter() {
+        stake += committee.stake_of(*member);
+        if 2 * stake >= total {
+            return *time;
+        }
+    }
+    unreachable!("a quorum reported the transaction")
+}
+
+/*
+* Takes client transactions, one per frame, into the mempool. A transaction
+* is dropped while the mempool is full; one that is too large ends the
+* connection (the frames are capped at MAX_TX_SIZE already, see
+* Sequencer::spawn_tx_receiver).
+*
+* Nothing is sent back. Once the round reporting the transaction is covered
+* by a stable checkpoint, the client gets its inclusion proof, with the round
+* root and the checkpoint certificate, from the DAS endpoint of the node it
+* submitted to (see das::DasClient::tx_inclusion).
+*/
+#[derive(Clone)]
+pub struct TxReceiverHandler {
+    pub mempool: Arc<tk_mutex<Mempool>>,
+}
+
+#[async_trait]
+impl MessageHandler for TxReceiverHandler {
+    async fn dispatch(&self, _writer: &mut Writer, peer: &Peer, message: Bytes)
+        -> Result<(), Box<dyn Error>>
+    {
+        match self.mempool.lock().await.add(message.to_vec()) {
+            Err(MempoolError::Full) => eprintln!("mempool is full, dropped a transaction from {}", peer.address),
+            result => result?,
+        }
+        Ok(())
+    }
+}
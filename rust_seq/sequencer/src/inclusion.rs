@@ -0,0 +1,147 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use das::merkle::MerkleTree;
+use das::{batch_leaf, tx_leaf, CheckpointCert, Inclusion};
+
+use crate::sequencer::U8Arr;
+
+#[cfg(test)]
+#[path = "tests/inclusion_tests.rs"]
+pub mod inclusion_tests;
+
+/// Rounds whose trees are kept to serve inclusion proofs.
+pub const ROUND_ROOTS_KEPT: usize = 1024;
+
+/*
+* The tree of a folded round: a leaf per batch, in committee order, then a
+* leaf per transaction reported in them (see Report::sequenced). Every node
+* builds the same tree, its root goes into the checkpoint's data root.
+*/
+pub struct RoundTree {
+    round: u32,
+    originators: Vec<u32>, // originators[i] -> originator of leaf i
+    txs: HashMap<(u32, U8Arr), usize>, // (originator, tx digest) -> leaf
+    tree: MerkleTree,
+}
+
+impl RoundTree {
+    /*
+    * `batch_digests` are the round's (originator, batch digest), in committee
+    * order, and `tx_digests` the (originator, tx digest) of the reported
+    * transactions, in batch order.
+    */
+    pub fn new(round:u32, batch_digests:&[(u32, U8Arr)], tx_digests:&[(u32, U8Arr)]) -> Self {
+        let leaves: Vec<U8Arr> = batch_digests.iter()
+            .map(|(originator, batch_digest)| batch_leaf(*originator, batch_digest))
+            .chain(tx_digests.iter().map(|(originator, tx_digest)| tx_leaf(*originator, tx_digest)))
+            .collect();
+        let tree = MerkleTree::new(&leaves);
+        let originators = batch_digests.iter().map(|(originator, _)| *originator).collect();
+        let mut txs = HashMap::with_capacity(tx_digests.len());
+        for (index, tx) in tx_digests.iter().enumerate() {
+            txs.entry(tx.clone()).or_insert(batch_digests.len() + index);
+        }
+        RoundTree { round, originators, txs, tree }
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn root(&self) -> U8Arr {
+        self.tree.root()
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        self.originators.len() + self.txs.len()
+    }
+
+    pub fn batch_index(&self, originator:u32) -> Option<usize> {
+        self.originators.iter().position(|id| *id == originator)
+    }
+
+    pub fn tx_index(&self, originator:u32, tx_digest:&[u8]) -> Option<usize> {
+        self.txs.get(&(originator, tx_digest.to_vec())).copied()
+    }
+}
+
+/*
+* The trees of the last ROUND_ROOTS_KEPT folded rounds. Rounds are folded in
+* order, so the trees are kept in round order too.
+*/
+#[derive(Default)]
+pub struct RoundRoots {
+    trees: VecDeque<Arc<RoundTree>>,
+    txs: HashMap<(u32, U8Arr), BTreeSet<u32>>, // (originator, tx digest) -> kept rounds reporting it
+}
+
+impl RoundRoots {
+    pub fn push(&mut self, tree:RoundTree) {
+        if let Some(last) = self.trees.back() {
+            assert_eq!(tree.round, last.round + 1, "rounds are folded in order");
+        }
+        if self.trees.len() == ROUND_ROOTS_KEPT {
+            if let Some(oldest) = self.trees.pop_front() {
+                for tx in oldest.txs.keys() {
+                    if let Some(rounds) = self.txs.get_mut(tx) {
+                        rounds.remove(&oldest.round);
+                        if rounds.is_empty() {
+                            self.txs.remove(tx);
+                        }
+                    }
+                }
+            }
+        }
+        for tx in tree.txs.keys() {
+            self.txs.entry(tx.clone()).or_default().insert(tree.round);
+        }
+        self.trees.push_back(Arc::new(tree));
+    }
+
+    pub fn get(&self, round:u32) -> Option<Arc<RoundTree>> {
+        let first = self.trees.front()?.round;
+        self.trees.get(round.checked_sub(first)? as usize).cloned()
+    }
+
+    /* the round a transaction of the originator was first reported in, among those kept */
+    pub fn tx_round(&self, originator:u32, tx_digest:&[u8]) -> Option<u32> {
+        self.txs.get(&(originator, tx_digest.to_vec()))?.first().copied()
+    }
+
+    fn data_tree(&self, from:u32, to:u32) -> Option<MerkleTree> {
+        let roots = (from..to)
+            .map(|round| self.get(round).map(|tree| tree.root()))
+            .collect::<Option<Vec<U8Arr>>>()?;
+        Some(MerkleTree::new(&roots))
+    }
+
+    /* the Merkle root of the round roots of [from, to), None if one is not kept */
+    pub fn data_root(&self, from:u32, to:u32) -> Option<U8Arr> {
+        self.data_tree(from, to).map(|tree| tree.root())
+    }
+
+    /*
+    * The proof that leaf `index` is in the round, with `checkpoint`, the
+    * stable checkpoint whose interval covers the round.
+    */
+    pub fn inclusion(&self, round:u32, index:usize, checkpoint:CheckpointCert) -> Option<Inclusion> {
+        let from = checkpoint.rn.checked_sub(das::CHECKPOINT_INTERVAL)?;
+        if !(from..checkpoint.rn).contains(&round) {
+            return None;
+        }
+        let tree = self.get(round)?;
+        if index >= tree.num_leaves() {
+            return None;
+        }
+        let data_tree = self.data_tree(from, checkpoint.rn)?;
+        Some(Inclusion {
+            round,
+            index: index as u32,
+            num_leaves: tree.num_leaves() as u32,
+            proof: tree.tree.proof(index),
+            round_root: tree.root(),
+            round_proof: data_tree.proof((round - from) as usize),
+            checkpoint,
+        })
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::time as tk_time;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::checkpoint::CHECKPOINT_INTERVAL;
+use crate::store::{CheckpointCert, Store};
+
+#[cfg(test)]
+#[path = "tests/l1_tests.rs"]
+pub mod l1_tests;
+
+/// How often the settler checks for a new stable checkpoint and its pending tx (in ms).
+pub const L1_POLL: u64 = 1_000;
+/// Confirmations of a settlement, its own block included, before it is final.
+pub const L1_CONFIRMATIONS: u64 = 3;
+/// How often the mock chain of a node mines a block (in ms).
+pub const MOCK_BLOCK_TIME: u64 = 2_000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum L1Error {
+    #[error("Nonce {0} is stale, the chain expects {1}")]
+    StaleNonce(u64, u64),
+
+    #[error("L1 is unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("No stable checkpoint of rounds [{0}, {1}) here to settle them")]
+    NoCheckpoint(u32, u32),
+
+    #[error("Failed to read a checkpoint: {0}")]
+    Store(String),
+}
+
+/* where stable checkpoints are settled: `none` (default) or an in-process `mock` chain */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum L1Backend {
+    #[default]
+    None,
+    Mock,
+}
+
+impl FromStr for L1Backend {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(L1Backend::None),
+            "mock" => Ok(L1Backend::Mock),
+            _ => Err(format!("unknown L1 backend {}", s)),
+        }
+    }
+}
+
+/*
+* What is posted to the L1 for the rounds [from_round, to_round), a checkpoint
+* interval: the checkpoint certificate of to_round, in which 2f+1 nodes sign
+* the digest of the log up to it and the data root of the interval, the
+* Merkle root of its round roots (see RoundTree).
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Settlement {
+    pub from_round: u32,
+    pub to_round: u32,
+    pub cert: CheckpointCert,
+}
+
+pub type L1TxId = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum L1TxStatus {
+    Pending,
+    Included{ confirmations: u64 },
+    /* included, but the contract rejected it */
+    Reverted(String),
+    /* neither pending nor in the chain, e.g. dropped after a reorg */
+    Unknown,
+}
+
+/*
+* An L1 account of the node, and the settlement contract it posts to. The
+* contract takes settlements in round order, each starting where the previous
+* one ended, and checks their certificate.
+*/
+#[async_trait]
+pub trait L1Poster: Send + Sync {
+    /* rounds below are settled in the current chain, final or not */
+    async fn settled_round(&self) -> Result<u32, L1Error>;
+
+    /* the nonce of the account's next tx */
+    async fn next_nonce(&self) -> Result<u64, L1Error>;
+
+    async fn submit(&self, nonce:u64, settlement:Settlement) -> Result<L1TxId, L1Error>;
+
+    async fn status(&self, tx:L1TxId) -> Result<L1TxStatus, L1Error>;
+}
+
+/*
+* Settler posts the stable checkpoints of the node, one settlement at a time,
+* each of the checkpoint interval after where the chain is settled, from the
+* certificates kept in the store. So a node that restarts, or an L1 that was
+* down for several intervals, settles them in turn.
+* Every node runs one: the first settlement of a range to be included wins,
+* and the others revert. A settlement that is dropped (e.g. reorged out and
+* evicted) is built again from where the chain is settled, and resubmitted.
+*/
+pub struct Settler {
+    poster: Arc<dyn L1Poster>,
+    in_flight: Option<(L1TxId, Settlement)>,
+}
+
+impl Settler {
+    pub fn new(poster:Arc<dyn L1Poster>) -> Self {
+        Settler { poster, in_flight: None }
+    }
+
+    /*
+    * One poll: follows the tx in flight, or submits the next interval once a
+    * later one is stable. Fails if the certificate of that interval is not
+    * in the store, e.g. it was stable before we joined.
+    */
+    pub async fn step(&mut self, store:&dyn Store) -> Result<(), L1Error> {
+        if let Some((tx, settlement)) = &self.in_flight {
+            match self.poster.status(*tx).await? {
+                L1TxStatus::Pending => return Ok(()),
+                L1TxStatus::Included{ confirmations } if confirmations < L1_CONFIRMATIONS => return Ok(()),
+                L1TxStatus::Included{ .. } => {
+                    println!("rounds [{}, {}) are settled on L1", settlement.from_round, settlement.to_round);
+                }
+                L1TxStatus::Reverted(reason) => {
+                    println!("settlement of rounds [{}, {}) reverted: {}", settlement.from_round, settlement.to_round, reason);
+                }
+                L1TxStatus::Unknown => {
+                    eprintln!("settlement of rounds [{}, {}) was dropped, resubmitting", settlement.from_round, settlement.to_round);
+                }
+            }
+            self.in_flight = None;
+        }
+
+        let latest = match store.latest_checkpoint().map_err(|e| L1Error::Store(e.to_string()))? {
+            Some(latest) => latest,
+            None => return Ok(()),
+        };
+        let settled = self.poster.settled_round().await?;
+        if latest.rn <= settled {
+            return Ok(());
+        }
+        let to_round = settled + CHECKPOINT_INTERVAL;
+        let cert = store.checkpoint(to_round)
+            .map_err(|e| L1Error::Store(e.to_string()))?
+            .ok_or(L1Error::NoCheckpoint(settled, to_round))?;
+        let settlement = Settlement { from_round: settled, to_round, cert };
+        let nonce = self.poster.next_nonce().await?;
+        let tx = self.poster.submit(nonce, settlement.clone()).await?;
+        self.in_flight = Some((tx, settlement));
+        Ok(())
+    }
+}
+
+struct MockTx {
+    id: L1TxId,
+    sender: u32,
+    nonce: u64,
+    settlement: Settlement,
+}
+
+#[derive(Default)]
+struct ChainState {
+    blocks: Vec<Vec<(MockTx, Result<(), String>)>>,
+    pending: Vec<MockTx>,
+    next_id: L1TxId,
+    down: bool,
+}
+
+impl ChainState {
+    fn check_up(&self) -> Result<(), L1Error> {
+        match self.down {
+            true => Err(L1Error::Unavailable("mock chain is down".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    fn included(&self) -> impl Iterator<Item = &(MockTx, Result<(), String>)> {
+        self.blocks.iter().flatten()
+    }
+
+    fn settled_round(&self) -> u32 {
+        self.included()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(tx, _)| tx.settlement.to_round)
+            .last()
+            .unwrap_or(0)
+    }
+
+    fn next_nonce(&self, sender:u32) -> u64 {
+        self.included().filter(|(tx, _)| tx.sender == sender).count() as u64
+    }
+}
+
+/*
+* An in-process chain for tests and local runs. Blocks are mined on demand,
+* and can be reorged out. The contract checks that a settlement starts where
+* the last one ended and that its certificate has `quorum` distinct signers;
+* the signatures themselves are left to a real contract, which knows the keys
+* of the committee.
+*/
+pub struct MockChain {
+    quorum: usize,
+    state: Mutex<ChainState>,
+}
+
+impl MockChain {
+    pub fn new(quorum:usize) -> Self {
+        MockChain { quorum, state: Mutex::new(ChainState::default()) }
+    }
+
+    /* the account of `sender` on this chain */
+    pub fn poster(self:&Arc<Self>, sender:u32) -> MockPoster {
+        MockPoster { chain: self.clone(), sender }
+    }
+
+    /* includes the pending txs whose nonce is next for their sender, in submission order */
+    pub fn mine(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut block: Vec<(MockTx, Result<(), String>)> = Vec::new();
+        let pending = std::mem::take(&mut state.pending);
+        for tx in pending {
+            let next_nonce = state.next_nonce(tx.sender)
+                + block.iter().filter(|(included, _)| included.sender == tx.sender).count() as u64;
+            if tx.nonce != next_nonce {
+                state.pending.push(tx);
+                continue;
+            }
+            let settled = block.iter()
+                .filter(|(_, result)| result.is_ok())
+                .map(|(included, _)| included.settlement.to_round)
+                .next_back()
+                .unwrap_or_else(|| state.settled_round());
+            let result = self.execute(settled, &tx.settlement);
+            block.push((tx, result));
+        }
+        state.blocks.push(block);
+    }
+
+    /* drops the last `depth` blocks, their txs go back to the pool */
+    pub fn reorg(&self, depth:usize) {
+        let mut state = self.state.lock().unwrap();
+        let keep = state.blocks.len().saturating_sub(depth);
+        let dropped: Vec<MockTx> = state.blocks.drain(keep..).flatten().map(|(tx, _)| tx).collect();
+        state.pending.splice(0..0, dropped);
+    }
+
+    /* evicts every pending tx */
+    pub fn drop_pending(&self) {
+        self.state.lock().unwrap().pending.clear();
+    }
+
+    /* mines a block every `block_time` ms */
+    pub fn spawn_miner(self:&Arc<Self>, block_time:u64) {
+        let chain = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tk_time::interval(tk_time::Duration::from_millis(block_time));
+            loop {
+                interval.tick().await;
+                chain.mine();
+            }
+        });
+    }
+
+    /* while down, every call of a poster fails */
+    pub fn set_down(&self, down:bool) {
+        self.state.lock().unwrap().down = down;
+    }
+
+    fn execute(&self, settled:u32, settlement:&Settlement) -> Result<(), String> {
+        if settlement.from_round != settled {
+            return Err(format!("rounds from {} are not next, settled up to {}", settlement.from_round, settled));
+        }
+        if settlement.to_round != settlement.from_round + CHECKPOINT_INTERVAL || settlement.to_round != settlement.cert.rn {
+            return Err(format!("bad range [{}, {})", settlement.from_round, settlement.to_round));
+        }
+        let signers: HashSet<u32> = settlement.cert.signs.iter().map(|(signer, _)| *signer).collect();
+        if signers.len() < self.quorum {
+            return Err(format!("{} signers, {} needed", signers.len(), self.quorum));
+        }
+        Ok(())
+    }
+}
+
+pub struct MockPoster {
+    chain: Arc<MockChain>,
+    sender: u32,
+}
+
+#[async_trait]
+impl L1Poster for MockPoster {
+    async fn settled_round(&self) -> Result<u32, L1Error> {
+        let state = self.chain.state.lock().unwrap();
+        state.check_up()?;
+        Ok(state.settled_round())
+    }
+
+    async fn next_nonce(&self) -> Result<u64, L1Error> {
+        let state = self.chain.state.lock().unwrap();
+        state.check_up()?;
+        Ok(state.next_nonce(self.sender))
+    }
+
+    async fn submit(&self, nonce:u64, settlement:Settlement) -> Result<L1TxId, L1Error> {
+        let mut state = self.chain.state.lock().unwrap();
+        state.check_up()?;
+        let next_nonce = state.next_nonce(self.sender);
+        if nonce < next_nonce {
+            return Err(L1Error::StaleNonce(nonce, next_nonce));
+        }
+        // a tx with the same nonce replaces the pending one
+        let sender = self.sender;
+        state.pending.retain(|tx| tx.sender != sender || tx.nonce != nonce);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.push(MockTx { id, sender, nonce, settlement });
+        Ok(id)
+    }
+
+    async fn status(&self, tx:L1TxId) -> Result<L1TxStatus, L1Error> {
+        let state = self.chain.state.lock().unwrap();
+        state.check_up()?;
+        if state.pending.iter().any(|pending| pending.id == tx) {
+            return Ok(L1TxStatus::Pending);
+        }
+        for (height, block) in state.blocks.iter().enumerate() {
+            if let Some((_, result)) = block.iter().find(|(included, _)| included.id == tx) {
+                return Ok(match result {
+                    Ok(()) => L1TxStatus::Included{ confirmations: (state.blocks.len() - height) as u64 },
+                    Err(reason) => L1TxStatus::Reverted(reason.clone()),
+                });
+            }
+        }
+        Ok(L1TxStatus::Unknown)
+    }
+}
@@ -3,13 +3,43 @@ use tokio::sync::mpsc as tokio_mpsc;
 use bytes::Bytes;
 use tokio::signal;
 use std::sync::Arc;
+use std::collections::HashMap;
 use std::fs;
 
 pub mod sequencer;
 mod signature;
+pub mod store;
+pub mod sync;
+pub mod checkpoint;
+pub mod committee;
+pub mod crypto_pool;
+pub mod avid;
+pub mod sampling;
+pub mod inclusion;
+pub mod fair;
+pub mod sealed;
+pub mod l1;
+pub mod delivery;
+#[cfg(test)]
+#[path = "tests/fixtures.rs"]
+pub mod fixtures;
 use sequencer::*;
+use store::FileStore;
+use signature::{BlsKeyPair, CertScheme, KeyPair};
+use crypto_pool::{CryptoPool, CRYPTO_QUEUE_DEPTH};
+use avid::Dispersal;
+use fair::TxOrder;
+use sealed::TxPrivacy;
+use l1::{L1Backend, MockChain, MOCK_BLOCK_TIME};
+use delivery::{Delivery, LinkSecurity};
+use network::{Overflow, Peer, QueueConfig, SecureConfig};
 
-const CHANNEL_CAPACITY: usize = 1_000_000;
+/*
+* The sender task no longer waits on a slow peer (its queue drops instead, see
+* QueueConfig), so a full channel means this node is overloaded: waiting is the
+* backpressure we want, it eventually stops reading from the peers' sockets.
+*/
+const CHANNEL_CAPACITY: usize = 10_000;
 
 #[tokio::main]
 async fn main() {
@@ -20,38 +50,135 @@ async fn main() {
         .split_whitespace();
     let num_nodes: u32 = iter.next().unwrap().parse().unwrap();
     let payload_size:usize = iter.next().unwrap().parse().unwrap();
+    // threads hashing and checking signatures, one per core unless configured
+    let crypto_threads: usize = iter.next().map_or_else(
+        || std::thread::available_parallelism().map_or(1, |n| n.get()),
+        |s| s.parse().expect("failed to parse crypto threads")
+    );
+    // `ed25519` (default) or `bls`, for aggregated certificates
+    let scheme: CertScheme = iter.next().map_or(CertScheme::Ed25519, |s| s.parse().unwrap());
+    // `full` (default) or `avid`, to erasure code batches
+    let dispersal: Dispersal = iter.next().map_or(Dispersal::Full, |s| s.parse().unwrap());
+    // `rounds` (default) or `fair`, to order client transactions by receive times
+    let tx_order: TxOrder = iter.next().map_or(TxOrder::Rounds, |s| s.parse().unwrap());
+    // `plain` (default) or `sealed`, for txs committed before they are revealed
+    let tx_privacy: TxPrivacy = iter.next().map_or(TxPrivacy::Plain, |s| s.parse().unwrap());
+    // `none` (default) or `mock`, to settle stable checkpoints on an L1
+    let l1: L1Backend = iter.next().map_or(L1Backend::None, |s| s.parse().unwrap());
+    // `simple` (default) or `reliable`, to retransmit the messages of a round until acknowledged
+    let delivery: Delivery = iter.next().map_or(Delivery::Simple, |s| s.parse().unwrap());
+    // `plain` (default) or `secure`, to authenticate and encrypt the links to peers
+    let links: LinkSecurity = iter.next().map_or(LinkSecurity::Plain, |s| s.parse().unwrap());
+    // `drop-lowest` (default, bulk first), `drop-oldest`, `drop-newest` or `disconnect`, when the queue to a peer is full
+    let overflow: Overflow = iter.next().map_or(Overflow::DropLowest, |s| s.parse().unwrap());
     let node_ind: u32 = std::env::args()
         .nth(1)
         .expect("usage: cargo r --bin seq -- <NODE_INDEX>")
         .parse()
         .unwrap();
-    let address_book: Vec<SocketAddr> = fp_str.lines()
+    // each line is `<address> [stake] [hex public key]`, the stake defaults to 1
+    let nodes: Vec<(SocketAddr, u64, Option<Vec<u8>>)> = fp_str.lines()
         .skip(1)
-        .map(|s| s.parse().expect("failed to parse SocketAddr"))
+        .map(|s| {
+            let mut iter = s.split_whitespace();
+            let address: SocketAddr = iter.next().unwrap().parse().expect("failed to parse SocketAddr");
+            let stake = iter.next().map_or(1, |s| s.parse().expect("failed to parse stake"));
+            let pub_key = iter.next().map(|s| committee::from_hex(s).expect("failed to parse public key"));
+            (address, stake, pub_key)
+        })
         .collect();
+    let address_book: Vec<SocketAddr> = nodes.iter().map(|(address, _, _)| *address).collect();
+    let stakes: Vec<u64> = nodes.iter().map(|(_, stake, _)| *stake).collect();
 
-    println!("# of node {}, node ind {}, payload {}\naddress_book: {:?}", 
+    println!("# of node {}, node ind {}, payload {}, crypto threads {}, certificates {:?}, dispersal {:?}, ordering {:?}, privacy {:?}, l1 {:?}, delivery {:?}, links {:?}, overflow {:?}\naddress_book: {:?}\nstakes: {:?}", 
         num_nodes, 
         node_ind, 
         payload_size, 
-        address_book);
-    assert!(node_ind < num_nodes);
+        crypto_threads,
+        scheme,
+        dispersal,
+        tx_order,
+        tx_privacy,
+        l1,
+        delivery,
+        links,
+        overflow,
+        address_book,
+        stakes);
+    // nodes past num_nodes are not in the first committee, they join later
+    assert!((node_ind as usize) < address_book.len());
 
-    let (tx_recv, rx_recv) = tokio_mpsc::channel::<Bytes>(CHANNEL_CAPACITY);
+    let (tx_recv, rx_recv) = tokio_mpsc::channel::<(Peer, Bytes)>(CHANNEL_CAPACITY);
     let (tx_send, rx_send) = tokio_mpsc::channel::<CastType>(CHANNEL_CAPACITY);
     let measurement = Arc::new(MeasureDs::new());
+    let key_path = format!("./keys/node_{}.pk8", node_ind);
+    let keypair = KeyPair::load_or_generate(&key_path).expect("failed to load key");
+    println!("public key: {}", committee::to_hex(&keypair.pub_key));
+    let store = Arc::new(
+        FileStore::open(format!("./store/node_{}", node_ind))
+            .expect("failed to open store")
+    );
 
-    let curr_node = Sequencer::new(
+    let crypto = Arc::new(CryptoPool::new(crypto_threads, CRYPTO_QUEUE_DEPTH));
+    let keys: Vec<Option<Vec<u8>>> = nodes.iter().map(|(_, _, pub_key)| pub_key.clone()).collect();
+    let genesis = committee::Committee::genesis(&address_book, num_nodes)
+        .with_stakes(&stakes)
+        .with_keys(&keys);
+    let quorum = genesis.quorum() as usize;
+    let mut curr_node = Sequencer::new(
         node_ind, 
-        num_nodes, 
-        address_book, 
+        genesis,
+        address_book[node_ind as usize],
         payload_size,
+        keypair,
+        store,
         measurement.clone()
-    );
+    )
+    .with_crypto_pool(crypto.clone())
+    .with_dispersal(dispersal)
+    .with_tx_order(tx_order)
+    .with_tx_privacy(tx_privacy)
+    .with_delivery(delivery)
+    .with_queues(QueueConfig{ overflow, ..QueueConfig::default() });
+    if links == LinkSecurity::Secure {
+        // the links are authenticated with the key of the Syn, and only to the
+        // nodes of ip.config, each at its own address
+        let peer_keys: HashMap<SocketAddr, Vec<u8>> = nodes.iter()
+            .map(|(address, _, pub_key)| {
+                let pub_key = pub_key.clone().expect("secure links need the public key of every node in ip.config");
+                (*address, pub_key)
+            })
+            .collect();
+        let committee_keys: Vec<Vec<u8>> = peer_keys.values().cloned().collect();
+        let pkcs8 = fs::read(&key_path).expect("failed to read key");
+        let secure = SecureConfig::from_pkcs8(&pkcs8)
+            .expect("failed to load key")
+            .with_authorize(move |k| committee_keys.iter().any(|key| key == k))
+            .with_peer_keys(peer_keys);
+        curr_node = curr_node.with_secure_links(secure);
+    }
+    if scheme == CertScheme::Bls {
+        let bls = BlsKeyPair::load_or_generate(format!("./keys/node_{}.bls", node_ind))
+            .expect("failed to load BLS key");
+        curr_node = curr_node.with_bls(bls);
+    }
 
     curr_node.spawn_receiver(tx_recv);
+    curr_node.spawn_das();
+    if tx_order == TxOrder::Fair {
+        curr_node.spawn_tx_receiver();
+    }
+    if l1 == L1Backend::Mock {
+        // a chain of our own: it shows the flow, not agreement with the others' chains
+        let chain = Arc::new(MockChain::new(quorum));
+        chain.spawn_miner(MOCK_BLOCK_TIME);
+        curr_node.spawn_settler(Arc::new(chain.poster(node_ind)));
+    }
     curr_node.spawn_sender(rx_send);
     curr_node.spawn_periodic_sender(tx_send.clone()); //, tx_main);
+    curr_node.spawn_sync(tx_send.clone());
+    curr_node.spawn_checkpointer(tx_send.clone());
+    curr_node.spawn_reconfig_watcher(format!("./reconfig/node_{}.config", node_ind).into());
     tokio::spawn(async move {
         curr_node.run_main_loop(rx_recv, tx_send).await;
     });
@@ -68,6 +195,8 @@ async fn main() {
     // it off for now 
     tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
 
+    println!("{}", crypto.metrics());
+
     measurement.write_measurements(
         format!("./eval/node_{}.eval", node_ind), 
         node_ind,
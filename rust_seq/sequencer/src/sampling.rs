@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex as tk_mutex;
+use bytes::Bytes;
+use async_trait::async_trait;
+use futures::sink::SinkExt as _;
+use network::{MessageHandler, Peer, Writer};
+use das::merkle::MerkleTree;
+use das::{DasHeader, DasRequest, DasResponse, DasSample, CHECKPOINT_INTERVAL, DAS_MAX_SAMPLES};
+
+use crate::avid::{self, Dispersal};
+use crate::inclusion::RoundRoots;
+use crate::crypto_pool::CryptoPool;
+use crate::sequencer::U8Arr;
+use crate::store::{Record, Store};
+
+#[cfg(test)]
+#[path = "tests/sampling_tests.rs"]
+pub mod sampling_tests;
+
+/// The DAS endpoint of a node listens on the node's port plus this offset.
+pub const DAS_PORT_OFFSET: u16 = 1000;
+
+/* a folded batch, coded for sampling */
+pub struct Coded {
+    header: DasHeader,
+    chunks: Vec<U8Arr>,
+    tree: MerkleTree,
+}
+
+impl Coded {
+    /*
+    * Codes a delivered batch. The root is part of the certified digest (see
+    * avid::batch_digest), so the batch is recomputed with it as a check:
+    * None if it does not match.
+    */
+    pub fn new(dispersal:Dispersal, num_chunks:usize, record:Record) -> Option<Self> {
+        let chunks = avid::das_chunks(&record.payload);
+        let tree = MerkleTree::new(&chunks);
+        let header = DasHeader {
+            originator: record.originator,
+            round: record.round,
+            payload_digest: avid::payload_digest(dispersal, num_chunks, &record.payload),
+            root: tree.root(),
+            cert: record.certificate,
+        };
+        (header.digest() == record.digest).then_some(Coded { header, chunks, tree })
+    }
+}
+
+/*
+* The coded batches of the folded rounds, by (round, originator), kept until
+* the rounds are pruned.
+*/
+#[derive(Default)]
+pub struct Sampled {
+    coded: BTreeMap<(u32, u32), Arc<Coded>>,
+    below: u32, // rounds below are pruned
+}
+
+impl Sampled {
+    pub fn insert(&mut self, coded:Coded) {
+        if coded.header.round >= self.below {
+            self.coded.insert((coded.header.round, coded.header.originator), Arc::new(coded));
+        }
+    }
+
+    pub fn get(&self, originator:u32, round:u32) -> Option<Arc<Coded>> {
+        self.coded.get(&(round, originator)).cloned()
+    }
+
+    pub fn prune(&mut self, below:u32) {
+        self.below = self.below.max(below);
+        self.coded = self.coded.split_off(&(self.below, 0));
+    }
+
+    pub fn len(&self) -> usize {
+        self.coded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coded.is_empty()
+    }
+}
+
+/* codes a folded batch on the crypto pool, and keeps it for sampling */
+pub async fn code_batch(
+    crypto:Arc<CryptoPool>,
+    sampled:Arc<tk_mutex<Sampled>>,
+    dispersal:Dispersal,
+    num_chunks:usize,
+    record:Record,
+){
+    let (originator, round) = (record.originator, record.round);
+    match crypto.run(move || Coded::new(dispersal, num_chunks, record)).await {
+        Some(coded) => sampled.lock().await.insert(coded),
+        None => eprintln!("das: {}'s msg of round {} does not match its digest", originator, round),
+    }
+}
+
+/*
+* Serves DAS requests of light clients (see the das crate). Each batch is
+* coded as its round is folded (see code_batch) and served until the round is
+* pruned; the header carries the batch's certificate, which covers the root.
+* Inclusion proofs, of a batch or of a reported transaction, come from the
+* round trees the checkpointer builds, and are served once the checkpoint
+* covering the round is stable: its certificate signs the data root.
+*/
+#[derive(Clone)]
+pub struct DasHandler {
+    sampled: Arc<tk_mutex<Sampled>>,
+    round_roots: Arc<tk_mutex<RoundRoots>>,
+    store: Arc<dyn Store>,
+}
+
+impl DasHandler {
+    pub fn new(sampled:Arc<tk_mutex<Sampled>>, round_roots:Arc<tk_mutex<RoundRoots>>, store:Arc<dyn Store>) -> Self {
+        DasHandler { sampled, round_roots, store }
+    }
+
+    pub async fn handle(&self, request:DasRequest) -> DasResponse {
+        match request {
+            DasRequest::Header{ originator, round } => match self.coded(originator, round).await {
+                Some(coded) => DasResponse::Header(coded.header.clone()),
+                None => DasResponse::NotFound,
+            },
+            DasRequest::Samples{ originator, round, indices } => {
+                let coded = match self.coded(originator, round).await {
+                    Some(coded) => coded,
+                    None => return DasResponse::NotFound,
+                };
+                let samples = indices.into_iter()
+                    .filter(|index| (*index as usize) < coded.chunks.len())
+                    .take(DAS_MAX_SAMPLES)
+                    .map(|index| DasSample {
+                        index,
+                        chunk: coded.chunks[index as usize].clone(),
+                        proof: coded.tree.proof(index as usize),
+                    })
+                    .collect();
+                DasResponse::Samples(samples)
+            }
+            DasRequest::Inclusion{ originator, round } => {
+                let index = self.round_roots.lock().await.get(round).and_then(|tree| tree.batch_index(originator));
+                self.inclusion(round, index).await
+            }
+            DasRequest::TxInclusion{ originator, tx_digest } => {
+                let round_roots = self.round_roots.lock().await;
+                let round = round_roots.tx_round(originator, &tx_digest);
+                let index = round
+                    .and_then(|round| round_roots.get(round))
+                    .and_then(|tree| tree.tx_index(originator, &tx_digest));
+                drop(round_roots);
+                match round {
+                    Some(round) => self.inclusion(round, index).await,
+                    None => DasResponse::NotFound,
+                }
+            }
+        }
+    }
+
+    /*
+    * The proof of leaf `index` of the round, up to the data root of the
+    * stable checkpoint of its interval: NotFound until there is one.
+    */
+    async fn inclusion(&self, round:u32, index:Option<usize>) -> DasResponse {
+        let rn = (round / CHECKPOINT_INTERVAL + 1) * CHECKPOINT_INTERVAL;
+        let (index, checkpoint) = match (index, self.store.checkpoint(rn)) {
+            (Some(index), Ok(Some(checkpoint))) => (index, checkpoint),
+            (_, Err(e)) => {
+                eprintln!("das: failed to read the checkpoint of round {}: {}", rn, e);
+                return DasResponse::NotFound;
+            }
+            _ => return DasResponse::NotFound,
+        };
+        match self.round_roots.lock().await.inclusion(round, index, checkpoint) {
+            Some(inclusion) => DasResponse::Inclusion(inclusion),
+            None => DasResponse::NotFound,
+        }
+    }
+
+    async fn coded(&self, originator:u32, round:u32) -> Option<Arc<Coded>> {
+        self.sampled.lock().await.get(originator, round)
+    }
+}
+
+#[async_trait]
+impl MessageHandler for DasHandler {
+    async fn dispatch(&self, writer: &mut Writer, _peer: &Peer, message: Bytes)
+        -> Result<(), Box<dyn Error>>
+    {
+        let request: DasRequest = bincode::deserialize(&message)?;
+        let response = self.handle(request).await;
+        writer.send(Bytes::from(bincode::serialize(&response)?)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::sequencer::U8Arr;
+
+#[cfg(test)]
+#[path = "tests/sealed_tests.rs"]
+pub mod sealed_tests;
+
+/// Rounds a client has to reveal its transaction once its commitment is ordered.
+pub const REVEAL_WINDOW: u32 = 20;
+/// Rounds an unrevealed commitment may hold up the revealed transactions behind it.
+pub const REVEAL_DEADLINE: u32 = 4;
+pub const SALT_LEN: usize = 32;
+
+const COMMIT_TAG: &[u8] = b"commit";
+const REVEAL_TAG: &[u8] = b"reveal";
+
+/*
+* Whether members see client transactions before they are ordered. With
+* Sealed, a client first submits a salted commitment to its transaction, and
+* reveals the transaction only once the commitment is ordered: members fix
+* the order without knowing what they order. Reveals go through the same
+* reports as commitments, so every node sees them at the same point of the
+* log. Sealed goes with TxOrder::Fair, which carries client transactions.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TxPrivacy {
+    #[default]
+    Plain,
+    Sealed,
+}
+
+impl FromStr for TxPrivacy {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(TxPrivacy::Plain),
+            "sealed" => Ok(TxPrivacy::Sealed),
+            _ => Err(format!("unknown tx privacy {}", s)),
+        }
+    }
+}
+
+/* what a client sends in sealed mode, one per frame */
+#[derive(Clone, Debug, PartialEq)]
+pub enum SealedTx {
+    Commit(U8Arr),
+    Reveal{ salt: U8Arr, tx: U8Arr },
+}
+
+impl SealedTx {
+    /* `commit` || commitment, or `reveal` || salt || tx */
+    pub fn to_bytes(&self) -> U8Arr {
+        match self {
+            SealedTx::Commit(commitment) => [COMMIT_TAG, commitment].concat(),
+            SealedTx::Reveal{ salt, tx } => [REVEAL_TAG, salt, tx].concat(),
+        }
+    }
+
+    pub fn from_bytes(bytes:&[u8]) -> Option<SealedTx> {
+        if let Some(commitment) = bytes.strip_prefix(COMMIT_TAG) {
+            let len = digest::SHA256.output_len();
+            return (commitment.len() == len).then(|| SealedTx::Commit(commitment.to_vec()));
+        }
+        let bytes = bytes.strip_prefix(REVEAL_TAG)?;
+        (bytes.len() >= SALT_LEN).then(|| SealedTx::Reveal{
+            salt: bytes[..SALT_LEN].to_vec(),
+            tx: bytes[SALT_LEN..].to_vec(),
+        })
+    }
+}
+
+pub fn commitment(salt:&[u8], tx:&[u8]) -> U8Arr {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(salt);
+    ctx.update(tx);
+    ctx.finish().as_ref().to_vec()
+}
+
+/* the commit a client submits first, and the reveal it submits once the commit is ordered */
+pub fn seal(tx:&[u8]) -> (SealedTx, SealedTx) {
+    let mut salt = vec![0; SALT_LEN];
+    SystemRandom::new().fill(&mut salt).expect("failed to generate salt");
+    let commit = SealedTx::Commit(commitment(&salt, tx));
+    (commit, SealedTx::Reveal{ salt, tx: tx.to_vec() })
+}
+
+/*
+* Revealer turns the ordered commitments and reveals of each round into the
+* log of transactions, in the order of their commitments. A reveal counts only
+* if its commitment was ordered in an earlier round, so seeing a transaction
+* early cannot help to order around it. A transaction waits for those
+* committed before it, but not for long: while a revealed transaction waits,
+* the commitments before it not revealed within REVEAL_DEADLINE rounds are
+* skipped, so a client that commits and never reveals stalls the others by
+* REVEAL_DEADLINE rounds at most, however many commitments it makes. With no
+* transaction waiting, a commitment is kept REVEAL_WINDOW rounds.
+*/
+#[derive(Default)]
+pub struct Revealer {
+    committed: VecDeque<U8Arr>, // commitments not revealed yet, in order
+    rounds: HashMap<U8Arr, u32>, // rounds[commitment] -> round it was ordered
+    revealed: HashMap<U8Arr, U8Arr>, // revealed[commitment] -> tx
+}
+
+impl Revealer {
+    /* `ordered` are the round's transactions, in order */
+    pub fn add_round(&mut self, round:u32, ordered:Vec<U8Arr>) -> Vec<U8Arr> {
+        for bytes in ordered {
+            match SealedTx::from_bytes(&bytes) {
+                Some(SealedTx::Commit(commitment)) if !self.rounds.contains_key(&commitment) => {
+                    self.rounds.insert(commitment.clone(), round);
+                    self.committed.push_back(commitment);
+                }
+                Some(SealedTx::Reveal{ salt, tx }) => {
+                    let commitment = commitment(&salt, &tx);
+                    if self.rounds.get(&commitment).is_some_and(|committed| *committed < round) {
+                        self.revealed.entry(commitment).or_insert(tx);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut log = Vec::new();
+        while let Some(commitment) = self.committed.front() {
+            if let Some(tx) = self.revealed.remove(commitment) {
+                log.push(tx);
+            } else {
+                let window = if self.revealed.is_empty() { REVEAL_WINDOW } else { REVEAL_DEADLINE };
+                if self.rounds[commitment] + window > round {
+                    break;
+                }
+            }
+            let commitment = self.committed.pop_front().unwrap();
+            self.rounds.remove(&commitment);
+        }
+        log
+    }
+}
@@ -1,24 +1,45 @@
+#![allow(clippy::too_many_arguments)]
+use std::collections::HashSet;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 use std::error::Error;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
 use std::time::{Instant};
 use tokio::sync::mpsc as tokio_mpsc;
-use tokio::sync::{Mutex as tk_mutex, RwLock as tk_rwlock};
+use tokio::sync::{Mutex as tk_mutex, RwLock as tk_rwlock, Semaphore};
 use tokio::time as tk_time;
 use bytes::Bytes;
 use async_trait::async_trait;
-use network::{Receiver, MessageHandler, Writer, SimpleSender};
-use message::Message;
+use network::{ConnectionEvent, Receiver, MessageHandler, Peer, Priority, Writer, QueueConfig, ReliableSender, SecureConfig, SimpleSender, Framing, Tcp, Transport};
+use futures::sink::SinkExt as _;
+use message::{AggCert, Message, SignCert, Signers};
 use ring::digest;
 
-use crate::signature::KeyPair;
+use crate::avid::{self, ChunkList, Dispersal};
+use crate::signature::{self, BlsKeyPair, CertCache, CertScheme, Certificate, KeyPair, verify_batch};
+use crate::crypto_pool::CryptoPool;
+use crate::store::{Record, Store};
+use crate::sampling::{self, DasHandler, Sampled, DAS_PORT_OFFSET};
+use crate::inclusion::{RoundRoots, RoundTree};
+use crate::fair::{self, FairOrderer, Mempool, Report, TxOrder, TxReceiverHandler, MAX_TX_SIZE, TX_PORT_OFFSET};
+use crate::sealed::{Revealer, TxPrivacy};
+use crate::l1::{L1Poster, Settler, L1_POLL};
+use crate::delivery::{peer_framing, Delivery, QueueMonitor, RoundCancels, CANCEL_TICK, QUEUE_REPORT};
+use crate::sync::{self, SYNC_INTERVAL};
+use crate::checkpoint::{self, Checkpoints, Closes, FoldTimer, CHECKPOINT_INTERVAL, CHECKPOINT_TICK};
+use crate::committee::{self, Committee, Epochs, Reconfig, MAX_NODES, RECONFIG_POLL};
 
 #[cfg(test)]
 #[path = "tests/sequencer_tests.rs"]
 pub mod sequencer_tests;
 
-type U8Arr = Vec<u8>;
+/// What the receiver answers to every message it hands to the main logic.
+pub const ACK: &[u8] = b"Ack";
+
+pub(crate) type U8Arr = Vec<u8>;
+type EchoList = tk_rwlock<Vec<Vec<(u32, U8Arr)>>>;
+pub(crate) type CertList = Vec<tk_rwlock<Vec<Certificate>>>;
 
 pub struct MeasureDs {
     total_sent:tk_mutex<Vec<usize>>,
@@ -27,6 +48,13 @@ pub struct MeasureDs {
     bytes_recv:tk_mutex<usize>,
     round_start:tk_mutex<Vec<Instant>>,
     deliver_latency:tk_mutex<Vec<u128>>,
+    total_cert:tk_mutex<Vec<(usize, usize)>>,
+    cert_sent:tk_mutex<(usize, usize)>, // certificate bytes sent, and what they take as Ed25519 signatures
+}
+impl Default for MeasureDs {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl MeasureDs {
     pub fn new() -> Self {
@@ -37,6 +65,8 @@ impl MeasureDs {
             bytes_recv: tk_mutex::new(0),
             round_start: tk_mutex::new(Vec::new()),
             deliver_latency: tk_mutex::new(Vec::new()),
+            total_cert: tk_mutex::new(Vec::new()),
+            cert_sent: tk_mutex::new((0, 0)),
         }
     }
     async fn incr_bytes_sent(&self, len:usize){
@@ -49,6 +79,13 @@ impl MeasureDs {
         *bytes_recv += len;
     }
 
+    /* a certificate sent in a Fin or Sup to `copies` peers */
+    async fn incr_cert_sent(&self, cert:&Certificate, copies:usize){
+        let mut cert_sent = self.cert_sent.lock().await;
+        cert_sent.0 += cert.encoded_len() * copies;
+        cert_sent.1 += cert.signs_len() * copies;
+    }
+
     async fn append_round(&self) {
         {
             let mut total_sent = self.total_sent.lock().await;
@@ -62,17 +99,35 @@ impl MeasureDs {
             total_recv.push(*bytes_recv);
             *bytes_recv = 0;
         }
+        {
+            let mut total_cert = self.total_cert.lock().await;
+            let mut cert_sent = self.cert_sent.lock().await;
+            total_cert.push(*cert_sent);
+            *cert_sent = (0, 0);
+        }
         let mut round_start = self.round_start.lock().await;
         round_start.push(Instant::now());
     }
 
     async fn measure_latency(&self, rn:usize){
+        let round_start = self.round_start.lock().await;
+        // a node that joined the committee late did not start the early rounds
+        let round_start = match round_start.get(rn) {
+            Some(round_start) => round_start,
+            None => return,
+        };
         let mut deliver_latency = self.deliver_latency.lock().await;
         while deliver_latency.len() <= rn {
             deliver_latency.push(0);
         }
+        deliver_latency[rn] = round_start.elapsed().as_millis();
+    }
+
+    /* the latency of each round started, None if our batch was not delivered */
+    pub async fn round_latency(&self) -> Vec<Option<u128>> {
         let round_start = self.round_start.lock().await;
-        deliver_latency[rn] = round_start[rn].elapsed().as_millis();
+        let deliver_latency = self.deliver_latency.lock().await;
+        (0..round_start.len()).map(|rn| deliver_latency.get(rn).copied()).collect()
     }
 
     pub async fn write_measurements(
@@ -88,52 +143,88 @@ impl MeasureDs {
         let total_sent = self.total_sent.lock().await;
         let total_recv = self.total_recv.lock().await;
         let deliver_latency = self.deliver_latency.lock().await;
+        let total_cert = self.total_cert.lock().await;
 
         _ = writeln!(file, "index: {}, node_num: {}, payload_size: {}", 
             node_ind, 
             node_num, 
             payload_size
         );
-        _ = writeln!(file, "deliver_latency(ms) total_sent(byte) total_recv(byte)");
+        _ = writeln!(file, "deliver_latency(ms) total_sent(byte) total_recv(byte) cert_sent(byte) cert_sent_ed25519(byte)");
         for i in 0..total_sent.len() {
             if deliver_latency.len() > i {
-                _ = writeln!(file, "r{:03}: {} {} {}", 
+                _ = writeln!(file, "r{:03}: {} {} {} {} {}", 
                     i,
                     deliver_latency[i], 
                     total_sent[i], 
-                    total_recv[i]
+                    total_recv[i],
+                    total_cert[i].0,
+                    total_cert[i].1
                 );
             }
             else {
-                _ = writeln!(file, "r{:03}: INF {} {}", 
+                _ = writeln!(file, "r{:03}: INF {} {} {} {}", 
                     i,
                     total_sent[i], 
-                    total_recv[i]
+                    total_recv[i],
+                    total_cert[i].0,
+                    total_cert[i].1
                 );
             }
         }
+        // with aggregated certificates, what they saved over Ed25519 signatures
+        let (cert_sent, as_signs) = total_cert.iter().fold((0, 0), |(sent, signs), round| (sent + round.0, signs + round.1));
+        if as_signs > 0 {
+            _ = writeln!(file, "certificates: {} bytes sent, {} as Ed25519 signatures ({:.1}%)",
+                cert_sent,
+                as_signs,
+                100.0 * cert_sent as f64 / as_signs as f64
+            );
+        }
 
     }
 }
 
 pub struct Sequencer {
     node_ind: u32,
-    num_nodes: u32,
-    f_cnt: usize,
     payload_size: usize,
 
     /* address related */
     self_addr: SocketAddr,
-    address_book: Vec<SocketAddr>,
+
+    /* committee related */
+    epochs: Arc<tk_rwlock<Epochs>>, // committees (addresses, keys, quorums) per epoch
+    pending_reconfig: Arc<tk_mutex<Option<Reconfig>>>, // proposed in our next batch
 
     /* key related */
     keypair: Arc<KeyPair>,
     peer_pkeys: Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    bls: Option<Arc<BlsKeyPair>>, // signs echoes if certificates are aggregated
+    bls_pkeys: Arc<tk_rwlock<Vec<Option<U8Arr>>>>, // proven BLS keys, ours included
+    cert_cache: Arc<tk_mutex<CertCache>>, // certificates already verified
+    crypto: Arc<CryptoPool>, // hashing and signature checks run there
+
+    /* how batches are sent, in full or erasure coded */
+    dispersal: Dispersal,
+    /* how transactions are ordered, and those waiting to be reported */
+    tx_order: TxOrder,
+    tx_privacy: TxPrivacy,
+    mempool: Arc<tk_mutex<Mempool>>,
+    /* whether the messages of a round are retransmitted until acknowledged */
+    delivery: Delivery,
+    /* if set, the links to peers are authenticated and encrypted */
+    secure: Option<SecureConfig>,
+    /* bounds the messages waiting for each peer */
+    queues: QueueConfig,
+    /* where our endpoints listen and our links to peers are opened, TCP unless testing */
+    transport: Arc<dyn Transport>,
 
     /* transactions and data related */
     tx_list: Arc<Vec<tk_rwlock<Vec<U8Arr>>>>, // txs[0][1][2] -> peer 0's msg of round 1, the third u8
     hash_list: Arc<Vec<tk_rwlock<Vec<U8Arr>>>>, // TODO: vec<arc<rwlock<vec<u8arr>>>>
-    echo_list: Arc<tk_rwlock<Vec<Vec<(u32, U8Arr)>>>>, // signs[0][1] -> second (peer index, sign) in round 0
+    echo_list: Arc<EchoList>, // signs[0][1] -> second (peer index, sign) in round 0
+    cert_list: Arc<CertList>, // cert_list[0][1] -> verified certificate of peer 0's msg of round 1
+    chunk_list: Arc<ChunkList>, // chunk_list[0][1] -> chunks received of peer 0's msg of round 1
 
     /* checks if a node has sent message to peers */
     sent_echo: Arc<Vec<tk_mutex<Vec<bool>>>>,  // sent_echo[0][1] -> sent echo to sender 0 in round 1
@@ -142,110 +233,313 @@ pub struct Sequencer {
 
     /* deliver related */
     delivered: Arc<Vec<tk_rwlock<Vec<bool>>>>,   // delivered[0][1]  -> peer 0's msg in round 1 is delivered. 
-    recv_sup_cnt: Arc<Vec<tk_rwlock<Vec<u8>>>>, // recv_sup_cnt[0][1] -> cnt of peer 0's sup msg recv for round 1
+    recv_sup: Arc<Vec<tk_rwlock<Vec<HashSet<u32>>>>>, // recv_sup[0][1] -> senders of peer 0's sup msg for round 1
 
+    /* delivered batches and their certificates */
+    store: Arc<dyn Store>,
+    sync_serving: Arc<Semaphore>, // held while streaming batches to a lagging peer
+    checkpoints: Arc<tk_mutex<Checkpoints>>,
+    closes: Arc<tk_mutex<Closes>>, // votes to close rounds without some batches
+    round_roots: Arc<tk_mutex<RoundRoots>>, // trees of the folded rounds, for inclusion proofs
+    sampled: Arc<tk_mutex<Sampled>>, // the folded batches, coded for sampling
     /* thruput, latency measurements */
     measure: Arc<MeasureDs>,
 }
 
 impl Sequencer {
+    /*
+    * `genesis` is the committee of epoch 0. A node does not have to be in it
+    * to start (e.g. it is about to be added), in which case `self_addr` is
+    * where the others will reach it once it is.
+    */
     pub fn new(
         node_ind:u32, 
-        num_nodes:u32, 
-        address_book:Vec<SocketAddr>,
+        genesis:Committee,
+        self_addr:SocketAddr,
         payload_size:usize,
+        keypair:KeyPair,
+        store:Arc<dyn Store>,
         measure:Arc<MeasureDs>,
     ) -> Self {
-        let mut peer_pkeys = Vec::with_capacity(num_nodes as usize);
-        let mut tx_list = Vec::with_capacity(num_nodes as usize);
-        let mut hash_list = Vec::with_capacity(num_nodes as usize);
-        let mut sent_echo = Vec::with_capacity(num_nodes as usize);
-        let mut sent_sup = Vec::with_capacity(num_nodes as usize);
-        let mut delivered = Vec::with_capacity(num_nodes as usize);
-        let mut recv_sup_cnt = Vec::with_capacity(num_nodes as usize);
-
-        for _i in 0..num_nodes {
+        assert!(genesis.members.iter().all(|m| (m.id as usize) < MAX_NODES));
+        assert!((node_ind as usize) < MAX_NODES);
+        let mut peer_pkeys = Vec::with_capacity(MAX_NODES);
+        let mut tx_list = Vec::with_capacity(MAX_NODES);
+        let mut hash_list = Vec::with_capacity(MAX_NODES);
+        let mut cert_list = Vec::with_capacity(MAX_NODES);
+        let mut chunk_list = Vec::with_capacity(MAX_NODES);
+        let mut sent_echo = Vec::with_capacity(MAX_NODES);
+        let mut sent_sup = Vec::with_capacity(MAX_NODES);
+        let mut delivered = Vec::with_capacity(MAX_NODES);
+        let mut recv_sup = Vec::with_capacity(MAX_NODES);
+
+        for _i in 0..MAX_NODES {
             peer_pkeys.push(None);
             tx_list.push(tk_rwlock::new(Vec::new()));
             hash_list.push(tk_rwlock::new(Vec::new()));
+            cert_list.push(tk_rwlock::new(Vec::new()));
+            chunk_list.push(tk_mutex::new(Vec::new()));
             sent_echo.push(tk_mutex::new(Vec::new()));
             sent_sup.push(tk_mutex::new(Vec::new()));
             delivered.push(tk_rwlock::new(Vec::new()));
-            recv_sup_cnt.push(tk_rwlock::new(Vec::new()));
+            recv_sup.push(tk_rwlock::new(Vec::new()));
         }
 
         Sequencer {
             /* basic info */
             node_ind,
-            num_nodes,
-            f_cnt: (num_nodes as usize - 1) / 3,
             payload_size,
             /* address */
-            self_addr: address_book[node_ind as usize],
-            address_book,
+            self_addr,
+            /* committee */
+            epochs: Arc::new(tk_rwlock::new(Epochs::new(genesis))),
+            pending_reconfig: Arc::new(tk_mutex::new(None)),
             /* keys */
-            keypair: Arc::new(KeyPair::new()),
+            keypair: Arc::new(keypair),
             peer_pkeys: Arc::new(tk_rwlock::new(peer_pkeys)),
+            bls: None,
+            bls_pkeys: Arc::new(tk_rwlock::new(vec![None; MAX_NODES])),
+            cert_cache: Arc::new(tk_mutex::new(CertCache::default())),
+            crypto: Arc::new(CryptoPool::default()),
+            dispersal: Dispersal::default(),
+            tx_order: TxOrder::default(),
+            tx_privacy: TxPrivacy::default(),
+            delivery: Delivery::default(),
+            secure: None,
+            queues: QueueConfig::default(),
+            transport: Arc::new(Tcp),
+            mempool: Arc::new(tk_mutex::new(Mempool::default())),
             /* transactions */
             tx_list: Arc::new(tx_list),
             hash_list: Arc::new(hash_list),
             echo_list: Arc::new(tk_rwlock::new(Vec::new())),
+            cert_list: Arc::new(cert_list),
+            chunk_list: Arc::new(chunk_list),
             /* checking flags */
             sent_echo: Arc::new(sent_echo),
             sent_fin: Arc::new(tk_mutex::new(Vec::new())),
             sent_sup: Arc::new(sent_sup),
             /* deliver */
             delivered: Arc::new(delivered),
-            recv_sup_cnt: Arc::new(recv_sup_cnt),
+            recv_sup: Arc::new(recv_sup),
+            store,
+            sync_serving: Arc::new(Semaphore::new(1)),
+            checkpoints: Arc::new(tk_mutex::new(Checkpoints::default())),
+            closes: Arc::new(tk_mutex::new(Closes::default())),
+            round_roots: Arc::new(tk_mutex::new(RoundRoots::default())),
+            sampled: Arc::new(tk_mutex::new(Sampled::default())),
             measure,
         }
     }
 
-    pub fn spawn_receiver(&self, tx_recv: tokio_mpsc::Sender<Bytes>){
+    /* replaces the default pool, one thread per core */
+    pub fn with_crypto_pool(mut self, crypto:Arc<CryptoPool>) -> Self {
+        self.crypto = crypto;
+        self
+    }
+
+    /* all members of a committee have to disperse batches the same way */
+    pub fn with_dispersal(mut self, dispersal:Dispersal) -> Self {
+        self.dispersal = dispersal;
+        self
+    }
+
+    /* and to order transactions the same way */
+    pub fn with_tx_order(mut self, tx_order:TxOrder) -> Self {
+        self.tx_order = tx_order;
+        self
+    }
+
+    /* sealed transactions are committed, then revealed once ordered */
+    pub fn with_tx_privacy(mut self, tx_privacy:TxPrivacy) -> Self {
+        assert!(tx_privacy == TxPrivacy::Plain || self.tx_order == TxOrder::Fair, "sealed txs need the fair ordering");
+        self.tx_privacy = tx_privacy;
+        self
+    }
+
+    /* how the messages of a round reach the peers (see Delivery) */
+    pub fn with_delivery(mut self, delivery:Delivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /*
+    * Secures the links to peers with the node key (a Noise handshake, see
+    * network::SecureConfig). Which peers are served and which key each address
+    * must answer with is up to `secure`: main.rs only accepts the keys of the
+    * committee in ip.config, and a Syn must come over the link of its sender's key.
+    */
+    pub fn with_secure_links(mut self, secure:SecureConfig) -> Self {
+        self.secure = Some(secure);
+        self
+    }
+
+    /*
+    * Bounds the queue of messages waiting for each peer, and what to drop
+    * when a peer falls that far behind (see network::Overflow).
+    */
+    pub fn with_queues(mut self, queues:QueueConfig) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    /*
+    * Runs every endpoint and link of this node over `transport` instead of
+    * TCP, e.g. a network::MemoryNetwork shared by a whole committee in a test.
+    */
+    pub fn with_transport(mut self, transport:Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /*
+    * Signs echoes with a BLS key, so that certificates are aggregated into
+    * AggFin and AggSup messages. Every member of the committee has to do so.
+    */
+    pub fn with_bls(mut self, bls:BlsKeyPair) -> Self {
+        let mut bls_pkeys = vec![None; MAX_NODES];
+        bls_pkeys[self.node_ind as usize] = Some(bls.pub_key.clone());
+        self.bls_pkeys = Arc::new(tk_rwlock::new(bls_pkeys));
+        self.bls = Some(Arc::new(bls));
+        self
+    }
+
+    pub fn spawn_receiver(&self, tx_recv: tokio_mpsc::Sender<(Peer, Bytes)>){
         let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0,0,0,0)), self.self_addr.port());
         println!("receiver listens on {:?}", socket);
-        Receiver::spawn(socket, PeerReceiverHandler{tx_recv});
+        Receiver::spawn_with_transport(socket, PeerReceiverHandler{tx_recv}, peer_framing(), self.secure.clone(), self.transport.clone());
+    }
+
+    /* serves DAS samples of the stored batches to light clients */
+    pub fn spawn_das(&self){
+        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0,0,0,0)), self.self_addr.port() + DAS_PORT_OFFSET);
+        println!("das endpoint listens on {:?}", socket);
+        let handler = DasHandler::new(self.sampled.clone(), self.round_roots.clone(), self.store.clone());
+        Receiver::spawn_with_transport(socket, handler, Framing::default(), None, self.transport.clone());
+    }
+
+    /* posts stable checkpoints to the L1 (see Settler) */
+    pub fn spawn_settler(&self, poster:Arc<dyn L1Poster>){
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            let mut settler = Settler::new(poster);
+            let mut interval = tk_time::interval(tk_time::Duration::from_millis(L1_POLL));
+            loop {
+                interval.tick().await;
+                if let Err(e) = settler.step(store.as_ref()).await {
+                    eprintln!("settler: {}", e);
+                }
+            }
+        });
+    }
+
+    /* takes transactions from clients, to be reported in our next batch */
+    pub fn spawn_tx_receiver(&self){
+        let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0,0,0,0)), self.self_addr.port() + TX_PORT_OFFSET);
+        println!("tx receiver listens on {:?}", socket);
+        let framing = Framing{ max_frame_length: MAX_TX_SIZE, ..Framing::default() };
+        Receiver::spawn_with_transport(socket, TxReceiverHandler{ mempool: self.mempool.clone() }, framing, None, self.transport.clone());
     }
 
     /*
     * Spawn a task that handles sending messages to other peers.
     * The task reads from the channel, and behave based on what it received. When
     * it receives Send_format::Multicast via the channel, it sends the message to
-    * all connected peers in bytes. Multicast include: Send, Final, Ready. When it
-    * receives Send_format::Unicast via the channel, it sends the message to the
-    * indicated peer (or dest). Unicast include: Echo, Ready.   -> Ready??
+    * the other members of the committee of the given epoch. Multicast include:
+    * Send, Final, Ready. When it receives Send_format::Unicast via the channel,
+    * it sends the message to the indicated peer (or dest). Unicast include: Echo,
+    * Ready.   -> Ready??
     */
     pub fn spawn_sender(
         &self,
         mut rx_send:tokio_mpsc::Receiver<CastType>
     ){
-        let address_book = self.address_book.clone();
-        let syn_msg = Message::Syn{ 
-            sender: self.node_ind,
-            pub_key: self.keypair.pub_key.clone(),
-        };
-        let mut peers:Vec<SocketAddr> = vec![];
-        for address in &address_book {
-            if *address != self.self_addr {
-                peers.push(*address);
-            }
-        }
+        let node_ind = self.node_ind;
+        let syn_bytes = self.syn_bytes();
+        let epochs = self.epochs.clone();
+        let delivery = self.delivery;
+        let checkpoints = self.checkpoints.clone();
+        let secure = self.secure.clone();
+        let queues = self.queues;
+        let transport = self.transport.clone();
         let measure = self.measure.clone();
         tokio::spawn(async move {
-            let mut msg_sender = SimpleSender::new();
-            msg_sender.init(Message::to_bytes(syn_msg).unwrap(), peers.clone()).await;
+            let peers = epochs.read().await.latest().members.iter()
+                .filter(|m| m.id != node_ind)
+                .map(|m| m.address)
+                .collect();
+            let (tx_events, rx_events) = tokio_mpsc::channel(100);
+            spawn_connection_log(rx_events, epochs.clone());
+            let mut msg_sender = SimpleSender::with_framing(peer_framing())
+                .with_events(tx_events)
+                .with_queues(queues)
+                .with_transport(transport.clone());
+            let mut reliable_sender = ReliableSender::with_framing(peer_framing())
+                .with_queues(queues)
+                .with_transport(transport);
+            if let Some(secure) = secure {
+                msg_sender = msg_sender.with_secure(secure.clone());
+                reliable_sender = reliable_sender.with_secure(secure);
+            }
+            // the Syn is replayed on every reconnection, a restarted peer learns our keys again
+            msg_sender.init(syn_bytes, peers).await;
+            let mut cancels = RoundCancels::default();
+            let mut cancel_tick = tk_time::interval(tk_time::Duration::from_millis(CANCEL_TICK));
+            let mut queue_report = tk_time::interval(tk_time::Duration::from_millis(QUEUE_REPORT));
+            let mut monitors = (QueueMonitor::new(queues.capacity), QueueMonitor::new(queues.capacity));
             loop {
-                if let Some(msg) = rx_send.recv().await {
-                    match msg {
-                        CastType::Multicast {bytes} => {
-                            measure.incr_bytes_sent(bytes.len() * peers.len()).await;
-                            msg_sender.broadcast(peers.clone(), bytes).await;
+                let msg = tokio::select! {
+                    msg = rx_send.recv() => match msg {
+                        Some(msg) => msg,
+                        None => return,
+                    },
+                    _ = cancel_tick.tick() => {
+                        cancels.cancel_below(checkpoints.lock().await.folded());
+                        continue;
+                    }
+                    _ = queue_report.tick() => {
+                        for (address, stats) in monitors.0.congested(msg_sender.queue_stats()) {
+                            eprintln!("queue to {}: {} msgs waiting, {} dropped", address, stats.depth, stats.dropped);
                         }
-                        CastType::Unicast {dest, bytes} => {
-                            measure.incr_bytes_sent(bytes.len()).await;
-                            msg_sender.send(address_book[dest as usize], bytes).await;
+                        for (address, stats) in monitors.1.congested(reliable_sender.queue_stats()) {
+                            eprintln!("reliable queue to {}: {} msgs waiting, {} dropped", address, stats.depth, stats.dropped);
                         }
+                        continue;
                     }
+                };
+                let (round, priority, addresses, bytes) = match msg {
+                    CastType::Multicast {epoch, round, priority, bytes} => {
+                        match epochs.read().await.get(epoch) {
+                            Some(committee) => {
+                                let peers: Vec<SocketAddr> = committee.members.iter()
+                                    .filter(|m| m.id != node_ind)
+                                    .map(|m| m.address)
+                                    .collect();
+                                (round, priority, peers, bytes)
+                            }
+                            None => {
+                                eprintln!("no committee for epoch {}, msg dropped", epoch);
+                                continue;
+                            }
+                        }
+                    }
+                    CastType::Unicast {dest, round, priority, bytes} => {
+                        match epochs.read().await.address(dest) {
+                            Some(address) => (round, priority, vec![address], bytes),
+                            None => {
+                                eprintln!("no address for node {}, msg dropped", dest);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                measure.incr_bytes_sent(bytes.len() * addresses.len()).await;
+                match (delivery, round) {
+                    (Delivery::Reliable, Some(round)) => {
+                        let handlers = reliable_sender.broadcast_with_priority(addresses, bytes, priority).await;
+                        cancels.add(round, handlers);
+                    }
+                    _ => msg_sender.broadcast_with_priority(addresses, bytes, priority).await,
                 }
             }
         });
@@ -254,7 +548,9 @@ impl Sequencer {
     * Spawns a task that send out Send_format::Multicast message to the sending task
     * via channel periodically.
     * The interval is set to 1 second. The task starts to send message to sending
-    * task after this task wakes from 5 seconds of sleep.
+    * task after this task wakes from 5 seconds of sleep. Rounds are only sent
+    * while this node is in the committee of the round; a node added by a
+    * reconfiguration starts at the round its committee takes over.
     */
     pub fn spawn_periodic_sender(
         &self,
@@ -266,7 +562,15 @@ impl Sequencer {
         let tx_list = Arc::clone(&self.tx_list);
         let hash_list = Arc::clone(&self.hash_list);
         let echo_list = Arc::clone(&self.echo_list);
+        let chunk_list = Arc::clone(&self.chunk_list);
         let keypair = Arc::clone(&self.keypair);
+        let bls = self.bls.clone();
+        let crypto = self.crypto.clone();
+        let dispersal = self.dispersal;
+        let tx_order = self.tx_order;
+        let mempool = self.mempool.clone();
+        let epochs = self.epochs.clone();
+        let pending_reconfig = self.pending_reconfig.clone();
         let measure = self.measure.clone();
 
         tokio::spawn(async move {
@@ -275,39 +579,103 @@ impl Sequencer {
             let mut interval = tk_time::interval(tk_time::Duration::from_millis(1000));
             let mut curr_round = 0;
             let usize_ind = node_ind as usize;
-            let payload = vec![node_ind as u8; payload_size];
+            let dummy_payload = vec![node_ind as u8; payload_size];
 
             loop {
                 interval.tick().await;
+                let committee = {
+                    let epochs = epochs.read().await;
+                    let latest = epochs.latest();
+                    if latest.contains(node_ind)
+                        && latest.start_round > curr_round as u32
+                        && !epochs.for_round(curr_round as u32).contains(node_ind)
+                    {
+                        curr_round = latest.start_round as usize;
+                    }
+                    let committee = epochs.for_round(curr_round as u32);
+                    if !committee.contains(node_ind) {
+                        continue;
+                    }
+                    committee.clone()
+                };
+                let epoch = committee.epoch;
                 println!("--- sending message from round {} --- ", curr_round);
                 measure.append_round().await;
 
-                let payload_digest = digest::digest(&digest::SHA256, &payload);
+                let payload = match pending_reconfig.lock().await.take() {
+                    Some(reconfig) => {
+                        println!("proposing the committee of epoch {}", reconfig.epoch);
+                        reconfig.to_payload()
+                    }
+                    None if tx_order == TxOrder::Fair => mempool.lock().await.report().to_payload(),
+                    None => dummy_payload.clone(),
+                };
+                // with Avid, the payload digest is the root of the chunks
+                let num_chunks = committee.size();
+                let (payload, payload_digest, das_root, batch_digest, sign, chunks) = {
+                    let keypair = keypair.clone();
+                    let bls = bls.clone();
+                    crypto.run(move || {
+                        let (payload_digest, chunks) = match dispersal {
+                            Dispersal::Full => (avid::payload_digest(dispersal, num_chunks, &payload), Vec::new()),
+                            Dispersal::Avid => avid::disperse(&payload, num_chunks),
+                        };
+                        let das_root = avid::das_root(&payload);
+                        let batch_digest = das::batch_digest(&payload_digest, &das_root);
+                        let sign = sign_echo(&keypair, bls.as_deref(), &batch_digest);
+                        (payload, payload_digest, das_root, batch_digest, sign, chunks)
+                    }).await
+                };
                 // append self transactions
-                { 
-                    let mut tx_list = tx_list[usize_ind].write().await;
-                    tx_list.push(payload.clone());
-                }
+                set_round(&tx_list[usize_ind], curr_round, payload.clone()).await;
                 // append self H(transactions)
-                { 
-                    let mut hash_list = hash_list[usize_ind].write().await;
-                    hash_list.push(payload_digest.as_ref().to_vec());
-                }
+                set_round(&hash_list[usize_ind], curr_round, batch_digest).await;
                 // append self S(H(transactions))
-                append_echo(
-                    &echo_list, 
-                    curr_round, 
-                    node_ind, 
-                    keypair.sign(payload_digest.as_ref())
-                ).await;
+                append_echo(&echo_list, curr_round, node_ind, sign).await;
+
+                if dispersal == Dispersal::Avid {
+                    // each member gets its own chunk only, ours is kept for those who ask
+                    for (index, (id, (chunk, proof))) in committee.ids().into_iter().zip(chunks).enumerate() {
+                        if id == node_ind {
+                            let own = avid::OwnChunk{ index, root: payload_digest.clone(), das_root: das_root.clone(), proof, chunk };
+                            avid::keep_own_chunk(node_ind, epoch, curr_round, usize_ind, own, &chunk_list, &tx_send).await;
+                            continue;
+                        }
+                        tx_send.send(CastType::Unicast{
+                            dest: id,
+                            round: Some(curr_round as u32),
+                            priority: Priority::Bulk,
+                            bytes: Message::Chunk{
+                                sender: node_ind,
+                                epoch,
+                                rn: curr_round as u32,
+                                originator: node_ind,
+                                index: index as u32,
+                                root: payload_digest.clone(),
+                                das_root: das_root.clone(),
+                                proof,
+                                chunk,
+                            }
+                            .to_bytes()
+                            .unwrap(),
+                        })
+                        .await
+                        .expect("periodic sender:: failed to send chunk to peer");
+                    }
+                    curr_round += 1;
+                    continue;
+                }
 
                 tx_send.send(
                     CastType::Multicast{
+                        epoch,
+                        round: Some(curr_round as u32),
+                        priority: Priority::Bulk,
                         bytes: Message::Send{
                             sender: node_ind,
+                            epoch,
                             rn: curr_round as u32,
-                            payload: payload.clone(),
-                            // TODO: change dummy payload
+                            payload,
                         }.to_bytes()
                         .unwrap(),
                     }
@@ -319,35 +687,661 @@ impl Sequencer {
         });
     }
 
+    /*
+    * Spawns a task that periodically tells one peer of the latest committee
+    * (round robin) how many rounds of each originator this node has delivered.
+    * The peer answers with SyncResp for the certified batches we are missing,
+    * e.g. because we started late, fell behind, or just joined.
+    */
+    pub fn spawn_sync(
+        &self,
+        tx_send: tokio_mpsc::Sender<CastType>,
+    ){
+        let node_ind = self.node_ind;
+        let epochs = self.epochs.clone();
+        let delivered = self.delivered.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tk_time::interval(tk_time::Duration::from_millis(SYNC_INTERVAL));
+            let mut turn = 0;
+            loop {
+                interval.tick().await;
+                let (peers, num_ids) = {
+                    let epochs = epochs.read().await;
+                    let peers: Vec<u32> = epochs.latest().ids()
+                        .into_iter()
+                        .filter(|id| *id != node_ind)
+                        .collect();
+                    (peers, epochs.num_ids())
+                };
+                if peers.is_empty() {
+                    continue;
+                }
+                turn += 1;
+                let peer = peers[turn % peers.len()];
+
+                let delivered = sync::delivered_prefix(&delivered[..num_ids]).await;
+                if tx_send.send(CastType::Unicast{
+                    dest: peer,
+                    round: None,
+                    priority: Priority::Urgent,
+                    bytes: Message::SyncReq{ sender: node_ind, delivered }
+                        .to_bytes()
+                        .unwrap(),
+                }).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /*
+    * Spawns a task that folds the delivered log into a running digest, round by
+    * round, once the batch of every member of the round's committee is
+    * delivered, or left out by a quorum of Close votes. We vote some time
+    * after the batches of a quorum are delivered (see FoldTimer and Closes).
+    * Reconfigurations are applied as they are folded, so every node
+    * applies them at the same point of the log. The batch digests of each
+    * folded round also go into a Merkle tree whose root it signs, so clients
+    * can get a proof that a batch is in the round, and the batches are coded
+    * for sampling. Every CHECKPOINT_INTERVAL
+    * rounds it signs the digest and the data root of the interval's round
+    * roots, and multicasts them as a Checkpoint; 2f+1 matching ones make the
+    * checkpoint stable. With TxOrder::Fair, the
+    * reports in the round's batches are then ordered (see FairOrderer), and
+    * with TxPrivacy::Sealed, the ordered commitments revealed (see Revealer).
+    * Rounds covered by a stable checkpoint and folded by every member are
+    * pruned, from memory and from the store.
+    */
+    pub fn spawn_checkpointer(
+        &self,
+        tx_send: tokio_mpsc::Sender<CastType>,
+    ){
+        let node_ind = self.node_ind;
+        let keypair = self.keypair.clone();
+        let peer_pkeys = self.peer_pkeys.clone();
+        let epochs = self.epochs.clone();
+        let delivered = self.delivered.clone();
+        let hash_list = self.hash_list.clone();
+        let tx_list = self.tx_list.clone();
+        let cert_list = self.cert_list.clone();
+        let chunk_list = self.chunk_list.clone();
+        let echo_list = self.echo_list.clone();
+        let recv_sup = self.recv_sup.clone();
+        let sent_echo = self.sent_echo.clone();
+        let checkpoints = self.checkpoints.clone();
+        let closes = self.closes.clone();
+        let round_roots = self.round_roots.clone();
+        let sampled = self.sampled.clone();
+        let crypto = self.crypto.clone();
+        let store = self.store.clone();
+        let syn_bytes = self.syn_bytes();
+        let dispersal = self.dispersal;
+        let tx_order = self.tx_order;
+        let tx_privacy = self.tx_privacy;
+
+        tokio::spawn(async move {
+            let mut interval = tk_time::interval(tk_time::Duration::from_millis(CHECKPOINT_TICK));
+            let mut log_digest = vec![0; digest::SHA256.output_len()];
+            let mut next_round = 0;
+            let mut orderer = FairOrderer::default();
+            let mut revealer = Revealer::default();
+            let mut fold_timer = FoldTimer::default();
+            loop {
+                interval.tick().await;
+                // the rounds after the next one to fold start waiting as soon as they have a quorum
+                let mut rn = next_round + 1;
+                loop {
+                    let committee = epochs.read().await.for_round(rn as u32).clone();
+                    let batch_digests = foldable_batches(rn, &committee, dispersal, &delivered, &hash_list, &tx_list).await;
+                    let present: Vec<u32> = batch_digests.iter().map(|(id, _)| *id).collect();
+                    if !fold_timer.observe(rn as u32, &committee, &present, Instant::now()) {
+                        break;
+                    }
+                    rn += 1;
+                }
+                loop {
+                    let committee = epochs.read().await.for_round(next_round as u32).clone();
+                    let batch_digests = foldable_batches(next_round, &committee, dispersal, &delivered, &hash_list, &tx_list).await;
+                    let present: Vec<u32> = batch_digests.iter().map(|(id, _)| *id).collect();
+                    if !fold_timer.ready(next_round as u32, &committee, &present, Instant::now()) {
+                        break;
+                    }
+                    if present.len() < committee.size() {
+                        let rn = next_round as u32;
+                        if committee.contains(node_ind) && !closes.lock().await.voted(rn, node_ind) {
+                            let absent = absent_batches(next_round, &committee, &present, &delivered, &sent_echo).await;
+                            let sign = keypair.sign(&checkpoint::close_msg(rn, &absent));
+                            closes.lock().await.add_vote(rn, node_ind, absent.clone(), sign.clone());
+                            tx_send.send(CastType::Multicast{
+                                epoch: committee.epoch,
+                                round: None,
+                                priority: Priority::Urgent,
+                                bytes: Message::Close{ sender: node_ind, voter: node_ind, rn, absent, sign }
+                                    .to_bytes()
+                                    .unwrap(),
+                            })
+                            .await
+                            .expect("failed to send close msg to peers");
+                        }
+                        let excluded = closes.lock().await.excluded(rn, &committee);
+                        if !checkpoint::closed(&committee, &present, &excluded) {
+                            break;
+                        }
+                        eprintln!("round {}: folded without the batches of {:?}", next_round, excluded);
+                    }
+                    fold_timer.folded(next_round as u32);
+                    for (originator, batch_digest) in batch_digests.iter() {
+                        log_digest = checkpoint::extend_digest(
+                            &log_digest,
+                            next_round as u32,
+                            *originator,
+                            batch_digest
+                        );
+                        let reconfig = tx_list[*originator as usize].read().await
+                            .get(next_round)
+                            .and_then(|tx| Reconfig::from_payload(tx));
+                        if let Some(reconfig) = reconfig {
+                            committee::handle_reconfig(
+                                node_ind,
+                                next_round as u32,
+                                *originator,
+                                reconfig,
+                                syn_bytes.clone(),
+                                epochs.clone(),
+                                peer_pkeys.clone(),
+                                &tx_send,
+                            ).await;
+                        }
+                    }
+                    let mut reports = Vec::with_capacity(batch_digests.len());
+                    for (originator, _) in batch_digests.iter() {
+                        let report = tx_list[*originator as usize].read().await
+                            .get(next_round)
+                            .and_then(|tx| Report::from_payload(tx));
+                        if let Some(report) = report {
+                            reports.push((*originator, report));
+                        }
+                    }
+                    // the reported transactions are leaves of the round tree, for inclusion proofs
+                    let tx_digests: Vec<(u32, U8Arr)> = reports.iter()
+                        .flat_map(|(originator, report)| report.sequenced().map(|(tx, _)| (*originator, fair::tx_digest(tx))))
+                        .collect();
+                    if tx_order == TxOrder::Fair {
+                        let order = orderer.add_round(next_round as u32, &committee, reports);
+                        if !order.is_empty() {
+                            println!("round {}: {} txs in fair order", next_round, order.len());
+                        }
+                        if tx_privacy == TxPrivacy::Sealed {
+                            let log = revealer.add_round(next_round as u32, order);
+                            if !log.is_empty() {
+                                println!("round {}: {} txs revealed", next_round, log.len());
+                            }
+                        }
+                    }
+                    for (originator, batch_digest) in batch_digests.iter() {
+                        let ind = *originator as usize;
+                        let payload = tx_list[ind].read().await.get(next_round).cloned().unwrap_or_default();
+                        if payload.is_empty() {
+                            continue;
+                        }
+                        let record = Record {
+                            round: next_round as u32,
+                            originator: *originator,
+                            digest: batch_digest.clone(),
+                            payload,
+                            certificate: cert_list[ind].read().await.get(next_round).cloned().unwrap_or_default(),
+                        };
+                        tokio::spawn(sampling::code_batch(crypto.clone(), sampled.clone(), dispersal, committee.size(), record));
+                    }
+                    let round_tree = RoundTree::new(next_round as u32, &batch_digests, &tx_digests);
+                    round_roots.lock().await.push(round_tree);
+                    next_round += 1;
+                    checkpoints.lock().await.set_folded(next_round as u32);
+
+                    let rn = next_round as u32;
+                    if !rn.is_multiple_of(CHECKPOINT_INTERVAL) {
+                        continue;
+                    }
+                    // the checkpoint is voted by the committee of its last round
+                    let committee = epochs.read().await.for_round(rn - 1).clone();
+                    if !committee.contains(node_ind) {
+                        continue;
+                    }
+                    // the interval's rounds were just folded, their trees are kept
+                    let data_root = round_roots.lock().await
+                        .data_root(rn - CHECKPOINT_INTERVAL, rn)
+                        .expect("the roots of the rounds just folded are kept");
+                    let sign = keypair.sign(&checkpoint::checkpoint_msg(rn, &log_digest, &data_root));
+                    tx_send.send(CastType::Multicast{
+                        epoch: committee.epoch,
+                        round: None,
+                        priority: Priority::Urgent,
+                        bytes: Message::Checkpoint{
+                            sender: node_ind,
+                            rn,
+                            digest: log_digest.clone(),
+                            data_root: data_root.clone(),
+                            sign: sign.clone(),
+                        }
+                        .to_bytes()
+                        .unwrap(),
+                    })
+                    .await
+                    .expect("failed to send checkpoint msg to peers");
+
+                    checkpoint::handle_checkpoint_msg(
+                        node_ind,
+                        &keypair.pub_key,
+                        node_ind,
+                        rn,
+                        log_digest.clone(),
+                        data_root,
+                        sign,
+                        committee,
+                        peer_pkeys.clone(),
+                        checkpoints.clone(),
+                        store.clone(),
+                    ).await;
+                }
+
+                let committee = epochs.read().await.latest().clone();
+                let prune = checkpoints.lock().await.start_pruning(&committee);
+                if let Some((from, below)) = prune {
+                    prune_rounds(from as usize, below as usize, &tx_list, &hash_list, &cert_list, &chunk_list, &echo_list, &recv_sup).await;
+                    closes.lock().await.prune(below);
+                    orderer.prune(below);
+                    sampled.lock().await.prune(below);
+                    if let Err(e) = store.prune(below) {
+                        eprintln!("failed to prune the store below round {}: {}", below, e);
+                    }
+                }
+            }
+        });
+    }
+
+    /*
+    * Spawns a task that watches for an operator's reconfiguration file (see
+    * Reconfig::from_config). Its committee is proposed in our next batch and
+    * the file is removed. It takes over once the same file has been given to
+    * members holding a quorum of the stake.
+    */
+    pub fn spawn_reconfig_watcher(&self, path:PathBuf){
+        let pending_reconfig = self.pending_reconfig.clone();
+        tokio::spawn(async move {
+            let mut interval = tk_time::interval(tk_time::Duration::from_millis(RECONFIG_POLL));
+            loop {
+                interval.tick().await;
+                let config = match fs::read_to_string(&path) {
+                    Ok(config) => config,
+                    Err(_) => continue,
+                };
+                let _ = fs::remove_file(&path);
+                match Reconfig::from_config(&config) {
+                    Some(reconfig) => *pending_reconfig.lock().await = Some(reconfig),
+                    None => eprintln!("malformed reconfiguration in {:?}, ignored", path),
+                }
+            }
+        });
+    }
+
+    fn scheme(&self) -> CertScheme {
+        match self.bls {
+            Some(_) => CertScheme::Bls,
+            None => CertScheme::Ed25519,
+        }
+    }
+
+    fn syn_bytes(&self) -> Bytes {
+        let (bls_key, bls_pop) = match &self.bls {
+            Some(bls) => (bls.pub_key.clone(), bls.pop.clone()),
+            None => (Vec::new(), Vec::new()),
+        };
+        Message::Syn{
+            sender: self.node_ind,
+            pub_key: self.keypair.pub_key.clone(),
+            bls_key,
+            bls_pop,
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    /*
+    * Returns the committee of round rn if a message of that round, stamped
+    * with `epoch`, may come from `sender`. Stale (or not yet known) epochs and
+    * non-members are rejected, and so are pruned rounds.
+    */
+    async fn committee_of(&self, epoch:u32, rn:u32, sender:u32) -> Option<Arc<Committee>> {
+        if rn < self.checkpoints.lock().await.pruned() {
+            return None;
+        }
+        let epochs = self.epochs.read().await;
+        if !epochs.check(epoch, rn, sender) {
+            eprintln!("dropping msg of round {} from {}: epoch {} is not the round's", rn, sender, epoch);
+            return None;
+        }
+        Some(epochs.for_round(rn).clone())
+    }
+
+    /* handles a Fin or AggFin message */
+    async fn spawn_fin(
+        &self,
+        sender:u32,
+        epoch:u32,
+        rn:u32,
+        cert:Certificate,
+        tx_send:&tokio_mpsc::Sender<CastType>
+    ){
+        let committee = match self.committee_of(epoch, rn, sender).await {
+            Some(committee) => committee,
+            None => return,
+        };
+        let self_node_ind = self.node_ind;
+        let keypair = self.keypair.clone();
+        let sent_sup = self.sent_sup.clone();
+        let hash_list = self.hash_list.clone();
+        let peer_pkeys = self.peer_pkeys.clone();
+        let bls_pkeys = self.bls_pkeys.clone();
+        let cert_cache = self.cert_cache.clone();
+        let crypto = self.crypto.clone();
+        let dispersal = self.dispersal;
+        let cert_list = self.cert_list.clone();
+        let delivered = self.delivered.clone();
+        let recv_sup = self.recv_sup.clone();
+        let tx_list = self.tx_list.clone();
+        let measure = self.measure.clone();
+        let tx_send = tx_send.clone();
+        tokio::spawn(async move {
+            handle_fin_msg(
+                self_node_ind,
+                &keypair.pub_key,
+                sender as usize,
+                rn as usize,
+                committee,
+                cert,
+                sent_sup,
+                hash_list,
+                cert_list,
+                peer_pkeys,
+                bls_pkeys,
+                cert_cache,
+                crypto,
+                dispersal,
+                delivered,
+                recv_sup,
+                tx_list,
+                measure,
+                &tx_send,
+            ).await;
+        });
+    }
+
+    /* handles a Sup or AggSup message */
+    async fn spawn_sup(
+        &self,
+        sender:u32,
+        epoch:u32,
+        rn:u32,
+        cert:Certificate,
+        originator:u32,
+        payload:U8Arr,
+        tx_send:&tokio_mpsc::Sender<CastType>
+    ){
+        let committee = match self.committee_of(epoch, rn, originator).await {
+            Some(committee) if committee.contains(sender) => committee,
+            _ => return,
+        };
+        let tx_send = tx_send.clone();
+        let keypair = self.keypair.clone();
+        let peer_pkeys = self.peer_pkeys.clone();
+        let bls_pkeys = self.bls_pkeys.clone();
+        let cert_cache = self.cert_cache.clone();
+        let crypto = self.crypto.clone();
+        let dispersal = self.dispersal;
+        let delivered = self.delivered.clone();
+        let recv_sup = self.recv_sup.clone();
+        let tx_list = self.tx_list.clone();
+        let hash_list = self.hash_list.clone();
+        let cert_list = self.cert_list.clone();
+        let chunk_list = self.chunk_list.clone();
+        let store = self.store.clone();
+        let measure = self.measure.clone();
+        let self_node_ind = self.node_ind;
+        tokio::spawn(async move {
+            handle_sup_msg(
+                self_node_ind as usize,
+                &keypair.pub_key,
+                sender,
+                originator as usize,
+                rn as usize,
+                cert,
+                payload,
+                committee,
+                peer_pkeys,
+                bls_pkeys,
+                cert_cache,
+                crypto,
+                dispersal,
+                delivered,
+                recv_sup,
+                tx_list,
+                hash_list,
+                cert_list,
+                chunk_list,
+                store,
+                measure,
+                &tx_send,
+            ).await;
+        });
+    }
+
+    /* handles a SyncResp or AggSyncResp message */
+    async fn spawn_sync_resp(
+        &self,
+        rn:u32,
+        cert:Certificate,
+        originator:u32,
+        payload:U8Arr
+    ){
+        if rn < self.checkpoints.lock().await.pruned() {
+            return;
+        }
+        let committee = {
+            let epochs = self.epochs.read().await;
+            let committee = epochs.for_round(rn);
+            if !committee.contains(originator) {
+                return;
+            }
+            committee.clone()
+        };
+        let self_node_ind = self.node_ind;
+        let keypair = self.keypair.clone();
+        let peer_pkeys = self.peer_pkeys.clone();
+        let bls_pkeys = self.bls_pkeys.clone();
+        let cert_cache = self.cert_cache.clone();
+        let crypto = self.crypto.clone();
+        let dispersal = self.dispersal;
+        let delivered = self.delivered.clone();
+        let recv_sup = self.recv_sup.clone();
+        let tx_list = self.tx_list.clone();
+        let hash_list = self.hash_list.clone();
+        let cert_list = self.cert_list.clone();
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            sync::handle_sync_resp(
+                self_node_ind,
+                &keypair.pub_key,
+                &committee,
+                originator as usize,
+                rn as usize,
+                cert,
+                payload,
+                peer_pkeys,
+                bls_pkeys,
+                cert_cache,
+                crypto,
+                dispersal,
+                delivered,
+                recv_sup,
+                tx_list,
+                hash_list,
+                cert_list,
+                store,
+            ).await;
+        });
+    }
+
+    /* handles a Chunk message */
+    async fn spawn_chunk(
+        &self,
+        sender:u32,
+        epoch:u32,
+        rn:u32,
+        originator:u32,
+        index:u32,
+        root:U8Arr,
+        das_root:U8Arr,
+        proof:Vec<U8Arr>,
+        chunk:U8Arr,
+        tx_send:&tokio_mpsc::Sender<CastType>
+    ){
+        let committee = match self.committee_of(epoch, rn, originator).await {
+            Some(committee) if committee.contains(sender) => committee,
+            _ => return,
+        };
+        let self_node_ind = self.node_ind;
+        let keypair = self.keypair.clone();
+        let bls = self.bls.clone();
+        let crypto = self.crypto.clone();
+        let sent_echo = self.sent_echo.clone();
+        let chunk_list = self.chunk_list.clone();
+        let tx_list = self.tx_list.clone();
+        let hash_list = self.hash_list.clone();
+        let cert_list = self.cert_list.clone();
+        let delivered = self.delivered.clone();
+        let store = self.store.clone();
+        let tx_send = tx_send.clone();
+        tokio::spawn(async move {
+            avid::handle_chunk_msg(
+                self_node_ind,
+                sender,
+                epoch,
+                rn as usize,
+                originator as usize,
+                index as usize,
+                root,
+                das_root,
+                proof,
+                chunk,
+                committee,
+                keypair,
+                bls,
+                crypto,
+                sent_echo,
+                chunk_list,
+                tx_list,
+                hash_list,
+                cert_list,
+                delivered,
+                store,
+                &tx_send,
+            ).await;
+        });
+    }
+
+    /*
+    * whether the message comes from the node it claims as its sender. Over a
+    * secure link, the peer proved it holds a node key: the sender must be the
+    * node of that key, as announced in its Syn or, before that, as configured
+    * for it (in ip.config or a reconfiguration). A key that is neither is not
+    * taken on trust, even in a Syn. Plain links do not authenticate the peer,
+    * so their messages are taken at their word.
+    */
+    async fn is_from_sender(&self, peer:&Peer, message:&Message) -> bool {
+        let key = match &peer.key {
+            Some(key) => key,
+            None => return true,
+        };
+        if let Some(Some(known)) = self.peer_pkeys.read().await.get(message.sender() as usize) {
+            return known == key;
+        }
+        self.epochs.read().await.pub_key(message.sender()) == Some(key)
+    }
+
     pub async fn run_main_loop(
         self,
-        mut rx_recv:tokio_mpsc::Receiver<Bytes>,
+        mut rx_recv:tokio_mpsc::Receiver<(Peer, Bytes)>,
         tx_send:tokio_mpsc::Sender<CastType>
     ){
         loop{
-            if let Some(bytes) = rx_recv.recv().await {
+            if let Some((peer, bytes)) = rx_recv.recv().await {
                 self.measure.incr_bytes_recv(bytes.len()).await;
-                match Message::from_bytes(bytes).unwrap() {
-                    Message::Syn{sender, pub_key} => {
+                let message = match Message::from_bytes(bytes) {
+                    Ok(message) => message,
+                    Err(()) => {
+                        eprintln!("dropped a malformed message over the link of {}", peer.address);
+                        continue;
+                    }
+                };
+                if !self.is_from_sender(&peer, &message).await {
+                    eprintln!("dropped a message claiming to come from node {} over the link of {}", message.sender(), peer.address);
+                    continue;
+                }
+                match message {
+                    Message::Syn{sender, pub_key, bls_key, bls_pop} => {
+                        if sender as usize >= MAX_NODES || sender == self.node_ind {
+                            continue;
+                        }
+                        if !bls_key.is_empty() && self.bls_pkeys.read().await[sender as usize].is_none() {
+                            if BlsKeyPair::verify_pop(&bls_key, &bls_pop) {
+                                self.bls_pkeys.write().await[sender as usize] = Some(bls_key);
+                            } else {
+                                eprintln!("peer {} sent a BLS key without a valid proof of possession", sender);
+                            }
+                        }
                         let mut pkeys = self.peer_pkeys.write().await;
-                        if pkeys[sender as usize] == None {
-                            pkeys[sender as usize] = Some(pub_key);
+                        match &pkeys[sender as usize] {
+                            None => {
+                                pkeys[sender as usize] = Some(pub_key);
+                                drop(pkeys);
+                                // the peer may have started after us (or just joined)
+                                tx_send.send(CastType::Unicast{
+                                    dest: sender,
+                                    round: None,
+                                    priority: Priority::Urgent,
+                                    bytes: self.syn_bytes(),
+                                })
+                                .await
+                                .expect("failed to send syn msg");
+                            }
+                            Some(known) if *known != pub_key => {
+                                eprintln!("peer {} sent another pkey, ignored", sender);
+                            }
+                            Some(_) => {}
                         }
-                        else { panic!("peer {} sent pkey twice!", sender); }
                     }
-                    Message::Send{sender, rn, payload} => {
-                            let keypair = self.keypair.clone();
-                            let sent_echo = self.sent_echo.clone();
-                            let tx_list = self.tx_list.clone();
-                            let hash_list = self.hash_list.clone();
-                            let tx_send = tx_send.clone();
-                            tokio::spawn(async move {
-                                handle_send_msg(
+                    Message::Send{sender, epoch, rn, payload} => {
+                        if self.committee_of(epoch, rn, sender).await.is_none() {
+                            continue;
+                        }
+                        let keypair = self.keypair.clone();
+                        let bls = self.bls.clone();
+                        let crypto = self.crypto.clone();
+                        let sent_echo = self.sent_echo.clone();
+                        let tx_list = self.tx_list.clone();
+                        let hash_list = self.hash_list.clone();
+                        let tx_send = tx_send.clone();
+                        tokio::spawn(async move {
+                            handle_send_msg(
                                 self.node_ind,
                                 sender as usize,
+                                epoch,
                                 rn as usize,
                                 payload,
                                 keypair,
+                                bls,
+                                crypto,
                                 tx_list,
                                 hash_list,
                                 sent_echo,
@@ -355,76 +1349,146 @@ impl Sequencer {
                             ).await;
                         });
                     },
-                    Message::Echo{sender, rn, sign} => {
+                    Message::Echo{sender, epoch, rn, sign} => {
+                        let committee = match self.committee_of(epoch, rn, sender).await {
+                            Some(committee) => committee,
+                            None => continue,
+                        };
                         let self_node_ind = self.node_ind;
-                        let num_nodes = self.num_nodes;
                         let sent_fin = self.sent_fin.clone();
-                        let peer_pkeys = self.peer_pkeys.clone();
+                        let scheme = self.scheme();
+                        // echoes are signed with the key of the scheme
+                        let peer_pkeys = match scheme {
+                            CertScheme::Ed25519 => self.peer_pkeys.clone(),
+                            CertScheme::Bls => self.bls_pkeys.clone(),
+                        };
+                        let crypto = self.crypto.clone();
                         let hash_list = self.hash_list.clone();
                         let echo_list = self.echo_list.clone();
+                        let cert_list = self.cert_list.clone();
                         let delivered = self.delivered.clone();
-                        let recv_sup_cnt = self.recv_sup_cnt.clone();
+                        let recv_sup = self.recv_sup.clone();
+                        let measure = self.measure.clone();
                         let tx_send = tx_send.clone();
                         tokio::spawn(async move {
                             handle_echo_msg(
                                 self_node_ind,
                                 sender as usize,
+                                epoch,
                                 rn as usize,
                                 sign,
-                                num_nodes as usize, // should change to 2f+1
+                                committee,
                                 sent_fin,
+                                scheme,
                                 peer_pkeys,
+                                crypto,
                                 hash_list,
                                 echo_list,
+                                cert_list,
                                 delivered,
-                                recv_sup_cnt,
-                                tx_send,
+                                recv_sup,
+                                measure,
+                                &tx_send,
                             ).await;
                         });
                     },
-                    Message::Fin{sender, rn, sign_cnt, signs} => {
+                    Message::Fin{sender, epoch, rn, cert} => {
+                        self.spawn_fin(sender, epoch, rn, Certificate::Signs(cert), &tx_send).await;
+                    },
+                    Message::AggFin{sender, epoch, rn, cert} => {
+                        self.spawn_fin(sender, epoch, rn, Certificate::Aggregate(cert), &tx_send).await;
+                    },
+                    Message::Sup{ sender, epoch, rn, cert, originator, payload } => {
+                        self.spawn_sup(sender, epoch, rn, Certificate::Signs(cert), originator, payload, &tx_send).await;
+                    },
+                    Message::AggSup{ sender, epoch, rn, cert, originator, payload } => {
+                        self.spawn_sup(sender, epoch, rn, Certificate::Aggregate(cert), originator, payload, &tx_send).await;
+                    },
+                    Message::SyncReq{ sender, delivered } => {
                         let self_node_ind = self.node_ind;
-                        let num_nodes = self.num_nodes;
-                        let sent_sup = self.sent_sup.clone();
-                        let hash_list = self.hash_list.clone();
-                        let peer_pkeys = self.peer_pkeys.clone();
-                        let delivered = self.delivered.clone();
-                        let recv_sup_cnt = self.recv_sup_cnt.clone();
-                        let tx_list = self.tx_list.clone();
+                        let num_ids = self.epochs.read().await.num_ids() as u32;
+                        let store = self.store.clone();
+                        let sync_serving = self.sync_serving.clone();
                         let tx_send = tx_send.clone();
                         tokio::spawn(async move {
-                            handle_fin_msg(
+                            sync::handle_sync_req(
                                 self_node_ind,
-                                sender as usize,
-                                rn as usize,
-                                sign_cnt as usize,
-                                num_nodes,
-                                signs,
-                                sent_sup,
-                                hash_list,
-                                peer_pkeys,
+                                sender,
                                 delivered,
-                                recv_sup_cnt,
-                                tx_list,
-                                tx_send,
+                                num_ids,
+                                store,
+                                sync_serving,
+                                &tx_send,
                             ).await;
                         });
                     },
-                    Message::Sup{ rn, originator, .. } => {
-                        let threashold = self.f_cnt * 2 + 1;
-                        let delivered = self.delivered.clone();
-                        let recv_sup_cnt = self.recv_sup_cnt.clone();
-                        let measure = self.measure.clone();
+                    Message::SyncResp{ rn, cert, originator, payload, .. } => {
+                        self.spawn_sync_resp(rn, Certificate::Signs(cert), originator, payload).await;
+                    },
+                    Message::AggSyncResp{ rn, cert, originator, payload, .. } => {
+                        self.spawn_sync_resp(rn, Certificate::Aggregate(cert), originator, payload).await;
+                    },
+                    Message::Chunk{ sender, epoch, rn, originator, index, root, das_root, proof, chunk } => {
+                        self.spawn_chunk(sender, epoch, rn, originator, index, root, das_root, proof, chunk, &tx_send).await;
+                    },
+                    Message::ChunkReq{ sender, epoch, rn, originator } => {
+                        if self.committee_of(epoch, rn, originator).await.is_none_or(|committee| !committee.contains(sender)) {
+                            continue;
+                        }
+                        let self_node_ind = self.node_ind;
+                        let chunk_list = self.chunk_list.clone();
+                        let tx_send = tx_send.clone();
+                        tokio::spawn(async move {
+                            avid::handle_chunk_req(self_node_ind, sender, epoch, rn as usize, originator as usize, chunk_list, &tx_send).await;
+                        });
+                    },
+                    Message::Checkpoint{ sender, rn, digest, data_root, sign } => {
+                        let committee = self.epochs.read().await.for_round(rn.saturating_sub(1)).clone();
+                        if !committee.contains(sender) {
+                            continue;
+                        }
                         let self_node_ind = self.node_ind;
+                        let keypair = self.keypair.clone();
+                        let peer_pkeys = self.peer_pkeys.clone();
+                        let checkpoints = self.checkpoints.clone();
+                        let store = self.store.clone();
                         tokio::spawn(async move {
-                            handle_sup_msg(
-                                self_node_ind as usize,
-                                originator as usize,
-                                rn as usize,
-                                threashold,
-                                delivered,
-                                recv_sup_cnt,
-                                measure,
+                            checkpoint::handle_checkpoint_msg(
+                                self_node_ind,
+                                &keypair.pub_key,
+                                sender,
+                                rn,
+                                digest,
+                                data_root,
+                                sign,
+                                committee,
+                                peer_pkeys,
+                                checkpoints,
+                                store,
+                            ).await;
+                        });
+                    },
+                    Message::Close{ sender, voter, rn, absent, sign } => {
+                        if !self.epochs.read().await.for_round(rn).contains(voter) {
+                            continue;
+                        }
+                        let self_node_ind = self.node_ind;
+                        let peer_pkeys = self.peer_pkeys.clone();
+                        let closes = self.closes.clone();
+                        let checkpoints = self.checkpoints.clone();
+                        let tx_send = tx_send.clone();
+                        tokio::spawn(async move {
+                            checkpoint::handle_close_msg(
+                                self_node_ind,
+                                sender,
+                                voter,
+                                rn,
+                                absent,
+                                sign,
+                                peer_pkeys,
+                                closes,
+                                checkpoints,
+                                &tx_send,
                             ).await;
                         });
                     },
@@ -438,38 +1502,34 @@ impl Sequencer {
 async fn handle_send_msg(
     self_node_ind:u32,
     sender:usize, 
+    epoch:u32,
     rn:usize, 
     payload:U8Arr,
     keypair:Arc<KeyPair>,
+    bls:Option<Arc<BlsKeyPair>>,
+    crypto:Arc<CryptoPool>,
     tx_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
     hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
     sent_echo:Arc<Vec<tk_mutex<Vec<bool>>>>,
     tx_send:tokio_mpsc::Sender<CastType>
 ){
-    let payload_digest = digest::digest(&digest::SHA256, &payload);
+    let (payload, payload_digest, sign) = crypto.run(move || {
+        // a Send carries the whole payload, which is Full dispersal: no chunks
+        let payload_digest = avid::batch_digest(Dispersal::Full, 0, &payload);
+        let sign = sign_echo(&keypair, bls.as_deref(), &payload_digest);
+        (payload, payload_digest, sign)
+    }).await;
     {
         let mut sent_echo = sent_echo[sender].lock().await;
         while sent_echo.len() <= rn {
             sent_echo.push(false);
         }
 
-        if sent_echo[rn] == false {
+        if !sent_echo[rn] {
             sent_echo[rn] = true;
             drop(sent_echo);
 
-            tx_send.send(CastType::Unicast{
-                dest: sender as u32,
-                bytes: Message::Echo{
-                    sender: self_node_ind,
-                    rn: rn as u32,
-                    sign: keypair.sign(payload_digest.as_ref()),
-                }
-                .to_bytes()
-                .unwrap()
-            })
-            .await
-            .expect("failed to send echo msg");
-
+            // stored before echoing, the Sup messages for it are checked against the hash
             {
                 let mut tx_list = tx_list[sender].write().await;
                 while tx_list.len() <= rn {
@@ -482,8 +1542,24 @@ async fn handle_send_msg(
                 while hash_list.len() <= rn {
                     hash_list.push(Vec::new());
                 }
-                hash_list[rn] = payload_digest.as_ref().to_vec();
+                hash_list[rn] = payload_digest;
             }
+
+            tx_send.send(CastType::Unicast{
+                dest: sender as u32,
+                round: Some(rn as u32),
+                priority: Priority::Urgent,
+                bytes: Message::Echo{
+                    sender: self_node_ind,
+                    epoch,
+                    rn: rn as u32,
+                    sign,
+                }
+                .to_bytes()
+                .unwrap()
+            })
+            .await
+            .expect("failed to send echo msg");
         }
     }
 }
@@ -491,30 +1567,41 @@ async fn handle_send_msg(
 async fn handle_echo_msg(
     self_node_ind:u32,
     sender:usize, 
+    epoch:u32,
     rn:usize, 
     sign:U8Arr, 
-    echo_threashold:usize,
+    committee:Arc<Committee>,
     sent_fin:Arc<tk_mutex<Vec<bool>>>,
-    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    scheme:CertScheme,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>, // keys of the scheme
+    crypto:Arc<CryptoPool>,
     hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
-    echo_list:Arc<tk_rwlock<Vec<Vec<(u32, U8Arr)>>>>,
+    echo_list:Arc<EchoList>,
+    cert_list:Arc<CertList>,
     delivered:Arc<Vec<tk_rwlock<Vec<bool>>>>,
-    recv_sup_cnt:Arc<Vec<tk_rwlock<Vec<u8>>>>,
-    ref tx_send:tokio_mpsc::Sender<CastType>
+    recv_sup:Arc<Vec<tk_rwlock<Vec<HashSet<u32>>>>>,
+    measure:Arc<MeasureDs>,
+    tx_send:&tokio_mpsc::Sender<CastType>
 ){
-
-    {
-        let peer_pkeys = peer_pkeys.read().await;
-        let hash = hash_list[self_node_ind as usize].read().await;
-        if !KeyPair::verify_signature(
-            &peer_pkeys[sender].as_ref().unwrap(),
-            &hash[rn],
-            &sign
-        ){
-            println!("wrong signature!!");
-            return;
-        }
-
+    // an echo from a peer we have no key of yet, or for a round we sent nothing in, is dropped
+    let pub_key = match peer_pkeys.read().await.get(sender) {
+        Some(Some(pub_key)) => pub_key.clone(),
+        _ => return,
+    };
+    let hash = match hash_list[self_node_ind as usize].read().await.get(rn) {
+        Some(hash) => hash.clone(),
+        None => return,
+    };
+    let (valid, sign) = crypto.run(move || {
+        let valid = match scheme {
+            CertScheme::Ed25519 => KeyPair::verify_signature(&pub_key, &hash, &sign),
+            CertScheme::Bls => BlsKeyPair::verify_signature(&pub_key, &hash, &sign),
+        };
+        (valid, sign)
+    }).await;
+    if !valid {
+        println!("wrong signature!!");
+        return;
     }
     append_echo(&echo_list, rn, sender as u32, sign).await;
 
@@ -523,52 +1610,63 @@ async fn handle_echo_msg(
     while sent_fin.len() <= rn {
         sent_fin.push(false);
     }
+    // a quorum of echoes, so that a crashed or slow member does not hold up our Fin
     if !sent_fin[rn] 
-        && got_enough_echo(&echo_list, rn, echo_threashold).await 
+        && got_enough_echo(&echo_list, rn, &committee, committee.quorum()).await 
     {
         sent_fin[rn] = true;
         drop(sent_fin);
 
-        let echo_list = echo_list.read().await;
-        tx_send.send(CastType::Multicast{
-            bytes: Message::Fin{
-                sender: self_node_ind,
-                rn: rn as u32,
-                sign_cnt: echo_list[rn].len() as u32,
-                signs: echo_list[rn].clone(),
+        let echoes = echo_list.read().await[rn].clone();
+        let cert = match scheme {
+            CertScheme::Ed25519 => Certificate::Signs(echoes.into_iter().collect()),
+            CertScheme::Bls => {
+                let (ids, signs): (Vec<u32>, Vec<U8Arr>) = echoes.into_iter().unzip();
+                let sign = crypto.run(move || {
+                    let signs: Vec<&[u8]> = signs.iter().map(|sign| sign.as_slice()).collect();
+                    signature::aggregate(&signs)
+                }).await;
+                match sign {
+                    Some(sign) => Certificate::Aggregate(AggCert::new(&ids, sign)),
+                    None => { eprintln!("failed to aggregate the echoes of round {}", rn); return; }
+                }
             }
-            .to_bytes()
-            .unwrap(),
+        };
+        // in the Fin and the Sup, to every other member
+        measure.incr_cert_sent(&cert, 2 * (committee.size() - 1)).await;
+        tx_send.send(CastType::Multicast{
+            epoch,
+            round: Some(rn as u32),
+            priority: Priority::Urgent,
+            bytes: fin_msg(self_node_ind, epoch, rn as u32, cert.clone())
+                .to_bytes()
+                .unwrap(),
         })
         .await
         .expect("failed to send fin msg to peers");
 
         tx_send.send(CastType::Multicast{
-            bytes: Message::Sup{
-                sender: self_node_ind,
-                rn: rn as u32,
-                sign_cnt: echo_list[rn].len() as u32,
-                signs: echo_list[rn].clone(),
-                originator: self_node_ind,
-                // TODO: distinguish, assume optimistic case for now
-                payload:Vec::new(), 
-            }
-            .to_bytes()
-            .unwrap(),
+            epoch,
+            round: Some(rn as u32),
+            priority: Priority::Urgent,
+            // TODO: distinguish, assume optimistic case for now
+            bytes: sup_msg(self_node_ind, epoch, rn as u32, cert.clone(), self_node_ind, Vec::new())
+                .to_bytes()
+                .unwrap(),
         })
         .await
         .expect("failed to send fin msg to peers");
 
-        drop(echo_list);
+        set_round(&cert_list[self_node_ind as usize], rn, cert).await;
 
         {
             let mut delivered = delivered[self_node_ind as usize].write().await;
-            let mut recv_sup_cnt = recv_sup_cnt[self_node_ind as usize].write().await;
+            let mut recv_sup = recv_sup[self_node_ind as usize].write().await;
             while delivered.len() <= rn {
                 delivered.push(false);
-                recv_sup_cnt.push(0);
+                recv_sup.push(HashSet::new());
             }
-            recv_sup_cnt[rn] += 1;
+            recv_sup[rn].insert(self_node_ind);
         }
     }
 } // end of handle_echo_msg()
@@ -576,139 +1674,495 @@ async fn handle_echo_msg(
 // TODO: after sending final message, should send sup message too
 async fn handle_fin_msg(
     self_node_ind:u32,
+    self_pub_key:&[u8],
     sender:usize,
     rn:usize,
-    sign_cnt:usize,
-    num_nodes:u32,
-    sign_list:Vec<(u32, U8Arr)>,
+    committee:Arc<Committee>,
+    cert:Certificate,
     sent_sup:Arc<Vec<tk_mutex<Vec<bool>>>>,
     hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    cert_list:Arc<CertList>,
     peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    bls_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    cert_cache:Arc<tk_mutex<CertCache>>,
+    crypto:Arc<CryptoPool>,
+    dispersal:Dispersal,
     delivered:Arc<Vec<tk_rwlock<Vec<bool>>>>,
-    recv_sup_cnt:Arc<Vec<tk_rwlock<Vec<u8>>>>,
+    recv_sup:Arc<Vec<tk_rwlock<Vec<HashSet<u32>>>>>,
     tx_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
-    ref tx_send:tokio_mpsc::Sender<CastType>
+    measure:Arc<MeasureDs>,
+    tx_send:&tokio_mpsc::Sender<CastType>
 ){
-    if sign_list.len() != sign_cnt {
-        eprintln!("Error: Mismatched sign count");
+    // a Fin that overtakes its Send is dropped without marking the round, so a later one still counts
+    let h_tx = match hash_list[sender].read().await.get(rn) {
+        Some(h_tx) if !h_tx.is_empty() => h_tx.clone(),
+        _ => { eprintln!("hash not found!"); return; }
+    };
+    if sent_sup[sender].lock().await.get(rn) == Some(&true) {
         return;
     }
-
-    {
-        let mut sent_sup = sent_sup[sender].lock().await;
-        while sent_sup.len() <= rn {
-            sent_sup.push(false);
-        }
-        if sent_sup[rn] == true {
-            return;
-        }
-        else {sent_sup[rn] = true; }
-    }
-
-    let mut valid_signatures = 0;
-    {
-        let hashes = hash_list[sender].read().await;
-        if let Some(h_tx) = hashes.get(rn) {
-            for (signer_id, sign) in sign_list.iter() {
-                if *signer_id == self_node_ind {
-                    valid_signatures += 1;
-                    continue;
-                }
-
-                let pkey = peer_pkeys.read().await;
-                if KeyPair::verify_signature(
-                    &pkey[*signer_id as usize].clone().unwrap(), 
-                    h_tx, 
-                    sign
-                ) {
-                    valid_signatures += 1;
-                }
+    let valid_stake = certified_stake(
+        self_node_ind,
+        self_pub_key,
+        &committee,
+        &peer_pkeys,
+        &bls_pkeys,
+        &cert_cache,
+        &crypto,
+        h_tx,
+        &cert
+    ).await;
+    let quorum = committee.quorum();
+    if valid_stake >= quorum {
+        {
+            let mut sent_sup = sent_sup[sender].lock().await;
+            while sent_sup.len() <= rn {
+                sent_sup.push(false);
+            }
+            if sent_sup[rn] {
+                return;
             }
+            sent_sup[rn] = true;
         }
-        else { eprintln!("hash not found!"); return; }
-    }
-    
-    let f_cnt = (num_nodes - 1) / 3;
-    if valid_signatures >= 2 * f_cnt + 1 {
-        let signers_set: HashSet<u32> = sign_list.iter().map(|(id, _)| *id).collect();
-        for i in 0..num_nodes {
+        set_round(&cert_list[sender], rn, cert.clone()).await;
+        for i in committee.ids() {
             if i == self_node_ind {
                 {
                     let mut delivered = delivered[sender].write().await;
-                    let mut recv_sup_cnt = recv_sup_cnt[sender].write().await;
+                    let mut recv_sup = recv_sup[sender].write().await;
                     while delivered.len() <= rn {
                         delivered.push(false);
-                        recv_sup_cnt.push(0);
+                        recv_sup.push(HashSet::new());
                     }
-                    recv_sup_cnt[rn] += 1;
+                    recv_sup[rn].insert(self_node_ind);
                 }
                 continue;
             }
-            let sup_msg = if signers_set.contains(&i) {
-                Message::Sup {
-                    sender: self_node_ind,
-                    rn: rn as u32,
-                    sign_cnt: sign_cnt as u32, 
-                    signs: sign_list.clone(),
-                    originator: sender as u32,
-                    payload: Vec::new(), // Empty payload
-                }
-            } 
-            else {
-                Message::Sup {
-                    sender: self_node_ind,
-                    rn: rn as u32,
-                    sign_cnt: sign_cnt as u32, // No signs included
-                    signs: sign_list.clone(),
-                    originator: sender as u32,
-                    payload: tx_list[sender].read().await[rn].clone(),
-                }
+            // signers have the payload already, and a dispersed one is decoded from chunks
+            let payload = if cert.is_signer(i) || dispersal == Dispersal::Avid {
+                Vec::new()
+            } else {
+                tx_list[sender].read().await.get(rn).cloned().unwrap_or_default()
             };
+            // a Sup carrying the payload is as bulky as the Send it stands for
+            let priority = if payload.is_empty() { Priority::Urgent } else { Priority::Bulk };
+            let sup = sup_msg(self_node_ind, committee.epoch, rn as u32, cert.clone(), sender as u32, payload);
+            measure.incr_cert_sent(&cert, 1).await;
             if let Err(e) = tx_send.send(CastType::Unicast {
                 dest: i,
-                bytes: sup_msg.to_bytes().unwrap(),
+                round: Some(rn as u32),
+                priority,
+                bytes: sup.to_bytes().unwrap(),
             }).await {
                 eprintln!("Failed to send SUP message: {}", e);
             }
         } 
     } else {
-        eprintln!("Verification failed. Not enough valid signatures: {} / {}", valid_signatures, 2 * f_cnt + 1);
+        eprintln!("Verification failed. Not enough signed stake: {} / {}", valid_stake, quorum);
         // TODO: Handle insufficient valid signatures
     } 
 } // end of handle_fin_msg()
 
 async fn handle_sup_msg(
     self_node_ind:usize,
+    self_pub_key:&[u8],
+    sender:u32,
     originator:usize,
     rn: usize,
-    threashold: usize,
+    cert:Certificate,
+    payload:U8Arr,
+    committee:Arc<Committee>,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    bls_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    cert_cache:Arc<tk_mutex<CertCache>>,
+    crypto:Arc<CryptoPool>,
+    dispersal:Dispersal,
     delivered:Arc<Vec<tk_rwlock<Vec<bool>>>>,
-    recv_sup_cnt:Arc<Vec<tk_rwlock<Vec<u8>>>>,
+    recv_sup:Arc<Vec<tk_rwlock<Vec<HashSet<u32>>>>>,
+    tx_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    cert_list:Arc<CertList>,
+    chunk_list:Arc<ChunkList>,
+    store:Arc<dyn Store>,
     measure:Arc<MeasureDs>,
+    tx_send:&tokio_mpsc::Sender<CastType>
 ){
-    let mut delivered = delivered[originator].write().await;
-    let mut recv_sup_cnt = recv_sup_cnt[originator].write().await;
-    while delivered.len() <= rn {
-        delivered.push(false);
-        recv_sup_cnt.push(0);
-    }
-    recv_sup_cnt[rn] += 1;
-    /* 
-     * TODO: amplify
-     * chk sent_sup's length, 
-     * send() if got more than f+1 sup msg and has not sent sup msg
+    /*
+    * The certificate is on the digest of the payload (the root of its chunks
+    * with Avid), which is either in the Sup message or already known from the
+    * Send (Chunk) message.
     */
-    if delivered[rn] == false && recv_sup_cnt[rn] as usize >= threashold {
+    let num_chunks = committee.size();
+    let (payload, cert_digest) = if !payload.is_empty() {
+        crypto.run(move || {
+            let payload_digest = avid::batch_digest(dispersal, num_chunks, &payload);
+            (payload, payload_digest)
+        }).await
+    } else {
+        match hash_list[originator].read().await.get(rn) {
+            Some(hash) if !hash.is_empty() => (payload, hash.clone()),
+            _ => {
+                eprintln!("no payload for {}'s msg of round {}, sup dropped", originator, rn);
+                return;
+            }
+        }
+    };
+    let stake = certified_stake(
+        self_node_ind as u32,
+        self_pub_key,
+        &committee,
+        &peer_pkeys,
+        &bls_pkeys,
+        &cert_cache,
+        &crypto,
+        cert_digest.clone(),
+        &cert
+    ).await;
+    if stake < committee.quorum() {
+        eprintln!("bad certificate in sup for {}'s msg of round {}", originator, rn);
+        return;
+    }
+    // kept before delivering, a batch decoded later is stored with it
+    if cert_list[originator].read().await.get(rn).is_none_or(|known| known.is_empty()) {
+        set_round(&cert_list[originator], rn, cert.clone()).await;
+    }
+
+    {
+        let mut delivered = delivered[originator].write().await;
+        let mut recv_sup = recv_sup[originator].write().await;
+        while delivered.len() <= rn {
+            delivered.push(false);
+            recv_sup.push(HashSet::new());
+        }
+        recv_sup[rn].insert(sender);
+        /* 
+         * TODO: amplify
+         * chk sent_sup's length, 
+         * send() if got sup msg from more than committee.validity() stake
+         * and has not sent sup msg
+        */
+        if delivered[rn] || committee.stake(recv_sup[rn].iter().copied()) < committee.quorum() {
+            return;
+        }
         delivered[rn] = true;
-        println!("{}'s msg for round {} is delivered!", originator, rn);
-        if originator == self_node_ind {
-            measure.measure_latency(rn).await;
+    }
+    println!("{}'s msg for round {} is delivered!", originator, rn);
+    if originator == self_node_ind {
+        measure.measure_latency(rn).await;
+    }
+
+    /*
+    * Persist the delivered batch. Prefer what we got (and checked) ourselves
+    * through Send and Fin, and fall back to what the Sup message carries.
+    */
+    let known_payload = tx_list[originator].read().await
+        .get(rn)
+        .filter(|tx| !tx.is_empty())
+        .cloned();
+    let payload = match known_payload {
+        Some(tx) => tx,
+        None if payload.is_empty() && dispersal == Dispersal::Avid => {
+            println!("{}'s msg for round {} is stored once decoded", originator, rn);
+            let root = hash_list[originator].read().await.get(rn).cloned().unwrap_or_default();
+            tokio::spawn(avid::retrieve(self_node_ind as u32, committee.epoch, rn, originator, root, chunk_list, tx_send.clone()));
+            return;
+        }
+        None => {
+            set_round(&tx_list[originator], rn, payload.clone()).await;
+            payload
+        }
+    };
+    let known_digest = hash_list[originator].read().await
+        .get(rn)
+        .filter(|hash| !hash.is_empty())
+        .cloned();
+    // otherwise the payload came with the Sup, and the certificate was checked on its digest
+    let digest = match known_digest {
+        Some(hash) => hash,
+        None => {
+            set_round(&hash_list[originator], rn, cert_digest.clone()).await;
+            cert_digest
+        }
+    };
+    let certificate = match cert_list[originator].read().await.get(rn) {
+        Some(known) if !known.is_empty() => known.clone(),
+        _ => cert,
+    };
+    let record = Record {
+        round: rn as u32,
+        originator: originator as u32,
+        digest,
+        payload,
+        certificate,
+    };
+    if let Err(e) = store.put(record) {
+        eprintln!("failed to store {}'s msg for round {}: {}", originator, rn, e);
+    }
+}
+
+/* sets list[rn], growing the list with defaults if needed */
+pub(crate) async fn set_round<T: Default>(
+    list:&tk_rwlock<Vec<T>>,
+    rn:usize,
+    value:T
+){
+    let mut list = list.write().await;
+    while list.len() <= rn {
+        list.push(T::default());
+    }
+    list[rn] = value;
+}
+
+/*
+* The (originator, digest) of the batches of round rn that can be folded:
+* delivered, and decoded if dispersed, in the order of the committee.
+*/
+async fn foldable_batches(
+    rn:usize,
+    committee:&Committee,
+    dispersal:Dispersal,
+    delivered:&[tk_rwlock<Vec<bool>>],
+    hash_list:&[tk_rwlock<Vec<U8Arr>>],
+    tx_list:&[tk_rwlock<Vec<U8Arr>>],
+) -> Vec<(u32, U8Arr)> {
+    let mut batch_digests = Vec::with_capacity(committee.size());
+    for originator in committee.ids() {
+        let ind = originator as usize;
+        if delivered[ind].read().await.get(rn) != Some(&true) {
+            continue;
+        }
+        let hash = match hash_list[ind].read().await.get(rn) {
+            Some(hash) if !hash.is_empty() => hash.clone(),
+            _ => continue,
+        };
+        // a dispersed batch may be delivered before it is decoded
+        if dispersal == Dispersal::Avid
+            && tx_list[ind].read().await.get(rn).is_none_or(|tx| tx.is_empty())
+        {
+            continue;
+        }
+        batch_digests.push((originator, hash));
+    }
+    batch_digests
+}
+
+/*
+* The originators of round rn we vote to leave out: those whose batch we
+* have neither delivered nor echoed. Their echo counts as sent from now on,
+* so that we never echo a batch we voted out (see Closes).
+*/
+async fn absent_batches(
+    rn:usize,
+    committee:&Committee,
+    present:&[u32],
+    delivered:&[tk_rwlock<Vec<bool>>],
+    sent_echo:&[tk_mutex<Vec<bool>>],
+) -> Signers {
+    let mut absent = Signers::default();
+    for originator in committee.ids() {
+        let ind = originator as usize;
+        if present.contains(&originator) || delivered[ind].read().await.get(rn) == Some(&true) {
+            continue;
+        }
+        let mut sent_echo = sent_echo[ind].lock().await;
+        while sent_echo.len() <= rn {
+            sent_echo.push(false);
+        }
+        if !std::mem::replace(&mut sent_echo[rn], true) {
+            absent.insert(originator);
+        }
+    }
+    absent
+}
+
+/*
+* Drops what is kept in memory about the rounds [from, below): payloads,
+* digests, certificates, chunks, echoes and Sup senders. The sent and
+* delivered flags stay, a byte per round, as they keep a late message from
+* echoing or delivering a pruned round again.
+*/
+async fn prune_rounds(
+    from:usize,
+    below:usize,
+    tx_list:&[tk_rwlock<Vec<U8Arr>>],
+    hash_list:&[tk_rwlock<Vec<U8Arr>>],
+    cert_list:&CertList,
+    chunk_list:&ChunkList,
+    echo_list:&EchoList,
+    recv_sup:&[tk_rwlock<Vec<HashSet<u32>>>],
+){
+    async fn clear<T: Default>(list:&tk_rwlock<Vec<T>>, from:usize, below:usize) {
+        let mut list = list.write().await;
+        let below = below.min(list.len());
+        for entry in list.iter_mut().take(below).skip(from) {
+            *entry = T::default();
         }
     }
+    for ind in 0..tx_list.len() {
+        clear(&tx_list[ind], from, below).await;
+        clear(&hash_list[ind], from, below).await;
+        clear(&cert_list[ind], from, below).await;
+        clear(&recv_sup[ind], from, below).await;
+        let mut chunks = chunk_list[ind].lock().await;
+        let below = below.min(chunks.len());
+        for entry in chunks.iter_mut().take(below).skip(from) {
+            *entry = Default::default();
+        }
+    }
+    clear(echo_list, from, below).await;
+}
+
+/* Fin, or AggFin for an aggregated certificate */
+fn fin_msg(sender:u32, epoch:u32, rn:u32, cert:Certificate) -> Message {
+    match cert {
+        Certificate::Signs(cert) => Message::Fin{ sender, epoch, rn, cert },
+        Certificate::Aggregate(cert) => Message::AggFin{ sender, epoch, rn, cert },
+    }
+}
+
+/* Sup, or AggSup for an aggregated certificate */
+fn sup_msg(sender:u32, epoch:u32, rn:u32, cert:Certificate, originator:u32, payload:U8Arr) -> Message {
+    match cert {
+        Certificate::Signs(cert) => Message::Sup{ sender, epoch, rn, cert, originator, payload },
+        Certificate::Aggregate(cert) => Message::AggSup{ sender, epoch, rn, cert, originator, payload },
+    }
+}
+
+/* the echo on a payload digest, with the BLS key if certificates are aggregated */
+pub(crate) fn sign_echo(keypair:&KeyPair, bls:Option<&BlsKeyPair>, payload_digest:&[u8]) -> U8Arr {
+    match bls {
+        Some(bls) => bls.sign(payload_digest),
+        None => keypair.sign(payload_digest),
+    }
+}
+
+/* the stake that signed `msg` in a certificate of either scheme */
+pub(crate) async fn certified_stake(
+    self_node_ind:u32,
+    self_pub_key:&[u8],
+    committee:&Committee,
+    peer_pkeys:&tk_rwlock<Vec<Option<U8Arr>>>,
+    bls_pkeys:&tk_rwlock<Vec<Option<U8Arr>>>,
+    cert_cache:&tk_mutex<CertCache>,
+    crypto:&CryptoPool,
+    msg:U8Arr,
+    cert:&Certificate
+) -> u64 {
+    match cert {
+        Certificate::Signs(cert) => signed_stake(
+            self_node_ind,
+            self_pub_key,
+            committee,
+            peer_pkeys,
+            cert_cache,
+            crypto,
+            msg,
+            cert
+        ).await,
+        Certificate::Aggregate(cert) => aggregate_stake(committee, bls_pkeys, cert_cache, crypto, msg, cert).await,
+    }
+}
+
+/*
+* Returns the stake of the signers of an aggregated certificate on `msg`, or 0
+* if it is invalid. Unlike a list of signatures, an aggregate is valid or not
+* as a whole: it does not count if a signer is not a committee member or
+* their key is unknown.
+*/
+pub(crate) async fn aggregate_stake(
+    committee:&Committee,
+    bls_pkeys:&tk_rwlock<Vec<Option<U8Arr>>>,
+    cert_cache:&tk_mutex<CertCache>,
+    crypto:&CryptoPool,
+    msg:U8Arr,
+    cert:&AggCert
+) -> u64 {
+    let signers = cert.signer_ids();
+    let signs: Option<Vec<(u32, U8Arr, U8Arr)>> = {
+        let bls_pkeys = bls_pkeys.read().await;
+        signers.iter()
+            .map(|id| {
+                if !committee.contains(*id) {
+                    return None;
+                }
+                let pub_key = bls_pkeys.get(*id as usize)?.clone()?;
+                Some((*id, pub_key, cert.sign.clone()))
+            })
+            .collect()
+    };
+    let signs = match signs {
+        Some(signs) => signs,
+        None => return 0,
+    };
+
+    let key = CertCache::key(&msg, &signs);
+    if let Some(signers) = cert_cache.lock().await.get(&key) {
+        return committee.stake(signers);
+    }
+    let sign = cert.sign.clone();
+    let valid = crypto.run(move || {
+        let pub_keys: Vec<&[u8]> = signs.iter().map(|(_, pub_key, _)| pub_key.as_slice()).collect();
+        signature::verify_aggregate(&msg, &pub_keys, &sign)
+    }).await;
+    let signers = if valid { signers } else { Vec::new() };
+    let stake = committee.stake(signers.iter().copied());
+    cert_cache.lock().await.insert(key, signers);
+    stake
+}
+
+/*
+* Returns the stake of the distinct committee members whose signature on `msg`
+* is valid. The signatures are checked in one batch, and the outcome is cached
+* so that a certificate seen again (e.g. in Fin, then in Sup) is not checked
+* again.
+*/
+pub(crate) async fn signed_stake(
+    self_node_ind:u32,
+    self_pub_key:&[u8],
+    committee:&Committee,
+    peer_pkeys:&tk_rwlock<Vec<Option<U8Arr>>>,
+    cert_cache:&tk_mutex<CertCache>,
+    crypto:&CryptoPool,
+    msg:U8Arr,
+    cert:&SignCert
+) -> u64 {
+    let signs: Vec<(u32, U8Arr, U8Arr)> = {
+        let peer_pkeys = peer_pkeys.read().await;
+        cert.iter()
+            .filter(|(signer_id, _)| committee.contains(*signer_id))
+            .filter_map(|(signer_id, sign)| {
+                let pub_key = if signer_id == self_node_ind {
+                    self_pub_key.to_vec()
+                } else {
+                    peer_pkeys.get(signer_id as usize)?.clone()?
+                };
+                Some((signer_id, pub_key, sign.to_vec()))
+            })
+            .collect()
+    };
+
+    let key = CertCache::key(&msg, &signs);
+    if let Some(signers) = cert_cache.lock().await.get(&key) {
+        return committee.stake(signers);
+    }
+    let signers: Vec<u32> = crypto.run(move || {
+        let pairs: Vec<(&[u8], &[u8])> = signs.iter()
+            .map(|(_, pub_key, sign)| (pub_key.as_slice(), sign.as_slice()))
+            .collect();
+        verify_batch(&msg, &pairs)
+            .into_iter()
+            .zip(signs.iter())
+            .filter(|(valid, _)| *valid)
+            .map(|(_, (signer_id, _, _))| *signer_id)
+            .collect()
+    }).await;
+    let stake = committee.stake(signers.iter().copied());
+    cert_cache.lock().await.insert(key, signers);
+    stake
 }
 
 async fn append_echo(
-    ref echo_list:&Arc<tk_rwlock<Vec<Vec<(u32, U8Arr)>>>>, 
+    echo_list:&EchoList, 
     rn:usize, 
     sender:u32, 
     sign:U8Arr
@@ -717,16 +2171,21 @@ async fn append_echo(
     while echo_list.len() <= rn {
         echo_list.push(Vec::<(u32, U8Arr)>::new());
     }
+    // a repeated echo would count twice in the certificate
+    if echo_list[rn].iter().any(|(id, _)| *id == sender) {
+        return;
+    }
     echo_list[rn].push((sender, sign));
 }
 
 async fn got_enough_echo(
-    ref echo_list:&Arc<tk_rwlock<Vec<Vec<(u32, U8Arr)>>>>, 
+    echo_list:&EchoList, 
     rn:usize,
-    echo_threashold:usize
+    committee:&Committee,
+    echo_threashold:u64
 ) -> bool {
     if let Some(echo_list_rn) = echo_list.read().await.get(rn){
-        if echo_list_rn.len() == echo_threashold {
+        if committee.stake(echo_list_rn.iter().map(|(id, _)| *id)) >= echo_threashold {
             return true;
         }
     }
@@ -738,10 +2197,38 @@ async fn got_enough_echo(
 * The sending task spawned with `spawn_sender()` will read a Send_format from
 * the channel, and behave based on it.
 */
+/*
+* `round` is the round the message belongs to, if any. With reliable delivery
+* these messages are retransmitted until acknowledged or the round is folded,
+* the others (Syn, sync, checkpoints, close votes) are sent once.
+* `priority` puts the votes and certificates (Urgent) ahead of the payloads
+* (Bulk) queued for the same peer, so a round's certificate path does not wait
+* on batches of later rounds.
+*/
 pub enum CastType {
-    Unicast{dest:u32, bytes:Bytes},
-    Multicast{bytes:Bytes},
+    Unicast{dest:u32, round:Option<u32>, priority:Priority, bytes:Bytes},
+    Multicast{epoch:u32, round:Option<u32>, priority:Priority, bytes:Bytes}, // to the committee of the epoch
 }
+/* reports the connections of the sender to the peers going up and down */
+fn spawn_connection_log(
+    mut rx_events:tokio_mpsc::Receiver<ConnectionEvent>,
+    epochs:Arc<tk_rwlock<Epochs>>
+){
+    tokio::spawn(async move {
+        while let Some(event) = rx_events.recv().await {
+            let (address, state) = match event {
+                ConnectionEvent::Connected(address) => (address, "up"),
+                ConnectionEvent::Disconnected(address) => (address, "down"),
+            };
+            let epochs = epochs.read().await;
+            match (0..epochs.num_ids() as u32).find(|id| epochs.address(*id) == Some(address)) {
+                Some(id) => println!("connection to node {} ({}) is {}", id, address, state),
+                None => println!("connection to {} is {}", address, state),
+            }
+        }
+    });
+}
+
 /*
 * PeerReceiverHandler is struct for the communication between receiver and main
 * logic.
@@ -752,17 +2239,19 @@ pub enum CastType {
 */
 #[derive(Clone)]
 struct PeerReceiverHandler {
-    tx_recv: tokio_mpsc::Sender<Bytes>
+    tx_recv: tokio_mpsc::Sender<(Peer, Bytes)>
 }
 #[async_trait]
 impl MessageHandler for PeerReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes)
+    async fn dispatch(&self, writer: &mut Writer, peer: &Peer, message: Bytes)
         -> Result<(), Box<dyn Error>>
     {
         self.tx_recv
-            .send(message)
+            .send((peer.clone(), message))
             .await
             .expect("failed to send received data");
+        // acknowledged once handed to the main logic, senders that do not wait for it drop it
+        writer.send(Bytes::from_static(ACK)).await?;
         Ok(())
     }
 }
\ No newline at end of file
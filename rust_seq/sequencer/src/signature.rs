@@ -1,5 +1,47 @@
-use ring::{rand, signature};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use ring::{digest, rand};
+use ring::rand::SecureRandom;
 use ring::signature::{Ed25519KeyPair, KeyPair as RingKeyPair}; // Import the KeyPair trait
+use ed25519_dalek::{Signature, VerifyingKey};
+use blst::BLST_ERROR;
+use blst::min_pk as bls;
+use das::BLS_SIGN_DST;
+pub use message::Certificate;
+
+#[cfg(test)]
+#[path = "tests/signature_tests.rs"]
+pub mod signature_tests;
+
+/// Number of verified certificates remembered by a CertCache.
+pub const CERT_CACHE_SIZE: usize = 4096;
+/* domain separation tag of the proofs of possession; echoes are signed with das::BLS_SIGN_DST, which clients check */
+const BLS_POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/*
+* How echoes are signed and certificates are formed. With Ed25519, a
+* certificate lists 2f+1 signatures; with BLS, they are aggregated into one,
+* and the certificate has the same size whatever the committee.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CertScheme {
+    Ed25519,
+    Bls,
+}
+
+impl FromStr for CertScheme {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self, Self::Err> {
+        match s {
+            "ed25519" => Ok(CertScheme::Ed25519),
+            "bls" => Ok(CertScheme::Bls),
+            _ => Err(format!("unknown certificate scheme {}", s)),
+        }
+    }
+}
 
 pub struct KeyPair {
     pub pub_key: Vec<u8>,
@@ -15,17 +57,255 @@ impl KeyPair {
         KeyPair { pub_key, keypair }
     }
 
+    /*
+    * Loads the key from a PKCS#8 file, or generates one and saves it there.
+    * A stable key lets operators name the node in a reconfiguration.
+    */
+    pub fn load_or_generate<P: AsRef<Path>>(path:P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let document = match fs::read(path) {
+            Ok(document) => document,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let rng = rand::SystemRandom::new();
+                let document = Ed25519KeyPair::generate_pkcs8(&rng).unwrap().as_ref().to_vec();
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, &document)?;
+                document
+            }
+            Err(e) => return Err(e),
+        };
+        let keypair = Ed25519KeyPair::from_pkcs8(&document)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let pub_key = keypair.public_key().as_ref().to_vec();
+        Ok(KeyPair { pub_key, keypair })
+    }
+
     pub fn sign(&self, message: &[u8]) -> Vec<u8> {
         self.keypair.sign(message).as_ref().to_vec()
     }
 
+    /* checked the way verify_batch checks it, see parse_signed */
     pub fn verify_signature(
         pub_key: &[u8],
         message: &[u8],
         sign: &[u8]
     ) -> bool {
-        let pub_key = signature::UnparsedPublicKey::new(&signature::ED25519, pub_key);
-        pub_key.verify(message, sign).is_ok()
+        match parse_signed(pub_key, sign) {
+            Some((pub_key, sign)) => ed25519_dalek::verify_batch(&[message], &[sign], &[pub_key]).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/*
+* Parses an Ed25519 key and signature, and rejects small-order keys and R
+* values. Signatures are then checked with the cofactored equation on every
+* path, since batch verification can only check that one: a signature is valid
+* alone exactly when it is valid in a batch, and nodes checking a certificate
+* one way or the other agree on it.
+*/
+fn parse_signed(pub_key:&[u8], sign:&[u8]) -> Option<(VerifyingKey, Signature)> {
+    let pub_key = VerifyingKey::from_bytes(pub_key.try_into().ok()?).ok()?;
+    let sign = Signature::from_slice(sign).ok()?;
+    let r = VerifyingKey::from_bytes(sign.r_bytes()).ok()?;
+    if pub_key.is_weak() || r.is_weak() {
+        return None;
+    }
+    Some((pub_key, sign))
+}
+
+/*
+* Verifies signatures of the same message by several keys, given as
+* (pub_key, sign) pairs, and tells which ones are valid. All of them are first
+* checked at once with batch verification; if that fails, they are checked
+* one by one to find the bad ones.
+*/
+pub fn verify_batch(message:&[u8], signs:&[(&[u8], &[u8])]) -> Vec<bool> {
+    let parsed: Option<(Vec<VerifyingKey>, Vec<Signature>)> = signs.iter()
+        .map(|(pub_key, sign)| parse_signed(pub_key, sign))
+        .collect();
+    if let Some((pub_keys, signs)) = parsed {
+        let messages = vec![message; signs.len()];
+        if ed25519_dalek::verify_batch(&messages, &signs, &pub_keys).is_ok() {
+            return vec![true; signs.len()];
+        }
     }
+    signs.iter()
+        .map(|(pub_key, sign)| KeyPair::verify_signature(pub_key, message, sign))
+        .collect()
 }
 
+/*
+* A BLS key, used to sign echoes when certificates are aggregated. The public
+* key comes with a proof of possession, a signature on the key itself, without
+* which a node could announce a key crafted to cancel out the others in an
+* aggregate.
+*/
+pub struct BlsKeyPair {
+    pub pub_key: Vec<u8>,
+    pub pop: Vec<u8>,
+    secret: bls::SecretKey,
+}
+
+impl BlsKeyPair {
+    pub fn new() -> Self {
+        let mut ikm = [0; 32];
+        rand::SystemRandom::new().fill(&mut ikm).unwrap();
+        Self::from_ikm(&ikm)
+    }
+
+    fn from_ikm(ikm:&[u8]) -> Self {
+        let secret = bls::SecretKey::key_gen(ikm, &[]).unwrap();
+        let pub_key = secret.sk_to_pk().to_bytes().to_vec();
+        let pop = secret.sign(&pub_key, BLS_POP_DST, &[]).to_bytes().to_vec();
+        BlsKeyPair { pub_key, pop, secret }
+    }
+
+    /* loads the key material from a file, or generates it and saves it there */
+    pub fn load_or_generate<P: AsRef<Path>>(path:P) -> io::Result<Self> {
+        let path = path.as_ref();
+        match fs::read(path) {
+            Ok(ikm) if ikm.len() == 32 => Ok(Self::from_ikm(&ikm)),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "bad BLS key file")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut ikm = [0; 32];
+                rand::SystemRandom::new().fill(&mut ikm).unwrap();
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, ikm)?;
+                Ok(Self::from_ikm(&ikm))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn sign(&self, message:&[u8]) -> Vec<u8> {
+        self.secret.sign(message, BLS_SIGN_DST, &[]).to_bytes().to_vec()
+    }
+
+    pub fn verify_signature(pub_key:&[u8], message:&[u8], sign:&[u8]) -> bool {
+        verify_bls(pub_key, message, sign, BLS_SIGN_DST)
+    }
+
+    pub fn verify_pop(pub_key:&[u8], pop:&[u8]) -> bool {
+        verify_bls(pub_key, pub_key, pop, BLS_POP_DST)
+    }
+}
+
+impl Default for BlsKeyPair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn verify_bls(pub_key:&[u8], message:&[u8], sign:&[u8], dst:&[u8]) -> bool {
+    match (bls::PublicKey::key_validate(pub_key), bls::Signature::from_bytes(sign)) {
+        (Ok(pub_key), Ok(sign)) => sign.verify(true, message, dst, &[], &pub_key, false) == BLST_ERROR::BLST_SUCCESS,
+        _ => false,
+    }
+}
+
+/* aggregates BLS signatures (of the same message) into one */
+pub fn aggregate(signs:&[&[u8]]) -> Option<Vec<u8>> {
+    let signs: Vec<bls::Signature> = signs.iter()
+        .map(|sign| bls::Signature::from_bytes(sign).ok())
+        .collect::<Option<_>>()?;
+    let signs: Vec<&bls::Signature> = signs.iter().collect();
+    let aggregate = bls::AggregateSignature::aggregate(&signs, true).ok()?;
+    Some(aggregate.to_signature().to_bytes().to_vec())
+}
+
+/*
+* Checks an aggregated signature of `message` by all the given keys. The keys
+* must have been checked with BlsKeyPair::verify_pop.
+*/
+pub fn verify_aggregate(message:&[u8], pub_keys:&[&[u8]], sign:&[u8]) -> bool {
+    let pub_keys: Option<Vec<bls::PublicKey>> = pub_keys.iter()
+        .map(|pub_key| bls::PublicKey::from_bytes(pub_key).ok())
+        .collect();
+    match (pub_keys, bls::Signature::from_bytes(sign)) {
+        (Some(pub_keys), Ok(sign)) if !pub_keys.is_empty() => {
+            let pub_keys: Vec<&bls::PublicKey> = pub_keys.iter().collect();
+            sign.fast_aggregate_verify(true, message, BLS_SIGN_DST, &pub_keys) == BLST_ERROR::BLST_SUCCESS
+        }
+        _ => false,
+    }
+}
+
+/*
+* CertCache remembers which signers of a certificate were valid, so that the
+* same certificate arriving several times (in Fin, Sup, SyncResp) is verified
+* once. A certificate is identified by the message, and the signer ids, keys,
+* and signatures; the oldest entries are evicted first.
+*/
+pub struct CertCache {
+    signers: HashMap<Vec<u8>, Vec<u32>>,
+    order: VecDeque<Vec<u8>>,
+    capacity: usize,
+    hits: u64,
+}
+
+impl Default for CertCache {
+    fn default() -> Self {
+        Self::new(CERT_CACHE_SIZE)
+    }
+}
+
+impl CertCache {
+    pub fn new(capacity:usize) -> Self {
+        CertCache {
+            signers: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            hits: 0,
+        }
+    }
+
+    pub fn key(message:&[u8], signs:&[(u32, Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(message);
+        for (id, pub_key, sign) in signs.iter() {
+            ctx.update(&id.to_le_bytes());
+            ctx.update(pub_key);
+            ctx.update(sign);
+        }
+        ctx.finish().as_ref().to_vec()
+    }
+
+    pub fn get(&mut self, key:&[u8]) -> Option<Vec<u32>> {
+        let signers = self.signers.get(key).cloned();
+        if signers.is_some() {
+            self.hits += 1;
+        }
+        signers
+    }
+
+    pub fn insert(&mut self, key:Vec<u8>, signers:Vec<u32>) {
+        if self.capacity == 0 || self.signers.contains_key(&key) {
+            return;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.signers.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.signers.insert(key, signers);
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /* number of lookups answered from the cache */
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::signature::Certificate;
+pub use das::CheckpointCert;
+
+#[cfg(test)]
+#[path = "tests/store_tests.rs"]
+pub mod store_tests;
+
+/// Segments are rolled over once they grow past this size (in bytes).
+const SEGMENT_SIZE: u64 = 256 * 1024 * 1024;
+/// Every record on disk is prefixed with its length.
+const LEN_PREFIX: u64 = 4;
+/// Latest stable checkpoint certificate.
+const CHECKPOINT_FILE: &str = "checkpoint.bin";
+/// Every stable checkpoint certificate is also kept in a file of its own, `checkpoint_<rn>.bin`.
+const CHECKPOINT_PREFIX: &str = "checkpoint_";
+/// Records below the round kept in this file have been pruned.
+const PRUNED_FILE: &str = "pruned";
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Storage I/O failed: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to decode record in segment {0} at offset {1}: {2}")]
+    Corrupted(u32, u64, bincode::Error),
+
+    #[error("Failed to encode record: {0}")]
+    Encode(bincode::Error),
+
+    #[error("Failed to decode checkpoint: {0}")]
+    BadCheckpoint(bincode::Error),
+}
+
+/*
+* Record is everything we keep about a delivered batch: the payload, its digest
+* and the certificate (2f+1 echo signatures, or their aggregate) of it. Records are keyed by
+* (round, originator).
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub round: u32,
+    pub originator: u32,
+    pub digest: Vec<u8>,
+    pub payload: Vec<u8>,
+    pub certificate: Certificate,
+}
+
+impl Record {
+    pub fn key(&self) -> (u32, u32) {
+        (self.round, self.originator)
+    }
+}
+
+/*
+* Store keeps delivered batches so that they outlive the process. Putting the
+* same (round, originator) twice overwrites the previous record.
+*/
+pub trait Store: Send + Sync {
+    fn put(&self, record: Record) -> Result<(), StoreError>;
+
+    fn get(&self, round: u32, originator: u32) -> Result<Option<Record>, StoreError>;
+
+    /// Returns all records with `from <= round < to`, ordered by (round, originator).
+    fn range(&self, from: u32, to: u32) -> Result<Vec<Record>, StoreError>;
+
+    /// Highest round for which at least one record is stored.
+    fn highest_round(&self) -> Option<u32>;
+
+    /// Keeps `cert` if it is newer than the stored one.
+    fn put_checkpoint(&self, cert: CheckpointCert) -> Result<(), StoreError>;
+
+    fn latest_checkpoint(&self) -> Result<Option<CheckpointCert>, StoreError>;
+
+    /// The stable checkpoint of round `rn`, if it was put. Checkpoints are not pruned.
+    fn checkpoint(&self, rn: u32) -> Result<Option<CheckpointCert>, StoreError>;
+
+    /// Drops all records with `round < below`.
+    fn prune(&self, below: u32) -> Result<(), StoreError>;
+}
+
+/// Keeps every record in memory. Used by tests and by nodes that don't need persistence.
+#[derive(Default)]
+pub struct MemStore {
+    records: Mutex<BTreeMap<(u32, u32), Record>>,
+    checkpoints: Mutex<BTreeMap<u32, CheckpointCert>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemStore {
+    fn put(&self, record: Record) -> Result<(), StoreError> {
+        self.records.lock().unwrap().insert(record.key(), record);
+        Ok(())
+    }
+
+    fn get(&self, round: u32, originator: u32) -> Result<Option<Record>, StoreError> {
+        Ok(self.records.lock().unwrap().get(&(round, originator)).cloned())
+    }
+
+    fn range(&self, from: u32, to: u32) -> Result<Vec<Record>, StoreError> {
+        if from >= to {
+            return Ok(Vec::new());
+        }
+        Ok(self.records
+            .lock()
+            .unwrap()
+            .range((from, 0)..(to, 0))
+            .map(|(_, record)| record.clone())
+            .collect())
+    }
+
+    fn highest_round(&self) -> Option<u32> {
+        self.records.lock().unwrap().keys().next_back().map(|(round, _)| *round)
+    }
+
+    fn put_checkpoint(&self, cert: CheckpointCert) -> Result<(), StoreError> {
+        self.checkpoints.lock().unwrap().insert(cert.rn, cert);
+        Ok(())
+    }
+
+    fn latest_checkpoint(&self) -> Result<Option<CheckpointCert>, StoreError> {
+        Ok(self.checkpoints.lock().unwrap().values().next_back().cloned())
+    }
+
+    fn checkpoint(&self, rn: u32) -> Result<Option<CheckpointCert>, StoreError> {
+        Ok(self.checkpoints.lock().unwrap().get(&rn).cloned())
+    }
+
+    fn prune(&self, below: u32) -> Result<(), StoreError> {
+        let mut records = self.records.lock().unwrap();
+        *records = records.split_off(&(below, 0));
+        Ok(())
+    }
+}
+
+/// Where a record lives on disk.
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    len: u32,
+}
+
+struct FileStoreInner {
+    /// The segment we append to.
+    active: File,
+    active_id: u32,
+    active_len: u64,
+    index: BTreeMap<(u32, u32), Location>,
+    pruned_below: u32,
+}
+
+/*
+* FileStore appends records to segment files (`segment_000000.log`, ...) in the
+* given directory. Each record is written as a little-endian u32 length followed
+* by the bincode encoded `Record`. The index is kept in memory and rebuilt by
+* scanning the segments on `open()`; a partially written record at the tail of
+* the last segment (e.g. after a crash) is truncated away.
+* Pruning deletes the segments that only hold pruned rounds, and remembers the
+* pruned round so that leftovers in the other segments are skipped on `open()`.
+*/
+pub struct FileStore {
+    dir: PathBuf,
+    segment_size: u64,
+    inner: Mutex<FileStoreInner>,
+    /// Held from reading the stored checkpoint to replacing it, so an older one never wins.
+    checkpoint: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, StoreError> {
+        Self::open_with_segment_size(dir, SEGMENT_SIZE)
+    }
+
+    pub fn open_with_segment_size<P: AsRef<Path>>(
+        dir: P,
+        segment_size: u64
+    ) -> Result<Self, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segment_ids: Vec<u32> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| parse_segment_id(&entry.file_name().to_string_lossy()))
+            .collect();
+        segment_ids.sort_unstable();
+
+        let pruned_below = match fs::read(dir.join(PRUNED_FILE)) {
+            Ok(bytes) if bytes.len() == 4 => u32::from_le_bytes(bytes[..].try_into().unwrap()),
+            Ok(_) => 0,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut index = BTreeMap::new();
+        let mut active_len = 0;
+        for id in segment_ids.iter() {
+            active_len = scan_segment(&dir, *id, &mut index)?;
+        }
+        index = index.split_off(&(pruned_below, 0));
+
+        let active_id = segment_ids.last().copied().unwrap_or(0);
+        let active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&dir, active_id))?;
+        // drop a torn record left behind by a crash
+        active.set_len(active_len)?;
+
+        Ok(Self {
+            dir,
+            segment_size,
+            inner: Mutex::new(FileStoreInner { active, active_id, active_len, index, pruned_below }),
+            checkpoint: Mutex::new(()),
+        })
+    }
+
+    fn read_at(&self, location: Location) -> Result<Record, StoreError> {
+        let mut file = File::open(segment_path(&self.dir, location.segment))?;
+        file.seek(SeekFrom::Start(location.offset + LEN_PREFIX))?;
+        let mut buf = vec![0; location.len as usize];
+        file.read_exact(&mut buf)?;
+        bincode::deserialize(&buf)
+            .map_err(|e| StoreError::Corrupted(location.segment, location.offset, e))
+    }
+}
+
+impl Store for FileStore {
+    fn put(&self, record: Record) -> Result<(), StoreError> {
+        let bytes = bincode::serialize(&record).map_err(StoreError::Encode)?;
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.active_len > 0 && inner.active_len + LEN_PREFIX + bytes.len() as u64 > self.segment_size {
+            inner.active_id += 1;
+            inner.active = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.dir, inner.active_id))?;
+            inner.active_len = 0;
+        }
+
+        let location = Location {
+            segment: inner.active_id,
+            offset: inner.active_len,
+            len: bytes.len() as u32,
+        };
+        inner.active.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        inner.active.write_all(&bytes)?;
+        inner.active.sync_data()?;
+        inner.active_len += LEN_PREFIX + bytes.len() as u64;
+        inner.index.insert(record.key(), location);
+        Ok(())
+    }
+
+    fn get(&self, round: u32, originator: u32) -> Result<Option<Record>, StoreError> {
+        let location = self.inner.lock().unwrap().index.get(&(round, originator)).copied();
+        match location {
+            Some(location) => Ok(Some(self.read_at(location)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn range(&self, from: u32, to: u32) -> Result<Vec<Record>, StoreError> {
+        if from >= to {
+            return Ok(Vec::new());
+        }
+        let locations: Vec<Location> = self.inner
+            .lock()
+            .unwrap()
+            .index
+            .range((from, 0)..(to, 0))
+            .map(|(_, location)| *location)
+            .collect();
+        locations.into_iter().map(|location| self.read_at(location)).collect()
+    }
+
+    fn highest_round(&self) -> Option<u32> {
+        self.inner.lock().unwrap().index.keys().next_back().map(|(round, _)| *round)
+    }
+
+    fn put_checkpoint(&self, cert: CheckpointCert) -> Result<(), StoreError> {
+        let _checkpoint = self.checkpoint.lock().unwrap();
+        let bytes = bincode::serialize(&cert).map_err(StoreError::Encode)?;
+        write_atomic(&self.dir, &format!("{}{:010}.bin", CHECKPOINT_PREFIX, cert.rn), &bytes)?;
+        if let Some(latest) = self.latest_checkpoint()? {
+            if latest.rn >= cert.rn {
+                return Ok(());
+            }
+        }
+        write_atomic(&self.dir, CHECKPOINT_FILE, &bytes)
+    }
+
+    fn latest_checkpoint(&self) -> Result<Option<CheckpointCert>, StoreError> {
+        read_checkpoint(&self.dir.join(CHECKPOINT_FILE))
+    }
+
+    fn checkpoint(&self, rn: u32) -> Result<Option<CheckpointCert>, StoreError> {
+        read_checkpoint(&self.dir.join(format!("{}{:010}.bin", CHECKPOINT_PREFIX, rn)))
+    }
+
+    fn prune(&self, below: u32) -> Result<(), StoreError> {
+        let mut inner = self.inner.lock().unwrap();
+        if below <= inner.pruned_below {
+            return Ok(());
+        }
+        write_atomic(&self.dir, PRUNED_FILE, &below.to_le_bytes())?;
+        inner.pruned_below = below;
+        inner.index = inner.index.split_off(&(below, 0));
+
+        // segments no record points to anymore can go, except the one we append to
+        let live: HashSet<u32> = inner.index
+            .values()
+            .map(|location| location.segment)
+            .collect();
+        for id in 0..inner.active_id {
+            let path = segment_path(&self.dir, id);
+            if !live.contains(&id) && path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_checkpoint(path: &Path) -> Result<Option<CheckpointCert>, StoreError> {
+    match fs::read(path) {
+        Ok(bytes) => bincode::deserialize(&bytes)
+            .map(Some)
+            .map_err(StoreError::BadCheckpoint),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/* writes a small file so that readers see either the old or the new content */
+fn write_atomic(dir: &Path, name: &str, bytes: &[u8]) -> Result<(), StoreError> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_data()?;
+    fs::rename(tmp, dir.join(name))?;
+    Ok(())
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("segment_{:06}.log", id))
+}
+
+fn parse_segment_id(name: &str) -> Option<u32> {
+    name.strip_prefix("segment_")?.strip_suffix(".log")?.parse().ok()
+}
+
+/*
+* Reads every complete record of a segment into the index and returns the
+* length of the valid prefix of the segment.
+*/
+fn scan_segment(
+    dir: &Path,
+    id: u32,
+    index: &mut BTreeMap<(u32, u32), Location>
+) -> Result<u64, StoreError> {
+    let mut buf = Vec::new();
+    File::open(segment_path(dir, id))?.read_to_end(&mut buf)?;
+
+    let mut offset = 0;
+    while offset + LEN_PREFIX <= buf.len() as u64 {
+        let start = offset as usize;
+        let len = u32::from_le_bytes(buf[start..start + 4].try_into().unwrap());
+        let end = start + LEN_PREFIX as usize + len as usize;
+        if end > buf.len() {
+            break;
+        }
+        let record: Record = bincode::deserialize(&buf[start + LEN_PREFIX as usize..end])
+            .map_err(|e| StoreError::Corrupted(id, offset, e))?;
+        index.insert(record.key(), Location { segment: id, offset, len });
+        offset = end as u64;
+    }
+    Ok(offset)
+}
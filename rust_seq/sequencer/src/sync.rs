@@ -0,0 +1,213 @@
+#![allow(clippy::too_many_arguments)]
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::{Mutex as tk_mutex, RwLock as tk_rwlock, Semaphore};
+use tokio::time as tk_time;
+use message::Message;
+
+use crate::avid::{self, Dispersal};
+use crate::committee::Committee;
+use crate::crypto_pool::CryptoPool;
+use network::Priority;
+use crate::sequencer::{CastType, CertList, U8Arr, set_round, certified_stake};
+use crate::signature::{CertCache, Certificate};
+use crate::store::{Record, Store};
+
+#[cfg(test)]
+#[path = "tests/sync_tests.rs"]
+pub mod sync_tests;
+
+/// How often a node advertises what it has delivered (in ms).
+pub const SYNC_INTERVAL: u64 = 2_000;
+/// Rounds this close to the responder's latest round are left to the live protocol.
+pub const SYNC_LAG: u32 = 2;
+/// Max number of batches sent back for a single request.
+pub const SYNC_BATCH: usize = 16;
+/// Min gap between two batches sent by the responder (in ms), so that sync
+/// traffic does not starve live traffic in the sender queues.
+pub const SYNC_PACE: u64 = 50;
+
+/*
+* Returns, for each originator, the number of rounds delivered without gaps.
+* This is what a node advertises in its SyncReq.
+*/
+pub async fn delivered_prefix(delivered:&[tk_rwlock<Vec<bool>>]) -> Vec<u32> {
+    let mut prefix = Vec::with_capacity(delivered.len());
+    for rounds in delivered.iter() {
+        let rounds = rounds.read().await;
+        prefix.push(rounds.iter().take_while(|d| **d).count() as u32);
+    }
+    prefix
+}
+
+/*
+* Streams the certified batches the requester is missing, oldest round first.
+* Only one request is served at a time (requests arriving meanwhile are
+* dropped, the requester asks again later), at most SYNC_BATCH batches are
+* sent per request, and they are paced by SYNC_PACE.
+*/
+pub async fn handle_sync_req(
+    self_node_ind:u32,
+    requester:u32,
+    delivered:Vec<u32>,
+    num_ids:u32,
+    store:Arc<dyn Store>,
+    serving:Arc<Semaphore>,
+    tx_send:&tokio_mpsc::Sender<CastType>
+){
+    let _permit = match serving.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => return,
+    };
+    let limit = match store.highest_round() {
+        Some(tip) => tip.saturating_sub(SYNC_LAG),
+        None => return,
+    };
+    let delivered = &delivered[..delivered.len().min(num_ids as usize)];
+    let from = match delivered.iter().min() {
+        Some(from) => *from,
+        None => return,
+    };
+
+    let mut pace = tk_time::interval(tk_time::Duration::from_millis(SYNC_PACE));
+    let mut sent = 0;
+    for rn in from..limit {
+        for (originator, cnt) in delivered.iter().enumerate() {
+            if rn < *cnt {
+                continue;
+            }
+            let record = match store.get(rn, originator as u32) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("sync: failed to read round {} of {}: {}", rn, originator, e);
+                    return;
+                }
+            };
+
+            pace.tick().await;
+            if let Err(e) = tx_send.send(CastType::Unicast {
+                dest: requester,
+                round: None,
+                priority: Priority::Bulk,
+                bytes: sync_resp_msg(self_node_ind, record)
+                    .to_bytes()
+                    .unwrap(),
+            }).await {
+                eprintln!("sync: failed to send batch to {}: {}", requester, e);
+                return;
+            }
+
+            sent += 1;
+            if sent == SYNC_BATCH {
+                return;
+            }
+        }
+    }
+}
+
+/*
+* Delivers a batch received through sync, once its certificate holds valid
+* signatures of a quorum of the round's committee on the digest of the payload
+* (the root of its chunks, if batches are dispersed).
+*/
+pub async fn handle_sync_resp(
+    self_node_ind:u32,
+    self_pub_key:&[u8],
+    committee:&Committee,
+    originator:usize,
+    rn:usize,
+    cert:Certificate,
+    payload:U8Arr,
+    peer_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    bls_pkeys:Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    cert_cache:Arc<tk_mutex<CertCache>>,
+    crypto:Arc<CryptoPool>,
+    dispersal:Dispersal,
+    delivered:Arc<Vec<tk_rwlock<Vec<bool>>>>,
+    recv_sup:Arc<Vec<tk_rwlock<Vec<HashSet<u32>>>>>,
+    tx_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    hash_list:Arc<Vec<tk_rwlock<Vec<U8Arr>>>>,
+    cert_list:Arc<CertList>,
+    store:Arc<dyn Store>,
+){
+    if originator >= delivered.len() {
+        return;
+    }
+    if let Some(true) = delivered[originator].read().await.get(rn) {
+        return;
+    }
+
+    let num_chunks = committee.size();
+    let (payload, payload_digest) = crypto.run(move || {
+        let payload_digest = avid::batch_digest(dispersal, num_chunks, &payload);
+        (payload, payload_digest)
+    }).await;
+    let stake = certified_stake(
+        self_node_ind,
+        self_pub_key,
+        committee,
+        &peer_pkeys,
+        &bls_pkeys,
+        &cert_cache,
+        &crypto,
+        payload_digest.clone(),
+        &cert
+    ).await;
+    if stake < committee.quorum() {
+        eprintln!("sync: bad certificate for {}'s msg of round {} ({} stake signed)",
+            originator,
+            rn,
+            stake
+        );
+        return;
+    }
+
+    {
+        let mut delivered = delivered[originator].write().await;
+        let mut recv_sup = recv_sup[originator].write().await;
+        while delivered.len() <= rn {
+            delivered.push(false);
+            recv_sup.push(HashSet::new());
+        }
+        if delivered[rn] {
+            return;
+        }
+        delivered[rn] = true;
+    }
+    println!("{}'s msg for round {} is delivered (sync)!", originator, rn);
+
+    set_round(&tx_list[originator], rn, payload.clone()).await;
+    set_round(&hash_list[originator], rn, payload_digest.clone()).await;
+    set_round(&cert_list[originator], rn, cert.clone()).await;
+    if let Err(e) = store.put(Record {
+        round: rn as u32,
+        originator: originator as u32,
+        digest: payload_digest,
+        payload,
+        certificate: cert,
+    }) {
+        eprintln!("failed to store {}'s msg for round {}: {}", originator, rn, e);
+    }
+}
+
+/* SyncResp, or AggSyncResp for a batch with an aggregated certificate */
+fn sync_resp_msg(sender:u32, record:Record) -> Message {
+    match record.certificate {
+        Certificate::Signs(cert) => Message::SyncResp {
+            sender,
+            rn: record.round,
+            cert,
+            originator: record.originator,
+            payload: record.payload,
+        },
+        Certificate::Aggregate(cert) => Message::AggSyncResp {
+            sender,
+            rn: record.round,
+            cert,
+            originator: record.originator,
+            payload: record.payload,
+        },
+    }
+}
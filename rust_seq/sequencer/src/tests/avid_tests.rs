@@ -0,0 +1,164 @@
+// avid_tests.rs
+use super::*;
+use crate::store::MemStore;
+use crate::fixtures::{committee, lists};
+
+const NUM_NODES: usize = 4;
+
+/* keeps the chunks at `indexes` */
+fn keep(chunks:&[U8Arr], indexes:&[usize]) -> Vec<Option<U8Arr>> {
+    chunks.iter()
+        .enumerate()
+        .map(|(i, chunk)| indexes.contains(&i).then(|| chunk.clone()))
+        .collect()
+}
+
+#[test]
+fn test_decode_from_any_f_plus_one() {
+    let payload: U8Arr = (0..1000).map(|i| i as u8).collect();
+    for num_chunks in [1, 2, 4, 7, 10] {
+        let chunks = encode(&payload, num_chunks);
+        let k = data_chunks(num_chunks);
+        assert_eq!(chunks.len(), num_chunks);
+        assert!(chunks.iter().all(|chunk| chunk.len() == (8 + payload.len()).div_ceil(k)));
+
+        let first: Vec<usize> = (0..k).collect();
+        let last: Vec<usize> = (num_chunks - k..num_chunks).collect();
+        assert_eq!(decode(keep(&chunks, &first)), Some(payload.clone()));
+        assert_eq!(decode(keep(&chunks, &last)), Some(payload.clone()));
+        if k > 1 {
+            assert_eq!(decode(keep(&chunks, &first[1..])), None);
+        }
+    }
+    assert_eq!(decode(keep(&encode(&[], 4), &[1, 3])), Some(Vec::new()));
+}
+
+#[test]
+fn test_inconsistent_encoding_is_rejected() {
+    let payload = vec![7; 100];
+    let mut chunks = encode(&payload, 4);
+    let (root, _) = disperse(&payload, 4);
+    let digest = das::batch_digest(&root, &das_root(&payload));
+    assert_eq!(batch_digest(Dispersal::Avid, 4, &payload), digest);
+    assert_eq!(decode_checked(keep(&chunks, &[0, 3]), &digest), Some(payload.clone()));
+    // the sampling root is checked too
+    assert_eq!(decode_checked(keep(&chunks, &[0, 3]), &das::batch_digest(&root, &das_root(&[7; 99]))), None);
+
+    // committed chunks that are not a codeword: f+1 of them decode, but to
+    // different payloads depending on which
+    chunks[3][0] ^= 1;
+    let digest = das::batch_digest(&MerkleTree::new(&chunks).root(), &das_root(&payload));
+    assert_eq!(decode(keep(&chunks, &[0, 1])), Some(payload));
+    assert_eq!(decode_checked(keep(&chunks, &[0, 1]), &digest), None);
+    assert_eq!(decode_checked(keep(&chunks, &[1, 3]), &digest), None);
+}
+
+#[test]
+fn test_chunks_decode_once_per_root() {
+    let mut chunks = Chunks::default();
+    assert!(chunks.add(b"root", 4, 1, vec![1]).is_none());
+    assert!(chunks.add(b"other", 4, 2, vec![2]).is_none());
+    assert_eq!(chunks.add(b"root", 4, 3, vec![3]), Some(vec![None, Some(vec![1]), None, Some(vec![3])]));
+    assert!(chunks.add(b"root", 4, 2, vec![2]).is_none());
+    assert!(chunks.add(b"other", 4, 9, vec![9]).is_none());
+}
+
+#[tokio::test]
+async fn test_chunks_are_echoed_requested_and_decoded() {
+    let committee = Arc::new(committee(&[1; NUM_NODES]));
+    let keypair = Arc::new(KeyPair::new());
+    let crypto = Arc::new(CryptoPool::new(1, 8));
+    let sent_echo = lists();
+    let chunk_list = lists();
+    let tx_list = lists();
+    let hash_list = lists();
+    let cert_list = lists();
+    let delivered: Arc<Vec<tk_rwlock<Vec<bool>>>> = lists();
+    let store = Arc::new(MemStore::new());
+    let (tx_send, mut rx_send) = tokio_mpsc::channel(16);
+
+    let payload = vec![3; 10_000];
+    let (root, chunks) = disperse(&payload, NUM_NODES);
+    let das_root = das_root(&payload);
+    let digest = batch_digest(Dispersal::Avid, NUM_NODES, &payload);
+    // delivered by now, so stored once decoded
+    set_round(&delivered[0], 0, true).await;
+
+    let chunk = |sender: u32, index: usize| handle_chunk_msg(
+        1,
+        sender,
+        0,
+        0,
+        0,
+        index,
+        root.clone(),
+        das_root.clone(),
+        chunks[index].1.clone(),
+        chunks[index].0.clone(),
+        committee.clone(),
+        keypair.clone(),
+        None,
+        crypto.clone(),
+        sent_echo.clone(),
+        chunk_list.clone(),
+        tx_list.clone(),
+        hash_list.clone(),
+        cert_list.clone(),
+        delivered.clone(),
+        store.clone(),
+        &tx_send,
+    );
+
+    // member 3 asks for our chunk before we have it
+    handle_chunk_req(1, 3, 0, 0, 0, chunk_list.clone(), &tx_send).await;
+    assert!(rx_send.try_recv().is_err());
+
+    // our chunk from the originator is echoed on the root and the sampling root
+    chunk(0, 1).await;
+    match rx_send.try_recv() {
+        Ok(CastType::Unicast{ dest: 0, round: Some(0), priority: Priority::Urgent, bytes }) => match Message::from_bytes(bytes) {
+            Ok(Message::Echo{ sign, .. }) => assert!(KeyPair::verify_signature(&keypair.pub_key, &digest, &sign)),
+            _ => panic!("not an echo"),
+        },
+        _ => panic!("no echo"),
+    }
+    assert_eq!(hash_list[0].read().await[0], digest);
+    // then sent to member 3, which asked for it
+    match rx_send.try_recv() {
+        Ok(CastType::Unicast{ dest: 3, bytes, .. }) => {
+            assert!(bytes.len() < payload.len() / 2 + 200);
+            assert!(matches!(Message::from_bytes(bytes), Ok(Message::Chunk{ sender: 1, index: 1, .. })));
+        }
+        _ => panic!("chunk not sent on request"),
+    }
+    // and we ask member 2, the next one, for its chunk: with ours, the f+1 we need
+    match rx_send.try_recv() {
+        Ok(CastType::Unicast{ dest: 2, bytes, .. }) => {
+            assert_eq!(Message::from_bytes(bytes), Ok(Message::ChunkReq{ sender: 1, epoch: 0, rn: 0, originator: 0 }));
+        }
+        _ => panic!("no chunk request"),
+    }
+    assert!(rx_send.try_recv().is_err());
+    // a later request is answered at once
+    handle_chunk_req(1, 2, 0, 0, 0, chunk_list.clone(), &tx_send).await;
+    assert!(matches!(rx_send.try_recv(), Ok(CastType::Unicast{ dest: 2, .. })));
+
+    // a member may only forward its own chunk
+    chunk(3, 2).await;
+    assert!(tx_list[0].read().await.is_empty());
+
+    chunk(2, 2).await;
+    assert!(rx_send.try_recv().is_err());
+    assert_eq!(tx_list[0].read().await[0], payload);
+    let record = store.get(0, 0).unwrap().unwrap();
+    assert_eq!((record.digest, record.payload), (digest, payload));
+}
+
+#[test]
+fn test_chunk_sources() {
+    let committee = committee(&[1; 7]);
+    // f+1 is 3: ours and those of the two after us, past the originator
+    assert_eq!(chunk_sources(&committee, 1, 0), vec![2, 3]);
+    assert_eq!(chunk_sources(&committee, 5, 6), vec![0, 1]);
+    assert_eq!(chunk_sources(&committee, 6, 0), vec![1, 2]);
+}
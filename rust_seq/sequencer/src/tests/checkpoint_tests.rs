@@ -0,0 +1,259 @@
+// checkpoint_tests.rs
+use super::*;
+use crate::store::MemStore;
+use crate::fixtures::{committee, peer_pkeys_of};
+
+#[test]
+fn test_extend_digest() {
+    let zero = vec![0; 32];
+    let a = extend_digest(&zero, 0, 0, &[1; 32]);
+    let b = extend_digest(&a, 0, 1, &[2; 32]);
+    assert_eq!(b, extend_digest(&extend_digest(&zero, 0, 0, &[1; 32]), 0, 1, &[2; 32]));
+    // the order of the log matters
+    assert_ne!(b, extend_digest(&extend_digest(&zero, 0, 1, &[2; 32]), 0, 0, &[1; 32]));
+}
+
+#[test]
+fn test_fold_timer() {
+    let committee = committee(&[1, 1, 1, 1]);
+    let mut timer = FoldTimer::default();
+    let start = Instant::now();
+    let timeout = Duration::from_millis(FOLD_TIMEOUT);
+
+    assert!(timer.ready(0, &committee, &[0, 1, 2, 3], start));
+    timer.folded(0);
+    // without a quorum, a round waits however long it takes
+    assert!(!timer.ready(1, &committee, &[0, 1], start));
+    assert!(!timer.ready(1, &committee, &[0, 1], start + 2 * timeout));
+    // with one, the missing batch is waited for FOLD_TIMEOUT, counted from the quorum
+    let quorum_at = start + 3 * timeout;
+    assert!(!timer.ready(1, &committee, &[0, 1, 3], quorum_at));
+    assert!(!timer.ready(1, &committee, &[0, 1, 3], quorum_at + timeout / 2));
+    assert!(timer.ready(1, &committee, &[0, 1, 3], quorum_at + timeout));
+    // until it is folded, e.g. while the Close votes come in, it stays ready
+    assert!(timer.ready(1, &committee, &[0, 1, 3], quorum_at + 2 * timeout));
+    timer.folded(1);
+    // and the next round waits again
+    assert!(!timer.ready(2, &committee, &[0, 1, 3], quorum_at + timeout));
+
+    // rounds observed meanwhile have waited already
+    assert!(timer.observe(4, &committee, &[0, 1, 3], quorum_at));
+    assert!(!timer.observe(5, &committee, &[0], quorum_at));
+    assert!(timer.ready(2, &committee, &[0, 1, 3], quorum_at + 2 * timeout));
+    assert!(timer.ready(3, &committee, &[0, 1, 2, 3], quorum_at + 2 * timeout));
+    assert!(timer.ready(4, &committee, &[0, 1, 3], quorum_at + 2 * timeout));
+    timer.folded(4);
+    assert!(!timer.ready(5, &committee, &[0, 1, 3], quorum_at + 2 * timeout));
+}
+
+#[test]
+fn test_closes() {
+    // total stake 7: a quorum is 5
+    let committee = committee(&[1, 1, 1, 4]);
+    let mut closes = Closes::default();
+    let absent = |ids: &[u32]| Signers::from_ids(ids);
+
+    assert!(closes.add_vote(3, 0, absent(&[1, 2]), vec![0]));
+    assert!(closes.add_vote(3, 1, absent(&[2]), vec![1]));
+    // only the first vote of a node counts
+    assert!(!closes.add_vote(3, 0, absent(&[]), vec![0]));
+    assert!(closes.voted(3, 0) && !closes.voted(3, 3));
+    assert_eq!(closes.excluded(3, &committee), Vec::<u32>::new());
+    assert!(!closed(&committee, &[0, 3], &closes.excluded(3, &committee)));
+
+    // the votes are weighted by stake
+    assert!(closes.add_vote(3, 3, absent(&[2]), vec![3]));
+    assert_eq!(closes.excluded(3, &committee), vec![2]);
+    assert!(closed(&committee, &[0, 1, 3], &closes.excluded(3, &committee)));
+    assert!(!closed(&committee, &[0, 3], &closes.excluded(3, &committee)));
+    assert_eq!(closes.votes(3).len(), 3);
+
+    closes.prune(4);
+    assert!(closes.votes(3).is_empty());
+}
+
+#[tokio::test]
+async fn test_handle_close_msg() {
+    let keypairs: Vec<KeyPair> = (0..4).map(|_| KeyPair::new()).collect();
+    let peer_pkeys = peer_pkeys_of(&keypairs, 0);
+    let closes = Arc::new(tk_mutex::new(Closes::default()));
+    let checkpoints = Arc::new(tk_mutex::new(Checkpoints::default()));
+    checkpoints.lock().await.set_folded(5);
+    let (tx_send, mut rx_send) = tokio_mpsc::channel(10);
+    let vote = |voter: usize, rn: u32| {
+        let absent = Signers::from_ids(&[3]);
+        let sign = keypairs[voter].sign(&close_msg(rn, &absent));
+        (absent, sign)
+    };
+    let close = |sender: u32, voter: u32, rn: u32, (absent, sign): (Signers, U8Arr)| {
+        handle_close_msg(0, sender, voter, rn, absent, sign, peer_pkeys.clone(), closes.clone(), checkpoints.clone(), &tx_send)
+    };
+
+    // a vote with a wrong signature, or too far ahead, is dropped
+    close(1, 1, 7, (Signers::from_ids(&[3]), vec![9; 64])).await;
+    close(1, 1, 5 + CLOSE_WINDOW, vote(1, 5 + CLOSE_WINDOW)).await;
+    assert!(!closes.lock().await.voted(7, 1) && !closes.lock().await.voted(5 + CLOSE_WINDOW, 1));
+    // one relayed for a round we have not folded is kept
+    close(2, 1, 7, vote(1, 7)).await;
+    assert!(closes.lock().await.voted(7, 1));
+    assert!(rx_send.try_recv().is_err());
+
+    // the voter of a round we folded is behind: it gets the votes we hold
+    close(3, 2, 4, vote(2, 4)).await;
+    close(1, 1, 4, vote(1, 4)).await;
+    let mut voters = Vec::new();
+    while let Ok(CastType::Unicast{ dest, bytes, .. }) = rx_send.try_recv() {
+        assert_eq!(dest, 1);
+        match Message::from_bytes(bytes).unwrap() {
+            Message::Close{ sender: 0, voter, rn: 4, .. } => voters.push(voter),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+    voters.sort_unstable();
+    assert_eq!(voters, vec![1, 2]);
+}
+
+#[test]
+fn test_add_vote() {
+    let mut checkpoints = Checkpoints::default();
+    let committee = committee(&[1, 1, 1, 1]);
+    let good = vec![1; 32];
+    let bad = vec![2; 32];
+
+    assert!(checkpoints.add_vote(10, 0, good.clone(), vec![5; 32], vec![0], &committee).is_none());
+    assert!(checkpoints.add_vote(10, 1, bad.clone(), vec![5; 32], vec![1], &committee).is_none());
+    // a second vote from the same node does not count
+    assert!(checkpoints.add_vote(10, 0, good.clone(), vec![5; 32], vec![0], &committee).is_none());
+    assert!(checkpoints.add_vote(10, 2, good.clone(), vec![5; 32], vec![2], &committee).is_none());
+    assert!(checkpoints.add_vote(20, 0, good.clone(), vec![5; 32], vec![0], &committee).is_none());
+
+    let cert = checkpoints.add_vote(10, 3, good.clone(), vec![5; 32], vec![3], &committee).unwrap();
+    assert_eq!(cert.rn, 10);
+    assert_eq!(cert.digest, good);
+    assert_eq!(cert.data_root, vec![5; 32]);
+    assert_eq!(cert.signs, vec![(0, vec![0]), (2, vec![2]), (3, vec![3])]);
+    assert_eq!(checkpoints.stable(), 10);
+
+    // late votes for a stable checkpoint are ignored, newer rounds keep their votes
+    assert!(checkpoints.add_vote(10, 1, good.clone(), vec![5; 32], vec![1], &committee).is_none());
+    assert!(checkpoints.add_vote(20, 1, good.clone(), vec![5; 32], vec![1], &committee).is_none());
+    // the same digest with another data root is another checkpoint
+    assert!(checkpoints.add_vote(20, 2, good.clone(), vec![6; 32], vec![2], &committee).is_none());
+    assert!(checkpoints.add_vote(20, 3, good.clone(), vec![5; 32], vec![3], &committee).is_some());
+}
+
+#[test]
+fn test_add_vote_weighted() {
+    let mut checkpoints = Checkpoints::default();
+    // total stake 7: a quorum is 5
+    let committee = committee(&[1, 1, 1, 4]);
+    let digest = vec![1; 32];
+
+    for signer in 0..3 {
+        assert!(checkpoints.add_vote(10, signer, digest.clone(), vec![5; 32], vec![signer as u8], &committee).is_none());
+    }
+    let cert = checkpoints.add_vote(10, 3, digest.clone(), vec![5; 32], vec![3], &committee).unwrap();
+    assert_eq!(cert.signs.len(), 4);
+
+    assert!(checkpoints.add_vote(20, 3, digest.clone(), vec![5; 32], vec![3], &committee).is_none());
+    assert!(checkpoints.add_vote(20, 0, digest.clone(), vec![5; 32], vec![0], &committee).is_some());
+}
+
+#[tokio::test]
+async fn test_handle_checkpoint_msg() {
+    let keypairs: Vec<KeyPair> = (0..4).map(|_| KeyPair::new()).collect();
+    let self_node_ind = 0;
+    let peer_pkeys = peer_pkeys_of(&keypairs, self_node_ind);
+    let checkpoints = Arc::new(tk_mutex::new(Checkpoints::default()));
+    checkpoints.lock().await.set_folded(8);
+    let store = Arc::new(MemStore::new());
+    let digest = vec![7; 32];
+
+    for signer in [1, 3, 2] {
+        // a vote with a wrong signature is dropped
+        checkpoint_vote(
+            &keypairs,
+            self_node_ind,
+            signer,
+            vec![9; 64],
+            &digest,
+            &peer_pkeys,
+            &checkpoints,
+            &store
+        ).await;
+        assert_eq!(store.latest_checkpoint().unwrap(), None);
+
+        let sign = keypairs[signer].sign(&checkpoint_msg(10, &digest, &[5; 32]));
+        checkpoint_vote(
+            &keypairs,
+            self_node_ind,
+            signer,
+            sign,
+            &digest,
+            &peer_pkeys,
+            &checkpoints,
+            &store
+        ).await;
+    }
+
+    let cert = store.latest_checkpoint().unwrap().unwrap();
+    assert_eq!(cert.rn, 10);
+    assert_eq!(cert.signs.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2, 3]);
+    // nothing is pruned until every member folded the rounds, ourselves included
+    assert_eq!(checkpoints.lock().await.start_pruning(&committee(&[1, 1, 1, 1])), None);
+}
+
+#[test]
+fn test_start_pruning() {
+    let committee = committee(&[1, 1, 1, 1]);
+    let mut checkpoints = Checkpoints::default();
+    checkpoints.set_folded(25);
+    assert_eq!(checkpoints.start_pruning(&committee), None);
+    for signer in 0..3 {
+        checkpoints.add_vote(10, signer, vec![1], vec![5; 32], vec![], &committee);
+    }
+    // stable, but node 3 has not folded the rounds yet
+    assert_eq!(checkpoints.stable(), 10);
+    assert_eq!(checkpoints.start_pruning(&committee), None);
+    checkpoints.add_vote(10, 3, vec![1], vec![5; 32], vec![], &committee);
+    assert_eq!(checkpoints.start_pruning(&committee), Some((0, 10)));
+    assert_eq!(checkpoints.pruned(), 10);
+    assert_eq!(checkpoints.start_pruning(&committee), None);
+
+    // votes of the rounds a stable checkpoint covers are dropped
+    checkpoints.add_vote(30, 0, vec![1], vec![5; 32], vec![], &committee);
+    for signer in 0..3 {
+        checkpoints.add_vote(20, signer, vec![1], vec![5; 32], vec![], &committee);
+    }
+    assert_eq!(checkpoints.votes.keys().collect::<Vec<_>>(), vec![&30]);
+    assert_eq!(checkpoints.start_pruning(&committee), None);
+    // nor do we prune what we have not folded ourselves
+    checkpoints.set_folded(15);
+    checkpoints.add_vote(30, 3, vec![1], vec![5; 32], vec![], &committee);
+    assert_eq!(checkpoints.start_pruning(&committee), Some((10, 15)));
+}
+
+async fn checkpoint_vote(
+    keypairs: &[KeyPair],
+    self_node_ind: usize,
+    signer: usize,
+    sign: U8Arr,
+    digest: &U8Arr,
+    peer_pkeys: &Arc<tk_rwlock<Vec<Option<U8Arr>>>>,
+    checkpoints: &Arc<tk_mutex<Checkpoints>>,
+    store: &Arc<MemStore>,
+) {
+    handle_checkpoint_msg(
+        self_node_ind as u32,
+        &keypairs[self_node_ind].pub_key,
+        signer as u32,
+        10,
+        digest.clone(),
+        vec![5; 32],
+        sign,
+        Arc::new(committee(&[1, 1, 1, 1])),
+        peer_pkeys.clone(),
+        checkpoints.clone(),
+        store.clone(),
+    ).await;
+}
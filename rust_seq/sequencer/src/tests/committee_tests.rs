@@ -0,0 +1,188 @@
+// committee_tests.rs
+use super::*;
+use crate::fixtures::address;
+
+fn genesis(num_nodes: u32) -> Committee {
+    let address_book: Vec<SocketAddr> = (0..num_nodes).map(address).collect();
+    Committee::genesis(&address_book, num_nodes)
+}
+
+fn reconfig(epoch: u32, ids: &[u32]) -> Reconfig {
+    Reconfig {
+        epoch,
+        members: ids.iter()
+            .map(|id| Member { id: *id, address: address(*id), pub_key: Some(vec![*id as u8; 32]), stake: 1 })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_equal_stake_quorums() {
+    for (num_nodes, quorum, validity) in [(1, 1, 1), (4, 3, 2), (5, 4, 2), (6, 5, 3), (7, 5, 3), (10, 7, 4)] {
+        let committee = genesis(num_nodes);
+        assert_eq!(committee.total_stake(), num_nodes as u64);
+        assert_eq!(committee.quorum(), quorum);
+        assert_eq!(committee.validity(), validity);
+    }
+}
+
+#[test]
+fn test_uneven_stake_quorums() {
+    // total stake 10: f is 3, a quorum is 7 and validity 4
+    let committee = genesis(4).with_stakes(&[1, 1, 1, 7]);
+    assert_eq!(committee.quorum(), 7);
+    assert_eq!(committee.validity(), 4);
+    assert_eq!(committee.stake([0, 1, 2]), 3);
+    assert!(committee.stake([3]) >= committee.quorum());
+    // duplicates and non-members add nothing
+    assert_eq!(committee.stake([0, 0, 0, 9]), 1);
+
+    // one node holding most of the stake cannot be outvoted by the rest
+    let committee = genesis(5).with_stakes(&[10, 10, 10, 10, 60]);
+    assert_eq!(committee.quorum(), 67);
+    assert!(committee.stake([0, 1, 2, 3]) < committee.quorum());
+    assert!(committee.stake([0, 4]) >= committee.quorum());
+
+    // total stake 11: a quorum is 8, more than 2/3 although 2f+1 would be 7
+    let committee = genesis(4).with_stakes(&[2, 2, 2, 5]);
+    assert_eq!(committee.quorum(), 8);
+    assert_eq!(committee.validity(), 4);
+    assert!(committee.stake([0, 1, 2]) < committee.quorum());
+    assert!(committee.stake([0, 3, 1]) >= committee.quorum());
+}
+
+#[test]
+fn test_quorums_intersect() {
+    for total in 1..50u64 {
+        let committee = genesis(2).with_stakes(&[1, total - 1]);
+        // two quorums share more than the faulty stake, and a quorum meets any validity set
+        assert!(2 * committee.quorum() > total + committee.f_stake());
+        assert!(committee.quorum() + committee.validity() > total);
+        assert!(3 * committee.validity() > total);
+    }
+}
+
+#[test]
+fn test_epochs_switch_at_round_boundary() {
+    let mut epochs = Epochs::new(genesis(4));
+    assert_eq!(epochs.latest().quorum(), 3);
+
+    // node 3 leaves, nodes 4 to 6 join
+    let committee = epochs.apply(5, reconfig(1, &[6, 0, 1, 2, 5, 4])).unwrap();
+    assert_eq!(committee.start_round, 5 + RECONFIG_DELAY);
+    assert_eq!(committee.ids(), vec![0, 1, 2, 4, 5, 6]);
+    assert_eq!(committee.quorum(), 5);
+
+    let last_old = 4 + RECONFIG_DELAY;
+    assert_eq!(epochs.for_round(last_old).epoch, 0);
+    assert_eq!(epochs.for_round(last_old + 1).epoch, 1);
+    assert!(epochs.check(0, last_old, 3));
+    // stale epoch, and a node that left
+    assert!(!epochs.check(0, last_old + 1, 0));
+    assert!(!epochs.check(1, last_old + 1, 3));
+    // the new committee cannot speak for rounds before it starts
+    assert!(!epochs.check(1, last_old, 4));
+    assert!(epochs.check(1, last_old + 1, 4));
+
+    assert_eq!(epochs.address(5), Some(address(5)));
+    assert_eq!(epochs.address(7), None);
+    assert_eq!(epochs.num_ids(), 7);
+}
+
+#[test]
+fn test_apply_rejects_bad_reconfig() {
+    let mut epochs = Epochs::new(genesis(4));
+    assert!(epochs.apply(5, reconfig(2, &[0, 1, 2])).is_none()); // skips an epoch
+    assert!(epochs.apply(5, reconfig(1, &[])).is_none());
+    let mut no_stake = reconfig(1, &[0, 1]);
+    no_stake.members.iter_mut().for_each(|m| m.stake = 0);
+    assert!(epochs.apply(5, no_stake).is_none());
+    assert!(epochs.apply(5, reconfig(1, &[0, 1, 1])).is_none());
+    assert!(epochs.apply(5, reconfig(1, &[0, 1, MAX_NODES as u32])).is_none());
+    assert_eq!(epochs.latest().epoch, 0);
+
+    assert!(epochs.apply(5, reconfig(1, &[0, 1, 2])).is_some());
+    // a second change delivered in the same round would start at the same round
+    assert!(epochs.apply(5, reconfig(2, &[0, 1])).is_none());
+    assert!(epochs.apply(6, reconfig(2, &[0, 1])).is_some());
+    assert!(epochs.apply(u32::MAX, reconfig(3, &[0])).is_none());
+}
+
+#[test]
+fn test_propose_needs_a_quorum() {
+    let mut epochs = Epochs::new(genesis(4).with_stakes(&[1, 1, 1, 3]));
+    // node 3 alone holds half the stake, not a quorum; repeating itself does not help
+    assert_eq!(epochs.propose(5, 3, reconfig(1, &[0, 1, 2])), None);
+    assert_eq!(epochs.propose(6, 3, reconfig(1, &[0, 1, 2])), None);
+    // non-members, other epochs and other committees do not count
+    assert_eq!(epochs.propose(6, 7, reconfig(1, &[0, 1, 2])), None);
+    assert_eq!(epochs.propose(6, 0, reconfig(2, &[0, 1, 2])), None);
+    assert_eq!(epochs.propose(6, 0, reconfig(1, &[0, 1])), None);
+    assert_eq!(epochs.propose(6, 1, reconfig(1, &[2, 1, 0])), None);
+    assert_eq!(epochs.propose(6, 2, reconfig(1, &[0, 1, 2])), Some(reconfig(1, &[0, 1, 2])));
+
+    // once the epoch is applied, the other proposals for it are dropped
+    epochs.apply(6, reconfig(1, &[0, 1, 2])).unwrap();
+    assert_eq!(epochs.propose(7, 1, reconfig(1, &[0, 1])), None);
+    assert!(epochs.proposals.is_empty());
+}
+
+#[test]
+fn test_reconfig_payload() {
+    let reconfig = reconfig(1, &[0, 1, 4]);
+    assert_eq!(Reconfig::from_payload(&reconfig.to_payload()), Some(reconfig));
+    assert_eq!(Reconfig::from_payload(&[1; 100]), None);
+    assert_eq!(Reconfig::from_payload(b"reconfig garbage"), None);
+}
+
+#[test]
+fn test_reconfig_from_config() {
+    let key = vec![0xab; 32];
+    let config = format!("3\n0 127.0.0.1:8080 -\n\n4 127.0.0.1:8084 {} 5\n", to_hex(&key));
+    assert_eq!(Reconfig::from_config(&config), Some(Reconfig {
+        epoch: 3,
+        members: vec![
+            Member { id: 0, address: address(0), pub_key: None, stake: 1 },
+            Member { id: 4, address: address(4), pub_key: Some(key), stake: 5 },
+        ],
+    }));
+    assert_eq!(Reconfig::from_config("3\n0 127.0.0.1:8080 - heavy\n"), None);
+    assert_eq!(Reconfig::from_config("3\n0 127.0.0.1:8080\n"), None);
+    assert_eq!(Reconfig::from_config("3\n0 127.0.0.1:8080 abc\n"), None);
+    assert_eq!(from_hex("00ff10"), Some(vec![0, 255, 16]));
+}
+
+#[tokio::test]
+async fn test_handle_reconfig() {
+    let epochs = Arc::new(tk_rwlock::new(Epochs::new(genesis(4))));
+    let peer_pkeys = Arc::new(tk_rwlock::new(vec![None; MAX_NODES]));
+    let (tx_send, mut rx_send) = tokio_mpsc::channel(10);
+    let syn = Bytes::from_static(b"syn");
+
+    for proposer in [0, 1] {
+        handle_reconfig(0, 5, proposer, reconfig(1, &[0, 1, 2, 4]), syn.clone(), epochs.clone(), peer_pkeys.clone(), &tx_send).await;
+    }
+    assert_eq!(epochs.read().await.latest().epoch, 0);
+    assert!(rx_send.try_recv().is_err());
+    handle_reconfig(0, 5, 2, reconfig(1, &[4, 2, 1, 0]), syn.clone(), epochs.clone(), peer_pkeys.clone(), &tx_send).await;
+    assert_eq!(epochs.read().await.latest().epoch, 1);
+    {
+        let peer_pkeys = peer_pkeys.read().await;
+        assert_eq!(peer_pkeys[0], None); // our own key is not a peer key
+        assert_eq!(peer_pkeys[4], Some(vec![4; 32]));
+    }
+    match rx_send.recv().await {
+        Some(CastType::Multicast{ epoch, round: None, priority: Priority::Urgent, bytes }) => {
+            assert_eq!(epoch, 1);
+            assert_eq!(bytes, syn);
+        }
+        _ => panic!("expected a greeting of the new committee"),
+    }
+
+    // replayed reconfiguration is ignored
+    for proposer in [0, 1, 2] {
+        handle_reconfig(0, 6, proposer, reconfig(1, &[0, 1]), syn.clone(), epochs.clone(), peer_pkeys.clone(), &tx_send).await;
+    }
+    assert_eq!(epochs.read().await.latest().size(), 4);
+    assert!(rx_send.try_recv().is_err());
+}
@@ -0,0 +1,56 @@
+// crypto_pool_tests.rs
+use std::sync::Arc;
+use super::*;
+
+fn thread_name() -> String {
+    std::thread::current().name().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn test_run_on_pool() {
+    let pool = CryptoPool::new(2, 8);
+    assert_eq!(pool.threads(), 2);
+    let name = pool.run(thread_name).await;
+    assert!(name.starts_with("crypto-"));
+    assert_eq!(pool.run(|| 1 + 1).await, 2);
+
+    let metrics = pool.metrics();
+    assert_eq!(metrics.submitted, 2);
+    assert_eq!(metrics.completed, 2);
+    assert_eq!(metrics.queued, 0);
+    assert!(metrics.max_queued >= 1);
+}
+
+#[tokio::test]
+async fn test_run_inline() {
+    let pool = CryptoPool::new(0, 8);
+    assert_eq!(pool.threads(), 0);
+    assert_eq!(pool.run(thread_name).await, thread_name());
+    let metrics = pool.metrics();
+    assert_eq!(metrics.completed, 1);
+    assert_eq!(metrics.max_queued, 0);
+}
+
+#[tokio::test]
+async fn test_queue_is_bounded() {
+    let pool = Arc::new(CryptoPool::new(1, 2));
+    let jobs: Vec<_> = (0..16)
+        .map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.run(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(2));
+                    i
+                }).await
+            })
+        })
+        .collect();
+    for (i, job) in jobs.into_iter().enumerate() {
+        assert_eq!(job.await.unwrap(), i);
+    }
+
+    let metrics = pool.metrics();
+    assert_eq!(metrics.completed, 16);
+    assert!(metrics.max_queued <= 2);
+    assert!(metrics.busy_us >= 16 * 2_000);
+}
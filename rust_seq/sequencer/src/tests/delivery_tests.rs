@@ -0,0 +1,41 @@
+// delivery_tests.rs
+use super::*;
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+#[test]
+fn test_delivery_from_str() {
+    assert_eq!("simple".parse::<Delivery>(), Ok(Delivery::Simple));
+    assert_eq!("reliable".parse::<Delivery>(), Ok(Delivery::Reliable));
+    assert!("acked".parse::<Delivery>().is_err());
+    assert_eq!("secure".parse::<LinkSecurity>(), Ok(LinkSecurity::Secure));
+    assert!("tls".parse::<LinkSecurity>().is_err());
+}
+
+#[test]
+fn test_cancel_below_folded() {
+    let mut cancels = RoundCancels::default();
+    let mut senders = Vec::new();
+    for round in [1, 2, 2, 3] {
+        let (sender, handler) = oneshot::channel::<Bytes>();
+        cancels.add(round, vec![handler]);
+        senders.push(sender);
+    }
+    cancels.cancel_below(3);
+    let cancelled: Vec<bool> = senders.iter().map(|sender| sender.is_closed()).collect();
+    assert_eq!(cancelled, vec![true, true, true, false]);
+    assert_eq!(cancels.handlers.keys().collect::<Vec<_>>(), vec![&3]);
+}
+
+#[test]
+fn test_congested_queues() {
+    let (a, b) = ("127.0.0.1:8080".parse().unwrap(), "127.0.0.1:8081".parse().unwrap());
+    let stats = |a_stats, b_stats| HashMap::from([(a, a_stats), (b, b_stats)]);
+    let mut monitor = QueueMonitor::new(10);
+    let backlog = QueueStats{ depth: 5, dropped: 0 };
+    let dropping = QueueStats{ depth: 0, dropped: 3 };
+    assert_eq!(monitor.congested(stats(backlog, dropping)), vec![(a, backlog), (b, dropping)]);
+    // drops are reported once, a backlog as long as it lasts
+    assert_eq!(monitor.congested(stats(backlog, dropping)), vec![(a, backlog)]);
+    assert_eq!(monitor.congested(stats(QueueStats::default(), dropping)), vec![]);
+}
@@ -0,0 +1,135 @@
+// fair_tests.rs
+use super::*;
+use crate::fixtures::committee;
+
+fn tx(name:&str) -> U8Arr {
+    name.as_bytes().to_vec()
+}
+
+fn report(txs:&[(&str, u64)]) -> Report {
+    Report { txs: txs.iter().map(|(name, time)| (tx(name), *time)).collect() }
+}
+
+/* correct members 0..3 all receive a at 10-12 and b at 20-22 */
+fn correct_reports() -> Vec<(u32, Report)> {
+    (0..3).map(|i| (i, report(&[("a", 10 + i as u64), ("b", 20 + i as u64)]))).collect()
+}
+
+#[test]
+fn test_single_member_cannot_reorder() {
+    let committee = committee(&[1; 4]);
+    let mut reports = correct_reports();
+    // member 3 claims it received b long before a
+    reports.push((3, report(&[("b", 0), ("a", 100)])));
+    let order = FairOrderer::default().add_round(0, &committee, reports);
+    assert_eq!(order, vec![tx("a"), tx("b")]);
+
+    // nor by leaving a out of its report
+    let mut reports = correct_reports();
+    reports.push((3, report(&[("b", 0)])));
+    let order = FairOrderer::default().add_round(0, &committee, reports);
+    assert_eq!(order, vec![tx("a"), tx("b")]);
+}
+
+#[test]
+fn test_single_member_cannot_order_alone() {
+    let committee = committee(&[1; 4]);
+    let mut orderer = FairOrderer::default();
+    // a tx only member 3 reports, and it claims it came first
+    let mut reports = correct_reports();
+    reports.push((3, report(&[("front", 0), ("a", 11), ("b", 21)])));
+    assert_eq!(orderer.add_round(0, &committee, reports), vec![tx("a"), tx("b")]);
+    // nor do reports of non-members count
+    assert!(orderer.add_round(1, &committee, vec![(2, report(&[("front", 0)])), (5, report(&[("front", 0)]))]).is_empty());
+}
+
+#[test]
+fn test_order_does_not_depend_on_report_order() {
+    let committee = committee(&[1; 4]);
+    let mut reports = correct_reports();
+    reports.push((3, report(&[("c", 15), ("b", 16), ("a", 17)])));
+    let order = FairOrderer::default().add_round(0, &committee, reports.clone());
+    reports.reverse();
+    assert_eq!(FairOrderer::default().add_round(0, &committee, reports), order);
+    assert_eq!(order, vec![tx("a"), tx("b")]);
+}
+
+#[test]
+fn test_pending_tx_waits_for_a_quorum() {
+    let committee = committee(&[1; 4]);
+    let mut orderer = FairOrderer::default();
+    let early: Vec<(u32, Report)> = (0..2).map(|i| (i, report(&[("a", 10)]))).collect();
+    assert!(orderer.add_round(0, &committee, early).is_empty());
+    assert_eq!(orderer.add_round(1, &committee, vec![(2, report(&[("a", 12)]))]), vec![tx("a")]);
+    // reports coming after it is ordered are ignored
+    assert!(orderer.add_round(2, &committee, vec![(3, report(&[("a", 13)]))]).is_empty());
+
+    // one that never gets there is dropped after FAIR_WINDOW rounds
+    assert!(orderer.add_round(3, &committee, vec![(0, report(&[("b", 30)]))]).is_empty());
+    assert!(orderer.add_round(3 + FAIR_WINDOW, &committee, vec![(1, report(&[("b", 31)]))]).is_empty());
+    assert!(orderer.add_round(4 + FAIR_WINDOW, &committee, vec![(2, report(&[("b", 32)]))]).is_empty());
+}
+
+#[test]
+fn test_mempool_reports_each_tx_once() {
+    let mut mempool = Mempool::default();
+    mempool.add(tx("a")).unwrap();
+    mempool.add(tx("b")).unwrap();
+    mempool.add(tx("a")).unwrap();
+
+    let report = mempool.report();
+    assert_eq!(report.txs.iter().map(|(tx, _)| tx.clone()).collect::<Vec<_>>(), vec![tx("a"), tx("b")]);
+    assert!(report.txs[0].1 <= report.txs[1].1);
+    assert_eq!(Report::from_payload(&report.to_payload()), Some(report));
+    assert!(mempool.report().txs.is_empty());
+    assert_eq!(Report::from_payload(b"not a report"), None);
+}
+
+#[test]
+fn test_mempool_bounds() {
+    let mut mempool = Mempool::default();
+    assert_eq!(mempool.add(vec![0; MAX_TX_SIZE + 1]), Err(MempoolError::TooLarge(MAX_TX_SIZE + 1)));
+
+    // full in bytes
+    let big = |i:usize| { let mut tx = vec![0; MAX_TX_SIZE]; tx[..8].copy_from_slice(&i.to_be_bytes()); tx };
+    let count = MEMPOOL_BYTES / MAX_TX_SIZE;
+    for i in 0..count {
+        mempool.add(big(i)).unwrap();
+    }
+    assert_eq!(mempool.add(big(count)), Err(MempoolError::Full));
+
+    // a report takes no more than MAX_REPORTED_BYTES, and frees them
+    let report = mempool.report();
+    assert_eq!(report.txs.len(), MAX_REPORTED_BYTES / MAX_TX_SIZE);
+    assert_eq!(report.txs[0].0, big(0));
+    mempool.add(big(count)).unwrap();
+
+    // full in count
+    let mut mempool = Mempool::default();
+    for i in 0..MEMPOOL_TXS {
+        mempool.add(i.to_be_bytes().to_vec()).unwrap();
+    }
+    assert_eq!(mempool.add(tx("a")), Err(MempoolError::Full));
+    assert_eq!(mempool.report().txs.len(), MAX_REPORTED_TXS);
+    mempool.add(tx("a")).unwrap();
+}
+
+#[test]
+fn test_ordered_tx_kept_until_pruned() {
+    let committee = committee(&[1; 4]);
+    let mut orderer = FairOrderer::default();
+    assert_eq!(orderer.add_round(0, &committee, correct_reports()), vec![tx("a"), tx("b")]);
+    // reported again long after, it is not ordered again
+    assert!(orderer.add_round(10 * FAIR_WINDOW, &committee, correct_reports()).is_empty());
+    // until its round is pruned
+    orderer.prune(1);
+    assert_eq!(orderer.add_round(10 * FAIR_WINDOW + 1, &committee, correct_reports()), vec![tx("a"), tx("b")]);
+}
+
+#[test]
+fn test_oversized_reported_tx_ignored() {
+    let committee = committee(&[1; 4]);
+    let huge = vec![7; MAX_TX_SIZE + 1];
+    let reports = (0..4).map(|i| (i, Report { txs: vec![(huge.clone(), 10), (tx("a"), 20)] })).collect();
+    assert_eq!(FairOrderer::default().add_round(0, &committee, reports), vec![tx("a")]);
+}
@@ -0,0 +1,44 @@
+// fixtures.rs: helpers shared by the tests of the sequencer modules
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock as tk_rwlock;
+use crate::committee::Committee;
+use crate::signature::{Certificate, KeyPair};
+use crate::store::Record;
+
+type U8Arr = Vec<u8>;
+
+pub fn address(id: u32) -> SocketAddr {
+    format!("127.0.0.1:{}", 8080 + id).parse().unwrap()
+}
+
+/* a genesis committee with one member per stake */
+pub fn committee(stakes: &[u64]) -> Committee {
+    let address_book: Vec<SocketAddr> = (0..stakes.len() as u32).map(address).collect();
+    Committee::genesis(&address_book, stakes.len() as u32).with_stakes(stakes)
+}
+
+/* one default entry per node of a 4-node committee, e.g. the per-originator round lists */
+pub fn lists<T>() -> Arc<Vec<T>> where T: Default {
+    Arc::new((0..4).map(|_| T::default()).collect())
+}
+
+/* the keys of all nodes but ourselves */
+pub fn peer_pkeys_of(keypairs: &[KeyPair], self_node_ind: usize) -> Arc<tk_rwlock<Vec<Option<U8Arr>>>> {
+    Arc::new(tk_rwlock::new(
+        keypairs.iter()
+            .enumerate()
+            .map(|(i, k)| if i == self_node_ind { None } else { Some(k.pub_key.clone()) })
+            .collect()
+    ))
+}
+
+pub fn record(round: u32, originator: u32) -> Record {
+    Record {
+        round,
+        originator,
+        digest: vec![round as u8; 32],
+        payload: vec![originator as u8; 100],
+        certificate: Certificate::Signs([(0, vec![1; 64]), (1, vec![2; 64]), (2, vec![3; 64])].into_iter().collect()),
+    }
+}
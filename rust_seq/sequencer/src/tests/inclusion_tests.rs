@@ -0,0 +1,105 @@
+// inclusion_tests.rs
+use super::*;
+use das::{checkpoint_msg, tx_digest, Member, CHECKPOINT_INTERVAL};
+use crate::signature::KeyPair;
+
+fn batch_digests(round:u32) -> Vec<(u32, U8Arr)> {
+    [3, 0, 5].into_iter().map(|originator| (originator, vec![(round + originator) as u8; 32])).collect()
+}
+
+/* two transactions reported by 3, one by 5 */
+fn tx_digests(round:u32) -> Vec<(u32, U8Arr)> {
+    vec![
+        (3, tx_digest(&round.to_le_bytes())),
+        (3, tx_digest(b"tx")),
+        (5, tx_digest(&(round + 1).to_le_bytes())),
+    ]
+}
+
+fn round_roots(rounds:std::ops::Range<u32>) -> RoundRoots {
+    let mut roots = RoundRoots::default();
+    for round in rounds {
+        roots.push(RoundTree::new(round, &batch_digests(round), &tx_digests(round)));
+    }
+    roots
+}
+
+/* the checkpoint of [rn - CHECKPOINT_INTERVAL, rn), signed by all of `keypairs` */
+fn checkpoint(roots:&RoundRoots, rn:u32, keypairs:&[KeyPair]) -> CheckpointCert {
+    let digest = vec![9; 32];
+    let data_root = roots.data_root(rn - CHECKPOINT_INTERVAL, rn).unwrap();
+    let msg = checkpoint_msg(rn, &digest, &data_root);
+    let signs = keypairs.iter().enumerate().map(|(id, keypair)| (id as u32, keypair.sign(&msg))).collect();
+    CheckpointCert { rn, digest, data_root, signs }
+}
+
+fn members(keypairs:&[KeyPair]) -> Vec<Member> {
+    keypairs.iter()
+        .enumerate()
+        .map(|(id, keypair)| Member { id: id as u32, stake: 1, pub_key: keypair.pub_key.clone(), bls_key: None })
+        .collect()
+}
+
+#[test]
+fn test_round_tree_proves_its_batches_and_txs() {
+    let keypairs: Vec<KeyPair> = (0..4).map(|_| KeyPair::new()).collect();
+    let committee = members(&keypairs);
+    let roots = round_roots(10..20);
+    let cert = checkpoint(&roots, 20, &keypairs);
+    let tree = roots.get(13).unwrap();
+    assert_eq!(tree.num_leaves(), 6);
+
+    for (originator, batch_digest) in batch_digests(13) {
+        let inclusion = roots.inclusion(13, tree.batch_index(originator).unwrap(), cert.clone()).unwrap();
+        assert!(inclusion.verify(&das::batch_leaf(originator, &batch_digest), &committee));
+        assert!(!inclusion.verify(&das::batch_leaf(originator, &batch_digests(14)[0].1), &committee));
+    }
+    assert!(tree.batch_index(4).is_none());
+
+    let index = tree.tx_index(3, &tx_digest(b"tx")).unwrap();
+    assert_eq!(index, 4);
+    let inclusion = roots.inclusion(13, index, cert.clone()).unwrap();
+    assert!(inclusion.verify(&das::tx_leaf(3, &tx_digest(b"tx")), &committee));
+    // reported by another member, it is another leaf
+    assert!(!inclusion.verify(&das::tx_leaf(5, &tx_digest(b"tx")), &committee));
+    assert!(tree.tx_index(5, &tx_digest(b"tx")).is_none());
+
+    // the checkpoint must cover the round
+    assert!(roots.inclusion(9, 0, cert.clone()).is_none());
+    assert!(roots.inclusion(13, 6, cert).is_none());
+}
+
+#[test]
+fn test_round_roots_keep_the_last_rounds() {
+    let mut roots = RoundRoots::default();
+    assert!(roots.get(0).is_none());
+    let last = ROUND_ROOTS_KEPT as u32 + 10;
+    for round in 0..=last {
+        roots.push(RoundTree::new(round, &batch_digests(round), &tx_digests(round)));
+    }
+    assert!(roots.get(10).is_none());
+    assert_eq!(roots.get(11).unwrap().round(), 11);
+    assert_eq!(roots.get(last).unwrap().round(), last);
+    assert!(roots.get(last + 1).is_none());
+
+    // a data root needs every round of its range
+    assert!(roots.data_root(5, 15).is_none());
+    assert!(roots.data_root(last - 9, last + 1).is_some());
+
+    // a transaction is found in the first kept round reporting it, until that round is evicted
+    assert_eq!(roots.tx_round(3, &tx_digest(b"tx")), Some(11));
+    assert_eq!(roots.tx_round(3, &tx_digest(&last.to_le_bytes())), Some(last));
+    assert_eq!(roots.tx_round(3, &tx_digest(&10u32.to_le_bytes())), None);
+}
+
+#[test]
+fn test_data_root_is_the_same_on_every_node() {
+    assert_eq!(round_roots(0..10).data_root(0, 10), round_roots(0..10).data_root(0, 10));
+    assert_ne!(round_roots(0..10).data_root(0, 10), round_roots(0..10).data_root(0, 9));
+    // the reported transactions are in it
+    let mut other = RoundRoots::default();
+    for round in 0..10 {
+        other.push(RoundTree::new(round, &batch_digests(round), &tx_digests(round)[..2]));
+    }
+    assert_ne!(other.data_root(0, 10), round_roots(0..10).data_root(0, 10));
+}
@@ -0,0 +1,181 @@
+// l1_tests.rs
+use super::*;
+use crate::store::MemStore;
+
+fn cert(rn:u32, signers:u32) -> CheckpointCert {
+    CheckpointCert {
+        rn,
+        digest: vec![rn as u8; 32],
+        data_root: vec![rn as u8 + 1; 32],
+        signs: (0..signers).map(|signer| (signer, vec![signer as u8; 64])).collect(),
+    }
+}
+
+/* a store holding the stable checkpoints of `rounds`, signed by `signers` */
+fn store(rounds:&[u32], signers:u32) -> MemStore {
+    let store = MemStore::new();
+    for rn in rounds {
+        store.put_checkpoint(cert(*rn, signers)).unwrap();
+    }
+    store
+}
+
+/* the settler's tx in flight, and its status */
+async fn in_flight(settler:&Settler) -> Option<(Settlement, L1TxStatus)> {
+    let (tx, settlement) = settler.in_flight.clone()?;
+    Some((settlement, settler.poster.status(tx).await.unwrap()))
+}
+
+#[tokio::test]
+async fn test_settles_after_confirmations() {
+    let chain = Arc::new(MockChain::new(3));
+    let poster = Arc::new(chain.poster(0));
+    let store = store(&[], 3);
+    let mut settler = Settler::new(poster.clone());
+
+    settler.step(&store).await.unwrap();
+    assert!(settler.in_flight.is_none());
+    store.put_checkpoint(cert(10, 3)).unwrap();
+    settler.step(&store).await.unwrap();
+    let (settlement, status) = in_flight(&settler).await.unwrap();
+    assert_eq!((settlement.from_round, settlement.to_round), (0, 10));
+    // the data root is the one the certificate signs
+    assert_eq!(settlement.cert, cert(10, 3));
+    assert_eq!(status, L1TxStatus::Pending);
+
+    chain.mine();
+    settler.step(&store).await.unwrap();
+    assert_eq!(in_flight(&settler).await.unwrap().1, L1TxStatus::Included{ confirmations: 1 });
+    assert_eq!(poster.settled_round().await, Ok(10));
+    for _ in 1..L1_CONFIRMATIONS {
+        chain.mine();
+    }
+    settler.step(&store).await.unwrap();
+    assert!(settler.in_flight.is_none());
+
+    // the next range starts where the last one ended, with the next nonce
+    store.put_checkpoint(cert(20, 3)).unwrap();
+    settler.step(&store).await.unwrap();
+    let (settlement, _) = in_flight(&settler).await.unwrap();
+    assert_eq!((settlement.from_round, settlement.to_round), (10, 20));
+    assert_eq!(poster.next_nonce().await, Ok(1));
+}
+
+#[tokio::test]
+async fn test_resubmits_after_reorg() {
+    let chain = Arc::new(MockChain::new(3));
+    let store = store(&[10], 3);
+    let mut settler = Settler::new(Arc::new(chain.poster(0)));
+
+    settler.step(&store).await.unwrap();
+    chain.mine();
+    // reorged out, the tx is pending again and makes it into the new chain
+    chain.reorg(1);
+    assert_eq!(in_flight(&settler).await.unwrap().1, L1TxStatus::Pending);
+    chain.mine();
+    assert_eq!(in_flight(&settler).await.unwrap().1, L1TxStatus::Included{ confirmations: 1 });
+
+    // reorged out and evicted: it is submitted again with the same nonce
+    chain.reorg(1);
+    chain.drop_pending();
+    let (tx, _) = settler.in_flight.clone().unwrap();
+    settler.step(&store).await.unwrap();
+    let (resubmitted, settlement) = settler.in_flight.clone().unwrap();
+    assert_ne!(resubmitted, tx);
+    assert_eq!((settlement.from_round, settlement.to_round), (0, 10));
+    for _ in 0..L1_CONFIRMATIONS {
+        chain.mine();
+    }
+    settler.step(&store).await.unwrap();
+    assert!(settler.in_flight.is_none());
+    assert_eq!(chain.poster(0).next_nonce().await, Ok(1));
+}
+
+#[tokio::test]
+async fn test_first_settlement_wins() {
+    let chain = Arc::new(MockChain::new(3));
+    let mut first = Settler::new(Arc::new(chain.poster(0)));
+    let mut second = Settler::new(Arc::new(chain.poster(1)));
+    let second_store = store(&[10], 4);
+
+    first.step(&store(&[10], 3)).await.unwrap();
+    second.step(&second_store).await.unwrap();
+    chain.mine();
+    assert!(matches!(in_flight(&second).await.unwrap().1, L1TxStatus::Reverted(_)));
+
+    // the range is settled, so the second settler has nothing left to post
+    second.step(&second_store).await.unwrap();
+    assert!(second.in_flight.is_none());
+    // its reverted tx used a nonce
+    second_store.put_checkpoint(cert(20, 4)).unwrap();
+    second.step(&second_store).await.unwrap();
+    assert_eq!(chain.poster(1).next_nonce().await, Ok(1));
+    chain.mine();
+    assert_eq!(chain.poster(1).settled_round().await, Ok(20));
+}
+
+#[tokio::test]
+async fn test_contract_checks_settlements() {
+    let chain = Arc::new(MockChain::new(3));
+    let poster = chain.poster(0);
+    let settlement = |from_round, to_round, signers| Settlement { from_round, to_round, cert: cert(to_round, signers) };
+
+    let too_few = poster.submit(0, settlement(0, 10, 2)).await.unwrap();
+    chain.mine();
+    assert!(matches!(poster.status(too_few).await, Ok(L1TxStatus::Reverted(_))));
+    assert_eq!(poster.submit(0, settlement(0, 10, 3)).await, Err(L1Error::StaleNonce(0, 1)));
+    // more than a checkpoint interval at once
+    let too_long = poster.submit(1, settlement(0, 20, 3)).await.unwrap();
+    chain.mine();
+    assert!(matches!(poster.status(too_long).await, Ok(L1TxStatus::Reverted(_))));
+
+    // a settler retries once the chain is back
+    let store = store(&[10], 3);
+    let mut settler = Settler::new(Arc::new(chain.poster(0)));
+    chain.set_down(true);
+    assert!(matches!(settler.step(&store).await, Err(L1Error::Unavailable(_))));
+    chain.set_down(false);
+    settler.step(&store).await.unwrap();
+    chain.mine();
+    assert_eq!(poster.settled_round().await, Ok(10));
+}
+
+#[tokio::test]
+async fn test_settles_every_interval_after_an_outage() {
+    let chain = Arc::new(MockChain::new(3));
+    let store = store(&[10, 20, 30, 40], 3);
+    let mut settler = Settler::new(Arc::new(chain.poster(0)));
+
+    // the L1 was down while four checkpoints became stable: a new settler,
+    // as after a restart, posts them one interval after the other
+    settler.step(&store).await.unwrap();
+    for to_round in [10, 20, 30, 40] {
+        let (settlement, _) = in_flight(&settler).await.unwrap();
+        assert_eq!((settlement.from_round, settlement.to_round), (to_round - 10, to_round));
+        assert_eq!(settlement.cert, cert(to_round, 3));
+        for _ in 0..L1_CONFIRMATIONS {
+            chain.mine();
+        }
+        // a final settlement makes way for the next one in the same poll
+        settler.step(&store).await.unwrap();
+    }
+    assert!(settler.in_flight.is_none());
+    assert_eq!(chain.poster(0).settled_round().await, Ok(40));
+}
+
+#[tokio::test]
+async fn test_missing_checkpoint_is_an_error() {
+    let chain = Arc::new(MockChain::new(3));
+    let mut settler = Settler::new(Arc::new(chain.poster(0)));
+    // the checkpoint of round 10 was stable before this node could see it
+    let store = store(&[20], 3);
+    assert_eq!(settler.step(&store).await, Err(L1Error::NoCheckpoint(0, 10)));
+    assert!(settler.in_flight.is_none());
+
+    // once another node settled it, this one carries on
+    chain.poster(1).submit(0, Settlement { from_round: 0, to_round: 10, cert: cert(10, 3) }).await.unwrap();
+    chain.mine();
+    settler.step(&store).await.unwrap();
+    let (settlement, _) = in_flight(&settler).await.unwrap();
+    assert_eq!((settlement.from_round, settlement.to_round), (10, 20));
+}
@@ -0,0 +1,154 @@
+// sampling_tests.rs
+use super::*;
+use std::net::SocketAddr;
+use std::time::Duration;
+use network::{Framing, MemoryNetwork, Receiver};
+use das::{CheckpointCert, DasClient, DasError, Member, DAS_CHUNKS, DAS_DATA_CHUNKS};
+use crate::signature::{Certificate, KeyPair};
+use crate::inclusion::RoundTree;
+use crate::store::MemStore;
+
+const NUM_NODES: usize = 4;
+
+/* the committee as a light client knows it, and the members' keys */
+fn members() -> (Vec<Member>, Vec<KeyPair>) {
+    let keypairs: Vec<KeyPair> = (0..NUM_NODES).map(|_| KeyPair::new()).collect();
+    let members = keypairs.iter()
+        .enumerate()
+        .map(|(id, keypair)| Member { id: id as u32, stake: 1, pub_key: keypair.pub_key.clone(), bls_key: None })
+        .collect();
+    (members, keypairs)
+}
+
+/* a delivered batch, certified by the first `signers` members */
+fn record(dispersal:Dispersal, round:u32, originator:u32, keypairs:&[KeyPair], signers:usize) -> Record {
+    let payload: U8Arr = (0..5000).map(|i| (i * 7 + originator) as u8).collect();
+    let digest = avid::batch_digest(dispersal, NUM_NODES, &payload);
+    let certificate = Certificate::Signs(
+        keypairs[..signers].iter().enumerate().map(|(id, keypair)| (id as u32, keypair.sign(&digest))).collect()
+    );
+    Record { round, originator, digest, payload, certificate }
+}
+
+fn handler(records:Vec<Record>) -> DasHandler {
+    let mut sampled = Sampled::default();
+    for record in records {
+        sampled.insert(Coded::new(Dispersal::Full, NUM_NODES, record).unwrap());
+    }
+    DasHandler::new(Arc::new(tk_mutex::new(sampled)), Arc::new(tk_mutex::new(RoundRoots::default())), Arc::new(MemStore::new()))
+}
+
+#[tokio::test]
+async fn test_samples_match_the_certified_header() {
+    let (members, keypairs) = members();
+    let handler = handler(vec![record(Dispersal::Full, 3, 1, &keypairs, 3)]);
+
+    let header = match handler.handle(DasRequest::Header{ originator: 1, round: 3 }).await {
+        DasResponse::Header(header) => header,
+        response => panic!("unexpected response {:?}", response),
+    };
+    assert_eq!(header.digest(), record(Dispersal::Full, 3, 1, &keypairs, 3).digest);
+    assert!(header.verify(&members));
+
+    let indices = vec![0, 17, 63, 64, 1000];
+    let samples = match handler.handle(DasRequest::Samples{ originator: 1, round: 3, indices }).await {
+        DasResponse::Samples(samples) => samples,
+        response => panic!("unexpected response {:?}", response),
+    };
+    // out of range indices are dropped
+    assert_eq!(samples.iter().map(|sample| sample.index).collect::<Vec<_>>(), vec![0, 17, 63]);
+    assert!(samples.iter().all(|sample| sample.verify(&header)));
+
+    // any half of the chunks decodes the batch
+    let indices: Vec<u32> = (0..DAS_CHUNKS as u32).filter(|index| index % 2 == 1).collect();
+    let mut chunks = vec![None; DAS_CHUNKS];
+    if let DasResponse::Samples(samples) = handler.handle(DasRequest::Samples{ originator: 1, round: 3, indices }).await {
+        for sample in samples {
+            chunks[sample.index as usize] = Some(sample.chunk);
+        }
+    }
+    assert_eq!(avid::decode_with(chunks, DAS_DATA_CHUNKS), Some(record(Dispersal::Full, 3, 1, &keypairs, 3).payload));
+
+    assert_eq!(handler.handle(DasRequest::Header{ originator: 0, round: 3 }).await, DasResponse::NotFound);
+}
+
+#[test]
+fn test_coded_batch_matches_its_digest() {
+    let (members, keypairs) = members();
+    let coded = Coded::new(Dispersal::Avid, NUM_NODES, record(Dispersal::Avid, 0, 2, &keypairs, 3)).unwrap();
+    assert!(coded.header.verify(&members));
+    // coded under another dispersal, or with another committee size, the root is not the one certified
+    assert!(Coded::new(Dispersal::Full, NUM_NODES, record(Dispersal::Avid, 0, 2, &keypairs, 3)).is_none());
+    assert!(Coded::new(Dispersal::Avid, NUM_NODES + 1, record(Dispersal::Avid, 0, 2, &keypairs, 3)).is_none());
+    let mut tampered = record(Dispersal::Avid, 0, 2, &keypairs, 3);
+    tampered.payload[0] ^= 1;
+    assert!(Coded::new(Dispersal::Avid, NUM_NODES, tampered).is_none());
+}
+
+#[test]
+fn test_sampled_kept_until_pruned() {
+    let (_, keypairs) = members();
+    let mut sampled = Sampled::default();
+    for round in 0..5 {
+        sampled.insert(Coded::new(Dispersal::Full, NUM_NODES, record(Dispersal::Full, round, 0, &keypairs, 3)).unwrap());
+    }
+    assert_eq!(sampled.len(), 5);
+    sampled.prune(3);
+    assert!(sampled.get(0, 2).is_none());
+    assert!(sampled.get(0, 3).is_some());
+    // a batch coded after its round was pruned is not kept
+    sampled.insert(Coded::new(Dispersal::Full, NUM_NODES, record(Dispersal::Full, 1, 0, &keypairs, 3)).unwrap());
+    assert_eq!(sampled.len(), 2);
+}
+
+#[tokio::test]
+async fn test_client_checks_availability() {
+    let (members, keypairs) = members();
+    let mut handler = handler(vec![
+        record(Dispersal::Full, 5, 3, &keypairs, 3),
+        // not a quorum
+        record(Dispersal::Full, 6, 3, &keypairs, 2),
+    ]);
+    let store = Arc::new(MemStore::new());
+    handler.store = store.clone();
+    let handler_roots = handler.round_roots.clone();
+    // the rounds of the first checkpoint interval, 3 reporting a transaction in round 4
+    let batch_digests: Vec<(u32, U8Arr)> = (0..4).map(|originator| (originator, vec![originator as u8; 32])).collect();
+    for round in 0..CHECKPOINT_INTERVAL {
+        let tx_digests = if round == 4 { vec![(3, das::tx_digest(b"tx"))] } else { Vec::new() };
+        handler.round_roots.lock().await.push(RoundTree::new(round, &batch_digests, &tx_digests));
+    }
+    let address: SocketAddr = "127.0.0.1:19500".parse().unwrap();
+    let network = Arc::new(MemoryNetwork::new());
+    Receiver::spawn_with_transport(address, handler, Framing::default(), None, network.clone());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut client = DasClient::connect_with_transport(address, network.as_ref()).await.unwrap();
+    let confidence = client.check_availability(3, 5, 20, &members).await.unwrap();
+    assert!(confidence > 0.9999);
+    assert!(matches!(client.check_availability(3, 7, 20, &members).await, Err(DasError::NotFound(3, 7))));
+    assert!(matches!(client.check_availability(3, 6, 20, &members).await, Err(DasError::BadHeader(3, 6))));
+    // checked against another committee
+    let (others, _) = self::members();
+    assert!(matches!(client.check_availability(3, 5, 20, &others).await, Err(DasError::BadHeader(3, 5))));
+
+    // no proof until the checkpoint is stable
+    assert!(matches!(client.inclusion(3, 0, &[3; 32], &members).await, Err(DasError::NotFound(3, 0))));
+    assert!(matches!(client.tx_inclusion(3, b"tx", &members).await, Err(DasError::TxNotFound)));
+    let digest = vec![9; 32];
+    let data_root = handler_roots.lock().await.data_root(0, CHECKPOINT_INTERVAL).unwrap();
+    let msg = das::checkpoint_msg(CHECKPOINT_INTERVAL, &digest, &data_root);
+    let signs = keypairs[..3].iter().enumerate().map(|(id, keypair)| (id as u32, keypair.sign(&msg))).collect();
+    store.put_checkpoint(CheckpointCert{ rn: CHECKPOINT_INTERVAL, digest, data_root, signs }).unwrap();
+
+    let inclusion = client.inclusion(3, 0, &[3; 32], &members).await.unwrap();
+    assert_eq!((inclusion.index, inclusion.checkpoint.rn), (3, CHECKPOINT_INTERVAL));
+    assert!(matches!(client.inclusion(3, 0, &[2; 32], &members).await, Err(DasError::BadInclusion(3, 0))));
+    assert!(matches!(client.inclusion(3, 0, &[3; 32], &others).await, Err(DasError::BadInclusion(3, 0))));
+    assert!(matches!(client.inclusion(3, CHECKPOINT_INTERVAL, &[3; 32], &members).await, Err(DasError::NotFound(3, _))));
+
+    let inclusion = client.tx_inclusion(3, b"tx", &members).await.unwrap();
+    assert_eq!((inclusion.round, inclusion.index), (4, 4));
+    assert!(matches!(client.tx_inclusion(2, b"tx", &members).await, Err(DasError::TxNotFound)));
+    assert!(matches!(client.tx_inclusion(3, b"other", &members).await, Err(DasError::TxNotFound)));
+}
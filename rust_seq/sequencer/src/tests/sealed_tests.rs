@@ -0,0 +1,86 @@
+// sealed_tests.rs
+use super::*;
+
+fn bytes(sealed:&SealedTx) -> U8Arr {
+    sealed.to_bytes()
+}
+
+#[test]
+fn test_sealed_tx_roundtrip() {
+    let (commit, reveal) = seal(b"transfer 10");
+    assert_eq!(SealedTx::from_bytes(&commit.to_bytes()), Some(commit.clone()));
+    assert_eq!(SealedTx::from_bytes(&reveal.to_bytes()), Some(reveal.clone()));
+    match (commit, reveal) {
+        (SealedTx::Commit(committed), SealedTx::Reveal{ salt, tx }) => assert_eq!(commitment(&salt, &tx), committed),
+        _ => panic!("seal returns a commit and a reveal"),
+    }
+    assert_eq!(SealedTx::from_bytes(b"commit too short"), None);
+    assert_eq!(SealedTx::from_bytes(b"plain tx"), None);
+}
+
+#[test]
+fn test_log_follows_commit_order() {
+    let (commit_a, reveal_a) = seal(b"a");
+    let (commit_b, reveal_b) = seal(b"b");
+    let mut revealer = Revealer::default();
+    assert!(revealer.add_round(0, vec![bytes(&commit_a), bytes(&commit_b)]).is_empty());
+    // b is revealed first, but waits for a
+    assert!(revealer.add_round(1, vec![bytes(&reveal_b)]).is_empty());
+    assert_eq!(revealer.add_round(2, vec![bytes(&reveal_a)]), vec![b"a".to_vec(), b"b".to_vec()]);
+    // replayed reveals do nothing
+    assert!(revealer.add_round(3, vec![bytes(&reveal_a), bytes(&reveal_b)]).is_empty());
+}
+
+#[test]
+fn test_early_reveal_is_ignored() {
+    let (commit, reveal) = seal(b"a");
+    let mut revealer = Revealer::default();
+    // revealed in the round its commitment is ordered: too early
+    assert!(revealer.add_round(0, vec![bytes(&reveal), bytes(&commit)]).is_empty());
+    assert!(revealer.add_round(1, Vec::new()).is_empty());
+    assert_eq!(revealer.add_round(2, vec![bytes(&reveal)]), vec![b"a".to_vec()]);
+
+    // a reveal that does not match any ordered commitment
+    let (_, other) = seal(b"a");
+    assert!(revealer.add_round(3, vec![bytes(&other)]).is_empty());
+}
+
+#[test]
+fn test_unrevealed_commit_is_skipped() {
+    let (commit_a, _) = seal(b"a");
+    let (commit_b, reveal_b) = seal(b"b");
+    let mut revealer = Revealer::default();
+    revealer.add_round(0, vec![bytes(&commit_a), bytes(&commit_b)]);
+    assert!(revealer.add_round(1, vec![bytes(&reveal_b)]).is_empty());
+    // a holds b up for REVEAL_DEADLINE rounds, not REVEAL_WINDOW
+    assert!(revealer.add_round(REVEAL_DEADLINE - 1, Vec::new()).is_empty());
+    assert_eq!(revealer.add_round(REVEAL_DEADLINE, Vec::new()), vec![b"b".to_vec()]);
+}
+
+#[test]
+fn test_many_stalled_commits_delay_by_deadline() {
+    // a client commits in every round and never reveals
+    let stalled: Vec<SealedTx> = (0..10).map(|i| seal(&[i]).0).collect();
+    let (commit_b, reveal_b) = seal(b"b");
+    let mut revealer = Revealer::default();
+    for (round, commit) in stalled.iter().enumerate() {
+        let mut ordered = vec![bytes(commit)];
+        if round == 9 {
+            ordered.push(bytes(&commit_b));
+        }
+        assert!(revealer.add_round(round as u32, ordered).is_empty());
+    }
+    // b waits for the commitment of round 9 only, the older ones are past the deadline
+    assert!(revealer.add_round(10, vec![bytes(&reveal_b)]).is_empty());
+    assert!(revealer.add_round(9 + REVEAL_DEADLINE - 1, Vec::new()).is_empty());
+    assert_eq!(revealer.add_round(9 + REVEAL_DEADLINE, Vec::new()), vec![b"b".to_vec()]);
+}
+
+#[test]
+fn test_unrevealed_commit_kept_while_nothing_waits() {
+    let (commit, reveal) = seal(b"a");
+    let mut revealer = Revealer::default();
+    revealer.add_round(0, vec![bytes(&commit)]);
+    assert!(revealer.add_round(REVEAL_DEADLINE + 1, Vec::new()).is_empty());
+    assert_eq!(revealer.add_round(REVEAL_WINDOW - 1, vec![bytes(&reveal)]), vec![b"a".to_vec()]);
+}
@@ -0,0 +1,110 @@
+// signature_tests.rs
+use super::*;
+
+#[test]
+fn test_verify_batch() {
+    let keypairs: Vec<KeyPair> = (0..5).map(|_| KeyPair::new()).collect();
+    let msg = b"digest";
+    let mut signs: Vec<Vec<u8>> = keypairs.iter().map(|k| k.sign(msg)).collect();
+    let pairs = |signs: &[Vec<u8>]| -> Vec<bool> {
+        let pairs: Vec<(&[u8], &[u8])> = keypairs.iter()
+            .zip(signs.iter())
+            .map(|(k, s)| (k.pub_key.as_slice(), s.as_slice()))
+            .collect();
+        verify_batch(msg, &pairs)
+    };
+
+    assert_eq!(pairs(&signs), vec![true; 5]);
+    signs[3] = keypairs[3].sign(b"other");
+    assert_eq!(pairs(&signs), vec![true, true, true, false, true]);
+    // malformed signatures fail on their own
+    signs[1] = vec![1; 10];
+    assert_eq!(pairs(&signs), vec![true, false, true, false, true]);
+    assert!(verify_batch(msg, &[]).is_empty());
+}
+
+#[test]
+fn test_small_order_keys_are_rejected() {
+    // the identity as key and R, with s = 0, satisfies the equation for any message
+    let identity = {
+        let mut point = [0; 32];
+        point[0] = 1;
+        point
+    };
+    let forged = [identity, [0; 32]].concat();
+    let msg = b"digest";
+    assert!(!KeyPair::verify_signature(&identity, msg, &forged));
+
+    // nor does it pass among valid signatures
+    let keypairs: Vec<KeyPair> = (0..3).map(|_| KeyPair::new()).collect();
+    let signs: Vec<Vec<u8>> = keypairs.iter().map(|k| k.sign(msg)).collect();
+    let mut pairs: Vec<(&[u8], &[u8])> = keypairs.iter()
+        .zip(signs.iter())
+        .map(|(k, s)| (k.pub_key.as_slice(), s.as_slice()))
+        .collect();
+    pairs.push((&identity, &forged));
+    assert_eq!(verify_batch(msg, &pairs), vec![true, true, true, false]);
+    assert!(KeyPair::verify_signature(&keypairs[0].pub_key, msg, &signs[0]));
+
+    // a small-order R with a real key
+    let mut small_r = signs[1].clone();
+    small_r[..32].copy_from_slice(&identity);
+    assert!(!KeyPair::verify_signature(&keypairs[1].pub_key, msg, &small_r));
+}
+
+#[test]
+fn test_cert_cache() {
+    let sign = |id: u32| (id, vec![id as u8; 32], vec![id as u8; 64]);
+    let key = CertCache::key(b"msg", &[sign(0), sign(1)]);
+    assert_ne!(key, CertCache::key(b"msg", &[sign(0)]));
+    assert_ne!(key, CertCache::key(b"other", &[sign(0), sign(1)]));
+
+    let mut cache = CertCache::new(2);
+    assert_eq!(cache.get(&key), None);
+    cache.insert(key.clone(), vec![0, 1]);
+    assert_eq!(cache.get(&key), Some(vec![0, 1]));
+    assert_eq!(cache.hits(), 1);
+
+    // the oldest certificate is evicted first
+    cache.insert(vec![2], vec![2]);
+    cache.insert(vec![3], vec![3]);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&key), None);
+    assert_eq!(cache.get(&[3]), Some(vec![3]));
+}
+
+#[test]
+fn test_bls_aggregate() {
+    let keypairs: Vec<BlsKeyPair> = (0..4).map(|_| BlsKeyPair::new()).collect();
+    let msg = b"digest";
+    let signs: Vec<Vec<u8>> = keypairs.iter().map(|k| k.sign(msg)).collect();
+    assert!(BlsKeyPair::verify_signature(&keypairs[0].pub_key, msg, &signs[0]));
+    assert!(!BlsKeyPair::verify_signature(&keypairs[1].pub_key, msg, &signs[0]));
+
+    let sign_refs: Vec<&[u8]> = signs[..3].iter().map(|s| s.as_slice()).collect();
+    let agg = aggregate(&sign_refs).unwrap();
+    assert_eq!(agg.len(), signs[0].len());
+    let pub_keys: Vec<&[u8]> = keypairs.iter().map(|k| k.pub_key.as_slice()).collect();
+    assert!(verify_aggregate(msg, &pub_keys[..3], &agg));
+    // the aggregate is of these three signers, on this message only
+    assert!(!verify_aggregate(msg, &pub_keys[1..], &agg));
+    assert!(!verify_aggregate(msg, &pub_keys, &agg));
+    assert!(!verify_aggregate(b"other", &pub_keys[..3], &agg));
+    assert!(!verify_aggregate(msg, &[], &agg));
+    assert!(aggregate(&[&[0; 96]]).is_none());
+}
+
+#[test]
+fn test_bls_proof_of_possession() {
+    let keypair = BlsKeyPair::new();
+    let other = BlsKeyPair::new();
+    assert!(BlsKeyPair::verify_pop(&keypair.pub_key, &keypair.pop));
+    assert!(!BlsKeyPair::verify_pop(&keypair.pub_key, &other.pop));
+    // a signature on the key is not a proof of possession, the tags differ
+    assert!(!BlsKeyPair::verify_pop(&keypair.pub_key, &keypair.sign(&keypair.pub_key)));
+
+    let path = std::env::temp_dir().join(format!("seq_bls_{}/node.bls", std::process::id()));
+    let loaded = BlsKeyPair::load_or_generate(&path).unwrap();
+    assert_eq!(BlsKeyPair::load_or_generate(&path).unwrap().pub_key, loaded.pub_key);
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
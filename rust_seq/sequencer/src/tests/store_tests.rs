@@ -0,0 +1,146 @@
+// store_tests.rs
+use super::*;
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use crate::fixtures::record;
+
+// Each test gets its own directory so they can run in parallel.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("seq_store_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn check_put_get_range(store: &dyn Store) {
+    assert_eq!(store.highest_round(), None);
+    for round in 0..5 {
+        for originator in (0..4).rev() {
+            store.put(record(round, originator)).unwrap();
+        }
+    }
+
+    assert_eq!(store.get(3, 2).unwrap(), Some(record(3, 2)));
+    assert_eq!(store.get(5, 0).unwrap(), None);
+    assert_eq!(store.highest_round(), Some(4));
+
+    let records = store.range(1, 3).unwrap();
+    let keys: Vec<(u32, u32)> = records.iter().map(|r| r.key()).collect();
+    assert_eq!(keys, vec![(1, 0), (1, 1), (1, 2), (1, 3), (2, 0), (2, 1), (2, 2), (2, 3)]);
+    assert!(store.range(3, 3).unwrap().is_empty());
+}
+
+#[test]
+fn test_mem_store() {
+    check_put_get_range(&MemStore::new());
+}
+
+#[test]
+fn test_file_store() {
+    let dir = test_dir("basic");
+    check_put_get_range(&FileStore::open(&dir).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_store_reopen() {
+    let dir = test_dir("reopen");
+    {
+        // small segments so that records spread over several files
+        let store = FileStore::open_with_segment_size(&dir, 512).unwrap();
+        for round in 0..10 {
+            store.put(record(round, 1)).unwrap();
+        }
+    }
+    assert!(fs::read_dir(&dir).unwrap().count() > 1);
+
+    let store = FileStore::open_with_segment_size(&dir, 512).unwrap();
+    assert_eq!(store.highest_round(), Some(9));
+    assert_eq!(store.range(0, 10).unwrap().len(), 10);
+    assert_eq!(store.get(7, 1).unwrap(), Some(record(7, 1)));
+
+    store.put(record(10, 1)).unwrap();
+    assert_eq!(store.get(10, 1).unwrap(), Some(record(10, 1)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_store_torn_write() {
+    let dir = test_dir("torn");
+    {
+        let store = FileStore::open(&dir).unwrap();
+        store.put(record(0, 0)).unwrap();
+        store.put(record(1, 0)).unwrap();
+    }
+    // simulate a crash in the middle of writing a record
+    let mut file = OpenOptions::new().append(true).open(segment_path(&dir, 0)).unwrap();
+    file.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    let store = FileStore::open(&dir).unwrap();
+    assert_eq!(store.range(0, 2).unwrap(), vec![record(0, 0), record(1, 0)]);
+    store.put(record(2, 0)).unwrap();
+    drop(store);
+
+    let store = FileStore::open(&dir).unwrap();
+    assert_eq!(store.range(0, 3).unwrap().len(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn check_checkpoint_and_prune(store: &dyn Store) {
+    for round in 0..10 {
+        store.put(record(round, 0)).unwrap();
+    }
+    assert_eq!(store.latest_checkpoint().unwrap(), None);
+    let cert = |rn| CheckpointCert { rn, digest: vec![rn as u8; 32], data_root: vec![1; 32], signs: vec![(1, vec![1; 64])] };
+    store.put_checkpoint(cert(5)).unwrap();
+    store.put_checkpoint(cert(3)).unwrap(); // older, not the latest, but kept
+    assert_eq!(store.latest_checkpoint().unwrap(), Some(cert(5)));
+    assert_eq!(store.checkpoint(3).unwrap(), Some(cert(3)));
+    assert_eq!(store.checkpoint(4).unwrap(), None);
+
+    store.prune(5).unwrap();
+    assert_eq!(store.get(4, 0).unwrap(), None);
+    assert_eq!(store.range(0, 10).unwrap().len(), 5);
+    assert_eq!(store.get(5, 0).unwrap(), Some(record(5, 0)));
+}
+
+#[test]
+fn test_mem_store_checkpoint_and_prune() {
+    check_checkpoint_and_prune(&MemStore::new());
+}
+
+#[test]
+fn test_file_store_checkpoint_and_prune() {
+    let dir = test_dir("prune");
+    {
+        let store = FileStore::open_with_segment_size(&dir, 512).unwrap();
+        check_checkpoint_and_prune(&store);
+    }
+    // segments holding only pruned rounds are gone, the rest is skipped on open
+    assert!(!segment_path(&dir, 0).exists());
+    let store = FileStore::open_with_segment_size(&dir, 512).unwrap();
+    assert_eq!(store.range(0, 10).unwrap().len(), 5);
+    assert_eq!(store.latest_checkpoint().unwrap().unwrap().rn, 5);
+    assert_eq!(store.checkpoint(3).unwrap().unwrap().rn, 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_store_concurrent_checkpoints() {
+    let dir = test_dir("concurrent_checkpoints");
+    let store = Arc::new(FileStore::open(&dir).unwrap());
+    let threads: Vec<_> = (1..=20)
+        .map(|rn| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                store.put_checkpoint(CheckpointCert { rn, digest: vec![rn as u8; 32], data_root: Vec::new(), signs: Vec::new() }).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    // whatever the interleaving, the newest one is kept
+    assert_eq!(store.latest_checkpoint().unwrap().unwrap().rn, 20);
+    fs::remove_dir_all(&dir).unwrap();
+}
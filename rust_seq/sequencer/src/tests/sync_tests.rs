@@ -0,0 +1,226 @@
+// sync_tests.rs
+use super::*;
+use message::SignCert;
+use crate::signature::KeyPair;
+use crate::store::MemStore;
+use crate::checkpoint::Checkpoints;
+use crate::fixtures::{committee, lists, peer_pkeys_of, record};
+
+const NUM_NODES: usize = 4;
+
+#[tokio::test]
+async fn test_delivered_prefix() {
+    let delivered = vec![
+        tk_rwlock::new(vec![true, true, false, true]),
+        tk_rwlock::new(vec![]),
+        tk_rwlock::new(vec![true, true, true]),
+    ];
+    assert_eq!(delivered_prefix(&delivered).await, vec![2, 0, 3]);
+}
+
+#[tokio::test]
+async fn test_sync_req_streams_missing_rounds() {
+    let store = Arc::new(MemStore::new());
+    for round in 0..10 {
+        for originator in 0..NUM_NODES as u32 {
+            store.put(record(round, originator)).unwrap();
+        }
+    }
+    let (tx_send, mut rx_send) = tokio_mpsc::channel(100);
+    handle_sync_req(
+        0,
+        3,
+        vec![2, 6, 6, 6],
+        NUM_NODES as u32,
+        store,
+        Arc::new(Semaphore::new(1)),
+        &tx_send
+    ).await;
+    drop(tx_send);
+
+    // rounds 7 to 9 are too close to the tip and left to the live protocol
+    let mut received = Vec::new();
+    while let Some(CastType::Unicast{ dest, round: None, priority: Priority::Bulk, bytes }) = rx_send.recv().await {
+        assert_eq!(dest, 3);
+        match Message::from_bytes(bytes).unwrap() {
+            Message::SyncResp{ rn, originator, payload, .. } => {
+                assert_eq!(payload, vec![originator as u8; 100]);
+                received.push((rn, originator));
+            }
+            _ => panic!("unexpected message"),
+        }
+    }
+    assert_eq!(received, vec![(2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (6, 1), (6, 2), (6, 3)]);
+}
+
+/*
+* Node 3 has folded only the first checkpoint interval when the second one
+* becomes stable: the rounds it misses stay in the store until it folds them,
+* and it syncs all of them.
+*/
+#[tokio::test]
+async fn test_lagging_member_catches_up() {
+    let committee = committee(&[1; NUM_NODES]);
+    let store = Arc::new(MemStore::new());
+    for round in 0..25 {
+        for originator in 0..NUM_NODES as u32 {
+            store.put(record(round, originator)).unwrap();
+        }
+    }
+    let mut checkpoints = Checkpoints::default();
+    checkpoints.set_folded(25);
+    for signer in 0..NUM_NODES as u32 {
+        checkpoints.add_vote(10, signer, vec![1], vec![1], vec![], &committee);
+    }
+    for signer in 0..3 {
+        checkpoints.add_vote(20, signer, vec![2], vec![2], vec![], &committee);
+    }
+    assert_eq!(checkpoints.stable(), 20);
+    let (_, below) = checkpoints.start_pruning(&committee).unwrap();
+    assert_eq!(below, 10);
+    store.prune(below).unwrap();
+
+    let serving = Arc::new(Semaphore::new(1));
+    let mut delivered = vec![10; NUM_NODES];
+    let mut received = Vec::new();
+    loop {
+        let (tx_send, mut rx_send) = tokio_mpsc::channel(100);
+        handle_sync_req(0, 3, delivered.clone(), NUM_NODES as u32, store.clone(), serving.clone(), &tx_send).await;
+        drop(tx_send);
+        let before = received.len();
+        while let Some(CastType::Unicast{ bytes, .. }) = rx_send.recv().await {
+            if let Message::SyncResp{ rn, originator, .. } = Message::from_bytes(bytes).unwrap() {
+                delivered[originator as usize] = rn + 1;
+                received.push((rn, originator));
+            }
+        }
+        if received.len() == before {
+            break;
+        }
+    }
+    // every round up to the responder's tip, but those left to the live protocol
+    let expected: Vec<(u32, u32)> = (10..24 - SYNC_LAG)
+        .flat_map(|rn| (0..NUM_NODES as u32).map(move |originator| (rn, originator)))
+        .collect();
+    received.sort_unstable();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn test_sync_req_dropped_while_serving() {
+    let store = Arc::new(MemStore::new());
+    for round in 0..10 {
+        store.put(record(round, 0)).unwrap();
+    }
+    let serving = Arc::new(Semaphore::new(1));
+    let _permit = serving.clone().try_acquire_owned().unwrap();
+    let (tx_send, mut rx_send) = tokio_mpsc::channel(100);
+    handle_sync_req(0, 1, vec![0; NUM_NODES], NUM_NODES as u32, store, serving, &tx_send).await;
+    drop(tx_send);
+    assert!(rx_send.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn test_sync_resp_verifies_certificate() {
+    let keypairs: Vec<KeyPair> = (0..NUM_NODES).map(|_| KeyPair::new()).collect();
+    let self_node_ind = 3;
+    let peer_pkeys = peer_pkeys_of(&keypairs, self_node_ind);
+    let delivered = lists();
+    let recv_sup = lists();
+    let tx_list = lists();
+    let hash_list = lists();
+    let cert_list = lists();
+    let store = Arc::new(MemStore::new());
+    let committee = committee(&[1; NUM_NODES]);
+    let cert_cache = Arc::new(tk_mutex::new(CertCache::default()));
+    let crypto = Arc::new(CryptoPool::new(1, 8));
+    let bls_pkeys = Arc::new(tk_rwlock::new(vec![None; NUM_NODES]));
+
+    let payload = vec![1u8; 32];
+    let payload_digest = avid::batch_digest(Dispersal::Full, NUM_NODES, &payload);
+    let signs: Vec<(u32, U8Arr)> = [0, 2, 3]
+        .iter()
+        .map(|i| (*i as u32, keypairs[*i].sign(&payload_digest)))
+        .collect();
+
+    // the same signer three times only counts once
+    let forged: SignCert = vec![signs[0].clone(), signs[0].clone(), signs[0].clone()].into_iter().collect();
+    assert_eq!(forged.len(), 1);
+    handle_sync_resp(
+        self_node_ind as u32,
+        &keypairs[self_node_ind].pub_key,
+        &committee,
+        1,
+        2,
+        Certificate::Signs(forged),
+        payload.clone(),
+        peer_pkeys.clone(),
+        bls_pkeys.clone(),
+        cert_cache.clone(),
+        crypto.clone(),
+        Dispersal::Full,
+        delivered.clone(),
+        recv_sup.clone(),
+        tx_list.clone(),
+        hash_list.clone(),
+        cert_list.clone(),
+        store.clone(),
+    ).await;
+    assert!(delivered[1].read().await.is_empty());
+    assert_eq!(store.get(2, 1).unwrap(), None);
+
+    // nor is a certificate signed by a node outside the round's committee
+    let outsider = KeyPair::new();
+    peer_pkeys.write().await.push(Some(outsider.pub_key.clone()));
+    let foreign: SignCert = vec![signs[0].clone(), signs[1].clone(), (4, outsider.sign(&payload_digest))]
+        .into_iter()
+        .collect();
+    handle_sync_resp(
+        self_node_ind as u32,
+        &keypairs[self_node_ind].pub_key,
+        &committee,
+        1,
+        2,
+        Certificate::Signs(foreign),
+        payload.clone(),
+        peer_pkeys.clone(),
+        bls_pkeys.clone(),
+        cert_cache.clone(),
+        crypto.clone(),
+        Dispersal::Full,
+        delivered.clone(),
+        recv_sup.clone(),
+        tx_list.clone(),
+        hash_list.clone(),
+        cert_list.clone(),
+        store.clone(),
+    ).await;
+    assert!(delivered[1].read().await.is_empty());
+
+    handle_sync_resp(
+        self_node_ind as u32,
+        &keypairs[self_node_ind].pub_key,
+        &committee,
+        1,
+        2,
+        Certificate::Signs(signs.iter().cloned().collect()),
+        payload.clone(),
+        peer_pkeys,
+        bls_pkeys,
+        cert_cache,
+        crypto,
+        Dispersal::Full,
+        delivered.clone(),
+        recv_sup.clone(),
+        tx_list.clone(),
+        hash_list,
+        cert_list,
+        store.clone(),
+    ).await;
+    assert_eq!(*delivered[1].read().await, vec![false, false, true]);
+    assert_eq!(recv_sup[1].read().await.len(), 3);
+    assert_eq!(tx_list[1].read().await[2], payload);
+    let record = store.get(2, 1).unwrap().unwrap();
+    assert_eq!(record.digest, payload_digest);
+    assert_eq!(record.certificate, Certificate::Signs(signs.into_iter().collect()));
+}